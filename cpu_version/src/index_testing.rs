@@ -38,4 +38,20 @@ impl Array3D {
 
         (x, y, z)
     }
+
+    /// Get the value at (x, y, z).
+    pub fn get_value(&self, x: u32, y: u32, z: u32) -> f32 {
+        self.array[self.get_index(x, y, z) as usize]
+    }
+
+    /// Set the value at (x, y, z).
+    pub fn set_value(&mut self, x: u32, y: u32, z: u32, value: f32) {
+        let index = self.get_index(x, y, z) as usize;
+        self.array[index] = value;
+    }
+
+    /// Get the dimensions of the array.
+    pub fn get_dimension(&self) -> (u32, u32, u32) {
+        self.dimension
+    }
 }
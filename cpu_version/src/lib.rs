@@ -1 +1,3 @@
 pub mod index_testing; 
+pub mod narrow_band; 
+pub mod voxelize; 
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use cgmath::{prelude::*, Vector3};
+use bytemuck::{Pod, Zeroable};
+use geometry::aabb::BBox;
+use geometry::triangle::{Triangle, barycentric_cooordinates};
+use geometry::ray::Ray;
+use geometry::grid::GridTraversal;
+
+/// Node tags. These must match the tags in the fmm shaders.
+pub const KNOWN: u32 = 0;
+pub const BAND: u32 = 1;
+pub const FAR: u32 = 3;
+
+/// The initial value of FAR nodes.
+pub const FAR_VALUE: f32 = 1000000.0;
+
+/// The dimensions of a single fmm block (4x4x4 nodes).
+pub const LOCAL_DIMENSION: [u32; 3] = [4, 4, 4];
+
+/// A closest point is on an edge or a vertex if its barycentric coordinates are closer to zero
+/// than this.
+const FEATURE_EPSILON: f32 = 0.00001;
+
+/// A single fmm node. The same layout as FMM_Node in the fmm shaders.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FMM_Node {
+    pub value: f32,
+    pub tag: u32,
+}
+
+unsafe impl Zeroable for FMM_Node {}
+unsafe impl Pod for FMM_Node {}
+
+/// The initial state of fmm computation domain. The nodes are stored block by block
+/// (global_dimensions are in blocks) and inside the block in x, y, z order. This is the same
+/// layout as the fmm_nodes buffer.
+pub struct NarrowBand {
+    pub nodes: Vec<FMM_Node>,
+    pub global_dimensions: [u32; 3],
+    pub origin: Vector3<f32>,
+    pub grid_spacing: f32,
+}
+
+/// Per node closest surface information.
+#[derive(Clone, Copy)]
+struct ClosestPoint {
+    distance: f32,
+    point: Vector3<f32>,
+    triangle: usize,
+}
+
+/// The position of a vertex as a hash key. + 0.0 converts -0.0 to 0.0.
+type PositionKey = [u32; 3];
+
+fn position_key(p: &Vector3<f32>) -> PositionKey {
+    [(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()]
+}
+
+fn edge_key(a: &Vector3<f32>, b: &Vector3<f32>) -> (PositionKey, PositionKey) {
+    let (a, b) = (position_key(a), position_key(b));
+    if a < b { (a, b) } else { (b, a) }
+}
+
+/// The angle weighted pseudonormals of a triangle mesh (Bærentsen and Aanæs, "Signed distance
+/// computation using the angle weighted pseudonormal"). The sign of the distance to the closest
+/// point is the sign of the dot product of the pseudonormal of the closest face, edge or vertex
+/// and the direction from the closest point. The vertices are shared by position.
+struct Pseudonormals {
+    faces: Vec<Vector3<f32>>,
+    edges: HashMap<(PositionKey, PositionKey), Vector3<f32>>,
+    vertices: HashMap<PositionKey, Vector3<f32>>,
+}
+
+impl Pseudonormals {
+
+    fn init(triangles: &[Triangle]) -> Self {
+
+        let mut faces = Vec::with_capacity(triangles.len());
+        let mut edges = HashMap::new();
+        let mut vertices = HashMap::new();
+
+        for tr in triangles.iter() {
+            let normal = (tr.b - tr.a).cross(tr.c - tr.a);
+            if normal.magnitude2() == 0.0 {
+                faces.push(Vector3::<f32>::zero());
+                continue;
+            }
+            let normal = normal.normalize();
+            faces.push(normal);
+
+            for (p, q, r) in [(tr.a, tr.b, tr.c), (tr.b, tr.c, tr.a), (tr.c, tr.a, tr.b)].iter() {
+                // The edge normal is the sum of the two face normals (both weighted by pi).
+                *edges.entry(edge_key(p, q)).or_insert_with(Vector3::<f32>::zero) += normal;
+                // The vertex normal is weighted by the incident angle of each face.
+                let angle = (q - p).angle(r - p).0;
+                *vertices.entry(position_key(p)).or_insert_with(Vector3::<f32>::zero) += angle * normal;
+            }
+        }
+
+        Self {
+            faces: faces,
+            edges: edges,
+            vertices: vertices,
+        }
+    }
+
+    /// The pseudonormal of the feature (face, edge or vertex) of the triangle that contains point.
+    fn get(&self, triangle_index: usize, tr: &Triangle, point: &Vector3<f32>) -> Vector3<f32> {
+        let uvw = barycentric_cooordinates(&tr.a, &tr.b, &tr.c, point);
+        let on_a = uvw.x.abs() < FEATURE_EPSILON;
+        let on_b = uvw.y.abs() < FEATURE_EPSILON;
+        let on_c = uvw.z.abs() < FEATURE_EPSILON;
+        match (on_a, on_b, on_c) {
+            (false, true, true) => self.vertices[&position_key(&tr.a)],
+            (true, false, true) => self.vertices[&position_key(&tr.b)],
+            (true, true, false) => self.vertices[&position_key(&tr.c)],
+            (true, false, false) => self.edges[&edge_key(&tr.b, &tr.c)],
+            (false, true, false) => self.edges[&edge_key(&tr.c, &tr.a)],
+            (false, false, true) => self.edges[&edge_key(&tr.a, &tr.b)],
+            _ => self.faces[triangle_index],
+        }
+    }
+}
+
+impl NarrowBand {
+
+    /// Compute the exact signed distances for each node within band_width nodes from the surface.
+    /// Those nodes are tagged KNOWN and all other nodes FAR. The aabb should be snapped to the grid
+    /// (BBox::expand_to_nearest_grids) with the same grid_spacing. The distance is negative inside
+    /// the mesh. The sign is solved from the angle weighted pseudonormal of the closest face, edge
+    /// or vertex, so the mesh should be closed and the triangles should be in ccw order. The
+    /// result can be uploaded to the fmm_nodes buffer as the initial state.
+    pub fn from_triangles(triangles: &Vec<Triangle>, aabb: &BBox, grid_spacing: f32, band_width: u32) -> Self {
+
+        assert!(grid_spacing > 0.0, "{}", format!("grid_spacing == {} > 0.0", grid_spacing));
+
+        let global_dimensions = NarrowBand::dimensions_from_aabb(aabb, grid_spacing);
+        let node_dimensions = [global_dimensions[0] * LOCAL_DIMENSION[0],
+                               global_dimensions[1] * LOCAL_DIMENSION[1],
+                               global_dimensions[2] * LOCAL_DIMENSION[2]];
+        let number_of_nodes = (node_dimensions[0] * node_dimensions[1] * node_dimensions[2]) as usize;
+
+        let max_distance = band_width as f32 * grid_spacing;
+        let pseudonormals = Pseudonormals::init(triangles);
+
+        let mut closest: Vec<Option<ClosestPoint>> = vec![None; number_of_nodes];
+
+        let mut result = Self {
+            nodes: vec![FMM_Node { value: FAR_VALUE, tag: FAR }; number_of_nodes],
+            global_dimensions: global_dimensions,
+            origin: aabb.min,
+            grid_spacing: grid_spacing,
+        };
+
+        for (triangle_index, tr) in triangles.iter().enumerate() {
+
+            // Skip degenerated triangles.
+            if pseudonormals.faces[triangle_index].magnitude2() == 0.0 { continue; }
+
+            // The nodes that might be inside the band of this triangle.
+            let mut tr_aabb = BBox::create_from_triangle(&tr.a, &tr.b, &tr.c);
+            tr_aabb.min -= Vector3::<f32>::new(max_distance, max_distance, max_distance);
+            tr_aabb.max += Vector3::<f32>::new(max_distance, max_distance, max_distance);
+            let (min_coord, max_coord) = match result.coordinate_range(&tr_aabb, &node_dimensions) {
+                Some(range) => range,
+                None => continue,
+            };
+
+            for k in min_coord[2]..=max_coord[2] {
+            for j in min_coord[1]..=max_coord[1] {
+            for i in min_coord[0]..=max_coord[0] {
+
+                let p = result.node_position(i, j, k);
+                let point = tr.closest_point_to_triangle(&p);
+                let distance = point.distance(p);
+
+                if distance > max_distance { continue; }
+
+                let index = result.node_index(i, j, k) as usize;
+
+                // The pseudonormals of the shared edges and vertices give the same sign for every
+                // triangle that shares the closest point, so the first closest one is kept.
+                closest[index] = match closest[index] {
+                    Some(c) if distance >= c.distance => Some(c),
+                    _ => Some(ClosestPoint { distance: distance, point: point, triangle: triangle_index }),
+                };
+            }}};
+        }
+
+        for (i, c) in closest.iter().enumerate() {
+            if let Some(c) = c {
+                let (x, y, z) = result.node_coordinate(i as u32);
+                let p = result.node_position(x, y, z);
+                let normal = pseudonormals.get(c.triangle, &triangles[c.triangle], &c.point);
+                let sign = if normal.dot(p - c.point) < 0.0 { -1.0 } else { 1.0 };
+                result.nodes[i] = FMM_Node { value: sign * c.distance, tag: KNOWN };
+            }
+        }
+
+        result
+    }
+
+    /// Compute the number of fmm blocks needed to cover the aabb.
+    pub fn dimensions_from_aabb(aabb: &BBox, grid_spacing: f32) -> [u32; 3] {
+        let extent = aabb.max - aabb.min;
+        let blocks = |e: f32, local: u32| -> u32 {
+            let node_count = (e / grid_spacing).round() as u32 + 1;
+            (node_count + local - 1) / local
+        };
+        [blocks(extent.x, LOCAL_DIMENSION[0]),
+         blocks(extent.y, LOCAL_DIMENSION[1]),
+         blocks(extent.z, LOCAL_DIMENSION[2])]
+    }
+
+    /// Get the index of the node (x, y, z) in the fmm node buffer.
+    pub fn node_index(&self, x: u32, y: u32, z: u32) -> u32 {
+        let block_index = x / LOCAL_DIMENSION[0] +
+                          y / LOCAL_DIMENSION[1] * self.global_dimensions[0] +
+                          z / LOCAL_DIMENSION[2] * self.global_dimensions[0] * self.global_dimensions[1];
+        let local_index = x % LOCAL_DIMENSION[0] +
+                          y % LOCAL_DIMENSION[1] * LOCAL_DIMENSION[0] +
+                          z % LOCAL_DIMENSION[2] * LOCAL_DIMENSION[0] * LOCAL_DIMENSION[1];
+        block_index * LOCAL_DIMENSION[0] * LOCAL_DIMENSION[1] * LOCAL_DIMENSION[2] + local_index
+    }
+
+    /// Get the node coordinate (x, y, z) from the index of fmm node buffer.
+    pub fn node_coordinate(&self, index: u32) -> (u32, u32, u32) {
+        let block_size = LOCAL_DIMENSION[0] * LOCAL_DIMENSION[1] * LOCAL_DIMENSION[2];
+        let block = split_index(index / block_size, self.global_dimensions[0], self.global_dimensions[1]);
+        let local = split_index(index % block_size, LOCAL_DIMENSION[0], LOCAL_DIMENSION[1]);
+        (block.0 * LOCAL_DIMENSION[0] + local.0,
+         block.1 * LOCAL_DIMENSION[1] + local.1,
+         block.2 * LOCAL_DIMENSION[2] + local.2)
+    }
+
+    /// Get the world position of the node (x, y, z).
+    pub fn node_position(&self, x: u32, y: u32, z: u32) -> Vector3<f32> {
+        self.origin + self.grid_spacing * Vector3::<f32>::new(x as f32, y as f32, z as f32)
+    }
+
+    /// Get the node (x, y, z).
+    pub fn get_node(&self, x: u32, y: u32, z: u32) -> FMM_Node {
+        self.nodes[self.node_index(x, y, z) as usize]
+    }
+
+    /// Find the first node along the ray that isn't FAR. Each node is treated as a cell of size
+    /// grid_spacing centered at the node. Returns the node coordinate, the node index and the node.
+    pub fn pick(&self, ray: &Ray) -> Option<([u32; 3], u32, FMM_Node)> {
+        let half = Vector3::<f32>::new(0.5, 0.5, 0.5) * self.grid_spacing;
+        let node_dimensions = [self.global_dimensions[0] * LOCAL_DIMENSION[0],
+                               self.global_dimensions[1] * LOCAL_DIMENSION[1],
+                               self.global_dimensions[2] * LOCAL_DIMENSION[2]];
+        GridTraversal::init(ray, &(self.origin - half), self.grid_spacing, node_dimensions)
+            .map(|cell| {
+                let c = cell.coordinate;
+                let index = self.node_index(c[0], c[1], c[2]);
+                (c, index, self.nodes[index as usize])
+            })
+            .find(|(_, _, node)| node.tag != FAR)
+    }
+
+    /// The node coordinate range that covers aabb. Returns None if aabb is outside the domain.
+    fn coordinate_range(&self, aabb: &BBox, node_dimensions: &[u32; 3]) -> Option<([u32; 3], [u32; 3])> {
+        let min = (aabb.min - self.origin) / self.grid_spacing;
+        let max = (aabb.max - self.origin) / self.grid_spacing;
+        let mut min_coord = [0; 3];
+        let mut max_coord = [0; 3];
+        for i in 0..3 {
+            let upper = node_dimensions[i] as f32 - 1.0;
+            if max[i] < 0.0 || min[i] > upper { return None; }
+            min_coord[i] = min[i].ceil().max(0.0) as u32;
+            max_coord[i] = max[i].floor().min(upper) as u32;
+        }
+        Some((min_coord, max_coord))
+    }
+}
+
+/// Split index to (x, y, z) using dimensions dim_x and dim_y.
+fn split_index(mut index: u32, dim_x: u32, dim_y: u32) -> (u32, u32, u32) {
+    let wh = dim_x * dim_y;
+    let z = index / wh;
+    index -= z * wh;
+    let y = index / dim_x;
+    index -= y * dim_x;
+    (index, y, z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A closed prism in ccw order. The polygon is in the xy-plane in ccw order and the triangles
+    /// of the caps are a fan from the first polygon vertex.
+    fn prism(polygon: &[(f32, f32)], z_min: f32, z_max: f32) -> Vec<Triangle> {
+        let v = |p: (f32, f32), z: f32| Vector3::<f32>::new(p.0, p.1, z);
+        let n = polygon.len();
+        let mut triangles = Vec::new();
+        for i in 1..n-1 {
+            triangles.push(Triangle { a: v(polygon[0], z_min), b: v(polygon[i+1], z_min), c: v(polygon[i], z_min) });
+            triangles.push(Triangle { a: v(polygon[0], z_max), b: v(polygon[i], z_max), c: v(polygon[i+1], z_max) });
+        }
+        for i in 0..n {
+            let (p, q) = (polygon[i], polygon[(i + 1) % n]);
+            triangles.push(Triangle { a: v(p, z_min), b: v(q, z_min), c: v(q, z_max) });
+            triangles.push(Triangle { a: v(p, z_min), b: v(q, z_max), c: v(p, z_max) });
+        }
+        triangles
+    }
+
+    /// Check that each KNOWN node has the signed distance to the mesh and that all nodes inside
+    /// the band are KNOWN.
+    fn check_signed_distances(triangles: &Vec<Triangle>, band: &NarrowBand, band_width: u32, inside: impl Fn(&Vector3<f32>) -> bool) {
+        let max_distance = band_width as f32 * band.grid_spacing;
+        for (i, node) in band.nodes.iter().enumerate() {
+            let (x, y, z) = band.node_coordinate(i as u32);
+            let p = band.node_position(x, y, z);
+            let distance = triangles.iter()
+                                    .map(|tr| tr.closest_point_to_triangle(&p).distance(p))
+                                    .fold(f32::MAX, f32::min);
+            if distance > max_distance {
+                assert_eq!(node.tag, FAR, "node {:?} at {:?} should be FAR", (x, y, z), p);
+                continue;
+            }
+            let expected = if inside(&p) { -distance } else { distance };
+            assert_eq!(node.tag, KNOWN, "node {:?} at {:?} should be KNOWN", (x, y, z), p);
+            assert!((node.value - expected).abs() < 1e-5, "node {:?} at {:?}: {} != {}", (x, y, z), p, node.value, expected);
+        }
+    }
+
+    #[test]
+    fn cube_signed_distances() {
+        let triangles = prism(&[(0.1, 0.1), (1.1, 0.1), (1.1, 1.1), (0.1, 1.1)], 0.1, 1.1);
+        let aabb = BBox { min: Vector3::<f32>::new(-0.5, -0.5, -0.5), max: Vector3::<f32>::new(1.75, 1.75, 1.75) };
+        let band = NarrowBand::from_triangles(&triangles, &aabb, 0.25, 2);
+
+        // The node (0.5, 0.5, 0.5) is 0.4 inside the three nearest faces.
+        let inner = band.get_node(4, 4, 4);
+        assert_eq!(inner.tag, KNOWN);
+        assert!((inner.value + 0.4).abs() < 1e-5, "inner: {}", inner.value);
+
+        // The outside node (0, 0, 0) is closest to the corner vertex (0.1, 0.1, 0.1).
+        let corner = band.get_node(2, 2, 2);
+        assert!((corner.value - 0.1 * 3.0_f32.sqrt()).abs() < 1e-5, "corner: {}", corner.value);
+
+        check_signed_distances(&triangles, &band, 2, |p| {
+            (0.1..1.1).contains(&p.x) && (0.1..1.1).contains(&p.y) && (0.1..1.1).contains(&p.z)
+        });
+    }
+
+    #[test]
+    fn concave_signed_distances() {
+        // An L-shaped prism. The caps are fanned from the reflex vertex (1.1, 1.1), so the concave
+        // edge and its vertices are shared by many triangles.
+        let triangles = prism(&[(1.1, 1.1), (1.1, 2.1), (0.1, 2.1), (0.1, 0.1), (2.1, 0.1), (2.1, 1.1)], 0.1, 1.1);
+        let aabb = BBox { min: Vector3::<f32>::new(-0.5, -0.5, -0.5), max: Vector3::<f32>::new(2.75, 2.75, 1.75) };
+        let band = NarrowBand::from_triangles(&triangles, &aabb, 0.25, 2);
+
+        check_signed_distances(&triangles, &band, 2, |p| {
+            let in_l = (0.1..2.1).contains(&p.x) && (0.1..2.1).contains(&p.y) && !(p.x > 1.1 && p.y > 1.1);
+            in_l && (0.1..1.1).contains(&p.z)
+        });
+    }
+
+    #[test]
+    fn tetrahedron_vertex_sign() {
+        // The nodes near the apex are closest to the apex vertex, where the sum of the face normals
+        // and the angle weighted pseudonormal may differ.
+        let v = |x: f32, y: f32, z: f32| Vector3::<f32>::new(x, y, z);
+        let (a, b, c, d) = (v(0.1, 0.1, 0.1), v(2.1, 0.1, 0.1), v(0.1, 2.1, 0.1), v(0.1, 0.1, 2.1));
+        let triangles = vec![
+            Triangle { a: a, b: c, c: b },
+            Triangle { a: a, b: b, c: d },
+            Triangle { a: a, b: d, c: c },
+            Triangle { a: b, b: c, c: d },
+        ];
+        let aabb = BBox { min: Vector3::<f32>::new(-0.5, -0.5, -0.5), max: Vector3::<f32>::new(2.75, 2.75, 2.75) };
+        let band = NarrowBand::from_triangles(&triangles, &aabb, 0.25, 2);
+
+        check_signed_distances(&triangles, &band, 2, |p| {
+            p.x > 0.1 && p.y > 0.1 && p.z > 0.1 && p.x + p.y + p.z < 2.3
+        });
+    }
+}
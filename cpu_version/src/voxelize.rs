@@ -0,0 +1,287 @@
+use cgmath::Vector3;
+use geometry::aabb::BBox;
+use geometry::triangle::Triangle;
+use crate::index_testing::Array3D;
+
+/// The value of an empty voxel.
+pub const EMPTY: u32 = 0;
+
+/// The value of an occupied voxel.
+pub const OCCUPIED: u32 = 1;
+
+/// An occupancy grid. The voxel (x, y, z) covers the cube
+/// [origin + voxel_size * (x, y, z), origin + voxel_size * (x + 1, y + 1, z + 1)]. The voxels are
+/// stored in x, y, z order (the same as Array3D and the voxel buffer of the voxelization shader).
+pub struct VoxelGrid {
+    pub data: Vec<u32>,
+    pub dimension: [u32; 3],
+    pub origin: Vector3<f32>,
+    pub voxel_size: f32,
+}
+
+impl VoxelGrid {
+
+    /// Create an empty voxel grid that covers the aabb.
+    pub fn init(aabb: &BBox, voxel_size: f32) -> Self {
+
+        assert!(voxel_size > 0.0, "{}", format!("voxel_size == {} > 0.0", voxel_size));
+
+        let dimension = VoxelGrid::dimensions_from_aabb(aabb, voxel_size);
+
+        Self {
+            data: vec![EMPTY; (dimension[0] * dimension[1] * dimension[2]) as usize],
+            dimension: dimension,
+            origin: aabb.min,
+            voxel_size: voxel_size,
+        }
+    }
+
+    /// Surface voxelization. A voxel is occupied if any of the triangles overlaps or touches it.
+    pub fn surface(triangles: &Vec<Triangle>, aabb: &BBox, voxel_size: f32) -> Self {
+
+        let mut result = VoxelGrid::init(aabb, voxel_size);
+
+        for tr in triangles.iter() {
+
+            let tr_aabb = BBox::create_from_triangle(&tr.a, &tr.b, &tr.c);
+            let min = (tr_aabb.min - result.origin) / voxel_size;
+            let max = (tr_aabb.max - result.origin) / voxel_size;
+
+            // The voxels that might overlap or touch the triangle.
+            let range = |i: usize| -> Option<(u32, u32)> {
+                let first = (min[i].ceil() - 1.0).max(0.0);
+                let last = max[i].floor().min(result.dimension[i] as f32 - 1.0);
+                if first > last { None } else { Some((first as u32, last as u32)) }
+            };
+            let ((x0, x1), (y0, y1), (z0, z1)) = match (range(0), range(1), range(2)) {
+                (Some(x), Some(y), Some(z)) => (x, y, z),
+                _ => continue,
+            };
+
+            for k in z0..=z1 {
+            for j in y0..=y1 {
+            for i in x0..=x1 {
+                if result.voxel_aabb(i, j, k).intersects_triangle(tr) {
+                    let index = result.get_index(i, j, k) as usize;
+                    result.data[index] = OCCUPIED;
+                }
+            }}};
+        }
+
+        result
+    }
+
+    /// Solid voxelization. A voxel is occupied if its center is inside the mesh. Each voxel row
+    /// is scanned along the x-axis and the parity of the surface crossings before the voxel
+    /// center tells if the voxel is inside. The mesh should be closed.
+    pub fn solid(triangles: &Vec<Triangle>, aabb: &BBox, voxel_size: f32) -> Self {
+
+        let mut result = VoxelGrid::init(aabb, voxel_size);
+
+        for tr in triangles.iter() {
+
+            let tr_aabb = BBox::create_from_triangle(&tr.a, &tr.b, &tr.c);
+            let min = (tr_aabb.min - result.origin) / voxel_size;
+            let max = (tr_aabb.max - result.origin) / voxel_size;
+
+            // The voxel rows whose center line might cross the triangle.
+            let range = |i: usize| -> Option<(u32, u32)> {
+                let first = (min[i] - 0.5).ceil().max(0.0);
+                let last = (max[i] - 0.5).floor().min(result.dimension[i] as f32 - 1.0);
+                if first > last { None } else { Some((first as u32, last as u32)) }
+            };
+            let ((y0, y1), (z0, z1)) = match (range(1), range(2)) {
+                (Some(y), Some(z)) => (y, z),
+                _ => continue,
+            };
+
+            for k in z0..=z1 {
+            for j in y0..=y1 {
+
+                let center = result.voxel_center(0, j, k);
+                let x = match scanline_crossing(tr, center.y, center.z) {
+                    Some(x) => x,
+                    None => continue,
+                };
+
+                // Flip the voxels whose center is after the crossing.
+                let first = ((x - result.origin.x) / voxel_size - 0.5).floor() + 1.0;
+                let first = first.max(0.0).min(result.dimension[0] as f32) as u32;
+                for i in first..result.dimension[0] {
+                    let index = result.get_index(i, j, k) as usize;
+                    result.data[index] ^= OCCUPIED;
+                }
+            }};
+        }
+
+        result
+    }
+
+    /// Compute the number of voxels needed to cover the aabb.
+    pub fn dimensions_from_aabb(aabb: &BBox, voxel_size: f32) -> [u32; 3] {
+        let extent = aabb.extent() / voxel_size;
+        [(extent.x.ceil() as u32).max(1),
+         (extent.y.ceil() as u32).max(1),
+         (extent.z.ceil() as u32).max(1)]
+    }
+
+    /// Get the index of the voxel (x, y, z).
+    pub fn get_index(&self, x: u32, y: u32, z: u32) -> u32 {
+        assert!(x < self.dimension[0] && y < self.dimension[1] && z < self.dimension[2],
+                "({}, {}, {}) not in range [(0, 0, 0), ({}, {}, {})[",
+                x, y, z, self.dimension[0], self.dimension[1], self.dimension[2]);
+        x + y * self.dimension[0] + z * self.dimension[0] * self.dimension[1]
+    }
+
+    /// Check if the voxel (x, y, z) is occupied.
+    pub fn is_occupied(&self, x: u32, y: u32, z: u32) -> bool {
+        self.data[self.get_index(x, y, z) as usize] == OCCUPIED
+    }
+
+    /// Get the aabb of the voxel (x, y, z).
+    pub fn voxel_aabb(&self, x: u32, y: u32, z: u32) -> BBox {
+        let min = self.origin + self.voxel_size * Vector3::<f32>::new(x as f32, y as f32, z as f32);
+        BBox {
+            min: min,
+            max: min + Vector3::<f32>::new(self.voxel_size, self.voxel_size, self.voxel_size),
+        }
+    }
+
+    /// Get the center of the voxel (x, y, z).
+    pub fn voxel_center(&self, x: u32, y: u32, z: u32) -> Vector3<f32> {
+        self.origin + self.voxel_size * Vector3::<f32>::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5)
+    }
+
+    /// Convert to Array3D. Occupied voxels get the value occupied_value and empty voxels
+    /// empty_value.
+    pub fn to_array3d(&self, occupied_value: f32, empty_value: f32) -> Array3D {
+        let mut result = Array3D::init(self.dimension[0], self.dimension[1], self.dimension[2], empty_value);
+        for k in 0..self.dimension[2] {
+        for j in 0..self.dimension[1] {
+        for i in 0..self.dimension[0] {
+            if self.is_occupied(i, j, k) { result.set_value(i, j, k, occupied_value); }
+        }}};
+        result
+    }
+}
+
+/// The x coordinate where the line parallel to x-axis through (y, z) crosses the triangle. A line
+/// that goes through a shared edge or vertex crosses exactly one of the triangles (top-left rule
+/// on the yz-plane). The same test is done in voxelize.wgsl.
+fn scanline_crossing(tr: &Triangle, y: f32, z: f32) -> Option<f32> {
+
+    // Twice the signed area of (a, b, p) on the yz-plane. edge(a, b) == -edge(b, a) exactly.
+    let edge = |a: &Vector3<f32>, b: &Vector3<f32>| -> f32 {
+        (a.y - y) * (b.z - z) - (a.z - z) * (b.y - y)
+    };
+
+    let area = (tr.b.y - tr.a.y) * (tr.c.z - tr.a.z) - (tr.b.z - tr.a.z) * (tr.c.y - tr.a.y);
+
+    // The triangle is parallel to the x-axis.
+    if area == 0.0 { return None; }
+
+    let sign = if area > 0.0 { 1.0 } else { -1.0 };
+
+    // The edge a->b owns the points on it if it is a top or left edge in ccw order.
+    let owns = |a: &Vector3<f32>, b: &Vector3<f32>| -> bool {
+        let du = sign * (b.y - a.y);
+        let dv = sign * (b.z - a.z);
+        dv < 0.0 || (dv == 0.0 && du > 0.0)
+    };
+
+    let inside = |w: f32, a: &Vector3<f32>, b: &Vector3<f32>| -> bool {
+        w > 0.0 || (w == 0.0 && owns(a, b))
+    };
+
+    let w0 = sign * edge(&tr.b, &tr.c);
+    let w1 = sign * edge(&tr.c, &tr.a);
+    let w2 = sign * edge(&tr.a, &tr.b);
+
+    if !(inside(w0, &tr.b, &tr.c) && inside(w1, &tr.c, &tr.a) && inside(w2, &tr.a, &tr.b)) { return None; }
+
+    let sum = w0 + w1 + w2;
+    if sum <= 0.0 { return None; }
+
+    Some((w0 * tr.a.x + w1 * tr.b.x + w2 * tr.c.x) / sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The 12 triangles of the cube [min, max]^3 in ccw order.
+    fn cube(min: f32, max: f32) -> Vec<Triangle> {
+        let v = |x: f32, y: f32, z: f32| Vector3::<f32>::new(x, y, z);
+        let (a, b) = (min, max);
+        let quads = [
+            [v(a, a, a), v(a, b, a), v(b, b, a), v(b, a, a)],
+            [v(a, a, b), v(b, a, b), v(b, b, b), v(a, b, b)],
+            [v(a, a, a), v(b, a, a), v(b, a, b), v(a, a, b)],
+            [v(a, b, a), v(a, b, b), v(b, b, b), v(b, b, a)],
+            [v(a, a, a), v(a, a, b), v(a, b, b), v(a, b, a)],
+            [v(b, a, a), v(b, b, a), v(b, b, b), v(b, a, b)],
+        ];
+        quads.iter().flat_map(|q| vec![Triangle { a: q[0], b: q[1], c: q[2] },
+                                       Triangle { a: q[0], b: q[2], c: q[3] }]).collect()
+    }
+
+    fn domain() -> BBox {
+        BBox { min: Vector3::<f32>::new(0.0, 0.0, 0.0), max: Vector3::<f32>::new(2.0, 2.0, 2.0) }
+    }
+
+    /// Check that the occupied voxels are the ones for which expected returns true.
+    fn check_cells(grid: &VoxelGrid, expected: impl Fn(u32, u32, u32) -> bool) {
+        for k in 0..grid.dimension[2] {
+        for j in 0..grid.dimension[1] {
+        for i in 0..grid.dimension[0] {
+            assert_eq!(grid.is_occupied(i, j, k), expected(i, j, k), "voxel ({}, {}, {})", i, j, k);
+        }}};
+    }
+
+    #[test]
+    fn surface_cube() {
+        // The faces of the cube [0.5, 1.5]^3 are on the voxel boundaries, so the voxels on both
+        // sides of each face touch it. The voxels [3, 4]^3 don't touch the surface.
+        let grid = VoxelGrid::surface(&cube(0.5, 1.5), &domain(), 0.25);
+        assert_eq!(grid.dimension, [8, 8, 8]);
+        let near = |i: u32| (1..=6).contains(&i);
+        let inner = |i: u32| (3..=4).contains(&i);
+        check_cells(&grid, |i, j, k| near(i) && near(j) && near(k) && !(inner(i) && inner(j) && inner(k)));
+    }
+
+    #[test]
+    fn solid_cube() {
+        // The voxel centers 0.125 + 0.25 * i inside ]0.5, 1.5[ are 2..=5. The center lines y == z
+        // go through the diagonals of the x faces, which must be crossed exactly once.
+        let grid = VoxelGrid::solid(&cube(0.5, 1.5), &domain(), 0.25);
+        let inside = |i: u32| (2..=5).contains(&i);
+        check_cells(&grid, |i, j, k| inside(i) && inside(j) && inside(k));
+    }
+
+    #[test]
+    fn triangle_on_voxel_boundary() {
+        // A triangle in the plane z == 0.5 between the voxel layers k == 1 and k == 2.
+        let v = |x: f32, y: f32, z: f32| Vector3::<f32>::new(x, y, z);
+        let triangle = vec![Triangle { a: v(0.6, 0.6, 0.5), b: v(1.4, 0.6, 0.5), c: v(0.6, 1.4, 0.5) }];
+        let grid = VoxelGrid::surface(&triangle, &domain(), 0.25);
+
+        for k in 0..8 {
+            assert_eq!(grid.is_occupied(2, 2, k), k == 1 || k == 2, "voxel (2, 2, {})", k);
+        }
+        // The voxel (5, 5, 1) is outside the hypotenuse x + y == 2.0.
+        assert!(grid.is_occupied(4, 2, 1));
+        assert!(!grid.is_occupied(5, 5, 1));
+
+        // The triangle is parallel to the scanlines, so the solid voxelization is empty.
+        let solid = VoxelGrid::solid(&triangle, &domain(), 0.25);
+        check_cells(&solid, |_, _, _| false);
+    }
+
+    #[test]
+    fn to_array3d() {
+        let grid = VoxelGrid::solid(&cube(0.5, 1.5), &domain(), 0.25);
+        let array = grid.to_array3d(-1.0, 1.0);
+        assert_eq!(array.get_value(3, 3, 3), -1.0);
+        assert_eq!(array.get_value(0, 3, 3), 1.0);
+    }
+}
@@ -1,118 +1,55 @@
 use jaankaup_core::wgpu;
-use jaankaup_core::render_pipelines::create_bind_group_layouts;
+use jaankaup_core::compute::{ComputeKernel, spirv_workgroup_size};
+use jaankaup_core::render_pipelines::{uniform_entry, storage_entry};
 
 pub struct AABB_pipeline {
-    layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>, 
-    bind_group_layouts: Vec<wgpu::BindGroupLayout>, 
-    pipeline: wgpu::ComputePipeline,
+    kernel: ComputeKernel,
 }
 
 impl AABB_pipeline {
 
     pub fn get_pipeline(&self) -> &wgpu::ComputePipeline {
-        &self.pipeline
+        self.kernel.get_pipeline()
     }
 
     pub fn get_bind_group_layouts(&self) -> &Vec<wgpu::BindGroupLayout> {
-        &self.bind_group_layouts
+        self.kernel.get_bind_group_layouts()
     }
 
     pub fn get_bind_group_layout_entries(&self) -> &Vec<Vec<wgpu::BindGroupLayoutEntry>> {
-        &self.layout_entries
+        self.kernel.get_bind_group_layout_entries()
+    }
+
+    pub fn create_bind_groups(&self, device: &wgpu::Device, bindings: &[((u32, u32), wgpu::BindingResource)]) -> Result<Vec<wgpu::BindGroup>, String> {
+        self.kernel.create_bind_groups(&device, bindings)
     }
 
     pub fn dispatch(&self, bind_groups: &Vec<wgpu::BindGroup>,
                     encoder: &mut wgpu::CommandEncoder,
                     x: u32, y: u32, z: u32) {
-
-        let mut pass = encoder.begin_compute_pass(
-            &wgpu::ComputePassDescriptor { label: None}
-        );
-        pass.set_pipeline(&self.pipeline);
-        for (e, bgs) in bind_groups.iter().enumerate() {
-            pass.set_bind_group(e as u32, &bgs, &[]);
-        }
-        pass.dispatch(x, y, z)
+        self.kernel.dispatch(bind_groups, encoder, x, y, z)
     }
 
-    pub fn init(device: &wgpu::Device
-                //comp_module: &wgpu::ShaderModule,
-                ) -> Self {
+    pub fn init(device: &wgpu::Device) -> Self {
 
         let comp_module = wgpu::include_spirv!("../../shaders/spirv/numbers.comp.spv");
+        let workgroup_size = spirv_workgroup_size(include_bytes!("../../shaders/spirv/numbers.comp.spv"), "main").unwrap();
 
-        // Define all bind grout entries for pipeline and bind groups.
         let layout_entries = vec![
                 // Set 0
-                    // uvec3 dimensions.
-                vec![wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStage::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                            },
-                        count: None,
-                    },
-                    // uint number_of_aabbs
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStage::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                            },
-                        count: None,
-                    },
-                    // Input. AABB data. [f32 ; 6]
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStage::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // The output distance data. 
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStage::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                ],
+                vec![uniform_entry(0, wgpu::ShaderStages::COMPUTE), // uvec3 dimensions.
+                     uniform_entry(1, wgpu::ShaderStages::COMPUTE), // uint number_of_aabbs
+                     storage_entry(2, wgpu::ShaderStages::COMPUTE, true), // Input. AABB data. [f32 ; 6]
+                     storage_entry(3, wgpu::ShaderStages::COMPUTE, false)], // The output distance data.
         ];
-        let bind_group_layouts = create_bind_group_layouts(&device, &layout_entries);
-
-        // TODO: refactor?.
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
-            push_constant_ranges: &[],
-        });
-
-        // Create the pipeline.
-        // TODO: refactor?.
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("aabb_shader_pipeline"),
-            layout: Some(&pipeline_layout),
-            module: &device.create_shader_module(&comp_module),
-            entry_point: "main",
-        });
-
 
         Self {
-            layout_entries, 
-            bind_group_layouts, 
-            pipeline,
+            kernel: ComputeKernel::init(&device,
+                                        &device.create_shader_module(&comp_module),
+                                        "main",
+                                        layout_entries,
+                                        workgroup_size,
+                                        Some("aabb_shader_pipeline")),
         }
     }
 }
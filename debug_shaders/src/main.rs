@@ -1,4 +1,3 @@
-use std::borrow::Cow;
 use jaankaup_core::wgpu;
 use rand::prelude::*;
 use cgmath::{prelude::*,Vector3,Vector4};
@@ -16,13 +15,10 @@ use jaankaup_core::camera::{Camera};
 use jaankaup_core::input::InputCache;
 use jaankaup_core::compute::*;
 use geometry::aabb::{BBox,BBox4};
+use geometry::curve::{bezier_1c, line};
 use debug_shaders::aabb_shader::AABB_pipeline;
 use debug_shaders::font::Font_pipeline;
-use jaankaup_core::render_pipelines::{
-    create_bind_groups,
-    draw,
-    check_correspondence,
-};
+use jaankaup_core::render_pipelines::draw;
 use jaankaup_core::mc::*;
 use render_shaders::{Render_vvvvnnnn, Render_vvvc};
 
@@ -62,71 +58,6 @@ struct JooAABB {
     max: Vector4<f32>,
 }
 
-pub fn bezier_1c(n: u32, 
-                c0: Vector3<f32>,
-                c1: Vector3<f32>,
-                c2: Vector3<f32>
-                ) -> Vec<Vector3<f32>> {
-    assert!(n > 1, "n < 2");
-    let mut result: Vec<Vector3<f32>> = Vec::new();
-    for i in 0..n {
-        let t = i as f32 / ((n-1) as f32);
-        let t2 = t * t;
-        let mt = 1.0 - t;
-        let mt2 = mt * mt;
-        let x =   c0.x  * mt2 + c1.x * 2.0 * mt*t + c2.x * t2;
-        let y =   c0.y  * mt2 + c1.y * 2.0 * mt*t + c2.y * t2;
-        let z =   c0.z  * mt2 + c1.z * 2.0 * mt*t + c2.z * t2;
-        result.push(Vector3::<f32>::new(x, y, z));
-    }
-    result
-}
-
-pub fn line(n: u32,
-            c0: Vector3<f32>,
-            c1: Vector3<f32>,
-            ) -> Vec<Vector3<f32>> {
-    assert!(n > 0, "n == 0");
-    let mut result: Vec<Vector3<f32>> = Vec::new();
-    for i in 0..n {
-
-        let t = i as f32 / (n as f32);
-        //let mt = 1.0 - t;
-
-        let dist = c1.distance(c0); 
-        let norm = (c1 - c0).normalize();
-
-        let point = c0 + norm * (dist * t);
-
-        result.push(point);
-    }
-    result
-}
-
-// (1.0, 1.0, 1,0) , (23.0, 23.0, 23.0, 5.0) , (1.0, 1.0, 15.0) , (23.0, 23.0, 20.0)
-pub fn bezier3D(n: u32,
-                c0: Vector3<f32>,
-                c1: Vector3<f32>,
-                c2: Vector3<f32>,
-                c3: Vector3<f32>,
-                ) -> Vec<Vector3<f32>> {
-    assert!(n > 0, "n == 0");
-    let mut result: Vec<Vector3<f32>> = Vec::new();
-    for i in 0..n {
-        let t = i as f32 / (n as f32);
-        let t2 = t * t;
-        let t3 = t2 * t;
-        let mt = 1.0 - t;
-        let mt2 = mt * mt;
-        let mt3 = mt2 * mt;
-        let x = c0.x  * mt3 + c1.x * 3.0 * mt2*t + c2.x * 3.0 * mt*t2 + c3.x * t3;
-        let y = c0.y  * mt3 + c1.y * 3.0 * mt2*t + c2.y * 3.0 * mt*t2 + c3.y * t3;
-        let z = c0.z  * mt3 + c1.z * 3.0 * mt2*t + c2.z * 3.0 * mt*t2 + c3.z * t3;
-        result.push(Vector3::<f32>::new(x, y, z));
-    }
-    result
-}
-
 impl Application for Debug_App {
 
     /// Initialize fmm application.
@@ -141,17 +72,16 @@ impl Application for Debug_App {
         let render_pipeline = Render_vvvvnnnn::init(
                     &configuration.device,
                     &configuration.sc_desc,
-                    &configuration.device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-                        label: Some("renderer_v3c1"),
-                        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../../shaders_wgsl/renderer_v3c1.wgsl"))),
-                        flags: wgpu::ShaderFlags::VALIDATION | wgpu::ShaderFlags::EXPERIMENTAL_TRANSLATION,
-                    }),
+                    1,
+                    include_str!("../../shaders_wgsl/renderer_v3c1.wgsl"),
                     //&configuration.device.create_shader_module(&vertex_shader_src),
                     //&configuration.device.create_shader_module(&fragment_shader_src)
+                    false,
         );
         let render_bind_groups = render_pipeline.create_bind_groups(
             &configuration.device,
-            &camera.get_camera_uniform(&configuration.device)
+            &camera.get_camera_uniform(&configuration.device),
+            None
         );
 
         let vertex_shader_src = wgpu::include_spirv!("../../shaders/spirv/render_vvvc_camera.vert.spv");
@@ -160,11 +90,8 @@ impl Application for Debug_App {
         let render_vvvc_pipeline = Render_vvvc::init(
                     &configuration.device,
                     &configuration.sc_desc,
-                    &configuration.device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-                        label: Some("renderer_v3c1"),
-                        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../../shaders_wgsl/renderer_v3c1.wgsl"))),
-                        flags: wgpu::ShaderFlags::VALIDATION | wgpu::ShaderFlags::EXPERIMENTAL_TRANSLATION,
-                    }),
+                    1,
+                    include_str!("../../shaders_wgsl/renderer_v3c1.wgsl"),
                     //&configuration.device.create_shader_module(&vertex_shader_src),
                     //&configuration.device.create_shader_module(&fragment_shader_src),
                     wgpu::PrimitiveTopology::PointList
@@ -177,11 +104,8 @@ impl Application for Debug_App {
         let render_vvvc_line_pipeline = Render_vvvc::init(
                     &configuration.device,
                     &configuration.sc_desc,
-                    &configuration.device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-                        label: Some("renderer_v3c1"),
-                        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../../shaders_wgsl/renderer_v3c1.wgsl"))),
-                        flags: wgpu::ShaderFlags::VALIDATION | wgpu::ShaderFlags::EXPERIMENTAL_TRANSLATION,
-                    }),
+                    1,
+                    include_str!("../../shaders_wgsl/renderer_v3c1.wgsl"),
                     // &configuration.device.create_shader_module(&vertex_shader_src),
                     // &configuration.device.create_shader_module(&fragment_shader_src),
                     wgpu::PrimitiveTopology::LineList
@@ -194,11 +118,8 @@ impl Application for Debug_App {
         let render_vvvc_triangle_pipeline = Render_vvvc::init(
                     &configuration.device,
                     &configuration.sc_desc,
-                    &configuration.device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-                        label: Some("renderer_v3c1"),
-                        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../../shaders_wgsl/renderer_v3c1.wgsl"))),
-                        flags: wgpu::ShaderFlags::VALIDATION | wgpu::ShaderFlags::EXPERIMENTAL_TRANSLATION,
-                    }),
+                    1,
+                    include_str!("../../shaders_wgsl/renderer_v3c1.wgsl"),
                     // &configuration.device.create_shader_module(&vertex_shader_src),
                     // &configuration.device.create_shader_module(&fragment_shader_src),
                     wgpu::PrimitiveTopology::TriangleList
@@ -235,6 +156,7 @@ impl Application for Debug_App {
         let depth_texture = JTexture::create_depth_texture(
             &configuration.device,
             &configuration.sc_desc,
+            1,
             Some("debug depth texture")
         );
 
@@ -247,7 +169,7 @@ impl Application for Debug_App {
                     base_vecs[i].y + 1.0,
                     base_vecs[i].z + 1.0,
             );
-            aabbs.push(BBox::create_from_line(&a, &b).convert_aabb_to_aabb4());
+            aabbs.push(BBox::create_from_line(&a, &b).convert_aabb_to_aabb4(2.5, 4.5));
         }
 
 
@@ -328,9 +250,8 @@ impl Application for Debug_App {
 
         let aabb = AABB_pipeline::init(&configuration.device);
 
-        let bind_groups = create_bind_groups(
+        let bind_groups = aabb.create_bind_groups(
                                 &configuration.device, 
-                                &aabb.get_bind_group_layout_entries(),
                                 &vec![
                                     vec![
                                         &buffers.get("dimensions").unwrap().as_entire_binding(),
@@ -346,23 +267,8 @@ impl Application for Debug_App {
 
         let font_pipeline = Font_pipeline::init(&configuration.device);
 
-        check_correspondence(
-            &font_pipeline.get_bind_group_layout_entries(),
-            &vec![
-                vec![
-                    &buffers.get("dimensions_font").unwrap().as_entire_binding(),
-                    &histogram.get_histogram().as_entire_binding(),
-                    &camera.get_camera_uniform(&configuration.device).as_entire_binding(),
-                    &buffers.get("font_output").unwrap().as_entire_binding(),
-                    &buffers.get("font_aabb_output").unwrap().as_entire_binding(),
-                ], 
-            ]
-        );
-
-
-        let font_bind_groups = create_bind_groups(
+        let font_bind_groups = font_pipeline.create_bind_groups(
                                 &configuration.device, 
-                                &font_pipeline.get_bind_group_layout_entries(),
                                 &vec![
                                     vec![
                                         &buffers.get("dimensions_font").unwrap().as_entire_binding(),
@@ -552,7 +458,7 @@ impl Application for Debug_App {
     }
 
     fn resize(&mut self, device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor, _new_size: winit::dpi::PhysicalSize<u32>) {
-        self.depth_texture = JTexture::create_depth_texture(&device, &sc_desc, Some("depth-texture"));
+        self.depth_texture = JTexture::create_depth_texture(&device, &sc_desc, 1, Some("depth-texture"));
         self.camera.resize(sc_desc.width as f32, sc_desc.height as f32);
     }
 
@@ -7,11 +7,10 @@ use jaankaup_core::misc::Convert2Vec;
 use std::mem;
 use std::borrow::Cow;
 use rand::prelude::*;
-pub use winit::event::VirtualKeyCode as Key;
 use jaankaup_core::wgpu;
-use render_shaders::{Render_vvvc, Render_vvvvnnnn};
-use jaankaup_core::buffer::{buffer_from_data, to_vec};
-use std::collections::HashMap;
+use render_shaders::{Render_vvvc, Render_aabb_lines};
+use render_shaders::pbr::{PbrPipeline, PbrMaterial, PbrMesh, Lights, LightUniform, PointLight};
+use jaankaup_core::buffer::{to_vec, buffer_from_data};
 use jaankaup_core::wgpu_system as ws;
 use jaankaup_core::wgpu_system::{
         WGPUFeatures,
@@ -22,18 +21,32 @@ use jaankaup_core::wgpu_system::{
 use jaankaup_core::two_triangles::TwoTriangles;
 
 use jaankaup_core::render_pipelines::{
-    create_bind_group_layouts,
-    draw,
-    create_bind_groups
+    DrawCall,
+    ShaderBindings,
+    uniform_entry,
+    storage_entry,
 };
 
-use jaankaup_core::compute::Histogram;
+use jaankaup_core::compute::{Histogram, ComputeKernel, spirv_workgroup_size};
+use jaankaup_core::indirect::{IndirectArgsKernel, IndirectArgs};
+use jaankaup_core::frustum_culling::{CullParams, FrustumCuller};
 use jaankaup_core::texture::Texture as JTexture;
-use jaankaup_core::camera::{Camera};
+use jaankaup_core::resources::{ResourceRegistry, BufferHandle, TextureHandle, BindGroupHandle};
+use jaankaup_core::frame_graph::{FrameGraph, GraphResource, TransientTextures, TransientTextureDescriptor};
+use jaankaup_core::camera::{Camera, DEFAULT_CAMERA_ACTIONS};
+use jaankaup_core::camera_path::{CameraPath, CameraRecorder, CameraPlayback, PlaybackClock};
 use jaankaup_core::input::InputCache;
-use jaankaup_core::misc::OutputVertex;
+use jaankaup_core::actions::ActionMap;
+use jaankaup_core::timestep::TimestepConfig;
+use jaankaup_core::misc::{OutputVertex, Vertex_vvvvnnnn};
 use index_tables::create_hash_table;
-use geometry::aabb::{BBox, Triangle, Triangle_vvvvnnnn};
+use cpu_version::narrow_band::{FMM_Node, NarrowBand, LOCAL_DIMENSION, FAR_VALUE};
+use cpu_version::voxelize::VoxelGrid;
+use geometry::aabb::{BBox, BBox4};
+use geometry::bvh::Bvh;
+use geometry::ray::Ray;
+use geometry::mesh::Mesh;
+use geometry::triangle::{Triangle, Triangle_vvvvnnnn};
 use model_loader::load_triangles_from_obj;
 use bytemuck::{Pod, Zeroable};
 
@@ -46,6 +59,23 @@ const DEBUG_BUFFER_OFFSET: u32 = 1024000; // 2097151 / 2 ~= 1048574
 const BLOCK_DIMENSIONS: [u32; 3] = [4, 8, 4];
 const TIME_STAMP_COUNT: u32 = 2;
 
+/// The initial fmm nodes are the narrow band computed on the cpu (NarrowBand::from_triangles). If
+/// false, the fmm data generator shader computes the initial nodes.
+const CPU_NARROW_BAND: bool = true;
+
+/// The width of the initial narrow band in nodes.
+const NARROW_BAND_WIDTH: u32 = 2;
+
+/// The speed of the global dimension and block navigation (blocks per second).
+const NAVIGATION_SPEED: f32 = 6.67;
+
+/// The default actions without the camera actions. fmm_project/actions.cfg overrides them when the
+/// app is started from the workspace root.
+const DEFAULT_ACTIONS: &str = include_str!("../actions.cfg");
+
+/// The file of the recorded camera path.
+const CAMERA_PATH_FILE: &str = "fmm_project/camera_path.txt";
+
 const FAR: u32 =  3;
 const BAND: u32 = 1;
 const BAND_NEW: u32 = 1;
@@ -110,16 +140,6 @@ struct FMM_Block {
 unsafe impl bytemuck::Zeroable for FMM_Block {}
 unsafe impl bytemuck::Pod for FMM_Block {}
 
-#[repr(C)]
-#[derive(Copy, Clone)]
-struct FMM_Node {
-    value: f32,
-    tag: u32,
-}
-
-unsafe impl bytemuck::Zeroable for FMM_Node {}
-unsafe impl bytemuck::Pod for FMM_Node {}
-
 #[repr(C)]
 #[derive(Copy, Clone)]
 struct FMM_Attributes {
@@ -132,30 +152,61 @@ struct FMM_Attributes {
 unsafe impl bytemuck::Zeroable for FMM_Attributes {}
 unsafe impl bytemuck::Pod for FMM_Attributes {}
 
+/// The buffers of the fmm application.
+struct FMM_Buffers {
+    sphere_tracer_output: BufferHandle,
+    index_hash_table: BufferHandle,
+    vec_to_offset: BufferHandle,
+    fmm_attributes: BufferHandle,
+    wood: BufferHandle,
+    wood_single: BufferHandle,
+    prefix_sum_temp: BufferHandle,
+    debug_points_output: BufferHandle,
+    fmm_nodes: BufferHandle,
+    fmm_blocks: BufferHandle,
+    fmm_data_gen_params: BufferHandle,
+    fmm_block_aabbs: BufferHandle,
+    unit_cube_lines: BufferHandle,
+    block_indices: BufferHandle,
+}
+
 // The fmm application.
 struct FMM_App {
-    textures: HashMap<String, JTexture>,
-    depth_texture: JTexture,
+    resources: ResourceRegistry,
+    buffers: FMM_Buffers,
+    transient_textures: TransientTextures,
     camera: Camera,
-    buffers: HashMap<String, wgpu::Buffer>,
     render_vvvc_point_pipeline: Render_vvvc, 
     render_vvvc_point_bind_groups: Vec<wgpu::BindGroup>,
     render_vvvc_triangle_pipeline: Render_vvvc, 
     render_vvvc_triangle_bind_groups: Vec<wgpu::BindGroup>,
     fmm_debug_pipeline: FMM_debug_pipeline,
-    fmm_debug_bind_groups: Vec<wgpu::BindGroup>,
+    fmm_debug_bind_groups: BindGroupHandle,
     histogram: Histogram, 
-    debug_point_count: u32,
-    debug_triangle_draw_count: u32,
+    indirect_args: IndirectArgsKernel,
+    fmm_active_blocks: FMM_active_blocks_pipeline,
+    fmm_active_blocks_bind_groups: Vec<wgpu::BindGroup>,
+    debug_point_args: IndirectArgs,
+    debug_triangle_args: IndirectArgs,
+    block_count: u32,
+    cull_params: CullParams,
+    frustum_culler: FrustumCuller,
+    cull_bind_groups: Vec<wgpu::BindGroup>,
+    aabb_lines_pipeline: Render_aabb_lines,
+    aabb_lines_bind_groups: Vec<wgpu::BindGroup>,
+    unit_cube_vertex_count: u32,
+    current_block_pipeline: Render_aabb_lines,
     fmm_data_generator: FMM_data_generator_debug_pipeline,
-    fmm_data_generator_bind_groups: Vec<wgpu::BindGroup>,
-    render_vvvvnnnn_pipeline: Render_vvvvnnnn,
-    render_vvvvnnnn_bind_groups: Vec<wgpu::BindGroup>,
+    fmm_data_generator_bind_groups: BindGroupHandle,
+    pbr_pipeline: PbrPipeline,
+    pbr_lights: Lights,
+    pbr_material: PbrMaterial,
+    pbr_bind_groups: Vec<wgpu::BindGroup>,
+    wood_mesh: PbrMesh,
     show_mesh: bool,
     current_block: [f32;3], 
     fmm_attributes: FMM_Attributes,
     current_global_dimensions: [f32;3], 
-    triangle_count: u32,
     update_data_generator: u32,
     triangle_data: Vec<Triangle_vvvvnnnn>,
     triangle_index: f32,
@@ -164,12 +215,121 @@ struct FMM_App {
     data_loaded: bool,
     query_sets: Option<QuerySets>,
     screen: TwoTriangles,
+    #[allow(dead_code)]
+    sphere_tracer_texture: TextureHandle,
+    #[allow(dead_code)]
+    screen_bind_groups: BindGroupHandle,
     sphere_tracer_pipeline: SphereTracerPipeline, 
-    sphere_tracer_bind_groups: Vec<wgpu::BindGroup>,
+    sphere_tracer_bind_groups: BindGroupHandle,
+    actions: ActionMap,
+    bvh: Bvh,
+    inside_mask: VoxelGrid,
+    camera_recorder: Option<CameraRecorder>,
+    camera_playback: Option<CameraPlayback>,
+    screen_size: [f32; 2],
 }
 
 impl FMM_App {
 
+    /// Write a timestamp if the timestamp queries are supported.
+    fn write_timestamp(&self, encoder: &mut wgpu::CommandEncoder, index: u32) {
+        if let Some(ref query_sets) = self.query_sets {
+            encoder.write_timestamp(&query_sets.timestamp, index);
+        }
+    }
+
+    /// Print the durations of the fmm data generator and the fmm debug pipeline.
+    fn print_timestamps(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if let Some(ref query_sets) = self.query_sets {
+            // We can ignore the future as we're about to wait for the device.
+            //
+            let result = to_vec::<QueryData>(&device,
+                    &queue,
+                    &query_sets.query_buffer,
+                    0 as wgpu::BufferAddress,
+                    (std::mem::size_of::<QueryData>() as usize) as wgpu::BufferAddress);
+            //println!("result :: {:?}", result);
+            for (i, elem) in result[0].timestamps.iter().enumerate() {
+                let nanoseconds =
+                    (elem.end - elem.start) as f32 * query_sets.timestamp_period;
+                let microseconds = nanoseconds / 1000.0;
+                let milli = microseconds / 1000.0;
+                //println!("{:?} time is {:?} micro seconds.", i, microseconds);
+                println!("{:?} time is {:?} milli seconds.", i, milli);
+            }
+        }
+    }
+
+    /// One u32 per pixel.
+    fn sphere_tracer_output_size(sc_desc: &wgpu::SurfaceConfiguration) -> wgpu::BufferAddress {
+        (sc_desc.width * sc_desc.height) as wgpu::BufferAddress * std::mem::size_of::<u32>() as wgpu::BufferAddress
+    }
+
+    fn create_sphere_tracer_bind_groups(device: &wgpu::Device,
+                                        pipeline: &SphereTracerPipeline,
+                                        camera: &mut Camera,
+                                        resources: &ResourceRegistry,
+                                        buffers: &FMM_Buffers) -> Vec<wgpu::BindGroup> {
+        pipeline.create_bind_groups(
+            &device,
+            &[
+                ((0, 0), camera.get_ray_camera_uniform(&device).as_entire_binding()),
+                ((0, 1), resources.get_buffer(buffers.fmm_nodes).as_entire_binding()),
+                ((0, 2), resources.get_buffer(buffers.index_hash_table).as_entire_binding()),
+                ((0, 3), resources.get_buffer(buffers.vec_to_offset).as_entire_binding()),
+                ((0, 4), resources.get_buffer(buffers.fmm_attributes).as_entire_binding()),
+                ((0, 5), resources.get_buffer(buffers.sphere_tracer_output).as_entire_binding()),
+            ]
+        ).unwrap()
+    }
+
+    /// Report the mesh triangle and the first non FAR fmm node that the ray hits. The picked node
+    /// becomes the current block.
+    fn pick(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, ray: &Ray) {
+
+        match self.bvh.intersect(&ray) {
+            Some(hit) => log::info!("Picked triangle {} at {:?} (t == {}).", hit.triangle_id, hit.position, hit.t),
+            None => log::info!("No triangle picked."),
+        }
+
+        // The fmm nodes are located at the integer coordinates.
+        let node_count = BLOCK_DIMENSIONS.iter().product::<u32>() * LOCAL_DIMENSION.iter().product::<u32>();
+        let data = to_vec::<u32>(&device,
+                                 &queue,
+                                 self.resources.get_buffer(self.buffers.fmm_nodes),
+                                 0 as wgpu::BufferAddress,
+                                 (node_count as usize * mem::size_of::<FMM_Node>()) as wgpu::BufferAddress);
+
+        let global_dimensions = self.fmm_attributes.global_dimensions;
+        let mut nodes: Vec<FMM_Node> = bytemuck::cast_slice(&data).to_vec();
+        nodes.resize((global_dimensions.iter().product::<u32>() * LOCAL_DIMENSION.iter().product::<u32>()) as usize,
+                     FMM_Node { value: 1000000.0, tag: FAR });
+
+        let narrow_band = NarrowBand {
+            nodes: nodes,
+            global_dimensions: global_dimensions,
+            origin: cgmath::Vector3::<f32>::new(0.0, 0.0, 0.0),
+            grid_spacing: 1.0,
+        };
+
+        match narrow_band.pick(&ray) {
+            Some((coordinate, index, node)) => {
+                let tag = match node.tag { KNOWN => "KNOWN", BAND => "BAND", _ => "FAR" };
+                let inside = self.inside_mask.is_occupied(coordinate[0], coordinate[1], coordinate[2]);
+                log::info!("Picked fmm node {:?} (index {}) :: value == {}, tag == {}, inside == {}.", coordinate, index, node.value, tag, inside);
+
+                let block = [coordinate[0] / LOCAL_DIMENSION[0], coordinate[1] / LOCAL_DIMENSION[1], coordinate[2] / LOCAL_DIMENSION[2]];
+                self.current_block = [block[0] as f32 + 0.5, block[1] as f32 + 0.5, block[2] as f32 + 0.5];
+                self.fmm_attributes.current_block = block;
+                queue.write_buffer(
+                    self.resources.get_buffer(self.buffers.fmm_attributes),
+                    0,
+                    bytemuck::cast_slice(&[self.fmm_attributes])
+                );
+            }
+            None => log::info!("No fmm node picked."),
+        }
+    }
 }
 
 impl Application for FMM_App {
@@ -207,19 +367,11 @@ impl Application for FMM_App {
             None
         };
 
-        let mut textures: HashMap<String, JTexture> = HashMap::new();
-        let mut buffers: HashMap<String, wgpu::Buffer> = HashMap::new();
+        let mut resources = ResourceRegistry::init();
 
         let changed = 0;
         let data_loaded = false;
 
-        // Create the depth texture for fmm application.
-        let depth_texture = JTexture::create_depth_texture(
-            &configuration.device,
-            &configuration.sc_desc,
-            Some("fmm depth texture")
-        ); 
-
         // Create the index hash table for local indexing in GPU (includes ghost region).
         let (offset_hash_table, vec_to_offset_table, ivec_offset_hash_table) =
             create_hash_table(4, 4, 4, BLOCK_DIMENSIONS[0], BLOCK_DIMENSIONS[1], BLOCK_DIMENSIONS[2]);
@@ -234,83 +386,74 @@ impl Application for FMM_App {
         };
         let update_data_generator = 0;
 
-        let sphere_tracer_texture =
-            JTexture::create_texture2d(&configuration.device, &configuration.sc_desc, 1, 256, 256);
-
-        textures.insert("sphere_tracer_texture".to_string(), sphere_tracer_texture);
+        // The sphere tracer texture and output have one texel per pixel. Recreated on resize.
+        let sphere_tracer_texture = resources.insert_texture("sphere_tracer_texture", |label|
+            JTexture::create_texture2d(&configuration.device, &configuration.sc_desc, 1, configuration.sc_desc.width, configuration.sc_desc.height, label)
+        );
 
         // Create the sphere tracer screen.
-        let screen = TwoTriangles::init(&configuration.device, &configuration.sc_desc);
-        let screen_group = TwoTriangles::create_bind_group(
+        let screen = TwoTriangles::init(&configuration.device, &configuration.sc_desc, 1);
+        let screen_group = screen.create_bind_group(
             &configuration.device,
-            &textures.get("sphere_tracer_texture").unwrap() 
-        );
+            resources.get_texture(sphere_tracer_texture)
+        ).unwrap();
+        let screen_bind_groups = resources.insert_bind_groups("screen_bind_groups", vec![screen_group], &[sphere_tracer_texture.into()]);
 
-        buffers.insert(
-            "sphere_tracer_output".to_string(),
-            buffer_from_data::<u32>(
+        let sphere_tracer_output = resources.create_buffer(
             &configuration.device,
-            &vec![0; 256*256],
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
-            None)
+            "sphere_tracer_output",
+            FMM_App::sphere_tracer_output_size(&configuration.sc_desc),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC
         );
 
-        buffers.insert(
-            "index_hash_table".to_string(),
-            buffer_from_data::<[i32; 4]>(
+        let index_hash_table = resources.create_buffer_from_data::<[i32; 4]>(
             &configuration.device,
+            "index_hash_table",
             &ivec_offset_hash_table,
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
-            None)
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC
         );
 
-        buffers.insert(
-            "vec_to_offset".to_string(),
-            buffer_from_data::<u32>(
+        let vec_to_offset = resources.create_buffer_from_data::<u32>(
             &configuration.device,
+            "vec_to_offset",
             &vec_to_offset_table,
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
-            None)
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC
         );
 
         println!("Creating fmm attributes");
 
-        buffers.insert(
-            "fmm_attributes".to_string(),
-            buffer_from_data::<FMM_Attributes>(
+        let fmm_attributes_buffer = resources.create_buffer_from_data::<FMM_Attributes>(
             &configuration.device,
+            "fmm_attributes",
             &[fmm_attributes],
-            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            None)
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST
         );
 
-        let (_, triangle_data, aabb): (Vec<Triangle>, Vec<Triangle_vvvvnnnn>, BBox) =
+        let (triangles, triangle_data, aabb): (Vec<Triangle>, Vec<Triangle_vvvvnnnn>, BBox) =
             load_triangles_from_obj("assets/models/wood.obj", 7.0, [20.0, 0.0, 26.0], None).unwrap();
             //load_triangles_from_obj("assets/models/wood.obj", 1.0, [5.0, -5.0, 18.0], Some(1)).unwrap();
 
-        let triangle_count: u32 = triangle_data.len() as u32;
-
         println!("WOOD vertex count (vvvv) = {}", triangle_data.len());
         println!("WOOD aabb = {:?}", aabb);
 
+        // For mouse picking.
+        let bvh = Bvh::build(&triangles);
+        let screen_size = [configuration.sc_desc.width as f32, configuration.sc_desc.height as f32];
+
         let triangle_index: f32 = 0.0;
         let show_whole_mesh = 0;
-        buffers.insert(
-            "wood".to_string(),
-            buffer_from_data::<Triangle_vvvvnnnn>(
+        let wood = resources.create_buffer_from_data::<Triangle_vvvvnnnn>(
             &configuration.device,
+            "wood",
             &triangle_data,
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
-            None)
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC
         );
 
-        buffers.insert(
-            "wood_single".to_string(),
-            buffer_from_data::<Triangle_vvvvnnnn>(
+        let wood_single = resources.create_buffer_from_data::<Triangle_vvvvnnnn>(
             &configuration.device,
+            "wood_single",
             &[triangle_data[0]],
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
-            None)
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC
         );
 
         // Initialize camera for fmm application.
@@ -319,132 +462,265 @@ impl Application for FMM_App {
         //camera.set_rotation_sensitivity(2.0);
         camera.set_rotation_sensitivity(0.2);
 
+        // The config file is applied over the default camera and fmm actions.
+        let mut actions = ActionMap::parse(DEFAULT_CAMERA_ACTIONS).expect("The default camera actions are invalid.");
+        actions.merge(&ActionMap::parse(DEFAULT_ACTIONS).expect("The default actions are invalid."));
+        match ActionMap::load("fmm_project/actions.cfg") {
+            Ok(user_actions) => actions.merge(&user_actions),
+            Err(e) => log::warn!("Using the default actions: {}", e),
+        }
+        for (a, b, binding) in actions.conflicts() {
+            log::warn!("Actions {} and {} are both bound to {}.", a, b, binding);
+        }
+        camera.set_actions(&actions);
+
+
+        // The fmm nodes are located at the integer coordinates.
+        let node_dimensions = [BLOCK_DIMENSIONS[0] * LOCAL_DIMENSION[0],
+                               BLOCK_DIMENSIONS[1] * LOCAL_DIMENSION[1],
+                               BLOCK_DIMENSIONS[2] * LOCAL_DIMENSION[2]];
+        let fmm_domain = BBox {
+            min: cgmath::Vector3::<f32>::new(0.0, 0.0, 0.0),
+            max: cgmath::Vector3::<f32>::new((node_dimensions[0] - 1) as f32, (node_dimensions[1] - 1) as f32, (node_dimensions[2] - 1) as f32),
+        };
+
+        // The inside/outside mask of the fmm nodes. The voxel centers are at the node positions.
+        let half = cgmath::Vector3::<f32>::new(0.5, 0.5, 0.5);
+        let inside_mask = VoxelGrid::solid(&triangles, &BBox { min: fmm_domain.min - half, max: fmm_domain.max + half }, 1.0);
+
+        let initial_nodes = if CPU_NARROW_BAND {
+            let narrow_band = NarrowBand::from_triangles(&triangles, &fmm_domain, 1.0, NARROW_BAND_WIDTH);
+
+            // The sign of the narrow band and the mask disagree if the mesh isn't closed.
+            let mismatches = narrow_band.nodes.iter().enumerate().filter(|(i, node)| {
+                let (x, y, z) = narrow_band.node_coordinate(*i as u32);
+                node.tag == KNOWN && node.value != 0.0 && (node.value < 0.0) != inside_mask.is_occupied(x, y, z)
+            }).count();
+            if mismatches > 0 {
+                log::warn!("The sign of {} narrow band nodes doesn't match the inside mask. Is the mesh closed?", mismatches);
+            }
+            narrow_band.nodes
+        } else {
+            vec![FMM_Node { value: FAR_VALUE, tag: FAR }; node_dimensions.iter().product::<u32>() as usize]
+        };
 
         // Create buffers for fmm alogrithm.
-        create_buffers(&configuration.device,
-                       BLOCK_DIMENSIONS, // block_dimension: [u32 ; 3],
-                       [4, 4, 4], //local_dimension: [u32 ; 3],
-                       DEBUG_BUFFER_SIZE, // 3193724
-                       &mut buffers
+        let (prefix_sum_temp, debug_points_output, fmm_nodes, fmm_blocks, fmm_data_gen_params) =
+            create_buffers(&configuration.device,
+                           BLOCK_DIMENSIONS, // block_dimension: [u32 ; 3],
+                           &initial_nodes,
+                           DEBUG_BUFFER_SIZE, // 3193724
+                           &mut resources
+            );
+
+        // The aabbs of the fmm blocks. A block covers the voxels of its 4x4x4 nodes.
+        let block_count = BLOCK_DIMENSIONS.iter().product::<u32>();
+        let block_aabbs: Vec<BBox4> = (0..block_count).map(|i| {
+            let block = [i % BLOCK_DIMENSIONS[0],
+                         (i / BLOCK_DIMENSIONS[0]) % BLOCK_DIMENSIONS[1],
+                         i / (BLOCK_DIMENSIONS[0] * BLOCK_DIMENSIONS[1])];
+            let min = cgmath::Vector3::<f32>::new((block[0] * LOCAL_DIMENSION[0]) as f32,
+                                                  (block[1] * LOCAL_DIMENSION[1]) as f32,
+                                                  (block[2] * LOCAL_DIMENSION[2]) as f32) - half;
+            let size = cgmath::Vector3::<f32>::new(LOCAL_DIMENSION[0] as f32, LOCAL_DIMENSION[1] as f32, LOCAL_DIMENSION[2] as f32);
+            BBox { min: min, max: min + size }.convert_aabb_to_aabb4(1.0, 1.0)
+        }).collect();
+        let fmm_block_aabbs = resources.create_buffer_from_data::<BBox4>(
+            &configuration.device,
+            "fmm_block_aabbs",
+            &block_aabbs,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST
         );
 
+        // The block edges are drawn as instances of the unit cube.
+        let unit_cube: Vec<[f32; 3]> = BBox { min: cgmath::Vector3::<f32>::new(0.0, 0.0, 0.0), max: cgmath::Vector3::<f32>::new(1.0, 1.0, 1.0) }
+            .to_lines().iter().map(|v| [v.x, v.y, v.z]).collect();
+        let unit_cube_lines = resources.create_buffer_from_data::<[f32; 3]>(
+            &configuration.device,
+            "unit_cube_lines",
+            &unit_cube,
+            wgpu::BufferUsages::VERTEX
+        );
+        let unit_cube_vertex_count = unit_cube.len() as u32;
+
+        // The instance buffer of the current block. Instance i draws the aabb of the block i.
+        let block_indices = resources.create_buffer_from_data::<u32>(
+            &configuration.device,
+            "block_indices",
+            &(0..block_count).collect::<Vec<u32>>(),
+            wgpu::BufferUsages::VERTEX
+        );
+
+        let buffers = FMM_Buffers {
+            sphere_tracer_output: sphere_tracer_output,
+            index_hash_table: index_hash_table,
+            vec_to_offset: vec_to_offset,
+            fmm_attributes: fmm_attributes_buffer,
+            wood: wood,
+            wood_single: wood_single,
+            prefix_sum_temp: prefix_sum_temp,
+            debug_points_output: debug_points_output,
+            fmm_nodes: fmm_nodes,
+            fmm_blocks: fmm_blocks,
+            fmm_data_gen_params: fmm_data_gen_params,
+            fmm_block_aabbs: fmm_block_aabbs,
+            unit_cube_lines: unit_cube_lines,
+            block_indices: block_indices,
+        };
+
+        // The blocks outside the view frustum are culled on the gpu. The visible buffer is the
+        // instance buffer of the block edges.
+        let cull_params = CullParams::init(&configuration.device, block_count, unit_cube_vertex_count);
+        let frustum_culler = FrustumCuller::init(&configuration.device);
+        let cull_bind_groups = frustum_culler.create_bind_groups(
+            &configuration.device,
+            &cull_params,
+            resources.get_buffer(buffers.fmm_block_aabbs)
+        ).unwrap();
+        let aabb_lines_pipeline = Render_aabb_lines::init(&configuration.device, &configuration.sc_desc, 1, false);
+        let aabb_lines_bind_groups = aabb_lines_pipeline.create_bind_groups(
+            &configuration.device,
+            &camera.get_camera_uniform(&configuration.device),
+            resources.get_buffer(buffers.fmm_block_aabbs)
+        ).unwrap();
+        // The current block uses the same bind groups.
+        let current_block_pipeline = Render_aabb_lines::init(&configuration.device, &configuration.sc_desc, 1, true);
+
         // The point pipeline.
         let render_vvvc_point_pipeline = Render_vvvc::init(
                     &configuration.device,
                     &configuration.sc_desc,
-                    &configuration.device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-                        label: Some("renderer_v3c1_module1"),
-                        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../../shaders_wgsl/renderer_v3c1.wgsl"))),
-                        //flags: wgpu::ShaderFlags::VALIDATION | wgpu::ShaderFlags::EXPERIMENTAL_TRANSLATION,
-                    }),
+                    1,
+                    include_str!("../../shaders_wgsl/renderer_v3c1.wgsl"),
                     wgpu::PrimitiveTopology::PointList
         );
         let render_vvvc_point_bind_groups = render_vvvc_point_pipeline.create_bind_groups(
             &configuration.device,
             &camera.get_camera_uniform(&configuration.device)
-        );
+        ).unwrap();
 
         // The triangle pipeline.
         let render_vvvc_triangle_pipeline = Render_vvvc::init(
                     &configuration.device,
                     &configuration.sc_desc,
-                    &configuration.device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-                        label: Some("renderer_v3c1_module2"),
-                        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../../shaders_wgsl/renderer_v3c1.wgsl"))),
-                    }),
+                    1,
+                    include_str!("../../shaders_wgsl/renderer_v3c1.wgsl"),
                     wgpu::PrimitiveTopology::TriangleList
         );
         let render_vvvc_triangle_bind_groups = render_vvvc_triangle_pipeline.create_bind_groups(
             &configuration.device,
             &camera.get_camera_uniform(&configuration.device)
-        );
-        let render_vvvvnnnn_pipeline = Render_vvvvnnnn::init(
-                &configuration.device,
-                &configuration.sc_desc,
-                &configuration.device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-                    label: Some("renderer_v4n4_module"),
-                    source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../../shaders_wgsl/renderer_v4n4_plain.wgsl"))),
-                })
-        );
-        let render_vvvvnnnn_bind_groups = render_vvvvnnnn_pipeline.create_bind_groups(
+        ).unwrap();
+        // The wood mesh is rendered indexed with the pbr pipeline.
+        let wood_mesh = {
+            let mesh = Mesh::from_triangles_vvvvnnnn(&triangle_data);
+            let vertices: Vec<Vertex_vvvvnnnn> = mesh.positions.iter().zip(mesh.normals.iter()).map(|(p, n)| Vertex_vvvvnnnn {
+                position: [p.x, p.y, p.z, 1.0],
+                normal: [n.x, n.y, n.z, 0.0],
+            }).collect();
+            PbrMesh::init(&configuration.device, &vertices, &mesh.indices)
+        };
+        let pbr_pipeline = PbrPipeline::init(&configuration.device, &configuration.queue, &configuration.sc_desc, 1, false);
+        let mut light_uniform = LightUniform::init();
+        light_uniform.add_point_light(PointLight {
+            position: [aabb.max.x + 2.0, aabb.max.y + 4.0, aabb.max.z + 2.0, 40.0],
+            color: [1.0, 0.6, 0.3, 60.0],
+        });
+        let pbr_lights = Lights::init(&configuration.device, light_uniform);
+        let pbr_material = PbrMaterial::init(&configuration.device, [0.55, 0.36, 0.2, 1.0], 0.0, 0.7);
+        pbr_material.update(&configuration.queue);
+        let pbr_bind_groups = pbr_pipeline.create_bind_groups(
             &configuration.device,
-            &camera.get_camera_uniform(&configuration.device)
-        );
-
-        let mut debug_point_count = 2; // WHY?
-        let mut debug_triangle_draw_count = DEBUG_BUFFER_SIZE;
+            &camera.get_camera_uniform(&configuration.device),
+            &pbr_lights,
+            &pbr_material,
+            None
+        ).unwrap();
 
         // Create histogram for fmm debug.
         let mut histogram = Histogram::init(&configuration.device, &vec![0, 2]); 
 
+        // The draw arguments of the debug points (histogram[0]) and the debug triangles
+        // (DEBUG_BUFFER_SIZE..histogram[1]) are computed on the gpu.
+        let indirect_args = IndirectArgsKernel::init(&configuration.device);
+        let debug_point_args = indirect_args.create_draw_args(&configuration.device, histogram.get_histogram(), 0, 0, 1);
+        let debug_triangle_args = indirect_args.create_draw_args(&configuration.device, histogram.get_histogram(), 1, DEBUG_BUFFER_SIZE, 1);
+
+        // The BAND nodes of the active blocks are added to the debug points.
+        let fmm_active_blocks = FMM_active_blocks_pipeline::init(&configuration.device, &indirect_args, BLOCK_DIMENSIONS);
+        let fmm_active_blocks_bind_groups = fmm_active_blocks.create_bind_groups(
+            &configuration.device,
+            resources.get_buffer(buffers.fmm_nodes),
+            resources.get_buffer(buffers.fmm_blocks),
+            histogram.get_histogram(),
+            resources.get_buffer(buffers.debug_points_output)
+        ).unwrap();
+
         let fmm_debug_pipeline = FMM_debug_pipeline::init(&configuration.device);
 
         let fmm_debug_bind_groups =
-                create_bind_groups(
+                fmm_debug_pipeline.create_bind_groups(
                     &configuration.device, 
-                    &fmm_debug_pipeline.get_bind_group_layout_entries(),
-                    &vec![
-                        vec![
-                            &camera.get_camera_uniform(&configuration.device).as_entire_binding(),
-                            &buffers.get("prefix_sum_temp").unwrap().as_entire_binding(),
-                            &buffers.get("debug_points_output").unwrap().as_entire_binding(),
-                            &buffers.get("fmm_nodes").unwrap().as_entire_binding(),
-                            &buffers.get("fmm_blocks").unwrap().as_entire_binding(),
-                            &histogram.get_histogram().as_entire_binding(),
-                            &buffers.get("index_hash_table").unwrap().as_entire_binding(),
-                            &buffers.get("vec_to_offset").unwrap().as_entire_binding(),
-                            &buffers.get("fmm_attributes").unwrap().as_entire_binding(),
-                        ], 
+                    &[
+                        ((0, 0), camera.get_camera_uniform(&configuration.device).as_entire_binding()),
+                        ((0, 1), resources.get_buffer(buffers.prefix_sum_temp).as_entire_binding()),
+                        ((0, 2), resources.get_buffer(buffers.debug_points_output).as_entire_binding()),
+                        ((0, 3), resources.get_buffer(buffers.fmm_nodes).as_entire_binding()),
+                        ((0, 4), resources.get_buffer(buffers.fmm_blocks).as_entire_binding()),
+                        ((0, 5), histogram.get_histogram().as_entire_binding()),
+                        ((0, 6), resources.get_buffer(buffers.index_hash_table).as_entire_binding()),
+                        ((0, 7), resources.get_buffer(buffers.vec_to_offset).as_entire_binding()),
+                        ((0, 8), resources.get_buffer(buffers.fmm_attributes).as_entire_binding()),
                     ]
+        ).unwrap();
+        let fmm_debug_bind_groups = resources.insert_bind_groups(
+            "fmm_debug_bind_groups",
+            fmm_debug_bind_groups,
+            &[buffers.prefix_sum_temp.into(), buffers.debug_points_output.into(), buffers.fmm_nodes.into(), buffers.fmm_blocks.into(),
+              buffers.index_hash_table.into(), buffers.vec_to_offset.into(), buffers.fmm_attributes.into()]
         );
         let fmm_data_generator = FMM_data_generator_debug_pipeline::init(&configuration.device);
         let fmm_data_generator_bind_groups =
-                create_bind_groups(
+                fmm_data_generator.create_bind_groups(
                     &configuration.device, 
-                    &fmm_data_generator.get_bind_group_layout_entries(),
-                    &vec![
-                        vec![
-                            &camera.get_camera_uniform(&configuration.device).as_entire_binding(),
-                            &histogram.get_histogram().as_entire_binding(),
-                            &buffers.get("debug_points_output").unwrap().as_entire_binding(),
-                            &buffers.get("fmm_nodes").unwrap().as_entire_binding(),
-                            &buffers.get("wood").unwrap().as_entire_binding(),
-                            &buffers.get("index_hash_table").unwrap().as_entire_binding(),
-                            &buffers.get("vec_to_offset").unwrap().as_entire_binding(),
-                            &buffers.get("fmm_attributes").unwrap().as_entire_binding(),
-                            &buffers.get("fmm_data_gen_params").unwrap().as_entire_binding(),
-                        ], 
+                    &[
+                        ((0, 0), camera.get_camera_uniform(&configuration.device).as_entire_binding()),
+                        ((0, 1), histogram.get_histogram().as_entire_binding()),
+                        ((0, 2), resources.get_buffer(buffers.debug_points_output).as_entire_binding()),
+                        ((0, 3), resources.get_buffer(buffers.fmm_nodes).as_entire_binding()),
+                        ((0, 4), resources.get_buffer(buffers.wood).as_entire_binding()),
+                        ((0, 5), resources.get_buffer(buffers.index_hash_table).as_entire_binding()),
+                        ((0, 6), resources.get_buffer(buffers.vec_to_offset).as_entire_binding()),
+                        ((0, 7), resources.get_buffer(buffers.fmm_attributes).as_entire_binding()),
+                        ((0, 8), resources.get_buffer(buffers.fmm_data_gen_params).as_entire_binding()),
                     ]
+        ).unwrap();
+        let fmm_data_generator_bind_groups = resources.insert_bind_groups(
+            "fmm_data_generator_bind_groups",
+            fmm_data_generator_bind_groups,
+            &[buffers.debug_points_output.into(), buffers.fmm_nodes.into(), buffers.wood.into(), buffers.index_hash_table.into(),
+              buffers.vec_to_offset.into(), buffers.fmm_attributes.into(), buffers.fmm_data_gen_params.into()]
         );
 
         println!("Creating Sphere tracer");
         let sphere_tracer_pipeline = SphereTracerPipeline::init(&configuration.device); 
-        let sphere_tracer_bind_groups = 
-                create_bind_groups(
-                    &configuration.device, 
-                    &sphere_tracer_pipeline.get_bind_group_layout_entries(),
-                    &vec![
-                        vec![
-                            &camera.get_ray_camera_uniform(&configuration.device).as_entire_binding(),
-                            &buffers.get("fmm_nodes").unwrap().as_entire_binding(),
-                            &buffers.get("index_hash_table").unwrap().as_entire_binding(),
-                            &buffers.get("vec_to_offset").unwrap().as_entire_binding(),
-                            &buffers.get("fmm_attributes").unwrap().as_entire_binding(),
-                            &buffers.get("sphere_tracer_output").unwrap().as_entire_binding(),
-                            //&wgpu::BindingResource::TextureView(&textures.get("sphere_tracer_texture").unwrap().view),
-                        ],
-                    ]
+        let sphere_tracer_bind_groups = resources.insert_bind_groups(
+            "sphere_tracer_bind_groups",
+            FMM_App::create_sphere_tracer_bind_groups(&configuration.device, &sphere_tracer_pipeline, &mut camera, &resources, &buffers),
+            &[buffers.fmm_nodes.into(), buffers.index_hash_table.into(), buffers.vec_to_offset.into(),
+              buffers.fmm_attributes.into(), buffers.sphere_tracer_output.into()]
         );
                 
         println!("Creating Sphere tracer :: OK");
+        log::info!("{}", resources.report());
 
         let show_mesh = false;
 
         Self {
-            textures,
-            depth_texture,
-            camera,
+            resources,
             buffers,
+            transient_textures: TransientTextures::init(),
+            camera,
             render_vvvc_point_pipeline, 
             render_vvvc_point_bind_groups,
             render_vvvc_triangle_pipeline, 
@@ -452,17 +728,30 @@ impl Application for FMM_App {
             fmm_debug_pipeline,
             fmm_debug_bind_groups,
             histogram,
-            debug_point_count,
-            debug_triangle_draw_count,
+            indirect_args,
+            fmm_active_blocks,
+            fmm_active_blocks_bind_groups,
+            debug_point_args,
+            debug_triangle_args,
+            block_count,
+            cull_params,
+            frustum_culler,
+            cull_bind_groups,
+            aabb_lines_pipeline,
+            aabb_lines_bind_groups,
+            unit_cube_vertex_count,
+            current_block_pipeline,
             fmm_data_generator,
             fmm_data_generator_bind_groups,
-            render_vvvvnnnn_pipeline,
-            render_vvvvnnnn_bind_groups,
+            pbr_pipeline,
+            pbr_lights,
+            pbr_material,
+            pbr_bind_groups,
+            wood_mesh,
             show_mesh,
             current_block,
             fmm_attributes,
             current_global_dimensions,
-            triangle_count,
             update_data_generator,
             triangle_data,
             triangle_index,
@@ -471,8 +760,16 @@ impl Application for FMM_App {
             data_loaded,
             query_sets,
             screen,
+            sphere_tracer_texture,
+            screen_bind_groups,
             sphere_tracer_pipeline,
             sphere_tracer_bind_groups,
+            actions,
+            bvh,
+            inside_mask,
+            camera_recorder: None,
+            camera_playback: None,
+            screen_size,
         }
     }
 
@@ -492,127 +789,182 @@ impl Application for FMM_App {
             },
         };
 
-        let mut encoder = device.create_command_encoder(
-            &wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-        });
-        let mut clear = true;
-        
         let view = frame.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let clear_color = wgpu::Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0, };
+
+        let mut graph = FrameGraph::<FMM_App>::init("fmm frame");
+        let depth_texture = graph.create_texture(
+            "fmm depth texture",
+            TransientTextureDescriptor::attachment(&sc_desc, JTexture::DEPTH_FORMAT)
+        );
 
         if self.show_mesh {
-            draw(&mut encoder,
-                 //&surface.get_current_frame().unwrap(),
-                 //&current_frame,
-                 &view,
-                 &self.depth_texture,
-                 &self.render_vvvvnnnn_bind_groups,
-                 &self.render_vvvvnnnn_pipeline.get_pipeline(),
-                 &self.buffers.get("wood").unwrap(),
-                 //0..300,
-                 //0..2036*3,
-                 0..self.triangle_count * 3,
-                 clear
-            );
-            clear = false;
+            graph.add_render_pass("fmm mesh")
+                 .color(GraphResource::Surface, clear_color)
+                 .depth(depth_texture, 1.0)
+                 .execute(|app, pass| {
+                     app.pbr_pipeline.draw_mesh(pass, &app.pbr_bind_groups, &app.wood_mesh);
+                 });
         }
 
-        //++ draw(&mut encoder,
-        //++      &frame,
-        //++      &self.depth_texture,
-        //++      &self.render_vvvvnnnn_bind_groups,
-        //++      &self.render_vvvvnnnn_pipeline.get_pipeline(),
-        //++      &self.buffers.get("wood_single").unwrap(),
-        //++      //0..300,
-        //++      //0..2036*3,
-        //++      0..3,
-        //++      clear
-        //++ );
-        //++ if clear { clear = false; }
-        
-        if self.debug_point_count > 0 {
-           draw(&mut encoder,
-                //&surface.get_current_frame().unwrap(),
-                //&current_frame,
-                &view,
-                &self.depth_texture,
-                &self.render_vvvc_point_bind_groups,
-                &self.render_vvvc_point_pipeline.get_pipeline(),
-                &self.buffers.get("debug_points_output").unwrap(),
-                0..self.debug_point_count,
-                //2..self.debug_point_count,
-                //3..3000,
-                clear
-           );
-           clear = false;
+        // The fmm data is generated once. The debug points are drawn after it in the same frame. The
+        // nodes are already initialized if the narrow band was computed on the cpu.
+        if !self.data_loaded {
+            graph.add_compute_pass("fmm data generator")
+                 .read(self.buffers.wood)
+                 .read(self.buffers.index_hash_table)
+                 .read(self.buffers.vec_to_offset)
+                 .read(self.buffers.fmm_attributes)
+                 .read(self.buffers.fmm_data_gen_params)
+                 .write(self.buffers.fmm_nodes)
+                 .write(self.buffers.debug_points_output)
+                 .execute(|app, encoder| {
+                     app.write_timestamp(encoder, 0);
+                     if !CPU_NARROW_BAND {
+                         app.fmm_data_generator.dispatch(app.resources.get_bind_groups(app.fmm_data_generator_bind_groups), encoder, 1, 1, 1);
+                     }
+                     app.write_timestamp(encoder, 1);
+                 });
+
+            graph.add_compute_pass("fmm debug")
+                 .read(self.buffers.fmm_nodes)
+                 .read(self.buffers.index_hash_table)
+                 .read(self.buffers.vec_to_offset)
+                 .read(self.buffers.fmm_attributes)
+                 .write(self.buffers.prefix_sum_temp)
+                 .write(self.buffers.fmm_blocks)
+                 .write(self.buffers.debug_points_output)
+                 .execute(|app, encoder| {
+                     app.write_timestamp(encoder, 2);
+                     app.fmm_debug_pipeline.dispatch(app.resources.get_bind_groups(app.fmm_debug_bind_groups), encoder, 1, 1, 1);
+                     app.write_timestamp(encoder, 3);
+                     app.fmm_active_blocks.dispatch(&app.fmm_active_blocks_bind_groups, &app.indirect_args, encoder);
+
+                     // The counters become the draw arguments of the debug points and triangles.
+                     app.indirect_args.write(&app.debug_point_args, encoder);
+                     app.indirect_args.write(&app.debug_triangle_args, encoder);
+
+                     if let Some(ref query_sets) = app.query_sets {
+                         let timestamp_query_count = 4;
+                         encoder.resolve_query_set(
+                                 &query_sets.timestamp,
+                                 0..timestamp_query_count,
+                                 &query_sets.query_buffer,
+                                 0,
+                                 );
+                     }
+                 });
         }
 
+        graph.add_render_pass("fmm debug points")
+             .color(GraphResource::Surface, clear_color)
+             .depth(depth_texture, 1.0)
+             .read(self.buffers.debug_points_output)
+             .execute(|app, pass| {
+                 DrawCall::init(app.render_vvvc_point_pipeline.get_pipeline(), &app.render_vvvc_point_bind_groups)
+                     .vertex_buffer(app.resources.get_buffer(app.buffers.debug_points_output))
+                     .draw_indirect(pass, app.debug_point_args.get_buffer(), 0);
+             });
+
+        graph.add_render_pass("fmm debug triangles")
+             .color(GraphResource::Surface, clear_color)
+             .depth(depth_texture, 1.0)
+             .read(self.buffers.debug_points_output)
+             .execute(|app, pass| {
+                 DrawCall::init(app.render_vvvc_triangle_pipeline.get_pipeline(), &app.render_vvvc_triangle_bind_groups)
+                     .vertex_buffer(app.resources.get_buffer(app.buffers.debug_points_output))
+                     .draw_indirect(pass, app.debug_triangle_args.get_buffer(), 0);
+             });
+
+        // The culling output (CullParams) isn't in the registry. The block edges are drawn after the
+        // culling because the passes are added in this order.
+        graph.add_compute_pass("cull fmm blocks")
+             .read(self.buffers.fmm_block_aabbs)
+             .execute(|app, encoder| {
+                 app.frustum_culler.dispatch(&app.cull_bind_groups, encoder, app.block_count);
+             });
+
+        graph.add_render_pass("fmm blocks")
+             .color(GraphResource::Surface, clear_color)
+             .depth(depth_texture, 1.0)
+             .read(self.buffers.fmm_block_aabbs)
+             .read(self.buffers.unit_cube_lines)
+             .execute(|app, pass| {
+                 DrawCall::init(app.aabb_lines_pipeline.get_pipeline(), &app.aabb_lines_bind_groups)
+                     .vertex_buffer(app.resources.get_buffer(app.buffers.unit_cube_lines))
+                     .vertex_buffer(&app.cull_params.visible_buffer)
+                     .draw_indirect(pass, &app.cull_params.draw_indirect_buffer, 0);
+             });
+
+        // The current block is drawn even if it's culled.
+        let block = self.fmm_attributes.current_block;
+        let current_block = block[0] + block[1] * BLOCK_DIMENSIONS[0] + block[2] * BLOCK_DIMENSIONS[0] * BLOCK_DIMENSIONS[1];
+        graph.add_render_pass("current fmm block")
+             .color(GraphResource::Surface, clear_color)
+             .depth(depth_texture, 1.0)
+             .read(self.buffers.fmm_block_aabbs)
+             .read(self.buffers.unit_cube_lines)
+             .read(self.buffers.block_indices)
+             .execute(move |app, pass| {
+                 DrawCall::init(app.current_block_pipeline.get_pipeline(), &app.aabb_lines_bind_groups)
+                     .vertex_buffer(app.resources.get_buffer(app.buffers.unit_cube_lines))
+                     .vertex_buffer(app.resources.get_buffer(app.buffers.block_indices))
+                     .instances(current_block..current_block + 1)
+                     .draw(pass, 0..app.unit_cube_vertex_count);
+             });
+
+        graph.allocate(&device, &mut self.transient_textures);
+        graph.execute(&device, &queue, &self.resources, Some(&view), &self.transient_textures, self);
 
-        if self.debug_triangle_draw_count > DEBUG_BUFFER_SIZE {
-           draw(&mut encoder,
-                //&surface.get_current_frame().unwrap(),
-                //&current_frame,
-                &view,
-                &self.depth_texture,
-                &self.render_vvvc_triangle_bind_groups,
-                &self.render_vvvc_triangle_pipeline.get_pipeline(),
-                &self.buffers.get("debug_points_output").unwrap(),
-                DEBUG_BUFFER_SIZE..self.debug_triangle_draw_count,
-                clear
-           );
+        if !self.data_loaded {
+            self.print_timestamps(&device, &queue);
+            self.data_loaded = true;
         }
-        queue.submit(Some(encoder.finish()));
     }
 
     fn input(&mut self, queue: &wgpu::Queue, input_cache: &InputCache) {
-        self.camera.update_from_input(&queue, &input_cache);
+        // The playback moves the camera.
+        if self.camera_playback.is_none() {
+            self.camera.update_from_input(&queue, &input_cache);
+        }
     }
 
-    fn resize(&mut self, device: &wgpu::Device, sc_desc: &wgpu::SurfaceConfiguration, _new_size: winit::dpi::PhysicalSize<u32>) {
-        self.depth_texture = JTexture::create_depth_texture(&device, &sc_desc, Some("depth-texture"));
-        self.camera.resize(sc_desc.width as f32, sc_desc.height as f32);
+    fn actions(&self) -> Option<&ActionMap> {
+        Some(&self.actions)
     }
 
-    fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, input: &InputCache) {
+    fn timestep(&self) -> Option<TimestepConfig> {
+        Some(TimestepConfig::default())
+    }
 
-        // Reset debug counters.
-        self.histogram.set_values_cpu_version(&queue, &vec![0, DEBUG_BUFFER_OFFSET]);
+    /// Move the global dimensions and the current block and adjust the lighting of the wood mesh.
+    fn fixed_update(&mut self, _device: &wgpu::Device, queue: &wgpu::Queue, input: &InputCache, dt: f32) {
 
-        // Get the keyboard state (camera movement).
-        let space_pressed = input.key_state(&Key::Space);
-        if !space_pressed.is_none() {
-            self.update_data_generator = (self.update_data_generator + 1) % 10;
-            if self.update_data_generator < 5 { self.show_mesh = true; }
-            else { self.show_mesh = false; }
-        }
-
-        queue.write_buffer(
-            &self.buffers.get("wood_single").unwrap(),
-            0,
-            bytemuck::cast_slice(&[self.triangle_data[self.triangle_index as usize]])
-        );
+        let time_offset = dt * NAVIGATION_SPEED;
 
-        let time_offset = input.get_time_delta() as f32 / 150000000.0;
+        // The exposure changes by a factor of e per second and the roughness by 0.5 per second.
+        let exposure = self.actions.axis(input, "exposure");
+        if exposure != 0.0 {
+            self.pbr_lights.get_uniform_mut().exposure *= (exposure * dt).exp();
+            self.pbr_lights.update(&queue);
+        }
+        let roughness = self.actions.axis(input, "roughness");
+        if roughness != 0.0 {
+            self.pbr_material.roughness = (self.pbr_material.roughness + roughness * dt * 0.5).clamp(0.0, 1.0);
+            self.pbr_material.update(&queue);
+        }
 
         // Global dimensions.
           
-        let key1_pressed = input.key_state(&Key::Key1);
-        let key2_pressed = input.key_state(&Key::Key2);
-        let key3_pressed = input.key_state(&Key::Key3);
-        let key4_pressed = input.key_state(&Key::Key4);
-        let key5_pressed = input.key_state(&Key::Key5);
-        let key6_pressed = input.key_state(&Key::Key6);
-
-        let mut global_dimensions = self.current_global_dimensions;
+        let dimension_x = self.actions.axis(input, "dimension_x");
+        let dimension_y = self.actions.axis(input, "dimension_y");
+        let dimension_z = self.actions.axis(input, "dimension_z");
 
-        let mut pressed: bool = false;
+        let global_dimensions = [self.current_global_dimensions[0] + dimension_x * time_offset,
+                                 self.current_global_dimensions[1] + dimension_y * time_offset,
+                                 self.current_global_dimensions[2] + dimension_z * time_offset];
 
-        if !key1_pressed.is_none() {global_dimensions = [global_dimensions[0] - time_offset, global_dimensions[1], global_dimensions[2]]; pressed = true; }
-        if !key2_pressed.is_none() {global_dimensions = [global_dimensions[0] + time_offset, global_dimensions[1], global_dimensions[2]];  pressed = true;}
-        if !key3_pressed.is_none() {global_dimensions = [global_dimensions[0] , global_dimensions[1] - time_offset, global_dimensions[2]];  pressed = true;}
-        if !key4_pressed.is_none() {global_dimensions = [global_dimensions[0] , global_dimensions[1] + time_offset, global_dimensions[2]];  pressed = true;}
-        if !key5_pressed.is_none() {global_dimensions = [global_dimensions[0] , global_dimensions[1], global_dimensions[2] - time_offset];  pressed = true;}
-        if !key6_pressed.is_none() {global_dimensions = [global_dimensions[0] , global_dimensions[1], global_dimensions[2] + time_offset];  pressed = true;}
+        let pressed = dimension_x != 0.0 || dimension_y != 0.0 || dimension_z != 0.0;
 
         if global_dimensions[0] > 1.0 && global_dimensions[1] > 1.0 &&  global_dimensions[2] > 1.0 && pressed {
 
@@ -634,7 +986,7 @@ impl Application for FMM_App {
                 }
 
                 queue.write_buffer(
-                    &self.buffers.get("fmm_attributes").unwrap(),
+                    self.resources.get_buffer(self.buffers.fmm_attributes),
                     0,
                     bytemuck::cast_slice(&[self.fmm_attributes])
                 );
@@ -644,7 +996,7 @@ impl Application for FMM_App {
                     create_hash_table(4, 4, 4, self.fmm_attributes.global_dimensions[0], self.fmm_attributes.global_dimensions[1], self.fmm_attributes.global_dimensions[2] );
 
                 queue.write_buffer(
-                    &self.buffers.get("index_hash_table").unwrap(),
+                    self.resources.get_buffer(self.buffers.index_hash_table),
                     0,
                     bytemuck::cast_slice(&ivec_offset_hash_table)
                 );
@@ -657,23 +1009,9 @@ impl Application for FMM_App {
 
         // Block location.
           
-        let g_pressed = input.key_state(&Key::G);
-        let h_pressed = input.key_state(&Key::H);
-        let j_pressed = input.key_state(&Key::J);
-        let y_pressed = input.key_state(&Key::Y);
-        let u_pressed = input.key_state(&Key::U);
-        let m_pressed = input.key_state(&Key::M);
-
-        let mut block_x_pos: f32 = self.current_block[0];
-        let mut block_y_pos: f32 = self.current_block[1];
-        let mut block_z_pos: f32 = self.current_block[2];
-
-        if !g_pressed.is_none() {block_x_pos = block_x_pos-time_offset; }
-        if !h_pressed.is_none() {block_z_pos = block_z_pos+time_offset; }
-        if !j_pressed.is_none() {block_x_pos = block_x_pos+time_offset; }
-        if !y_pressed.is_none() {block_z_pos = block_z_pos-time_offset; }
-        if !u_pressed.is_none() {block_y_pos = block_y_pos+time_offset; }
-        if !m_pressed.is_none() {block_y_pos = block_y_pos-time_offset; }
+        let block_x_pos: f32 = self.current_block[0] + self.actions.axis(input, "block_x") * time_offset;
+        let block_y_pos: f32 = self.current_block[1] + self.actions.axis(input, "block_y") * time_offset;
+        let block_z_pos: f32 = self.current_block[2] + self.actions.axis(input, "block_z") * time_offset;
 
         let mut block_pos: [f32;3] = [block_x_pos, block_y_pos, block_z_pos] ;
 
@@ -683,11 +1021,78 @@ impl Application for FMM_App {
                 self.current_block = block_pos; 
                 self.fmm_attributes.current_block  = [block_x_pos as u32, block_y_pos as u32, block_z_pos as u32];
                 queue.write_buffer(
-                    &self.buffers.get("fmm_attributes").unwrap(),
+                    self.resources.get_buffer(self.buffers.fmm_attributes),
                     0,
                     bytemuck::cast_slice(&[self.fmm_attributes])
                 );
         }
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, sc_desc: &wgpu::SurfaceConfiguration, _new_size: winit::dpi::PhysicalSize<u32>) {
+        self.camera.resize(sc_desc.width as f32, sc_desc.height as f32);
+        self.screen_size = [sc_desc.width as f32, sc_desc.height as f32];
+
+        // The screen sized sphere tracer resources and the bind groups that use them.
+        self.resources.recreate_texture(self.sphere_tracer_texture, |label|
+            JTexture::create_texture2d(&device, &sc_desc, 1, sc_desc.width, sc_desc.height, label)
+        );
+        self.resources.recreate_buffer(&device, self.buffers.sphere_tracer_output, FMM_App::sphere_tracer_output_size(&sc_desc));
+
+        let screen_group = self.screen.create_bind_group(&device, self.resources.get_texture(self.sphere_tracer_texture)).unwrap();
+        self.resources.replace_bind_groups(self.screen_bind_groups, vec![screen_group]);
+
+        let sphere_tracer_bind_groups = FMM_App::create_sphere_tracer_bind_groups(&device, &self.sphere_tracer_pipeline, &mut self.camera, &self.resources, &self.buffers);
+        self.resources.replace_bind_groups(self.sphere_tracer_bind_groups, sphere_tracer_bind_groups);
+    }
+
+    fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, input: &InputCache) {
+
+        // Reset debug counters.
+        self.histogram.set_values_cpu_version(&queue, &vec![0, DEBUG_BUFFER_OFFSET]);
+
+        // The first press starts the recording and the second saves the camera path.
+        if self.actions.is_pressed(&input, "record_camera_path") {
+            match self.camera_recorder.take() {
+                Some(recorder) => match recorder.save(CAMERA_PATH_FILE) {
+                    Ok(()) => log::info!("Saved the camera path to {}.", CAMERA_PATH_FILE),
+                    Err(e) => log::warn!("Failed to save the camera path to {}: {}", CAMERA_PATH_FILE, e),
+                },
+                None => self.camera_recorder = Some(CameraRecorder::init()),
+            }
+        }
+        if self.actions.is_pressed(&input, "play_camera_path") {
+            self.camera_playback = match CameraPath::load(CAMERA_PATH_FILE) {
+                Ok(path) => Some(CameraPlayback::init(path, PlaybackClock::RealTime, false)),
+                Err(e) => { log::warn!("Failed to load the camera path: {}", e); None }
+            };
+        }
+        if let Some(ref mut recorder) = self.camera_recorder {
+            recorder.record(&self.camera, input.get_time());
+        }
+        if let Some(ref mut playback) = self.camera_playback {
+            if !playback.update(&queue, &mut self.camera, input.get_time()) { self.camera_playback = None; }
+        }
+
+        self.cull_params.update_frustum(&queue, &self.camera.frustum());
+
+        // The toggle and fmm step actions also count the tick where the button is released.
+        if self.actions.is_active(&input, "toggle_mesh") {
+            self.update_data_generator = (self.update_data_generator + 1) % 10;
+            if self.update_data_generator < 5 { self.show_mesh = true; }
+            else { self.show_mesh = false; }
+        }
+
+        if self.actions.is_pressed(&input, "pick") {
+            if let Some(ray) = self.camera.create_cursor_ray(&input, self.screen_size[0], self.screen_size[1]) {
+                self.pick(&device, &queue, &ray);
+            }
+        }
+
+        queue.write_buffer(
+            self.resources.get_buffer(self.buffers.wood_single),
+            0,
+            bytemuck::cast_slice(&[self.triangle_data[self.triangle_index as usize]])
+        );
 
         // let enter_pressed = input.key_state(&Key::Return);
         // if !enter_pressed.is_none() {self.update_data_generator = (self.update_data_generator + 1) % 10; }
@@ -721,96 +1126,11 @@ impl Application for FMM_App {
 
         let mut increase_fmm_step = false;
 
-        let p_pressed = input.key_state(&Key::P);
-        if !p_pressed.is_none() {
+        if self.actions.is_active(&input, "fmm_step") {
             self.changed = (self.changed + 1) % 10;
             if self.changed > 5 { increase_fmm_step = true; self.changed = 0; }
         }
 
-        //if increase_fmm_step {
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("FMM update encoder.") });
-
-        if !self.data_loaded {
-            if let Some(ref query_sets) = self.query_sets {
-                encoder.write_timestamp(&query_sets.timestamp, 0);
-            }
-
-            self.fmm_data_generator.dispatch(&self.fmm_data_generator_bind_groups,
-                        &mut encoder,
-                        1,
-                        1,
-                        1
-            ); 
-
-            if let Some(ref query_sets) = self.query_sets {
-                encoder.write_timestamp(&query_sets.timestamp, 1);
-            }
-
-            if let Some(ref query_sets) = self.query_sets {
-                encoder.write_timestamp(&query_sets.timestamp, 2);
-            }
-
-            self.fmm_debug_pipeline.dispatch(&self.fmm_debug_bind_groups,
-                        &mut encoder,
-                        1,
-                        1,
-                        1
-            ); 
-
-            if let Some(ref query_sets) = self.query_sets {
-                encoder.write_timestamp(&query_sets.timestamp, 3);
-            }
-            
-            if let Some(ref query_sets) = self.query_sets {
-                let timestamp_query_count = 4;
-                encoder.resolve_query_set(
-                        &query_sets.timestamp,
-                        0..timestamp_query_count,
-                        &query_sets.query_buffer,
-                        0,
-                        );
-            }
-
-            queue.submit(Some(encoder.finish()));
-
-            //++let fmm_attributes = FMM_Attributes{ global_dimensions: BLOCK_DIMENSIONS,
-            //++                                     offset_hash_table_size: 160 as u32,
-            //++                                     current_block: [1,0,0],
-            //++                                     vec_to_offset_table_size: 216 as u32,
-            //++};
-
-            //++queue.write_buffer(
-            //++    &self.buffers.get("fmm_attributes").unwrap(),
-            //++    0,
-            //++    bytemuck::cast_slice(&[fmm_attributes])
-            //++);
-
-            if let Some(ref query_sets) = self.query_sets {
-                // We can ignore the future as we're about to wait for the device.
-                //
-                let result = to_vec::<QueryData>(&device,
-                        &queue,
-                        &query_sets.query_buffer,
-                        0 as wgpu::BufferAddress,
-                        (std::mem::size_of::<QueryData>() as usize) as wgpu::BufferAddress);
-                //println!("result :: {:?}", result);
-                for (i, elem) in result[0].timestamps.iter().enumerate() {
-                    let nanoseconds =
-                        (elem.end - elem.start) as f32 * query_sets.timestamp_period;
-                    let microseconds = nanoseconds / 1000.0;
-                    let milli = microseconds / 1000.0;
-                    //println!("{:?} time is {:?} micro seconds.", i, microseconds);
-                    println!("{:?} time is {:?} milli seconds.", i, milli);
-                }
-            }
-
-            // Get the counter values.
-            let histogram = self.histogram.get_values(device, queue);
-            self.debug_point_count = histogram[0];
-            self.debug_triangle_draw_count = histogram[1];
-            self.data_loaded = true;
-        } // if data_loaded
-        //if increase_fmm_step {
     }
 //                        encoder.copy_buffer_to_texture(
 //                        wgpu::BufferCopyView {
@@ -833,11 +1153,14 @@ impl Application for FMM_App {
 //                    });
 }
 
+/// Create the buffers of the fmm algorithm. The fmm_nodes buffer is initialized with
+/// initial_nodes. Returns the handles of prefix_sum_temp, debug_points_output, fmm_nodes,
+/// fmm_blocks and fmm_data_gen_params.
 fn create_buffers(device: &wgpu::Device,
                   block_dimension: [u32 ; 3],
-                  local_dimension: [u32 ; 3],
+                  initial_nodes: &[FMM_Node],
                   debug_point_output_size: u32,
-                  buffers: &mut HashMap<String, wgpu::Buffer>) {
+                  resources: &mut ResourceRegistry) -> (BufferHandle, BufferHandle, BufferHandle, BufferHandle, BufferHandle) {
 
         // Define the bank conflict free prefix sum temp array size.
         //let block_dimension_size_bkf = block_dimension[0] * block_dimension[1] * block_dimension[2] + 
@@ -848,39 +1171,32 @@ fn create_buffers(device: &wgpu::Device,
 
         // layout(set = 0, binding = 1) buffer Prefix_sums  {
         // This buffer holds the temporary bank confict free data.
-        buffers.insert(
-            "prefix_sum_temp".to_string(),
-            buffer_from_data::<u32>(
+        let prefix_sum_temp = resources.create_buffer_from_data::<u32>(
             &device,
+            "prefix_sum_temp",
             &vec![0 as u32; block_dimension_size as usize],
             //&vec![0; block_dimension_size_bkf * 4 as usize],
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
-            None)
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC
         );
 
         // layout(set = 0, binding = 3) buffer Points_out {
-        buffers.insert(
-            "debug_points_output".to_string(),
-            buffer_from_data::<OutputVertex>(
+        let debug_points_output = resources.create_buffer_from_data::<OutputVertex>(
             &device,
+            "debug_points_output",
             &vec![OutputVertex { pos: [0.0, 0.0, 0.0], color_point_size: 0 } ; (1024000*2) as usize],
-            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-            None)
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST
         );
 
         let number_of_blocks = block_dimension[0] * block_dimension[1] * block_dimension[2]; 
-        let number_of_nodes = number_of_blocks * local_dimension[0] * local_dimension[1] * local_dimension[2]; 
 
-        println!("Number of FMM_Nodes == {}", number_of_nodes);
+        println!("Number of FMM_Nodes == {}", initial_nodes.len());
 
         // layout(set = 0, binding = 4) buffer FMM_Nodes {
-        buffers.insert(
-            "fmm_nodes".to_string(),
-            buffer_from_data::<FMM_Node>(
+        let fmm_nodes = resources.create_buffer_from_data::<FMM_Node>(
             &device,
-            &vec![FMM_Node {value: 1000000.0, tag: FAR} ; number_of_nodes as usize],
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
-            None)
+            "fmm_nodes",
+            initial_nodes,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC
         );
 
         println!("CREATING BLOCKS");
@@ -893,23 +1209,21 @@ fn create_buffers(device: &wgpu::Device,
             test_blocks[i] = FMM_Block{ index: i as u32, band_points_count: 0 as u32};
         };
 
-        buffers.insert(
-            "fmm_blocks".to_string(),
-            buffer_from_data::<FMM_Block>(
+        let fmm_blocks = resources.create_buffer_from_data::<FMM_Block>(
             &device,
+            "fmm_blocks",
             &test_blocks,
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
-            None)
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC
         );
 
-        buffers.insert(
-            "fmm_data_gen_params".to_string(),
-            buffer_from_data::<[u32; 4]>(
+        let fmm_data_gen_params = resources.create_buffer_from_data::<[u32; 4]>(
             &device,
+            "fmm_data_gen_params",
             &[[2036, 0, 0, 0]],
-            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            None)
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST
         );
+
+        (prefix_sum_temp, debug_points_output, fmm_nodes, fmm_blocks, fmm_data_gen_params)
 }
 
 // fn create_fmm_buffer(device: &wgpu::Device,
@@ -930,525 +1244,227 @@ fn create_buffers(device: &wgpu::Device,
 //         );
 // }
 
-pub struct SphereTracerPipeline {
-    layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>, 
-    bind_group_layouts: Vec<wgpu::BindGroupLayout>, 
-    pipeline: wgpu::ComputePipeline,
-}
+/// Compute kernel accessors shared by the fmm pipelines.
+macro_rules! impl_kernel_accessors {
+    ($name:ty) => {
+        impl $name {
+            pub fn get_pipeline(&self) -> &wgpu::ComputePipeline {
+                self.kernel.get_pipeline()
+            }
 
-impl SphereTracerPipeline {
+            pub fn get_bind_group_layouts(&self) -> &Vec<wgpu::BindGroupLayout> {
+                self.kernel.get_bind_group_layouts()
+            }
 
-    pub fn get_pipeline(&self) -> &wgpu::ComputePipeline {
-        &self.pipeline
-    }
+            pub fn get_bind_group_layout_entries(&self) -> &Vec<Vec<wgpu::BindGroupLayoutEntry>> {
+                self.kernel.get_bind_group_layout_entries()
+            }
 
-    pub fn get_bind_group_layouts(&self) -> &Vec<wgpu::BindGroupLayout> {
-        &self.bind_group_layouts
-    }
+            pub fn create_bind_groups(&self, device: &wgpu::Device, bindings: &[((u32, u32), wgpu::BindingResource)]) -> Result<Vec<wgpu::BindGroup>, String> {
+                self.kernel.create_bind_groups(&device, bindings)
+            }
 
-    pub fn get_bind_group_layout_entries(&self) -> &Vec<Vec<wgpu::BindGroupLayoutEntry>> {
-        &self.layout_entries
+            pub fn dispatch(&self, bind_groups: &Vec<wgpu::BindGroup>,
+                            encoder: &mut wgpu::CommandEncoder,
+                            x: u32, y: u32, z: u32) {
+                self.kernel.dispatch(bind_groups, encoder, x, y, z)
+            }
+        }
     }
+}
 
-    pub fn dispatch(&self, bind_groups: &Vec<wgpu::BindGroup>,
-                    encoder: &mut wgpu::CommandEncoder,
-                    x: u32, y: u32, z: u32) {
+pub struct SphereTracerPipeline {
+    kernel: ComputeKernel,
+}
 
-        let mut pass = encoder.begin_compute_pass(
-            &wgpu::ComputePassDescriptor { label: None}
-        );
-        pass.set_pipeline(&self.pipeline);
-        for (e, bgs) in bind_groups.iter().enumerate() {
-            pass.set_bind_group(e as u32, &bgs, &[]);
-        }
-        pass.dispatch(x, y, z)
-    }
+impl_kernel_accessors!{SphereTracerPipeline}
+
+impl SphereTracerPipeline {
 
     pub fn init(device: &wgpu::Device) -> Self {
 
-        let mut comp_module = wgpu::include_spirv_raw!("../../shaders/spirv/sphere_tracer_fmm.comp.spv");
+        let comp_module = wgpu::include_spirv_raw!("../../shaders/spirv/sphere_tracer_fmm.comp.spv");
+        let workgroup_size = spirv_workgroup_size(include_bytes!("../../shaders/spirv/sphere_tracer_fmm.comp.spv"), "main").unwrap();
 
-        // Define all bind grout entries for pipeline and bind groups.
         let layout_entries = vec![
-                vec![
-                    // layout(set = 0, binding = 0) uniform RayCamera {
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                            },
-                        count: None,
-                    },
-                    // layout(set = 0, binding = 1) buffer FMM_Nodes {
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None, // wgpu::BufferSize::new(temp_prefix_sum_size),
-                        },
-                        count: None,
-                    },
-                    // layout(set = 0, binding = 2) buffer OffsetTable
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // layout(set = 0, binding = 3) buffer VecToHashTable
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // layout(set=0, binding=4) uniform FMM_Attributes
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 4,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                            //min_binding_size: wgpu::BufferSize::new(12),
-                            },
-                        count: None,
-                    },
-                    //wgpu::BindGroupLayoutEntry {
-                    //    binding: 2,
-                    //    visibility: wgpu::ShaderStages::COMPUTE,
-                    //    ty: wgpu::BindingType::StorageTexture {
-                    //        access: wgpu::StorageTextureAccess::ReadOnly,
-                    //        format: wgpu::TextureFormat::Rgba32Float,
-                    //        view_dimension: wgpu::TextureViewDimension::D2,
-                    //    },
-                    //    count: None,
-                    //},
-                    //layout(set = 0, binding = 5) buffer RayOutputBuffer {
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 5,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None, //wgpu::BufferSize::new(3193724),
-                        },
-                        count: None,
-                    },
-                ],
+                vec![uniform_entry(0, wgpu::ShaderStages::COMPUTE),        // layout(set = 0, binding = 0) uniform RayCamera
+                     storage_entry(1, wgpu::ShaderStages::COMPUTE, true),  // layout(set = 0, binding = 1) buffer FMM_Nodes
+                     storage_entry(2, wgpu::ShaderStages::COMPUTE, true),  // layout(set = 0, binding = 2) buffer OffsetTable
+                     storage_entry(3, wgpu::ShaderStages::COMPUTE, true),  // layout(set = 0, binding = 3) buffer VecToHashTable
+                     uniform_entry(4, wgpu::ShaderStages::COMPUTE),        // layout(set = 0, binding = 4) uniform FMM_Attributes
+                     storage_entry(5, wgpu::ShaderStages::COMPUTE, false)] // layout(set = 0, binding = 5) buffer RayOutputBuffer
         ];
-        let bind_group_layouts = create_bind_group_layouts(&device, &layout_entries);
-
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
-            push_constant_ranges: &[],
-        });
-
-        // Create the pipeline.
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("sphere_tracer_fmm_pipeline"),
-            layout: Some(&pipeline_layout),
-            module: unsafe { &device.create_shader_module_spirv(&comp_module) },
-            entry_point: "main",
-        });
-
 
         Self {
-            layout_entries, 
-            bind_group_layouts, 
-            pipeline,
+            kernel: ComputeKernel::init(&device,
+                                        unsafe { &device.create_shader_module_spirv(&comp_module) },
+                                        "main",
+                                        layout_entries,
+                                        workgroup_size,
+                                        Some("sphere_tracer_fmm_pipeline")),
         }
     }
 }
 
 /// Struct for fmm development version.
 pub struct FMM_debug_pipeline {
-    layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>, 
-    bind_group_layouts: Vec<wgpu::BindGroupLayout>, 
-    pipeline: wgpu::ComputePipeline,
+    kernel: ComputeKernel,
 }
 
-impl FMM_debug_pipeline {
+impl_kernel_accessors!{FMM_debug_pipeline}
 
-    pub fn get_pipeline(&self) -> &wgpu::ComputePipeline {
-        &self.pipeline
-    }
+impl FMM_debug_pipeline {
 
-    pub fn get_bind_group_layouts(&self) -> &Vec<wgpu::BindGroupLayout> {
-        &self.bind_group_layouts
-    }
+    pub fn init(device: &wgpu::Device) -> Self {
 
-    pub fn get_bind_group_layout_entries(&self) -> &Vec<Vec<wgpu::BindGroupLayoutEntry>> {
-        &self.layout_entries
-    }
+        let comp_module = wgpu::include_spirv_raw!("../../shaders/spirv/fmm.comp.spv");
 
-    pub fn dispatch(&self, bind_groups: &Vec<wgpu::BindGroup>,
-                    encoder: &mut wgpu::CommandEncoder,
-                    x: u32, y: u32, z: u32) {
+        let layout_entries = vec![
+                vec![uniform_entry(0, wgpu::ShaderStages::COMPUTE),        // layout(set = 0, binding = 0) uniform camerauniform
+                     storage_entry(1, wgpu::ShaderStages::COMPUTE, false), // layout(set = 0, binding = 1) buffer Prefix_sums
+                     storage_entry(2, wgpu::ShaderStages::COMPUTE, false), // layout(set = 0, binding = 2) buffer Points_out
+                     storage_entry(3, wgpu::ShaderStages::COMPUTE, false), // layout(set = 0, binding = 3) buffer FMM_Nodes
+                     storage_entry(4, wgpu::ShaderStages::COMPUTE, false), // layout(set = 0, binding = 4) buffer FMM_Blocks
+                     storage_entry(5, wgpu::ShaderStages::COMPUTE, false), // layout(set = 0, binding = 5) buffer Counters
+                     storage_entry(6, wgpu::ShaderStages::COMPUTE, true),  // layout(set = 0, binding = 6) buffer OffsetTable
+                     storage_entry(7, wgpu::ShaderStages::COMPUTE, true),  // layout(set = 0, binding = 7) buffer VecToHashTable
+                     uniform_entry(8, wgpu::ShaderStages::COMPUTE)]        // layout(set = 0, binding = 8) uniform FMM_Attributes
+        ];
 
-        let mut pass = encoder.begin_compute_pass(
-            &wgpu::ComputePassDescriptor { label: None}
-        );
-        pass.set_pipeline(&self.pipeline);
-        for (e, bgs) in bind_groups.iter().enumerate() {
-            pass.set_bind_group(e as u32, &bgs, &[]);
+        Self {
+            kernel: ComputeKernel::init(&device,
+                                        unsafe { &device.create_shader_module_spirv(&comp_module) },
+                                        "main",
+                                        layout_entries,
+                                        [64, 4, 1],
+                                        Some("fmm_debug_pipeline")),
         }
-        pass.dispatch(x, y, z)
     }
+}
 
-    pub fn init(device: &wgpu::Device) -> Self {
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ActiveBlockParams {
+    global_dimensions: [u32; 3],
+    block_count: u32,
+}
 
-        let mut comp_module = wgpu::include_spirv_raw!("../../shaders/spirv/fmm.comp.spv");
+unsafe impl bytemuck::Zeroable for ActiveBlockParams {}
+unsafe impl bytemuck::Pod for ActiveBlockParams {}
+
+/// Finds the active fmm blocks (the blocks with BAND nodes) and writes a debug point of each BAND
+/// node of them (see fmm_active_blocks.wgsl). The active blocks are counted on the gpu and the
+/// count becomes the dispatch arguments of the second kernel, so there is no readback.
+pub struct FMM_active_blocks_pipeline {
+    find_kernel: ComputeKernel,
+    band_points_kernel: ComputeKernel,
+    bindings: ShaderBindings,
+    block_count: u32,
+    #[allow(dead_code)]
+    params: wgpu::Buffer,
+    counter: wgpu::Buffer,
+    dispatch_args: IndirectArgs,
+}
 
-        // GLSL, validation disabled.
-        //comp_module.flags = wgpu::ShaderFlags::empty();
+impl FMM_active_blocks_pipeline {
 
-        // Define all bind grout entries for pipeline and bind groups.
-        let layout_entries = vec![
-                // layout(set = 0, binding = 0) uniform Dimensions. 
-                vec![
-                    // layout(set=0, binding=0) uniform camerauniform
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                            //min_binding_size: wgpu::BufferSize::new(16),
-                            },
-                        count: None,
-                    },
-                    // layout(set = 0, binding = 1) buffer Prefix_sums
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None, // wgpu::BufferSize::new(temp_prefix_sum_size),
-                        },
-                        count: None,
-                    },
-                    // layout(set = 0, binding = 2) buffer Points_out
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None, //wgpu::BufferSize::new(3193724),
-                        },
-                        count: None,
-                    },
-                    // layout(set = 0, binding = 3) buffer FMM_Nodes
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None, //wgpu::BufferSize::new(fmm_nodes_size),
-                        },
-                        count: None,
-                    },
-                    // layout(set = 0, binding = 4) buffer FMM_Blocks
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 4,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None, //wgpu::BufferSize::new(fmm_blocks_size),
-                        },
-                        count: None,
-                    },
-                    // layout(set = 0, binding = 5) buffer Counters
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 5,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // layout(set = 0, binding = 6) buffer OffsetTable
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 6,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // layout(set = 0, binding = 7) buffer VecToHashTable
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 7,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // layout(set=0, binding=8) uniform FMM_Attributes
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 8,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                            //min_binding_size: wgpu::BufferSize::new(12),
-                            },
-                        count: None,
-                    },
-                ],
-        ];
-        let bind_group_layouts = create_bind_group_layouts(&device, &layout_entries);
+    pub fn init(device: &wgpu::Device, indirect_args: &IndirectArgsKernel, block_dimensions: [u32; 3]) -> Self {
 
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
-            push_constant_ranges: &[],
+        let source = include_str!("../../shaders_wgsl/fmm_active_blocks.wgsl");
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("fmm_active_blocks.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
         });
+        // Both kernels have the layout of all bindings, so they share the bind groups.
+        let bindings = ShaderBindings::from_wgsl(source).unwrap();
 
-        // Create the pipeline.
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("sphere_tracer_fmm_pipeline"),
-            layout: Some(&pipeline_layout),
-            module: unsafe { &device.create_shader_module_spirv(&comp_module) },
-            entry_point: "main",
-        });
+        let block_count = block_dimensions.iter().product::<u32>();
+        let params = buffer_from_data::<ActiveBlockParams>(
+            &device,
+            &[ActiveBlockParams { global_dimensions: block_dimensions, block_count: block_count }],
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            None);
+        let counter = buffer_from_data::<u32>(
+            &device,
+            &[0],
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            None);
+
+        // One workgroup per active block.
+        let dispatch_args = indirect_args.create_dispatch_args(&device, &counter, 0, 1);
 
         Self {
-            layout_entries, 
-            bind_group_layouts, 
-            pipeline,
+            find_kernel: ComputeKernel::from_bindings(&device, &module, "find_active_blocks", &bindings, Some("find_active_blocks")).unwrap(),
+            band_points_kernel: ComputeKernel::from_bindings(&device, &module, "band_points", &bindings, Some("band_points")).unwrap(),
+            bindings: bindings,
+            block_count: block_count,
+            params: params,
+            counter: counter,
+            dispatch_args: dispatch_args,
         }
     }
-}
-
-/// Struct for fmm_data_generato development version.
-pub struct FMM_data_generator_debug_pipeline {
-    layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>, 
-    bind_group_layouts: Vec<wgpu::BindGroupLayout>, 
-    pipeline: wgpu::ComputePipeline,
-}
-
-impl FMM_data_generator_debug_pipeline {
 
-    pub fn get_pipeline(&self) -> &wgpu::ComputePipeline {
-        &self.pipeline
+    /// The active blocks are written after the blocks of fmm_blocks and the debug points are
+    /// counted by the u32 at index 0 of point_counter.
+    pub fn create_bind_groups(&self,
+                              device: &wgpu::Device,
+                              fmm_nodes: &wgpu::Buffer,
+                              fmm_blocks: &wgpu::Buffer,
+                              point_counter: &wgpu::Buffer,
+                              output_points: &wgpu::Buffer) -> Result<Vec<wgpu::BindGroup>, String> {
+        let resources = self.bindings.resolve(
+            &[("params", self.params.as_entire_binding()),
+              ("fmm_nodes", fmm_nodes.as_entire_binding()),
+              ("fmm_blocks", fmm_blocks.as_entire_binding()),
+              ("active_block_counter", self.counter.as_entire_binding()),
+              ("point_counter", point_counter.as_entire_binding()),
+              ("output_points", output_points.as_entire_binding())]
+        )?;
+        self.find_kernel.create_bind_groups(&device, &resources)
     }
 
-    pub fn get_bind_group_layouts(&self) -> &Vec<wgpu::BindGroupLayout> {
-        &self.bind_group_layouts
+    /// Find the active blocks and process them. The active block counter starts from zero, so
+    /// dispatch this only once.
+    pub fn dispatch(&self, bind_groups: &Vec<wgpu::BindGroup>, indirect_args: &IndirectArgsKernel, encoder: &mut wgpu::CommandEncoder) {
+        self.find_kernel.dispatch_items(bind_groups, encoder, [self.block_count, 1, 1]);
+        indirect_args.write(&self.dispatch_args, encoder);
+        self.band_points_kernel.dispatch_indirect(bind_groups, encoder, self.dispatch_args.get_buffer(), 0);
     }
+}
 
-    pub fn get_bind_group_layout_entries(&self) -> &Vec<Vec<wgpu::BindGroupLayoutEntry>> {
-        &self.layout_entries
-    }
+/// Struct for fmm_data_generato development version.
+pub struct FMM_data_generator_debug_pipeline {
+    kernel: ComputeKernel,
+}
 
-    pub fn dispatch(&self, bind_groups: &Vec<wgpu::BindGroup>,
-                    encoder: &mut wgpu::CommandEncoder,
-                    x: u32, y: u32, z: u32) {
+impl_kernel_accessors!{FMM_data_generator_debug_pipeline}
 
-        let mut pass = encoder.begin_compute_pass(
-            &wgpu::ComputePassDescriptor { label: None}
-        );
-        pass.set_pipeline(&self.pipeline);
-        for (e, bgs) in bind_groups.iter().enumerate() {
-            pass.set_bind_group(e as u32, &bgs, &[]);
-        }
-        pass.dispatch(x, y, z)
-    }
+impl FMM_data_generator_debug_pipeline {
 
     pub fn init(device: &wgpu::Device) -> Self {
 
-        let mut comp_module = wgpu::include_spirv_raw!("../../shaders/spirv/fmm_data_generator.comp.spv");
+        let comp_module = wgpu::include_spirv_raw!("../../shaders/spirv/fmm_data_generator.comp.spv");
+        let workgroup_size = spirv_workgroup_size(include_bytes!("../../shaders/spirv/fmm_data_generator.comp.spv"), "main").unwrap();
 
-        // GLSL, validation disabled.
-        //comp_module.flags = wgpu::ShaderFlags::empty();
-
-        // Define all bind grout entries for pipeline and bind groups.
         let layout_entries = vec![
-                // layout(set=0, binding=0) uniform camerauniform {
-                vec![
-                    // layout(set=0, binding=0) uniform camerauniform {
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                            //min_binding_size: wgpu::BufferSize::new(16),
-                            },
-                        count: None,
-                    },
-                    //layout(set = 0, binding = 1) buffer Counters {
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // layout(set = 0, binding = 2) buffer Points_out {
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    //layout(set = 0, binding = 3) buffer FMM_Nodes
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    //++wgpu::BindGroupLayoutEntry {
-                    //++    binding: 4,
-                    //++    visibility: wgpu::ShaderStages::COMPUTE,
-                    //++    ty: wgpu::BindingType::Buffer {
-                    //++        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                    //++        has_dynamic_offset: false,
-                    //++        min_binding_size: None, //wgpu::BufferSize::new(fmm_blocks_size),
-                    //++    },
-                    //++    count: None,
-                    //++},
-                    //layout(set = 0, binding = 4) buffer Triangle_data
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 4,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None, //wgpu::BufferSize::new(fmm_blocks_size),
-                        },
-                        count: None,
-                    },
-                    //layout(set = 0, binding = 5) readonly buffer OffsetTable
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 5,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None, //wgpu::BufferSize::new(fmm_blocks_size),
-                        },
-                        count: None,
-                    },
-                    //layout(set = 0, binding = 6) readonly buffer VecToHashTable
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 6,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None, //wgpu::BufferSize::new(fmm_blocks_size),
-                        },
-                        count: None,
-                    },
-                    // layout(set = 0, binding = 5) uniform texture1D z1z2_texture;
-                    //++wgpu::BindGroupLayoutEntry {
-                    //++    binding: 5,
-                    //++    visibility: wgpu::ShaderStages::COMPUTE,
-                    //++    ty: wgpu::BindingType::StorageTexture {
-                    //++        access: wgpu::StorageTextureAccess::ReadOnly,
-                    //++        format: wgpu::TextureFormat::Rgba32Float,
-                    //++        view_dimension: wgpu::TextureViewDimension::D1,
-                    //++    },
-                    //++    count: None,
-                    //++},
-                    // layout(set=0, binding=8) uniform FMM_Attributes {
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 7,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                            //min_binding_size: wgpu::BufferSize::new(24),
-                            },
-                        count: None,
-                    },
-                    //layout(set=0, binding=8) uniform General_params {
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 8,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                            //min_binding_size: wgpu::BufferSize::new(24),
-                            },
-                        count: None,
-                    },
-                    // layout(set = 0, binding = 6) uniform sampler z1z2_texture_sampler;
-                    // wgpu::BindGroupLayoutEntry {
-                    //     binding: 6,
-                    //     visibility: wgpu::ShaderStages::COMPUTE,
-                    //     ty: wgpu::BindingType::Sampler {
-                    //         filtering: true,
-                    //         comparison: false,
-                    //     },
-                    //     count: None,
-                    // },
-                ],
+                vec![uniform_entry(0, wgpu::ShaderStages::COMPUTE),        // layout(set = 0, binding = 0) uniform camerauniform
+                     storage_entry(1, wgpu::ShaderStages::COMPUTE, false), // layout(set = 0, binding = 1) buffer Counters
+                     storage_entry(2, wgpu::ShaderStages::COMPUTE, false), // layout(set = 0, binding = 2) buffer Points_out
+                     storage_entry(3, wgpu::ShaderStages::COMPUTE, false), // layout(set = 0, binding = 3) buffer FMM_Nodes
+                     storage_entry(4, wgpu::ShaderStages::COMPUTE, false), // layout(set = 0, binding = 4) buffer Triangle_data
+                     storage_entry(5, wgpu::ShaderStages::COMPUTE, true),  // layout(set = 0, binding = 5) readonly buffer OffsetTable
+                     storage_entry(6, wgpu::ShaderStages::COMPUTE, true),  // layout(set = 0, binding = 6) readonly buffer VecToHashTable
+                     uniform_entry(7, wgpu::ShaderStages::COMPUTE),        // layout(set = 0, binding = 7) uniform FMM_Attributes
+                     uniform_entry(8, wgpu::ShaderStages::COMPUTE)]        // layout(set = 0, binding = 8) uniform General_params
         ];
-        let bind_group_layouts = create_bind_group_layouts(&device, &layout_entries);
-
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
-            push_constant_ranges: &[],
-        });
-
-        // Create the pipeline.
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("fmm_data_generator_debug_pipeline"),
-            layout: Some(&pipeline_layout),
-            module: unsafe { &device.create_shader_module_spirv(&comp_module) },
-            entry_point: "main",
-        });
 
         Self {
-            layout_entries, 
-            bind_group_layouts, 
-            pipeline,
+            kernel: ComputeKernel::init(&device,
+                                        unsafe { &device.create_shader_module_spirv(&comp_module) },
+                                        "main",
+                                        layout_entries,
+                                        workgroup_size,
+                                        Some("fmm_data_generator_debug_pipeline")),
         }
     }
 }
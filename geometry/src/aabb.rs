@@ -1,7 +1,5 @@
-use cgmath::{prelude::*, Vector3, Vector4};
-use bytemuck::{Pod, Zeroable};
-//use crate::misc::VertexType;
-//use cgmath::structure::InnerSpace;
+use cgmath::{prelude::*, Vector3, Vector4, Matrix4};
+use crate::triangle::Triangle;
 
 /**************************************************************************************/
 
@@ -30,18 +28,114 @@ unsafe impl bytemuck::Pod for BBox {}
 
 impl BBox {
 
-    // pub fn new() -> BBox {
-    //     let result = BBox { min: Vector::<f32>::new(0.0, 0.0, 0.0), max: Vector::<f32>::new(0.0, 0.0, 0.0), };
-    //     result
-    // }
-    
-    pub fn convert_aabb_to_aabb4(&self) -> BBox4 {
+    /// Create an empty bounding box. Expanding an empty bounding box with point p gives a
+    /// bounding box that includes only p.
+    pub fn empty() -> Self {
+        Self {
+            min: Vector3::<f32>::new(std::f32::MAX, std::f32::MAX, std::f32::MAX),
+            max: Vector3::<f32>::new(std::f32::MIN, std::f32::MIN, std::f32::MIN),
+        }
+    }
+
+    /// Check if the bounding box is empty.
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Convert to BBox4. The w components of min and max are min_w and max_w.
+    pub fn convert_aabb_to_aabb4(&self, min_w: f32, max_w: f32) -> BBox4 {
         BBox4 {
-            min: Vector4::<f32>::new(self.min.x, self.min.y, self.min.z, 2.5),
-            max: Vector4::<f32>::new(self.max.x, self.max.y, self.max.z, 4.5),
+            min: Vector4::<f32>::new(self.min.x, self.min.y, self.min.z, min_w),
+            max: Vector4::<f32>::new(self.max.x, self.max.y, self.max.z, max_w),
         }
     }
 
+    /// Create bounding box that includes all the points.
+    pub fn from_points<I: IntoIterator<Item = Vector3<f32>>>(points: I) -> Self {
+        let mut result = BBox::empty();
+        for p in points { result.expand(&p); }
+        result
+    }
+
+    /// Create bounding box that includes all the bounding boxes.
+    pub fn union<I: IntoIterator<Item = BBox>>(boxes: I) -> Self {
+        let mut result = BBox::empty();
+        for b in boxes {
+            if !b.is_empty() { result = BBox::combine(&result, &b); }
+        }
+        result
+    }
+
+    /// The intersection of two bounding boxes. Returns None if the bounding boxes don't overlap.
+    pub fn intersection(&self, other: &BBox) -> Option<BBox> {
+        let result = BBox {
+            min: max_vec(&self.min, &other.min),
+            max: min_vec(&self.max, &other.max),
+        };
+        if result.is_empty() { None }
+        else { Some(result) }
+    }
+
+    /// Check if two bounding boxes overlap. Touching bounding boxes overlap.
+    pub fn intersects(&self, other: &BBox) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    /// The center of the bounding box.
+    pub fn center(&self) -> Vector3<f32> {
+        0.5 * (self.min + self.max)
+    }
+
+    /// The extent (max - min) of the bounding box.
+    pub fn extent(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    /// The surface area of the bounding box.
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() { return 0.0; }
+        let e = self.extent();
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    /// The index (0 == x, 1 == y, 2 == z) of the longest axis of the bounding box. Ties go to
+    /// the first axis.
+    pub fn longest_axis(&self) -> usize {
+        let e = self.extent();
+        if e.x >= e.y && e.x >= e.z { 0 }
+        else if e.y >= e.z { 1 }
+        else { 2 }
+    }
+
+    /// The volume of the bounding box.
+    pub fn volume(&self) -> f32 {
+        if self.is_empty() { return 0.0; }
+        let e = self.extent();
+        e.x * e.y * e.z
+    }
+
+    /// The eight corners of the bounding box.
+    pub fn corners(&self) -> [Vector3<f32>; 8] {
+        [Vector3::<f32>::new(self.min.x, self.min.y, self.min.z),
+         Vector3::<f32>::new(self.max.x, self.min.y, self.min.z),
+         Vector3::<f32>::new(self.min.x, self.max.y, self.min.z),
+         Vector3::<f32>::new(self.max.x, self.max.y, self.min.z),
+         Vector3::<f32>::new(self.min.x, self.min.y, self.max.z),
+         Vector3::<f32>::new(self.max.x, self.min.y, self.max.z),
+         Vector3::<f32>::new(self.min.x, self.max.y, self.max.z),
+         Vector3::<f32>::new(self.max.x, self.max.y, self.max.z)]
+    }
+
+    /// Transform the bounding box by matrix m. The result is the bounding box of the transformed
+    /// corners.
+    pub fn transform(&self, m: &Matrix4<f32>) -> BBox {
+        if self.is_empty() { return *self; }
+        BBox::from_points(self.corners().iter().map(|c| {
+            let p = m * c.extend(1.0);
+            p.truncate() / p.w
+        }))
+    }
+
     /// Create bounding box from two vectors.
     pub fn create_from_line(a: &Vector3<f32>, b: &Vector3<f32>) -> Self {
 
@@ -106,7 +200,44 @@ impl BBox {
         }
     }
 
-    pub fn to_lines(&self) -> Vec<f32> {
+    /// Triangle-aabb overlap test using the separating axis theorem (Akenine-Möller). Touching
+    /// triangles overlap.
+    pub fn intersects_triangle(&self, triangle: &Triangle) -> bool {
+
+        let center = self.center();
+        let half = 0.5 * self.extent();
+
+        let v = [triangle.a - center, triangle.b - center, triangle.c - center];
+        let e = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+
+        // Returns true if the axis separates the triangle and the box.
+        let separates = |axis: Vector3<f32>| -> bool {
+            let p0 = axis.dot(v[0]);
+            let p1 = axis.dot(v[1]);
+            let p2 = axis.dot(v[2]);
+            let r = half.x * axis.x.abs() + half.y * axis.y.abs() + half.z * axis.z.abs();
+            p0.min(p1).min(p2) > r || p0.max(p1).max(p2) < -r
+        };
+
+        // The cross products of the box normals and the triangle edges.
+        let box_normals = [Vector3::<f32>::unit_x(), Vector3::<f32>::unit_y(), Vector3::<f32>::unit_z()];
+        for n in box_normals.iter() {
+            for edge in e.iter() {
+                if separates(n.cross(*edge)) { return false; }
+            }
+        }
+
+        // The box normals.
+        for n in box_normals.iter() {
+            if separates(*n) { return false; }
+        }
+
+        // The triangle normal.
+        !separates(e[0].cross(e[1]))
+    }
+
+    /// Create the edges of the bounding box as line list (24 vertices).
+    pub fn to_lines(&self) -> Vec<Vector3<f32>> {
 
         let dx = self.max.x - self.min.x;
         let dy = self.max.y - self.min.y;
@@ -121,354 +252,127 @@ impl BBox {
         let p6 = self.min + Vector3::<f32>::new(dx            , dy            , 0.0);
         let p7 = self.min + Vector3::<f32>::new(dx            , 0.0           , 0.0);
 
-        let mut result: Vec<f32> = Vec::new();
-
-        result.push(p0.x); result.push(p0.y); result.push(p0.z); result.push(1.0); 
-        result.push(p1.x); result.push(p1.y); result.push(p1.z); result.push(1.0); 
-
-        result.push(p1.x); result.push(p1.y); result.push(p1.z); result.push(1.0); 
-        result.push(p2.x); result.push(p2.y); result.push(p2.z); result.push(1.0); 
-
-        result.push(p2.x); result.push(p2.y); result.push(p2.z); result.push(1.0); 
-        result.push(p3.x); result.push(p3.y); result.push(p3.z); result.push(1.0); 
-
-        result.push(p0.x); result.push(p0.y); result.push(p0.z); result.push(1.0); 
-        result.push(p3.x); result.push(p3.y); result.push(p3.z); result.push(1.0); 
-
-        result.push(p4.x); result.push(p4.y); result.push(p4.z); result.push(1.0); 
-        result.push(p5.x); result.push(p5.y); result.push(p5.z); result.push(1.0); 
-
-        result.push(p5.x); result.push(p5.y); result.push(p5.z); result.push(1.0); 
-        result.push(p6.x); result.push(p6.y); result.push(p6.z); result.push(1.0); 
-
-        result.push(p6.x); result.push(p6.y); result.push(p6.z); result.push(1.0); 
-        result.push(p7.x); result.push(p7.y); result.push(p7.z); result.push(1.0); 
-
-        result.push(p4.x); result.push(p4.y); result.push(p4.z); result.push(1.0); 
-        result.push(p7.x); result.push(p7.y); result.push(p7.z); result.push(1.0); 
-
-        result.push(p1.x); result.push(p1.y); result.push(p1.z); result.push(1.0); 
-        result.push(p5.x); result.push(p5.y); result.push(p5.z); result.push(1.0); 
-
-        result.push(p2.x); result.push(p2.y); result.push(p2.z); result.push(1.0); 
-        result.push(p6.x); result.push(p6.y); result.push(p6.z); result.push(1.0); 
-
-        result.push(p0.x); result.push(p0.y); result.push(p0.z); result.push(1.0); 
-        result.push(p4.x); result.push(p4.y); result.push(p4.z); result.push(1.0); 
-
-        result.push(p3.x); result.push(p3.y); result.push(p3.z); result.push(1.0); 
-        result.push(p7.x); result.push(p7.y); result.push(p7.z); result.push(1.0); 
-
-        result
+        vec![p0, p1,
+             p1, p2,
+             p2, p3,
+             p0, p3,
+             p4, p5,
+             p5, p6,
+             p6, p7,
+             p4, p7,
+             p1, p5,
+             p2, p6,
+             p0, p4,
+             p3, p7]
     }
 }
 
-/**************************************************************************************/
-
-#[repr(C)]
-#[derive(Clone, Copy)]
-pub struct Triangle {
-    pub a: Vector3<f32>,
-    pub b: Vector3<f32>,
-    pub c: Vector3<f32>,
+/// Return min vector from a and b components.
+fn min_vec(a: &Vector3<f32>, b: &Vector3<f32>) -> Vector3<f32> {
+    let result = Vector3::<f32>::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+    result
 }
 
-#[repr(C)]
-#[derive(Clone, Copy, Debug)]
-pub struct Triangle_vvvvnnnn {
-    pub a: Vector4<f32>,
-    pub na: Vector4<f32>,
-    pub b: Vector4<f32>,
-    pub nb: Vector4<f32>,
-    pub c: Vector4<f32>,
-    pub nc: Vector4<f32>,
+/// Return max vector from a and b components.
+fn max_vec(a: &Vector3<f32>, b: &Vector3<f32>) -> Vector3<f32> {
+    let result = Vector3::<f32>::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+    result
 }
 
-unsafe impl Pod for Triangle_vvvvnnnn {}
-unsafe impl Zeroable for Triangle_vvvvnnnn {}
-
-impl Triangle {
-
-    pub fn closest_point_to_triangle(&self, p: &Vector3<f32>) -> Vector3<f32> {
-
-        let a = self.a;
-        let b = self.b;
-        let c = self.c;
-
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let ab = b - a;
-        let ac = c - a;
-        let bc = c - b;
-
-        // Surface normal ccw.
-        let normal = (b-a).cross(c-a).normalize(); //ac.cross(ab).normalize();
-
-        let snom = (p-a).dot(ab);
-        let sdenom = (p-b).dot(a-b);
-
-        let tnom = (p-a).dot(ac);
-        let tdenom = (p-c).dot(a-c);
-
-        if (snom <= 0.0 && tnom <= 0.0) {
-            let result  = a;
-            // let minus = normal.dot(result.normalize());
-            // if minus < 0.0 { return (result, false); }
-            // else { return (result, true); }
-            return result;
-        }
-
-        let unom = (p-b).dot(bc);
-        let udenom = (p-c).dot(b-c);
-
-        if (sdenom <= 0.0 && unom <= 0.0) {
-            let result = b;
-            // let minus = normal.dot(result.normalize());
-            // if minus < 0.0 { return (result, false); }
-            // else { return (result, true); }
-            return result;
-        }
-        if (tdenom <= 0.0 && udenom <= 0.0) {
-            let result = c;
-            // let minus = normal.dot(result.normalize());
-            // if minus < 0.0 { return (result, false); }
-            // else { return (result, true); }
-            return result;
-        }
-
-        let n = (b-a).cross(c-a);
-        let vc = n.dot((a-p).cross(b-p));
-        if vc <= 0.0 && snom >= 0.0 && sdenom >= 0.0 {
-            let result = a + snom / (snom + sdenom) * ab;
-            // let minus = normal.dot(result.normalize());
-            // if minus < 0.0 { return (result, false); }
-            // else { return (result, true); }
-            return result;
-        }
-
-        let va = n.dot((b-p).cross(c-p));
-        if va <= 0.0 && unom >= 0.0 && udenom >= 0.0 {
-            let result = b + unom / (unom + udenom) * bc;
-            // let minus = normal.dot(result.normalize());
-            // if minus < 0.0 { return (result, false); }
-            // else { return (result, true); }
-            return result;
-        }
-
-        let vb = n.dot((c-p).cross(a-p));
-        if vb <= 0.0 && tnom >= 0.0 && tdenom >= 0.0 {
-            let result = a + tnom / (tnom + tdenom) * ac;
-            // let minus = normal.dot(result.normalize());
-            // if minus < 0.0 { return (result, false); }
-            // else { return (result, true); }
-            return result;
-        }
-
-        let u = va / (va + vb + vc);
-        let v = vb / (va + vb + vc);
-        let w = 1.0 - u - v;
-        let result = u * a + v * b + w * c;
-        // let minus = normal.dot(result.normalize());
-        // if minus < 0.0 { return (result, false); }
-        // else { return (result, true); }
-        result
+    fn v(x: f32, y: f32, z: f32) -> Vector3<f32> {
+        Vector3::<f32>::new(x, y, z)
     }
 
-    pub fn distance_to_triangle(&self, p: &Vector3<f32>) -> (f32, bool) { // (distance, is_positive)
-        // TODO: solve sign of distance.
-        // Surface normal ccw.
-        //let normal = (self.b-self.a).cross(self.c-self.a).normalize(); //ac.cross(ab).normalize();
-        let normal = (self.b-self.a).cross(self.c-self.a).normalize(); //ac.cross(ab).normalize();
-        let point = self.closest_point_to_triangle(&p);
-        let dot_p = normal.dot(point - p);
-        let sign = { 
-            if dot_p < 0.0 { true } // CHECK THESE 
-            else { false }
-        };
-        //println!("SIGN == {}", sign);
-        (point.distance(*p), sign)
-
-        // let (result, sign) = self.closest_point_to_triangle(&p).distance(*p);
-        // if sign == false { -result }
-        // else { result }
+    fn bbox(min: Vector3<f32>, max: Vector3<f32>) -> BBox {
+        BBox { min: min, max: max }
     }
 
-    pub fn divide_triangle_to_points(&self, max_n: u32) -> Vec<Vector3<f32>> {
-
-        let epsilon: f32 = 0.3;
-
-        let mut points: Vec<Vector3<f32>> = Vec::new();
-        let ab = self.b - self.a; 
-        let ac = self.c - self.a;
-        let bc = self.c - self.b;
-        // let ab = self.a - self.b; 
-        // let ac = self.a - self.c;
-        // let bc = self.b - self.c;
-
-        let n = ab.cross(ac).normalize(); 
-        let s: f32 = 0.5 as f32 * ab.cross(ac).magnitude(); 
-        
-        let mut n: u32 = (s/epsilon).sqrt().ceil() as u32;
-
-        if n > max_n { n = max_n; } 
-
-        let s1 = 1.0 / (n as f32) * ab;
-        let s2 = 1.0 / (n as f32) * bc;
-        let s3 = 1.0 / (n as f32) * ac;
-
-        let mut ps = 1.0/3.0 * (self.a + ( self.a + s1) + (self.a + s3));
-        points.push(ps.clone());
-        let mut i = 2;
-
-        while i <= n {
-            ps = ps + s1;
-            points.push(ps.clone());
-            let mut p = ps.clone();
-            let mut j = 2;
-
-            while j <= i {
-                p = p + s2;
-                points.push(p.clone());
-                j += 1;
-            }
-            i += 1;
-        }
-
-        println!("Points");
-        for p in points.iter() {
-            println!("Vector {{ x: {}, y: {}, z: {} }}", p.x, p.y, p.z);
-        }
-
-        points
+    #[test]
+    fn empty_box() {
+        let empty = BBox::empty();
+        assert!(empty.is_empty());
+        assert_eq!(empty.surface_area(), 0.0);
+        assert_eq!(empty.volume(), 0.0);
+        assert!(!empty.includes_point(&v(0.0, 0.0, 0.0), true));
+        assert!(!empty.intersects(&bbox(v(-1.0, -1.0, -1.0), v(1.0, 1.0, 1.0))));
+        assert!(BBox::union(Vec::new()).is_empty());
+        assert!(BBox::from_points(Vec::new()).is_empty());
+
+        // Expanding an empty box gives the point.
+        let mut b = BBox::empty();
+        b.expand(&v(1.0, 2.0, 3.0));
+        assert!(!b.is_empty());
+        assert_eq!(b.min, v(1.0, 2.0, 3.0));
+        assert_eq!(b.max, v(1.0, 2.0, 3.0));
+        assert_eq!(b.volume(), 0.0);
     }
 
-//    pub fn to_f32_vec(&self, vt: &VertexType) -> Vec<f32> {
-//        let mut result: Vec<f32> = Vec::new();
-//        match vt {
-//            VertexType::vvv() => {
-//                result.push(self.a.x); result.push(self.a.y); result.push(self.a.z);  
-//                result.push(self.b.x); result.push(self.b.y); result.push(self.b.z);  
-//                result.push(self.c.x); result.push(self.c.y); result.push(self.c.z);  
-//            }
-//            VertexType::vvvv() => {
-//                result.push(self.a.x); result.push(self.a.y); result.push(self.a.z); result.push(1.0);  
-//                result.push(self.b.x); result.push(self.b.y); result.push(self.b.z); result.push(1.0);  
-//                result.push(self.c.x); result.push(self.c.y); result.push(self.c.z); result.push(1.0);  
-//            }
-//            VertexType::vvvnnn() => {
-//                let n = (self.b-self.a).cross(self.c-self.a).normalize();
-//                result.push(self.a.x); result.push(self.a.y); result.push(self.a.z); 
-//                result.push(n.x); result.push(n.y); result.push(n.z); 
-//                result.push(self.b.x); result.push(self.b.y); result.push(self.b.z); 
-//                result.push(n.x); result.push(n.y); result.push(n.z); 
-//                result.push(self.c.x); result.push(self.c.y); result.push(self.c.z); 
-//                result.push(n.x); result.push(n.y); result.push(n.z); 
-//            }
-//            VertexType::vvvvnnnn() => {
-//                let n = (self.b-self.a).cross(self.c-self.a).normalize();
-//                result.push(self.a.x); result.push(self.a.y); result.push(self.a.z); result.push(1.0);  
-//                result.push(n.x); result.push(n.y); result.push(n.z); result.push(0.0);  
-//                result.push(self.b.x); result.push(self.b.y); result.push(self.b.z); result.push(1.0);  
-//                result.push(n.x); result.push(n.y); result.push(n.z); result.push(0.0);  
-//                result.push(self.c.x); result.push(self.c.y); result.push(self.c.z); result.push(1.0);  
-//                result.push(n.x); result.push(n.y); result.push(n.z); result.push(0.0);  
-//            }
-//        }
-//        result
-//    }
-
-
-    //pub fn create_sample_points(&self, h: f32) -> Vec<Vector3<f32>> {
-
-    //    
-
-    //    let v0 = self.b - self.a;
-    //    let v1 = self.c - self.a;
-    //    let v2 = p - a;
-    //    let d00 = v0.dot(v0);
-    //    let d01 = v0.dot(v1);
-    //    let d11 = v1.dot(v1);
-    //    let d20 = v2.dot(v0);
-    //    let d21 = v2.dot(v1);
-    //    let denom = d00 * d11 - d01 * d01;
-    //    let v = (d11 * d20 - d01 * d21) / denom;
-    //    let w = (d00 * d21 - d01 * d20) / denom;
-    //    let u = 1.0 - v - w;
-    //    let result = Vector3::<f32>::new(u,v,w);
-    //    result
-    //    }
-}
-
-unsafe impl Pod for Triangle {}
-unsafe impl Zeroable for Triangle {}
-
-#[derive(Clone, Copy)]
-pub struct Plane {
-    n: Vector3<f32>,    
-    d: f32,    
-}
-
-impl Plane {
-    pub fn new(a: &Vector3<f32>, b: &Vector3<f32>, c: &Vector3<f32>) -> Self {
-        let n = (b-a).cross(c-a).normalize();
-        let d = n.dot(*a);
-        Self {
-            n: n,
-            d: d,
-        }
+    #[test]
+    fn union_and_intersection() {
+        let a = bbox(v(0.0, 0.0, 0.0), v(1.0, 1.0, 1.0));
+        let b = bbox(v(0.5, -1.0, 0.5), v(2.0, 0.5, 3.0));
+        let u = BBox::union(vec![a, BBox::empty(), b]);
+        assert_eq!(u.min, v(0.0, -1.0, 0.0));
+        assert_eq!(u.max, v(2.0, 1.0, 3.0));
+
+        let i = a.intersection(&b).unwrap();
+        assert_eq!(i.min, v(0.5, 0.0, 0.5));
+        assert_eq!(i.max, v(1.0, 0.5, 1.0));
+
+        // Touching boxes intersect, separate boxes don't.
+        assert!(a.intersects(&bbox(v(1.0, 0.0, 0.0), v(2.0, 1.0, 1.0))));
+        assert!(a.intersection(&bbox(v(1.5, 0.0, 0.0), v(2.0, 1.0, 1.0))).is_none());
     }
 
-    pub fn closest_point_to_plane(self, q: &Vector3<f32>) -> Vector3<f32> {
-        let t = (self.n.dot(*q) - self.d) / self.n.dot(self.n);
-        q - t * self.n
+    #[test]
+    fn contains_point() {
+        let b = bbox(v(0.0, 0.0, 0.0), v(1.0, 2.0, 3.0));
+        assert!(b.includes_point(&v(0.5, 1.0, 1.5), false));
+        assert!(b.includes_point(&v(0.5, 1.0, 1.5), true));
+        assert!(!b.includes_point(&v(1.5, 1.0, 1.5), true));
+
+        // The boundary is included only if on_boundary is true.
+        assert!(b.includes_point(&v(1.0, 1.0, 1.5), true));
+        assert!(!b.includes_point(&v(1.0, 1.0, 1.5), false));
+        assert!(b.includes_point(&v(0.0, 0.0, 0.0), true));
     }
-}
 
+    #[test]
+    fn measures() {
+        let b = bbox(v(-1.0, 0.0, 1.0), v(1.0, 3.0, 5.0));
+        assert_eq!(b.extent(), v(2.0, 3.0, 4.0));
+        assert_eq!(b.center(), v(0.0, 1.5, 3.0));
+        assert_eq!(b.surface_area(), 2.0 * (6.0 + 12.0 + 8.0));
+        assert_eq!(b.volume(), 24.0);
+
+        // A flat box has an area but no volume.
+        let flat = bbox(v(0.0, 0.0, 0.0), v(2.0, 3.0, 0.0));
+        assert_eq!(flat.surface_area(), 12.0);
+        assert_eq!(flat.volume(), 0.0);
+    }
 
-/// Return min vector from a and b components.
-fn min_vec(a: &Vector3<f32>, b: &Vector3<f32>) -> Vector3<f32> {
-    let result = Vector3::<f32>::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
-    result
-}
+    #[test]
+    fn longest_axis() {
+        assert_eq!(bbox(v(0.0, 0.0, 0.0), v(3.0, 1.0, 2.0)).longest_axis(), 0);
+        assert_eq!(bbox(v(0.0, -2.0, 0.0), v(1.0, 2.0, 2.0)).longest_axis(), 1);
+        assert_eq!(bbox(v(0.0, 0.0, -5.0), v(1.0, 1.0, 0.0)).longest_axis(), 2);
+        assert_eq!(bbox(v(0.0, 0.0, 0.0), v(1.0, 1.0, 1.0)).longest_axis(), 0);
+        assert_eq!(bbox(v(0.0, 0.0, 0.0), v(1.0, 2.0, 2.0)).longest_axis(), 1);
+    }
 
-/// Return max vector from a and b components.
-fn max_vec(a: &Vector3<f32>, b: &Vector3<f32>) -> Vector3<f32> {
-    let result = Vector3::<f32>::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
-    result
-}
+    #[test]
+    fn transform() {
+        let b = bbox(v(0.0, 0.0, 0.0), v(1.0, 2.0, 3.0));
+        let moved = b.transform(&Matrix4::from_translation(v(1.0, -1.0, 0.5)));
+        assert_eq!(moved.min, v(1.0, -1.0, 0.5));
+        assert_eq!(moved.max, v(2.0, 1.0, 3.5));
 
-pub fn barycentric_cooordinates(a: &Vector3<f32>, b: &Vector3<f32>, c: &Vector3<f32>, r: &Vector3<f32>) -> Vector3<f32> {
-    let n = (b - a).cross(c - a);
-    let rab = n.dot((a-r).cross(b-r));
-    let rbc = n.dot((b-r).cross(c-r));
-    let rca = n.dot((c-r).cross(a-r));
-    let abc = rab + rbc + rca;
-    let u = rbc/abc;
-    let v = rca/abc;
-    let w = rab/abc;
-    let result = Vector3::<f32>::new(u,v,w);
-    result
+        // A rotation of 90 degrees around z swaps the x and y extents.
+        let rotated = b.transform(&Matrix4::from_angle_z(cgmath::Deg(90.0)));
+        assert!((rotated.extent() - v(2.0, 1.0, 3.0)).magnitude() < 1e-5, "{:?}", rotated.extent());
+    }
 }
-
-// /// Compute barycentric coordinates (u, v, w) for point p with respect to triangle (a, b, c)
-// pub fn barycentric_cooordinates(a: &Vector3<f32>, b: &Vector3<f32>, c: &Vector3<f32>, p: &Vector3<f32>) -> Vector3<f32> {
-//     let v0 = b - a;
-//     let v1 = c - a;
-//     let v2 = p - a;
-//     let d00 = v0.dot(v0);
-//     let d01 = v0.dot(v1);
-//     let d11 = v1.dot(v1);
-//     let d20 = v2.dot(v0);
-//     let d21 = v2.dot(v1);
-//     let denom = d00 * d11 - d01 * d01;
-//     let v = (d11 * d20 - d01 * d21) / denom;
-//     let w = (d00 * d21 - d01 * d20) / denom;
-//     let u = 1.0 - v - w;
-//     let result = Vector3::<f32>::new(u,v,w);
-//     result
-// }
-
-// pub struct Ray {
-//     origin: Vector3<f32>,
-//     dir: Vector3<f32>,
-// }
-
@@ -0,0 +1,325 @@
+use cgmath::Vector3;
+use crate::aabb::BBox;
+use crate::triangle::Triangle;
+use crate::ray::Ray;
+
+/// The number of bins used when searching the split plane.
+const SAH_BIN_COUNT: usize = 12;
+
+/// The maximum number of triangles in a leaf.
+const MAX_LEAF_SIZE: usize = 4;
+
+/// A node of the bvh. A leaf has count > 0 and the triangles are
+/// triangle_indices[first..first+count]. An inner node has count == 0 and the children are
+/// nodes[first] and nodes[first+1].
+#[derive(Clone, Copy, Debug)]
+struct BvhNode {
+    aabb: BBox,
+    first: u32,
+    count: u32,
+}
+
+/// The closest hit of a ray.
+/// triangle_id: the index of the triangle in the triangle list of Bvh::build.
+/// barycentric: the barycentric coordinates (u, v, w) of the hit point with respect to (a, b, c).
+#[derive(Clone, Copy, Debug)]
+pub struct BvhHit {
+    pub t: f32,
+    pub triangle_id: u32,
+    pub barycentric: Vector3<f32>,
+    pub position: Vector3<f32>,
+}
+
+/// A bounding volume hierarchy over a triangle list. Built with binned surface area heuristic.
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<Triangle>,
+    triangle_indices: Vec<u32>,
+}
+
+impl Bvh {
+
+    /// Build the bvh. The triangles are copied.
+    pub fn build(triangles: &[Triangle]) -> Self {
+
+        let mut result = Self {
+            nodes: Vec::with_capacity(2 * triangles.len()),
+            triangles: triangles.to_vec(),
+            triangle_indices: (0..triangles.len() as u32).collect(),
+        };
+
+        if triangles.is_empty() { return result; }
+
+        let aabbs: Vec<BBox> = triangles.iter().map(|t| BBox::create_from_triangle(&t.a, &t.b, &t.c)).collect();
+        let centers: Vec<Vector3<f32>> = aabbs.iter().map(|b| b.center()).collect();
+
+        result.nodes.push(BvhNode { aabb: BBox::union(aabbs.iter().cloned()), first: 0, count: triangles.len() as u32 });
+        result.subdivide(0, &aabbs, &centers);
+        result
+    }
+
+    /// The number of triangles.
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// The aabb of all triangles.
+    pub fn get_aabb(&self) -> BBox {
+        match self.nodes.first() {
+            Some(node) => node.aabb,
+            None => BBox::empty(),
+        }
+    }
+
+    /// Find the closest triangle that the ray hits between r_min and r_max.
+    pub fn intersect(&self, ray: &Ray) -> Option<BvhHit> {
+
+        if self.nodes.is_empty() { return None; }
+
+        let mut ray = *ray;
+        let mut closest: Option<BvhHit> = None;
+        let mut stack: Vec<u32> = vec![0];
+
+        while let Some(index) = stack.pop() {
+
+            let node = &self.nodes[index as usize];
+            if ray.intersect_aabb(&node.aabb).is_none() { continue; }
+
+            if node.count > 0 {
+                for i in node.first..node.first + node.count {
+                    let triangle_id = self.triangle_indices[i as usize];
+                    if let Some((t, barycentric)) = ray.intersect_triangle_watertight(&self.triangles[triangle_id as usize]) {
+                        ray.r_max = t;
+                        closest = Some(BvhHit {
+                            t: t,
+                            triangle_id: triangle_id,
+                            barycentric: barycentric,
+                            position: ray.point_at(t),
+                        });
+                    }
+                }
+                continue;
+            }
+
+            // Visit the nearer child first.
+            let left = node.first;
+            let right = node.first + 1;
+            let t_left = ray.intersect_aabb(&self.nodes[left as usize].aabb).map(|(t, _)| t);
+            let t_right = ray.intersect_aabb(&self.nodes[right as usize].aabb).map(|(t, _)| t);
+            match (t_left, t_right) {
+                (Some(a), Some(b)) if a <= b => { stack.push(right); stack.push(left); }
+                (Some(_), Some(_)) => { stack.push(left); stack.push(right); }
+                (Some(_), None) => stack.push(left),
+                (None, Some(_)) => stack.push(right),
+                (None, None) => {}
+            }
+        }
+
+        closest
+    }
+
+    /// Split the node recursively until the leaves are small or splitting doesn't pay off.
+    fn subdivide(&mut self, node_index: usize, aabbs: &[BBox], centers: &[Vector3<f32>]) {
+
+        let node = self.nodes[node_index];
+        if node.count as usize <= MAX_LEAF_SIZE { return; }
+
+        let first = node.first as usize;
+        let count = node.count as usize;
+
+        let (axis, split) = match self.find_split(&node, aabbs, centers) {
+            Some(s) => s,
+            None => return,
+        };
+
+        // Partition the triangles by the split plane.
+        let mut i = first;
+        let mut j = first + count;
+        while i < j {
+            if centers[self.triangle_indices[i] as usize][axis] < split { i += 1; }
+            else {
+                j -= 1;
+                self.triangle_indices.swap(i, j);
+            }
+        }
+
+        let left_count = i - first;
+        if left_count == 0 || left_count == count { return; }
+
+        let child_aabb = |indices: &[u32]| -> BBox { BBox::union(indices.iter().map(|k| aabbs[*k as usize])) };
+        let left_aabb = child_aabb(&self.triangle_indices[first..i]);
+        let right_aabb = child_aabb(&self.triangle_indices[i..first + count]);
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode { aabb: left_aabb, first: first as u32, count: left_count as u32 });
+        self.nodes.push(BvhNode { aabb: right_aabb, first: i as u32, count: (count - left_count) as u32 });
+        self.nodes[node_index].first = left as u32;
+        self.nodes[node_index].count = 0;
+
+        self.subdivide(left, aabbs, centers);
+        self.subdivide(left + 1, aabbs, centers);
+    }
+
+    /// Find the axis and the position of the split plane with the lowest surface area cost.
+    /// Returns None if no split is cheaper than the leaf.
+    fn find_split(&self, node: &BvhNode, aabbs: &[BBox], centers: &[Vector3<f32>]) -> Option<(usize, f32)> {
+
+        let indices = &self.triangle_indices[node.first as usize..(node.first + node.count) as usize];
+        let center_aabb = BBox::from_points(indices.iter().map(|i| centers[*i as usize]));
+
+        let leaf_cost = node.count as f32 * node.aabb.surface_area();
+        let mut best: Option<(usize, f32, f32)> = None;
+
+        for axis in 0..3 {
+
+            let min = center_aabb.min[axis];
+            let max = center_aabb.max[axis];
+            if max <= min { continue; }
+
+            let scale = SAH_BIN_COUNT as f32 / (max - min);
+            let mut bin_aabbs = [BBox::empty(); SAH_BIN_COUNT];
+            let mut bin_counts = [0u32; SAH_BIN_COUNT];
+
+            for i in indices.iter() {
+                let bin = (((centers[*i as usize][axis] - min) * scale) as usize).min(SAH_BIN_COUNT - 1);
+                bin_counts[bin] += 1;
+                bin_aabbs[bin] = BBox::union(vec![bin_aabbs[bin], aabbs[*i as usize]]);
+            }
+
+            // The cost of splitting between bins b and b+1.
+            for b in 0..SAH_BIN_COUNT - 1 {
+                let left_count: u32 = bin_counts[..=b].iter().sum();
+                let right_count: u32 = bin_counts[b + 1..].iter().sum();
+                if left_count == 0 || right_count == 0 { continue; }
+
+                let left_area = BBox::union(bin_aabbs[..=b].iter().cloned()).surface_area();
+                let right_area = BBox::union(bin_aabbs[b + 1..].iter().cloned()).surface_area();
+                let cost = left_count as f32 * left_area + right_count as f32 * right_area;
+
+                if best.map_or(true, |(_, _, c)| cost < c) {
+                    best = Some((axis, min + (b + 1) as f32 / scale, cost));
+                }
+            }
+        }
+
+        match best {
+            Some((axis, split, cost)) if cost < leaf_cost => Some((axis, split)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::prelude::*;
+
+    /// A deterministic pseudo random number generator for the test data.
+    struct Lcg(u64);
+
+    impl Lcg {
+        /// A number in [min, max[.
+        fn next(&mut self, min: f32, max: f32) -> f32 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            min + (max - min) * ((self.0 >> 40) as f32 / (1u64 << 24) as f32)
+        }
+
+        fn vector(&mut self, min: f32, max: f32) -> Vector3<f32> {
+            Vector3::<f32>::new(self.next(min, max), self.next(min, max), self.next(min, max))
+        }
+    }
+
+    /// Small random triangles in the cube [-10, 10]^3.
+    fn triangle_soup(rng: &mut Lcg, count: usize) -> Vec<Triangle> {
+        (0..count).map(|_| {
+            let a = rng.vector(-10.0, 10.0);
+            Triangle { a: a, b: a + rng.vector(-2.0, 2.0), c: a + rng.vector(-2.0, 2.0) }
+        }).collect()
+    }
+
+    /// The closest hit by testing all triangles.
+    fn brute_force(triangles: &[Triangle], ray: &Ray) -> Option<(f32, u32)> {
+        let mut closest: Option<(f32, u32)> = None;
+        for (i, triangle) in triangles.iter().enumerate() {
+            if let Some((t, _)) = ray.intersect_triangle_watertight(triangle) {
+                if closest.map_or(true, |(c, _)| t < c) { closest = Some((t, i as u32)); }
+            }
+        }
+        closest
+    }
+
+    fn assert_same_hit(bvh: &Bvh, triangles: &[Triangle], ray: &Ray) {
+        match (bvh.intersect(ray), brute_force(triangles, ray)) {
+            (None, None) => {}
+            (Some(hit), Some((t, triangle_id))) => {
+                assert_eq!(hit.t, t, "The distance differs for {:?}.", ray);
+                // Triangles hit at the same distance are equally good.
+                if hit.triangle_id != triangle_id {
+                    let other = ray.intersect_triangle_watertight(&triangles[hit.triangle_id as usize]);
+                    assert_eq!(other.map(|(t, _)| t), Some(t));
+                }
+                assert!((hit.position - ray.point_at(t)).magnitude() < 1e-4);
+                let b = hit.barycentric;
+                let triangle = &triangles[hit.triangle_id as usize];
+                assert!((b.x * triangle.a + b.y * triangle.b + b.z * triangle.c - hit.position).magnitude() < 1e-3);
+            }
+            (bvh_hit, expected) => panic!("{:?}: bvh {:?}, brute force {:?}.", ray, bvh_hit.map(|h| (h.t, h.triangle_id)), expected),
+        }
+    }
+
+    #[test]
+    fn intersect_matches_brute_force() {
+        let mut rng = Lcg(7);
+        let triangles = triangle_soup(&mut rng, 500);
+        let bvh = Bvh::build(&triangles);
+        assert_eq!(bvh.triangle_count(), 500);
+
+        let mut hits = 0;
+        for _ in 0..500 {
+            // Rays from outside the soup towards it and rays from inside in random directions.
+            let target = rng.vector(-10.0, 10.0);
+            let outside = Ray::from_points(&rng.vector(-30.0, 30.0), &target);
+            let inside = Ray::new(&target, &rng.vector(-1.0, 1.0));
+            for ray in [outside, inside].iter() {
+                assert_same_hit(&bvh, &triangles, ray);
+                if bvh.intersect(ray).is_some() { hits += 1; }
+            }
+        }
+        assert!(hits > 100, "Only {} rays hit the triangles.", hits);
+    }
+
+    #[test]
+    fn intersect_axis_parallel_rays() {
+        let mut rng = Lcg(11);
+        let triangles = triangle_soup(&mut rng, 300);
+        let bvh = Bvh::build(&triangles);
+
+        let axes = [Vector3::<f32>::unit_x(), Vector3::<f32>::unit_y(), Vector3::<f32>::unit_z()];
+        for _ in 0..200 {
+            let p = rng.vector(-10.0, 10.0);
+            for axis in axes.iter() {
+                for sign in [-1.0f32, 1.0].iter() {
+                    let ray = Ray::new(&(p - *sign * 20.0 * axis), &(*sign * axis));
+                    assert_same_hit(&bvh, &triangles, &ray);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn intersect_respects_the_ray_range() {
+        let triangles = vec![
+            Triangle { a: Vector3::new(-1.0, -1.0, 2.0), b: Vector3::new(1.0, -1.0, 2.0), c: Vector3::new(0.0, 1.0, 2.0) },
+            Triangle { a: Vector3::new(-1.0, -1.0, 5.0), b: Vector3::new(1.0, -1.0, 5.0), c: Vector3::new(0.0, 1.0, 5.0) },
+        ];
+        let bvh = Bvh::build(&triangles);
+        let mut ray = Ray::new(&Vector3::new(0.0, 0.0, 0.0), &Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(bvh.intersect(&ray).map(|h| h.triangle_id), Some(0));
+        ray.r_min = 3.0;
+        assert_eq!(bvh.intersect(&ray).map(|h| h.triangle_id), Some(1));
+        ray.r_max = 4.0;
+        assert!(bvh.intersect(&ray).is_none());
+
+        assert!(Bvh::build(&[]).intersect(&ray).is_none());
+    }
+}
@@ -0,0 +1,81 @@
+use cgmath::{prelude::*, Vector3};
+
+/// A point on the quadratic Bezier curve (c0, c1, c2) at t in [0, 1].
+pub fn quadratic_bezier(t: f32, c0: Vector3<f32>, c1: Vector3<f32>, c2: Vector3<f32>) -> Vector3<f32> {
+    let mt = 1.0 - t;
+    c0 * mt * mt + c1 * 2.0 * mt * t + c2 * t * t
+}
+
+/// A point on the cubic Bezier curve (c0, c1, c2, c3) at t in [0, 1].
+pub fn cubic_bezier(t: f32, c0: Vector3<f32>, c1: Vector3<f32>, c2: Vector3<f32>, c3: Vector3<f32>) -> Vector3<f32> {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    let mt = 1.0 - t;
+    let mt2 = mt * mt;
+    let mt3 = mt2 * mt;
+    c0 * mt3 + c1 * 3.0 * mt2 * t + c2 * 3.0 * mt * t2 + c3 * t3
+}
+
+/// A point on the uniform Catmull-Rom segment between p1 and p2 at t in [0, 1]. p0 and p3 are the
+/// neighboring points. The segment is evaluated as a cubic Bezier curve.
+pub fn catmull_rom(t: f32, p0: Vector3<f32>, p1: Vector3<f32>, p2: Vector3<f32>, p3: Vector3<f32>) -> Vector3<f32> {
+    cubic_bezier(t,
+                 p1,
+                 p1 + (p2 - p0) / 6.0,
+                 p2 - (p3 - p1) / 6.0,
+                 p2)
+}
+
+/// Sample n points from the quadratic Bezier curve (c0, c1, c2). Includes both end points.
+pub fn bezier_1c(n: u32,
+                c0: Vector3<f32>,
+                c1: Vector3<f32>,
+                c2: Vector3<f32>
+                ) -> Vec<Vector3<f32>> {
+    assert!(n > 1, "n < 2");
+    let mut result: Vec<Vector3<f32>> = Vec::new();
+    for i in 0..n {
+        let t = i as f32 / ((n-1) as f32);
+        result.push(quadratic_bezier(t, c0, c1, c2));
+    }
+    result
+}
+
+/// Sample n points from the line segment (c0, c1). Doesn't include c1.
+pub fn line(n: u32,
+            c0: Vector3<f32>,
+            c1: Vector3<f32>,
+            ) -> Vec<Vector3<f32>> {
+    assert!(n > 0, "n == 0");
+    let mut result: Vec<Vector3<f32>> = Vec::new();
+    for i in 0..n {
+
+        let t = i as f32 / (n as f32);
+
+        let dist = c1.distance(c0);
+        let norm = (c1 - c0).normalize();
+
+        let point = c0 + norm * (dist * t);
+
+        result.push(point);
+    }
+    result
+}
+
+/// Sample n points from the cubic Bezier curve (c0, c1, c2, c3). Doesn't include c3.
+// (1.0, 1.0, 1,0) , (23.0, 23.0, 23.0, 5.0) , (1.0, 1.0, 15.0) , (23.0, 23.0, 20.0)
+#[allow(non_snake_case)]
+pub fn bezier3D(n: u32,
+                c0: Vector3<f32>,
+                c1: Vector3<f32>,
+                c2: Vector3<f32>,
+                c3: Vector3<f32>,
+                ) -> Vec<Vector3<f32>> {
+    assert!(n > 0, "n == 0");
+    let mut result: Vec<Vector3<f32>> = Vec::new();
+    for i in 0..n {
+        let t = i as f32 / (n as f32);
+        result.push(cubic_bezier(t, c0, c1, c2, c3));
+    }
+    result
+}
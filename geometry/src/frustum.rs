@@ -0,0 +1,190 @@
+use cgmath::{prelude::*, Vector3, Vector4, Matrix4};
+use crate::aabb::BBox;
+use crate::triangle::Plane;
+
+/// A view frustum. The normals of the planes point inside the frustum.
+/// The planes are in order left, right, bottom, top, near, far.
+#[derive(Clone, Copy)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+
+    /// Extract the frustum planes from a view projection matrix (Gribb and Hartmann). The depth
+    /// range of the clip space must be [0, 1] (wgpu). Works also for reversed depth. If the
+    /// projection has no far plane, the far plane accepts all points.
+    pub fn from_matrix(m: &Matrix4<f32>) -> Self {
+
+        let row = |i: usize| -> Vector4<f32> { Vector4::<f32>::new(m.x[i], m.y[i], m.z[i], m.w[i]) };
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+
+        // The plane a*x + b*y + c*z + w >= 0.
+        let plane = |v: Vector4<f32>| -> Plane {
+            let n = v.truncate();
+            let length = n.magnitude();
+            if length > 0.0 { Plane { n: n / length, d: -v.w / length } }
+            else { Plane { n: n, d: -v.w } }
+        };
+
+        Self {
+            planes: [plane(r3 + r0),
+                     plane(r3 - r0),
+                     plane(r3 + r1),
+                     plane(r3 - r1),
+                     plane(r2),
+                     plane(r3 - r2)],
+        }
+    }
+
+    /// Check if point p is inside the frustum.
+    pub fn contains_point(&self, p: &Vector3<f32>) -> bool {
+        self.planes.iter().all(|plane| plane.n.dot(*p) - plane.d >= 0.0)
+    }
+
+    /// Check if the sphere is inside or intersects the frustum. May return true for some spheres
+    /// near the corners of the frustum.
+    pub fn intersects_sphere(&self, center: &Vector3<f32>, radius: f32) -> bool {
+        self.planes.iter().all(|plane| plane.n.dot(*center) - plane.d >= -radius)
+    }
+
+    /// Check if the aabb is inside or intersects the frustum. May return true for some aabbs near
+    /// the corners of the frustum.
+    pub fn intersects_aabb(&self, aabb: &BBox) -> bool {
+        // The corner that is farthest along the plane normal must be inside.
+        self.planes.iter().all(|plane| plane.n.dot(positive_vertex(aabb, &plane.n)) - plane.d >= 0.0)
+    }
+
+    /// Check if the aabb is completely inside the frustum.
+    pub fn contains_aabb(&self, aabb: &BBox) -> bool {
+        // The corner that is nearest along the plane normal must be inside.
+        self.planes.iter().all(|plane| plane.n.dot(positive_vertex(aabb, &(-plane.n))) - plane.d >= 0.0)
+    }
+
+    /// Returns the indices of the aabbs that are inside or intersect the frustum.
+    pub fn cull_aabbs(&self, aabbs: &[BBox]) -> Vec<u32> {
+        aabbs.iter()
+             .enumerate()
+             .filter(|(_, aabb)| self.intersects_aabb(aabb))
+             .map(|(i, _)| i as u32)
+             .collect()
+    }
+
+    /// The planes as (n, d) vectors for shaders. The point p is inside the plane if
+    /// dot(n, p) - d >= 0.
+    pub fn to_vec4(&self) -> [Vector4<f32>; 6] {
+        let v = |p: &Plane| -> Vector4<f32> { p.n.extend(p.d) };
+        [v(&self.planes[0]), v(&self.planes[1]), v(&self.planes[2]),
+         v(&self.planes[3]), v(&self.planes[4]), v(&self.planes[5])]
+    }
+}
+
+/// The corner of the aabb that is farthest in direction n.
+fn positive_vertex(aabb: &BBox, n: &Vector3<f32>) -> Vector3<f32> {
+    Vector3::<f32>::new(if n.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                        if n.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                        if n.z >= 0.0 { aabb.max.z } else { aabb.min.z })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3<f32> {
+        Vector3::<f32>::new(x, y, z)
+    }
+
+    /// An orthographic view projection of the box x in [-2, 2], y in [-1, 1], z in [-10, -1]
+    /// with the wgpu depth range.
+    #[rustfmt::skip]
+    fn box_matrix() -> Matrix4<f32> {
+        Matrix4::new(
+            0.5, 0.0, 0.0,         0.0,
+            0.0, 1.0, 0.0,         0.0,
+            0.0, 0.0, -1.0 / 9.0,  0.0,
+            0.0, 0.0, -1.0 / 9.0,  1.0,
+        )
+    }
+
+    /// A perspective view projection with 90 degrees field of view, aspect 1, near 1 and far 10
+    /// looking along -z with the wgpu depth range.
+    fn perspective_matrix() -> Matrix4<f32> {
+        #[rustfmt::skip]
+        let opengl_to_wgpu = Matrix4::new(
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 0.5, 0.0,
+            0.0, 0.0, 0.5, 1.0,
+        );
+        opengl_to_wgpu * cgmath::perspective(cgmath::Deg(90.0), 1.0, 1.0, 10.0)
+    }
+
+    fn assert_plane(plane: &Plane, n: Vector3<f32>, d: f32) {
+        assert!((plane.n - n).magnitude() < 1e-5 && (plane.d - d).abs() < 1e-5,
+                "plane ({:?}, {}) != ({:?}, {})", plane.n, plane.d, n, d);
+    }
+
+    #[test]
+    fn plane_extraction() {
+        let frustum = Frustum::from_matrix(&box_matrix());
+        assert_plane(&frustum.planes[0], v(1.0, 0.0, 0.0), -2.0);
+        assert_plane(&frustum.planes[1], v(-1.0, 0.0, 0.0), -2.0);
+        assert_plane(&frustum.planes[2], v(0.0, 1.0, 0.0), -1.0);
+        assert_plane(&frustum.planes[3], v(0.0, -1.0, 0.0), -1.0);
+        assert_plane(&frustum.planes[4], v(0.0, 0.0, -1.0), 1.0);
+        assert_plane(&frustum.planes[5], v(0.0, 0.0, 1.0), -10.0);
+
+        // The side planes of the perspective frustum are at 45 degrees.
+        let frustum = Frustum::from_matrix(&perspective_matrix());
+        let s = 0.5_f32.sqrt();
+        assert_plane(&frustum.planes[0], v(s, 0.0, -s), 0.0);
+        assert_plane(&frustum.planes[3], v(0.0, -s, -s), 0.0);
+        assert_plane(&frustum.planes[4], v(0.0, 0.0, -1.0), 1.0);
+        assert_plane(&frustum.planes[5], v(0.0, 0.0, 1.0), -10.0);
+    }
+
+    #[test]
+    fn points() {
+        let frustum = Frustum::from_matrix(&perspective_matrix());
+        assert!(frustum.contains_point(&v(0.0, 0.0, -5.0)));
+        assert!(frustum.contains_point(&v(4.9, -4.9, -5.0)));
+        assert!(!frustum.contains_point(&v(5.1, 0.0, -5.0)));
+        assert!(!frustum.contains_point(&v(0.0, 0.0, -0.5)));
+        assert!(!frustum.contains_point(&v(0.0, 0.0, -10.5)));
+        assert!(!frustum.contains_point(&v(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn spheres() {
+        let frustum = Frustum::from_matrix(&box_matrix());
+
+        // Inside.
+        assert!(frustum.intersects_sphere(&v(0.0, 0.0, -5.0), 0.5));
+        // Intersecting the right and the far plane.
+        assert!(frustum.intersects_sphere(&v(2.5, 0.0, -5.0), 1.0));
+        assert!(frustum.intersects_sphere(&v(0.0, 0.0, -10.5), 1.0));
+        // Outside.
+        assert!(!frustum.intersects_sphere(&v(2.5, 0.0, -5.0), 0.4));
+        assert!(!frustum.intersects_sphere(&v(0.0, 0.0, 1.0), 1.5));
+    }
+
+    #[test]
+    fn aabbs() {
+        let frustum = Frustum::from_matrix(&perspective_matrix());
+        let bbox = |min: Vector3<f32>, max: Vector3<f32>| BBox { min: min, max: max };
+
+        let inside = bbox(v(-1.0, -1.0, -6.0), v(1.0, 1.0, -4.0));
+        let intersecting = bbox(v(4.0, -1.0, -6.0), v(8.0, 1.0, -4.0));
+        let outside = bbox(v(7.0, -1.0, -6.0), v(9.0, 1.0, -4.0));
+        let behind = bbox(v(-1.0, -1.0, 0.0), v(1.0, 1.0, 2.0));
+        let around = bbox(v(-20.0, -20.0, -20.0), v(20.0, 20.0, 20.0));
+
+        assert!(frustum.intersects_aabb(&inside) && frustum.contains_aabb(&inside));
+        assert!(frustum.intersects_aabb(&intersecting) && !frustum.contains_aabb(&intersecting));
+        assert!(!frustum.intersects_aabb(&outside) && !frustum.contains_aabb(&outside));
+        assert!(!frustum.intersects_aabb(&behind));
+        assert!(frustum.intersects_aabb(&around) && !frustum.contains_aabb(&around));
+
+        assert_eq!(frustum.cull_aabbs(&[outside, inside, behind, intersecting]), vec![1, 3]);
+    }
+}
@@ -0,0 +1,199 @@
+use cgmath::Vector3;
+use crate::aabb::BBox;
+use crate::ray::Ray;
+
+/// A cell of a uniform grid and the distances where the ray enters and exits the cell.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridCell {
+    pub coordinate: [u32; 3],
+    pub t_enter: f32,
+    pub t_exit: f32,
+}
+
+/// The cells of a uniform grid that a ray passes through, in the order of the ray (Amanatides and
+/// Woo 1987). The grid starts from origin and has dimensions cells of size cell_size.
+pub struct GridTraversal {
+    coordinate: [i64; 3],
+    step: [i64; 3],
+    t_max: [f32; 3],
+    t_delta: [f32; 3],
+    t: f32,
+    t_end: f32,
+    dimensions: [u32; 3],
+}
+
+impl GridTraversal {
+
+    pub fn init(ray: &Ray, origin: &Vector3<f32>, cell_size: f32, dimensions: [u32; 3]) -> Self {
+
+        assert!(cell_size > 0.0, "cell_size == {} > 0.0", cell_size);
+
+        let aabb = BBox {
+            min: *origin,
+            max: origin + cell_size * Vector3::<f32>::new(dimensions[0] as f32, dimensions[1] as f32, dimensions[2] as f32),
+        };
+
+        // The ray misses the grid. Create a traversal that is already finished.
+        let (t_start, t_end) = ray.intersect_aabb(&aabb).unwrap_or((1.0, 0.0));
+
+        let start = ray.point_at(t_start);
+        let mut coordinate = [0; 3];
+        let mut step = [0; 3];
+        let mut t_max = [std::f32::MAX; 3];
+        let mut t_delta = [std::f32::MAX; 3];
+
+        for i in 0..3 {
+
+            let cell = ((start[i] - origin[i]) / cell_size).floor() as i64;
+            coordinate[i] = cell.max(0).min(dimensions[i] as i64 - 1);
+
+            if ray.direction[i] > 0.0 {
+                step[i] = 1;
+                t_delta[i] = cell_size / ray.direction[i];
+                t_max[i] = (origin[i] + (coordinate[i] + 1) as f32 * cell_size - ray.origin[i]) / ray.direction[i];
+            }
+            else if ray.direction[i] < 0.0 {
+                step[i] = -1;
+                t_delta[i] = -cell_size / ray.direction[i];
+                t_max[i] = (origin[i] + coordinate[i] as f32 * cell_size - ray.origin[i]) / ray.direction[i];
+            }
+        }
+
+        Self {
+            coordinate: coordinate,
+            step: step,
+            t_max: t_max,
+            t_delta: t_delta,
+            t: t_start,
+            t_end: t_end,
+            dimensions: dimensions,
+        }
+    }
+}
+
+impl Iterator for GridTraversal {
+    type Item = GridCell;
+
+    fn next(&mut self) -> Option<GridCell> {
+
+        if self.t > self.t_end { return None; }
+        for i in 0..3 {
+            if self.coordinate[i] < 0 || self.coordinate[i] >= self.dimensions[i] as i64 { return None; }
+        }
+
+        // The axis of the next cell boundary.
+        let axis = if self.t_max[0] < self.t_max[1] && self.t_max[0] < self.t_max[2] { 0 }
+                   else if self.t_max[1] < self.t_max[2] { 1 }
+                   else { 2 };
+
+        let result = GridCell {
+            coordinate: [self.coordinate[0] as u32, self.coordinate[1] as u32, self.coordinate[2] as u32],
+            t_enter: self.t,
+            t_exit: self.t_max[axis].min(self.t_end),
+        };
+
+        self.t = self.t_max[axis];
+        self.t_max[axis] += self.t_delta[axis];
+        self.coordinate[axis] += self.step[axis];
+
+        // Leaving through the end of the ray.
+        if result.t_exit >= self.t_end { self.t = std::f32::MAX; }
+
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::prelude::*;
+
+    /// A 4x4x4 grid of unit cells at the origin.
+    fn traverse(ray: &Ray) -> Vec<GridCell> {
+        GridTraversal::init(ray, &Vector3::new(0.0, 0.0, 0.0), 1.0, [4, 4, 4]).collect()
+    }
+
+    fn coordinates(cells: &[GridCell]) -> Vec<[u32; 3]> {
+        cells.iter().map(|c| c.coordinate).collect()
+    }
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn axis_parallel_ray_from_outside() {
+        let cells = traverse(&Ray::new(&Vector3::new(-1.0, 0.5, 2.5), &Vector3::new(1.0, 0.0, 0.0)));
+        assert_eq!(coordinates(&cells), vec![[0, 0, 2], [1, 0, 2], [2, 0, 2], [3, 0, 2]]);
+        for (i, cell) in cells.iter().enumerate() {
+            assert!(approx(cell.t_enter, 1.0 + i as f32) && approx(cell.t_exit, 2.0 + i as f32), "{:?}", cell);
+        }
+    }
+
+    #[test]
+    fn axis_parallel_ray_from_inside_in_negative_direction() {
+        let cells = traverse(&Ray::new(&Vector3::new(2.5, 3.5, 1.5), &Vector3::new(0.0, -1.0, 0.0)));
+        assert_eq!(coordinates(&cells), vec![[2, 3, 1], [2, 2, 1], [2, 1, 1], [2, 0, 1]]);
+        assert!(approx(cells[0].t_enter, 0.0) && approx(cells[0].t_exit, 0.5));
+        assert!(approx(cells[3].t_enter, 2.5) && approx(cells[3].t_exit, 3.5));
+    }
+
+    #[test]
+    fn diagonal_ray_from_outside() {
+        // The ray (-1, 0.25) + s * (2, 1) crosses the cell boundaries at s = 0.5 (x = 0),
+        // 0.75 (y = 1), 1.0 (x = 1), 1.5 (x = 2), 1.75 (y = 2), 2.0 (x = 3) and 2.5 (x = 4).
+        let ray = Ray::new(&Vector3::new(-1.0, 0.25, 0.5), &Vector3::new(2.0, 1.0, 0.0));
+        let cells = traverse(&ray);
+        assert_eq!(coordinates(&cells), vec![[0, 0, 0], [0, 1, 0], [1, 1, 0], [2, 1, 0], [2, 2, 0], [3, 2, 0]]);
+
+        let length = 5.0f32.sqrt();
+        let boundaries = [0.5, 0.75, 1.0, 1.5, 1.75, 2.0, 2.5];
+        for (i, cell) in cells.iter().enumerate() {
+            assert!(approx(cell.t_enter, boundaries[i] * length), "{:?}", cell);
+            assert!(approx(cell.t_exit, boundaries[i + 1] * length), "{:?}", cell);
+        }
+    }
+
+    #[test]
+    fn ray_range_limits_the_cells() {
+        let mut ray = Ray::new(&Vector3::new(0.5, 0.5, 0.5), &Vector3::new(0.0, 0.0, 1.0));
+        ray.r_max = 1.7;
+        let cells = traverse(&ray);
+        assert_eq!(coordinates(&cells), vec![[0, 0, 0], [0, 0, 1], [0, 0, 2]]);
+        assert!(approx(cells[2].t_enter, 1.5) && approx(cells[2].t_exit, 1.7));
+    }
+
+    #[test]
+    fn rays_that_miss_the_grid() {
+        // Passes above the grid.
+        assert!(traverse(&Ray::new(&Vector3::new(-1.0, 5.0, 0.5), &Vector3::new(1.0, 0.0, 0.0))).is_empty());
+        // Starts outside and points away.
+        assert!(traverse(&Ray::new(&Vector3::new(-1.0, 0.5, 0.5), &Vector3::new(-1.0, 0.0, 0.0))).is_empty());
+        // Parallel to a face, outside the grid.
+        assert!(traverse(&Ray::new(&Vector3::new(0.5, -0.5, -1.0), &Vector3::new(0.0, 0.0, 1.0))).is_empty());
+    }
+
+    #[test]
+    fn cells_are_connected() {
+        // Every step moves to a face neighbour, the cells share the boundary distances and the
+        // middle of each segment is inside its cell.
+        let directions = [Vector3::new(1.0, 0.3, -0.7), Vector3::new(-0.2, -1.0, 0.45), Vector3::new(0.6, 0.5, 0.7)];
+        for direction in directions.iter() {
+            let origin = Vector3::new(2.1, 1.9, 2.3) - 5.0 * direction.normalize();
+            let ray = Ray::new(&origin, direction);
+            let cells = traverse(&ray);
+            assert!(cells.len() >= 4, "{:?}", cells);
+            for pair in cells.windows(2) {
+                let distance: i64 = (0..3).map(|i| (pair[0].coordinate[i] as i64 - pair[1].coordinate[i] as i64).abs()).sum();
+                assert_eq!(distance, 1, "{:?}", pair);
+                assert!(approx(pair[0].t_exit, pair[1].t_enter), "{:?}", pair);
+            }
+            for cell in cells.iter() {
+                let middle = ray.point_at(0.5 * (cell.t_enter + cell.t_exit));
+                for i in 0..3 {
+                    assert!(middle[i] >= cell.coordinate[i] as f32 && middle[i] <= cell.coordinate[i] as f32 + 1.0, "{:?}", cell);
+                }
+            }
+        }
+    }
+}
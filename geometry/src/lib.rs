@@ -1 +1,9 @@
 pub mod aabb;
+pub mod triangle;
+pub mod ray;
+pub mod mesh;
+pub mod simplify;
+pub mod frustum;
+pub mod curve;
+pub mod bvh;
+pub mod grid;
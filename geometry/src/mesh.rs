@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use cgmath::{prelude::*, Vector3, Vector4};
+use crate::triangle::{Triangle, Triangle_vvvvnnnn};
+
+/// The vertices of from_triangles are merged if the angle between the triangle normals is at most
+/// this (cosine of 60 degrees). Sharper edges keep separate vertices.
+const CREASE_ANGLE_COS: f32 = 0.5;
+
+/// The vertices of from_triangles_vvvvnnnn are merged only if the normals are the same.
+const SAME_NORMAL_COS: f32 = 0.9999;
+
+/// An indexed triangle mesh. Each triangle is three consecutive indices in ccw order. Each
+/// vertex has a position and a normal.
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+
+    /// Create a mesh from a triangle list. The vertices with the same position are merged unless
+    /// the edge between the triangles is sharper than 60 degrees (hard edge), and the normals are
+    /// the area weighted averages of the merged triangle normals.
+    pub fn from_triangles(triangles: &Vec<Triangle>) -> Self {
+
+        let mut welder = Welder::new(CREASE_ANGLE_COS);
+        let mut indices = Vec::with_capacity(triangles.len() * 3);
+
+        for tr in triangles.iter() {
+            let normal = (tr.b - tr.a).cross(tr.c - tr.a);
+            indices.push(welder.add(&tr.a, &normal));
+            indices.push(welder.add(&tr.b, &normal));
+            indices.push(welder.add(&tr.c, &normal));
+        }
+
+        welder.into_mesh(indices)
+    }
+
+    /// Create a mesh from a Triangle_vvvvnnnn list. The vertices with the same position and
+    /// normal are merged. The vertices with different normals (hard edges) are kept separate.
+    pub fn from_triangles_vvvvnnnn(triangles: &Vec<Triangle_vvvvnnnn>) -> Self {
+
+        let mut welder = Welder::new(SAME_NORMAL_COS);
+        let mut indices = Vec::with_capacity(triangles.len() * 3);
+
+        for tr in triangles.iter() {
+            indices.push(welder.add(&tr.a.truncate(), &tr.na.truncate()));
+            indices.push(welder.add(&tr.b.truncate(), &tr.nb.truncate()));
+            indices.push(welder.add(&tr.c.truncate(), &tr.nc.truncate()));
+        }
+
+        welder.into_mesh(indices)
+    }
+
+    /// The number of triangles.
+    pub fn triangle_count(&self) -> u32 {
+        (self.indices.len() / 3) as u32
+    }
+
+    /// Convert the mesh to a triangle list.
+    pub fn to_triangles(&self) -> Vec<Triangle> {
+        self.indices.chunks(3).map(|t| Triangle {
+            a: self.positions[t[0] as usize],
+            b: self.positions[t[1] as usize],
+            c: self.positions[t[2] as usize],
+        }).collect()
+    }
+
+    /// Convert the mesh to a Triangle_vvvvnnnn list.
+    pub fn to_triangles_vvvvnnnn(&self) -> Vec<Triangle_vvvvnnnn> {
+        let v = |i: u32| -> Vector4<f32> { self.positions[i as usize].extend(1.0) };
+        let n = |i: u32| -> Vector4<f32> { self.normals[i as usize].extend(0.0) };
+        self.indices.chunks(3).map(|t| Triangle_vvvvnnnn {
+            a: v(t[0]),
+            na: n(t[0]),
+            b: v(t[1]),
+            nb: n(t[1]),
+            c: v(t[2]),
+            nc: n(t[2]),
+        }).collect()
+    }
+}
+
+/// Merges the vertices with the same position and a similar normal.
+struct Welder {
+    vertices: HashMap<[u32; 3], Vec<u32>>,
+    positions: Vec<Vector3<f32>>,
+    normals: Vec<Vector3<f32>>,
+    min_normal_cos: f32,
+}
+
+impl Welder {
+
+    /// The vertices are merged if the cosine of the angle between the normals is at least
+    /// min_normal_cos.
+    fn new(min_normal_cos: f32) -> Self {
+        Self {
+            vertices: HashMap::new(),
+            positions: Vec::new(),
+            normals: Vec::new(),
+            min_normal_cos: min_normal_cos,
+        }
+    }
+
+    /// Add a vertex and return its index. The normal is compared to the average normal of the
+    /// vertices merged so far. A zero normal is merged with the first vertex at the position.
+    fn add(&mut self, position: &Vector3<f32>, normal: &Vector3<f32>) -> u32 {
+        // + 0.0 converts -0.0 to 0.0.
+        let key = [(position.x + 0.0).to_bits(), (position.y + 0.0).to_bits(), (position.z + 0.0).to_bits()];
+        let direction = if normal.magnitude2() > 0.0 { Some(normal.normalize()) } else { None };
+        let candidates = self.vertices.entry(key).or_insert_with(Vec::new);
+        let normals = &self.normals;
+        let min_normal_cos = self.min_normal_cos;
+
+        let found = candidates.iter().cloned().find(|i| {
+            let n = normals[*i as usize];
+            match direction {
+                Some(d) => n.magnitude2() == 0.0 || n.normalize().dot(d) >= min_normal_cos,
+                None => true,
+            }
+        });
+
+        let index = match found {
+            Some(i) => i,
+            None => {
+                self.positions.push(*position);
+                self.normals.push(Vector3::<f32>::zero());
+                let i = (self.positions.len() - 1) as u32;
+                candidates.push(i);
+                i
+            }
+        };
+        self.normals[index as usize] += *normal;
+        index
+    }
+
+    fn into_mesh(self, indices: Vec<u32>) -> Mesh {
+        Mesh {
+            positions: self.positions,
+            normals: self.normals.iter().map(|n| if n.magnitude2() > 0.0 { n.normalize() } else { *n }).collect(),
+            indices: indices,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The 12 triangles of the unit cube in ccw order.
+    fn cube() -> Vec<Triangle> {
+        let v = |x: f32, y: f32, z: f32| Vector3::<f32>::new(x, y, z);
+        let quads = [
+            [v(0.0, 0.0, 0.0), v(0.0, 1.0, 0.0), v(1.0, 1.0, 0.0), v(1.0, 0.0, 0.0)],
+            [v(0.0, 0.0, 1.0), v(1.0, 0.0, 1.0), v(1.0, 1.0, 1.0), v(0.0, 1.0, 1.0)],
+            [v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0), v(1.0, 0.0, 1.0), v(0.0, 0.0, 1.0)],
+            [v(0.0, 1.0, 0.0), v(0.0, 1.0, 1.0), v(1.0, 1.0, 1.0), v(1.0, 1.0, 0.0)],
+            [v(0.0, 0.0, 0.0), v(0.0, 0.0, 1.0), v(0.0, 1.0, 1.0), v(0.0, 1.0, 0.0)],
+            [v(1.0, 0.0, 0.0), v(1.0, 1.0, 0.0), v(1.0, 1.0, 1.0), v(1.0, 0.0, 1.0)],
+        ];
+        quads.iter().flat_map(|q| vec![Triangle { a: q[0], b: q[1], c: q[2] },
+                                       Triangle { a: q[0], b: q[2], c: q[3] }]).collect()
+    }
+
+    #[test]
+    fn hard_edges_are_not_welded() {
+        let mesh = Mesh::from_triangles(&cube());
+
+        // Each corner has a vertex for each of the three faces.
+        assert_eq!(mesh.positions.len(), 24);
+        assert_eq!(mesh.triangle_count(), 12);
+        for n in mesh.normals.iter() {
+            assert!((n.x.abs() + n.y.abs() + n.z.abs() - 1.0).abs() < 1e-6, "{}", format!("normal {:?} isn't axis aligned", n));
+        }
+    }
+
+    #[test]
+    fn same_normals_are_welded() {
+        // Smooth normals pointing away from the center.
+        let triangles: Vec<Triangle_vvvvnnnn> = cube().iter().map(|t| {
+            let n = |p: Vector3<f32>| (p - Vector3::<f32>::new(0.5, 0.5, 0.5)).normalize().extend(0.0);
+            Triangle_vvvvnnnn { a: t.a.extend(1.0), na: n(t.a), b: t.b.extend(1.0), nb: n(t.b), c: t.c.extend(1.0), nc: n(t.c) }
+        }).collect();
+        let mesh = Mesh::from_triangles_vvvvnnnn(&triangles);
+        assert_eq!(mesh.positions.len(), 8);
+
+        // The flat shaded cube keeps the faces separate.
+        let flat = Mesh::from_triangles_vvvvnnnn(&Mesh::from_triangles(&cube()).to_triangles_vvvvnnnn());
+        assert_eq!(flat.positions.len(), 24);
+    }
+}
@@ -0,0 +1,288 @@
+use cgmath::{prelude::*, Vector3};
+use crate::aabb::BBox;
+use crate::triangle::{Triangle, Plane};
+
+/// A ray. The same as Ray in sphere tracer shader. Only the points between r_min and r_max are
+/// considered as hits.
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    pub direction: Vector3<f32>,
+    pub r_min: f32,
+    pub r_max: f32,
+}
+
+impl Ray {
+
+    /// Create a ray from origin to direction. The direction is normalized.
+    pub fn new(origin: &Vector3<f32>, direction: &Vector3<f32>) -> Self {
+        assert!(direction.magnitude2() > 0.0, "The direction of the ray must be non zero.");
+        Self {
+            origin: *origin,
+            direction: direction.normalize(),
+            r_min: 0.0,
+            r_max: std::f32::MAX,
+        }
+    }
+
+    /// Create a ray from point a towards point b.
+    pub fn from_points(a: &Vector3<f32>, b: &Vector3<f32>) -> Self {
+        Ray::new(a, &(b - a))
+    }
+
+    /// Get the point origin + t * direction.
+    pub fn point_at(&self, t: f32) -> Vector3<f32> {
+        self.origin + t * self.direction
+    }
+
+    /// Möller-Trumbore ray-triangle intersection. Both sides of the triangle are hit. Returns the
+    /// distance and the barycentric coordinates (u, v, w) of the hit point with respect to (a, b, c).
+    pub fn intersect_triangle(&self, triangle: &Triangle) -> Option<(f32, Vector3<f32>)> {
+
+        let ab = triangle.b - triangle.a;
+        let ac = triangle.c - triangle.a;
+
+        let p = self.direction.cross(ac);
+        let det = ab.dot(p);
+
+        // The ray is parallel to the triangle.
+        if det.abs() < std::f32::EPSILON { return None; }
+
+        let inv_det = 1.0 / det;
+        let s = self.origin - triangle.a;
+        let v = s.dot(p) * inv_det;
+        if v < 0.0 || v > 1.0 { return None; }
+
+        let q = s.cross(ab);
+        let w = self.direction.dot(q) * inv_det;
+        if w < 0.0 || v + w > 1.0 { return None; }
+
+        let t = ac.dot(q) * inv_det;
+        if t < self.r_min || t > self.r_max { return None; }
+
+        Some((t, Vector3::<f32>::new(1.0 - v - w, v, w)))
+    }
+
+    /// Watertight ray-triangle intersection (Woop, Benthin and Wald 2013). Rays that hit a shared
+    /// edge or vertex hit at least one of the triangles. Both sides of the triangle are hit.
+    /// Returns the distance and the barycentric coordinates (u, v, w) of the hit point with respect
+    /// to (a, b, c).
+    pub fn intersect_triangle_watertight(&self, triangle: &Triangle) -> Option<(f32, Vector3<f32>)> {
+
+        // Permute the axis so that z is the dominant direction.
+        let abs_dir = Vector3::<f32>::new(self.direction.x.abs(), self.direction.y.abs(), self.direction.z.abs());
+        let kz = if abs_dir.x > abs_dir.y && abs_dir.x > abs_dir.z { 0 }
+                 else if abs_dir.y > abs_dir.z { 1 }
+                 else { 2 };
+        let mut kx = (kz + 1) % 3;
+        let mut ky = (kx + 1) % 3;
+        if self.direction[kz] < 0.0 { std::mem::swap(&mut kx, &mut ky); }
+
+        // Shear constants.
+        let sx = self.direction[kx] / self.direction[kz];
+        let sy = self.direction[ky] / self.direction[kz];
+        let sz = 1.0 / self.direction[kz];
+
+        let a = triangle.a - self.origin;
+        let b = triangle.b - self.origin;
+        let c = triangle.c - self.origin;
+
+        let ax = a[kx] - sx * a[kz];
+        let ay = a[ky] - sy * a[kz];
+        let bx = b[kx] - sx * b[kz];
+        let by = b[ky] - sy * b[kz];
+        let cx = c[kx] - sx * c[kz];
+        let cy = c[ky] - sy * c[kz];
+
+        let mut u = cx * by - cy * bx;
+        let mut v = ax * cy - ay * cx;
+        let mut w = bx * ay - by * ax;
+
+        // Fall back to double precision on edges.
+        if u == 0.0 || v == 0.0 || w == 0.0 {
+            u = (cx as f64 * by as f64 - cy as f64 * bx as f64) as f32;
+            v = (ax as f64 * cy as f64 - ay as f64 * cx as f64) as f32;
+            w = (bx as f64 * ay as f64 - by as f64 * ax as f64) as f32;
+        }
+
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) { return None; }
+
+        let det = u + v + w;
+        if det == 0.0 { return None; }
+
+        let t = (u * sz * a[kz] + v * sz * b[kz] + w * sz * c[kz]) / det;
+        if t < self.r_min || t > self.r_max { return None; }
+
+        Some((t, Vector3::<f32>::new(u / det, v / det, w / det)))
+    }
+
+    /// Ray-aabb slab test. Returns the distances where the ray enters and exits the aabb. If the
+    /// origin is inside the aabb, the entering distance is r_min.
+    pub fn intersect_aabb(&self, aabb: &BBox) -> Option<(f32, f32)> {
+
+        let mut t_min = self.r_min;
+        let mut t_max = self.r_max;
+
+        for i in 0..3 {
+
+            // The ray is parallel to the slab.
+            if self.direction[i] == 0.0 {
+                if self.origin[i] < aabb.min[i] || self.origin[i] > aabb.max[i] { return None; }
+                continue;
+            }
+
+            let inv_dir = 1.0 / self.direction[i];
+            let t0 = (aabb.min[i] - self.origin[i]) * inv_dir;
+            let t1 = (aabb.max[i] - self.origin[i]) * inv_dir;
+
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+        }
+
+        if t_min > t_max { None }
+        else { Some((t_min, t_max)) }
+    }
+
+    /// Ray-plane intersection. Returns the distance to the plane.
+    pub fn intersect_plane(&self, plane: &Plane) -> Option<f32> {
+
+        let denom = plane.n.dot(self.direction);
+
+        // The ray is parallel to the plane.
+        if denom.abs() < std::f32::EPSILON { return None; }
+
+        let t = (plane.d - plane.n.dot(self.origin)) / denom;
+        if t < self.r_min || t > self.r_max { return None; }
+
+        Some(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3<f32> {
+        Vector3::<f32>::new(x, y, z)
+    }
+
+    /// A triangle in the plane z == 1.
+    fn triangle() -> Triangle {
+        Triangle { a: v(0.0, 0.0, 1.0), b: v(1.0, 0.0, 1.0), c: v(0.0, 1.0, 1.0) }
+    }
+
+    fn unit_box() -> BBox {
+        BBox { min: v(0.0, 0.0, 0.0), max: v(1.0, 1.0, 1.0) }
+    }
+
+    #[test]
+    fn triangle_hit_and_miss() {
+        let ray = Ray::new(&v(0.25, 0.25, 0.0), &v(0.0, 0.0, 2.0));
+        for (t, uvw) in [ray.intersect_triangle(&triangle()), ray.intersect_triangle_watertight(&triangle())].iter().map(|h| h.unwrap()) {
+            assert!((t - 1.0).abs() < 1e-6, "t == {}", t);
+            assert!((uvw - v(0.5, 0.25, 0.25)).magnitude() < 1e-6, "uvw == {:?}", uvw);
+            assert!((ray.point_at(t) - v(0.25, 0.25, 1.0)).magnitude() < 1e-6);
+        }
+
+        // The back side is hit too.
+        let back = Ray::new(&v(0.25, 0.25, 2.0), &v(0.0, 0.0, -1.0));
+        assert!(back.intersect_triangle(&triangle()).is_some());
+        assert!(back.intersect_triangle_watertight(&triangle()).is_some());
+
+        let outside = Ray::new(&v(0.75, 0.75, 0.0), &v(0.0, 0.0, 1.0));
+        assert!(outside.intersect_triangle(&triangle()).is_none());
+        assert!(outside.intersect_triangle_watertight(&triangle()).is_none());
+
+        let away = Ray::new(&v(0.25, 0.25, 0.0), &v(0.0, 0.0, -1.0));
+        assert!(away.intersect_triangle(&triangle()).is_none());
+        assert!(away.intersect_triangle_watertight(&triangle()).is_none());
+
+        let parallel = Ray::new(&v(-1.0, 0.25, 1.0), &v(1.0, 0.0, 0.0));
+        assert!(parallel.intersect_triangle(&triangle()).is_none());
+    }
+
+    #[test]
+    fn triangle_tangent_hit() {
+        // A ray through the shared edge of two triangles hits at least one of them.
+        let other = Triangle { a: v(1.0, 0.0, 1.0), b: v(1.0, 1.0, 1.0), c: v(0.0, 1.0, 1.0) };
+        let ray = Ray::new(&v(0.5, 0.5, 0.0), &v(0.0, 0.0, 1.0));
+        assert!(ray.intersect_triangle_watertight(&triangle()).is_some() ||
+                ray.intersect_triangle_watertight(&other).is_some());
+
+        // A ray through a vertex hits at distance 1.
+        let vertex = Ray::new(&v(0.0, 0.0, 0.0), &v(0.0, 0.0, 1.0));
+        let (t, uvw) = vertex.intersect_triangle_watertight(&triangle()).unwrap();
+        assert!((t - 1.0).abs() < 1e-6, "t == {}", t);
+        assert!((uvw - v(1.0, 0.0, 0.0)).magnitude() < 1e-6, "uvw == {:?}", uvw);
+    }
+
+    #[test]
+    fn triangle_t_range() {
+        let mut ray = Ray::new(&v(0.25, 0.25, 0.0), &v(0.0, 0.0, 1.0));
+        ray.r_max = 0.5;
+        assert!(ray.intersect_triangle(&triangle()).is_none());
+        assert!(ray.intersect_triangle_watertight(&triangle()).is_none());
+
+        ray.r_max = 2.0;
+        ray.r_min = 1.5;
+        assert!(ray.intersect_triangle(&triangle()).is_none());
+        assert!(ray.intersect_triangle_watertight(&triangle()).is_none());
+
+        ray.r_min = 0.5;
+        assert!(ray.intersect_triangle(&triangle()).is_some());
+        assert!(ray.intersect_triangle_watertight(&triangle()).is_some());
+    }
+
+    #[test]
+    fn aabb_hit_miss_and_tangent() {
+        let ray = Ray::new(&v(-1.0, 0.5, 0.5), &v(1.0, 0.0, 0.0));
+        assert_eq!(ray.intersect_aabb(&unit_box()), Some((1.0, 2.0)));
+
+        let miss = Ray::new(&v(-1.0, 2.0, 0.5), &v(1.0, 0.0, 0.0));
+        assert_eq!(miss.intersect_aabb(&unit_box()), None);
+
+        let behind = Ray::new(&v(2.0, 0.5, 0.5), &v(1.0, 0.0, 0.0));
+        assert_eq!(behind.intersect_aabb(&unit_box()), None);
+
+        // A ray along a face touches the aabb.
+        let tangent = Ray::new(&v(-1.0, 1.0, 0.5), &v(1.0, 0.0, 0.0));
+        assert_eq!(tangent.intersect_aabb(&unit_box()), Some((1.0, 2.0)));
+
+        // A diagonal ray that only touches the corner (1, 1, z).
+        let corner = Ray::new(&v(0.0, 2.0, 0.5), &v(1.0, -1.0, 0.0));
+        let (t_min, t_max) = corner.intersect_aabb(&unit_box()).unwrap();
+        assert!((t_min - t_max).abs() < 1e-6 && (t_min - 2.0_f32.sqrt()).abs() < 1e-6, "({}, {})", t_min, t_max);
+    }
+
+    #[test]
+    fn aabb_t_range() {
+        // The origin inside the aabb enters at r_min.
+        let inside = Ray::new(&v(0.5, 0.5, 0.5), &v(0.0, 1.0, 0.0));
+        assert_eq!(inside.intersect_aabb(&unit_box()), Some((0.0, 0.5)));
+
+        let mut ray = Ray::new(&v(-1.0, 0.5, 0.5), &v(1.0, 0.0, 0.0));
+        ray.r_max = 1.5;
+        assert_eq!(ray.intersect_aabb(&unit_box()), Some((1.0, 1.5)));
+        ray.r_max = 0.5;
+        assert_eq!(ray.intersect_aabb(&unit_box()), None);
+        ray.r_max = 10.0;
+        ray.r_min = 3.0;
+        assert_eq!(ray.intersect_aabb(&unit_box()), None);
+    }
+
+    #[test]
+    fn plane_hit_miss_and_t_range() {
+        let plane = Plane::new(&v(0.0, 0.0, 1.0), &v(1.0, 0.0, 1.0), &v(0.0, 1.0, 1.0));
+        let mut ray = Ray::from_points(&v(3.0, -2.0, -1.0), &v(3.0, -2.0, 0.0));
+        assert_eq!(ray.intersect_plane(&plane), Some(2.0));
+
+        ray.r_max = 1.0;
+        assert_eq!(ray.intersect_plane(&plane), None);
+
+        let away = Ray::new(&v(0.0, 0.0, 0.0), &v(0.0, 0.0, -1.0));
+        assert_eq!(away.intersect_plane(&plane), None);
+
+        let parallel = Ray::new(&v(0.0, 0.0, 0.0), &v(1.0, 1.0, 0.0));
+        assert_eq!(parallel.intersect_plane(&plane), None);
+    }
+}
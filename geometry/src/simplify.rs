@@ -0,0 +1,377 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use cgmath::{prelude::*, Vector3, Matrix3};
+use crate::mesh::Mesh;
+
+/// A collapse is rejected if it rotates the normal of a remaining triangle more than this
+/// (cosine of the angle).
+const MIN_NORMAL_DOT: f64 = 0.2;
+
+/// Quadric edge collapse simplification (Garland and Heckbert 1997). The edges are collapsed in
+/// the order of the quadric error until the mesh has at most target_triangle_count triangles or
+/// the error of the next collapse is greater than max_error. The quadric error is the sum of the
+/// squared distances to the planes of the original triangles. Use std::f32::MAX for no error
+/// bound and 0 for no triangle count target.
+///
+/// The boundary vertices and the vertices that share their position with other vertices (hard
+/// edges and other seams, see Mesh::from_triangles) are not moved or removed, so the boundary
+/// edges are kept, the hard edges keep their normals and the seams stay closed. The other
+/// vertices can still be collapsed into them. Collapses that flip triangles or break the
+/// manifold topology are rejected. Vertices of non-manifold edges are not moved. The normals are
+/// interpolated along the collapsed edges.
+pub fn simplify(mesh: &Mesh, target_triangle_count: u32, max_error: f32) -> Mesh {
+    let mut state = State::init(mesh);
+    state.run(target_triangle_count, max_error as f64);
+    state.into_mesh()
+}
+
+/// A symmetric 4x4 matrix. Only the upper triangle is stored.
+#[derive(Clone, Copy)]
+struct Quadric {
+    a: [f64; 10],
+}
+
+impl Quadric {
+
+    fn zero() -> Self {
+        Self { a: [0.0; 10] }
+    }
+
+    /// The quadric of the plane n.x + d == 0.
+    fn from_plane(n: &Vector3<f64>, d: f64) -> Self {
+        Self {
+            a: [n.x * n.x, n.x * n.y, n.x * n.z, n.x * d,
+                           n.y * n.y, n.y * n.z, n.y * d,
+                                      n.z * n.z, n.z * d,
+                                                 d * d],
+        }
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut result = *self;
+        for i in 0..10 { result.a[i] += other.a[i]; }
+        result
+    }
+
+    /// The error v^T Q v where v == (p, 1).
+    fn error(&self, p: &Vector3<f64>) -> f64 {
+        let a = &self.a;
+        a[0] * p.x * p.x + 2.0 * a[1] * p.x * p.y + 2.0 * a[2] * p.x * p.z + 2.0 * a[3] * p.x
+                         +       a[4] * p.y * p.y + 2.0 * a[5] * p.y * p.z + 2.0 * a[6] * p.y
+                                                  +       a[7] * p.z * p.z + 2.0 * a[8] * p.z
+                                                                           +       a[9]
+    }
+
+    /// The point that minimizes the error. None if the point is not unique.
+    fn optimal_point(&self) -> Option<Vector3<f64>> {
+        let a = &self.a;
+        // Column major.
+        let m = Matrix3::<f64>::new(a[0], a[1], a[2],
+                                    a[1], a[4], a[5],
+                                    a[2], a[5], a[7]);
+        let result = m.invert()? * Vector3::<f64>::new(-a[3], -a[6], -a[8]);
+        if result.x.is_finite() && result.y.is_finite() && result.z.is_finite() { Some(result) }
+        else { None }
+    }
+}
+
+/// A collapse of edge (v0, v1) into position. The stamps tell if the vertices have changed after
+/// the candidate was created.
+struct Candidate {
+    cost: f64,
+    v0: u32,
+    v1: u32,
+    stamp0: u32,
+    stamp1: u32,
+    position: Vector3<f64>,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    // Reversed, so the BinaryHeap pops the smallest cost first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+struct State {
+    positions: Vec<Vector3<f64>>,
+    normals: Vec<Vector3<f32>>,
+    quadrics: Vec<Quadric>,
+    stamps: Vec<u32>,
+    pinned: Vec<bool>,
+    locked: Vec<bool>,
+    removed: Vec<bool>,
+    faces: Vec<[u32; 3]>,
+    face_removed: Vec<bool>,
+    vertex_faces: Vec<Vec<u32>>,
+    triangle_count: u32,
+    heap: BinaryHeap<Candidate>,
+}
+
+impl State {
+
+    fn init(mesh: &Mesh) -> Self {
+
+        let vertex_count = mesh.positions.len();
+        let faces: Vec<[u32; 3]> = mesh.indices.chunks(3).map(|t| [t[0], t[1], t[2]]).collect();
+
+        let mut state = Self {
+            positions: mesh.positions.iter().map(|p| p.cast::<f64>().unwrap()).collect(),
+            normals: mesh.normals.clone(),
+            quadrics: vec![Quadric::zero(); vertex_count],
+            stamps: vec![0; vertex_count],
+            pinned: vec![false; vertex_count],
+            locked: vec![false; vertex_count],
+            removed: vec![false; vertex_count],
+            face_removed: vec![false; faces.len()],
+            vertex_faces: vec![Vec::new(); vertex_count],
+            triangle_count: faces.len() as u32,
+            faces: faces,
+            heap: BinaryHeap::new(),
+        };
+
+        // The vertices that have the same position.
+        let mut positions: HashMap<[u32; 3], Vec<u32>> = HashMap::new();
+        for (v, p) in mesh.positions.iter().enumerate() {
+            let key = [(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()];
+            positions.entry(key).or_insert_with(Vec::new).push(v as u32);
+        }
+        for vertices in positions.values().filter(|vertices| vertices.len() > 1) {
+            for v in vertices.iter() { state.pinned[*v as usize] = true; }
+        }
+
+        // The edges and the faces that have them.
+        let mut edges: HashMap<(u32, u32), Vec<u32>> = HashMap::new();
+
+        for (f, face) in state.faces.iter().enumerate() {
+            for i in 0..3 {
+                let (a, b) = (face[i], face[(i + 1) % 3]);
+                state.vertex_faces[a as usize].push(f as u32);
+                edges.entry((a.min(b), a.max(b))).or_insert_with(Vec::new).push(f as u32);
+            }
+
+            if let Some((n, d)) = state.face_plane(face) {
+                let q = Quadric::from_plane(&n, d);
+                for v in face.iter() {
+                    state.quadrics[*v as usize] = state.quadrics[*v as usize].add(&q);
+                }
+            }
+        }
+
+        for (&(a, b), edge_faces) in edges.iter() {
+            match edge_faces.len() {
+                1 => {
+                    state.pinned[a as usize] = true;
+                    state.pinned[b as usize] = true;
+                }
+                2 => {}
+                _ => {
+                    state.locked[a as usize] = true;
+                    state.locked[b as usize] = true;
+                }
+            }
+        }
+
+        for &(a, b) in edges.keys() {
+            state.push_candidate(a, b);
+        }
+
+        state
+    }
+
+    /// The unit normal and d of the plane n.x + d == 0 of the face. None if the face is degenerated.
+    fn face_plane(&self, face: &[u32; 3]) -> Option<(Vector3<f64>, f64)> {
+        let a = self.positions[face[0] as usize];
+        let b = self.positions[face[1] as usize];
+        let c = self.positions[face[2] as usize];
+        let n = (b - a).cross(c - a);
+        if n.magnitude2() == 0.0 { return None; }
+        let n = n.normalize();
+        Some((n, -n.dot(a)))
+    }
+
+    /// Compute the collapse position and cost for edge (v0, v1) and add it to the heap.
+    fn push_candidate(&mut self, v0: u32, v1: u32) {
+
+        if self.locked[v0 as usize] || self.locked[v1 as usize] { return; }
+
+        // A pinned vertex stays in place, so the other vertex is collapsed into it.
+        if self.pinned[v0 as usize] && self.pinned[v1 as usize] { return; }
+        let (v0, v1) = if self.pinned[v1 as usize] { (v1, v0) } else { (v0, v1) };
+
+        let q = self.quadrics[v0 as usize].add(&self.quadrics[v1 as usize]);
+        let p0 = self.positions[v0 as usize];
+        let p1 = self.positions[v1 as usize];
+        let mid = 0.5 * (p0 + p1);
+
+        // The optimal point is used only if it is near the edge.
+        let max_distance2 = (p1 - p0).magnitude2() * 4.0;
+        let mut candidates = vec![p0, p1, mid];
+        if let Some(p) = q.optimal_point() {
+            if (p - mid).magnitude2() <= max_distance2 { candidates.insert(0, p); }
+        }
+        if self.pinned[v0 as usize] { candidates = vec![p0]; }
+
+        let (cost, position) = candidates.iter()
+            .map(|p| (q.error(p).max(0.0), *p))
+            .fold((std::f64::MAX, mid), |best, c| if c.0 < best.0 { c } else { best });
+
+        self.heap.push(Candidate {
+            cost: cost,
+            v0: v0,
+            v1: v1,
+            stamp0: self.stamps[v0 as usize],
+            stamp1: self.stamps[v1 as usize],
+            position: position,
+        });
+    }
+
+    fn run(&mut self, target_triangle_count: u32, max_error: f64) {
+
+        while self.triangle_count > target_triangle_count {
+
+            let candidate = match self.heap.pop() {
+                Some(c) => c,
+                None => break,
+            };
+
+            let (v0, v1) = (candidate.v0 as usize, candidate.v1 as usize);
+
+            // The candidate is out of date.
+            if self.removed[v0] || self.removed[v1] ||
+               self.stamps[v0] != candidate.stamp0 || self.stamps[v1] != candidate.stamp1 { continue; }
+
+            if candidate.cost > max_error { break; }
+
+            if self.is_valid_collapse(candidate.v0, candidate.v1, &candidate.position) {
+                self.collapse(candidate.v0, candidate.v1, &candidate.position);
+            }
+        }
+    }
+
+    /// The vertices that share a face with v.
+    fn neighbors(&self, v: u32) -> Vec<u32> {
+        let mut result: Vec<u32> = self.vertex_faces[v as usize].iter()
+            .flat_map(|f| self.faces[*f as usize].iter().cloned())
+            .filter(|w| *w != v)
+            .collect();
+        result.sort();
+        result.dedup();
+        result
+    }
+
+    fn is_valid_collapse(&self, v0: u32, v1: u32, position: &Vector3<f64>) -> bool {
+
+        let shared_faces: Vec<u32> = self.vertex_faces[v0 as usize].iter()
+            .filter(|f| self.faces[**f as usize].contains(&v1))
+            .cloned()
+            .collect();
+
+        // The edge doesn't exist anymore.
+        if shared_faces.is_empty() { return false; }
+
+        // Link condition: the only common neighbors are the opposite vertices of the edge.
+        let n0 = self.neighbors(v0);
+        let n1 = self.neighbors(v1);
+        let common = n0.iter().filter(|w| n1.binary_search(w).is_ok()).count();
+        if common != shared_faces.len() { return false; }
+
+        // The remaining faces must not flip or degenerate.
+        for &v in [v0, v1].iter() {
+            for &f in self.vertex_faces[v as usize].iter() {
+                let face = self.faces[f as usize];
+                if face.contains(&v0) && face.contains(&v1) { continue; }
+
+                let old: Vec<Vector3<f64>> = face.iter().map(|w| self.positions[*w as usize]).collect();
+                let new: Vec<Vector3<f64>> = face.iter().map(|w| if *w == v { *position } else { self.positions[*w as usize] }).collect();
+                let n_old = (old[1] - old[0]).cross(old[2] - old[0]);
+                let n_new = (new[1] - new[0]).cross(new[2] - new[0]);
+
+                if n_new.magnitude2() == 0.0 { return false; }
+                if n_old.magnitude2() > 0.0 && n_old.normalize().dot(n_new.normalize()) < MIN_NORMAL_DOT { return false; }
+            }
+        }
+
+        true
+    }
+
+    /// Collapse v1 into v0.
+    fn collapse(&mut self, v0: u32, v1: u32, position: &Vector3<f64>) {
+
+        let (i0, i1) = (v0 as usize, v1 as usize);
+
+        // Interpolate the normal along the edge.
+        let edge = self.positions[i1] - self.positions[i0];
+        let t = if edge.magnitude2() > 0.0 { ((position - self.positions[i0]).dot(edge) / edge.magnitude2()).max(0.0).min(1.0) } else { 0.5 };
+        let normal = self.normals[i0] * (1.0 - t as f32) + self.normals[i1] * t as f32;
+        if normal.magnitude2() > 0.0 { self.normals[i0] = normal.normalize(); }
+
+        let mut faces = std::mem::replace(&mut self.vertex_faces[i0], Vec::new());
+        faces.extend(std::mem::replace(&mut self.vertex_faces[i1], Vec::new()));
+        faces.sort();
+        faces.dedup();
+
+        for &f in faces.iter() {
+            let face = &mut self.faces[f as usize];
+            if face.contains(&v0) && face.contains(&v1) {
+                self.face_removed[f as usize] = true;
+                self.triangle_count -= 1;
+                // Remove the face from the opposite vertex.
+                let opposite = *face.iter().find(|w| **w != v0 && **w != v1).unwrap();
+                self.vertex_faces[opposite as usize].retain(|x| *x != f);
+            }
+            else {
+                for w in face.iter_mut() { if *w == v1 { *w = v0; } }
+                self.vertex_faces[i0].push(f);
+            }
+        }
+
+        self.positions[i0] = *position;
+        self.quadrics[i0] = self.quadrics[i0].add(&self.quadrics[i1]);
+        self.removed[i1] = true;
+        self.stamps[i0] += 1;
+
+        for w in self.neighbors(v0) {
+            self.push_candidate(v0, w);
+        }
+    }
+
+    fn into_mesh(self) -> Mesh {
+
+        let mut new_index: Vec<Option<u32>> = vec![None; self.positions.len()];
+        let mut result = Mesh { positions: Vec::new(), normals: Vec::new(), indices: Vec::new() };
+
+        for (f, face) in self.faces.iter().enumerate() {
+            if self.face_removed[f] { continue; }
+            for &v in face.iter() {
+                let index = match new_index[v as usize] {
+                    Some(i) => i,
+                    None => {
+                        result.positions.push(self.positions[v as usize].cast::<f32>().unwrap());
+                        result.normals.push(self.normals[v as usize]);
+                        let i = (result.positions.len() - 1) as u32;
+                        new_index[v as usize] = Some(i);
+                        i
+                    }
+                };
+                result.indices.push(index);
+            }
+        }
+
+        result
+    }
+}
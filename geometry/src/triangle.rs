@@ -1,3 +1,6 @@
+use cgmath::{prelude::*, Vector3, Vector4};
+use bytemuck::{Pod, Zeroable};
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct Triangle {
@@ -6,6 +9,20 @@ pub struct Triangle {
     pub c: Vector3<f32>,
 }
 
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Triangle_vvvvnnnn {
+    pub a: Vector4<f32>,
+    pub na: Vector4<f32>,
+    pub b: Vector4<f32>,
+    pub nb: Vector4<f32>,
+    pub c: Vector4<f32>,
+    pub nc: Vector4<f32>,
+}
+
+unsafe impl Pod for Triangle_vvvvnnnn {}
+unsafe impl Zeroable for Triangle_vvvvnnnn {}
+
 impl Triangle {
 
     pub fn closest_point_to_triangle(&self, p: &Vector3<f32>) -> Vector3<f32> {
@@ -19,9 +36,6 @@ impl Triangle {
         let ac = c - a;
         let bc = c - b;
 
-        // Surface normal ccw.
-        let normal = (b-a).cross(c-a).normalize(); //ac.cross(ab).normalize();
-
         let snom = (p-a).dot(ab);
         let sdenom = (p-b).dot(a-b);
 
@@ -123,7 +137,6 @@ impl Triangle {
         // let ac = self.a - self.c;
         // let bc = self.b - self.c;
 
-        let n = ab.cross(ac).normalize(); 
         let s: f32 = 0.5 as f32 * ab.cross(ac).magnitude(); 
         
         let mut n: u32 = (s/epsilon).sqrt().ceil() as u32;
@@ -222,8 +235,8 @@ unsafe impl Zeroable for Triangle {}
 
 #[derive(Clone, Copy)]
 pub struct Plane {
-    n: Vector3<f32>,    
-    d: f32,    
+    pub n: Vector3<f32>,    
+    pub d: f32,    
 }
 
 impl Plane {
@@ -243,18 +256,6 @@ impl Plane {
 }
 
 
-/// Return min vector from a and b components.
-fn min_vec(a: &Vector3<f32>, b: &Vector3<f32>) -> Vector3<f32> {
-    let result = Vector3::<f32>::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
-    result
-}
-
-/// Return max vector from a and b components.
-fn max_vec(a: &Vector3<f32>, b: &Vector3<f32>) -> Vector3<f32> {
-    let result = Vector3::<f32>::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
-    result
-}
-
 pub fn barycentric_cooordinates(a: &Vector3<f32>, b: &Vector3<f32>, c: &Vector3<f32>, r: &Vector3<f32>) -> Vector3<f32> {
     let n = (b - a).cross(c - a);
     let rab = n.dot((a-r).cross(b-r));
@@ -286,7 +287,4 @@ pub fn barycentric_cooordinates(a: &Vector3<f32>, b: &Vector3<f32>, c: &Vector3<
 //     result
 // }
 
-// pub struct Ray {
-//     origin: Vector3<f32>,
-//     dir: Vector3<f32>,
-// }
+
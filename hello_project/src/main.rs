@@ -1,7 +1,6 @@
 //use byteorder::{BigEndian, ReadBytesExt};
 use std::borrow::Cow;
 use jaankaup_core::wgpu;
-use std::collections::HashMap;
 use jaankaup_core::wgpu_system as ws;
 use jaankaup_core::wgpu_system::{
         WGPUFeatures,
@@ -12,17 +11,26 @@ use jaankaup_core::wgpu_system::{
 //use glsl_to_spirv;
 use jaankaup_core::buffer::*;
 use jaankaup_core::texture::Texture as JTexture;
+use jaankaup_core::resources::{ResourceRegistry, BufferHandle, TextureHandle, BindGroupHandle};
+use jaankaup_core::frame_graph::{FrameGraph, GraphResource, TransientTextures, TransientTextureDescriptor};
 //use jaankaup_core::two_triangles::*;
 use jaankaup_core::mc::*;
 use jaankaup_core::camera::{Camera};
 use jaankaup_core::input::InputCache;
 use jaankaup_core::render_pipelines::{
-    draw,
-    create_bind_groups,
+    DrawCall,
     TestLayoutEntry,
-    // check_correspondence,
 };
 use jaankaup_core::noise3d::*;
+use jaankaup_core::shadow::{ShadowMap, ShadowPipeline, shadow_bindings, with_shadows};
+
+/// The number of samples per pixel (MSAA) of the terrain.
+const SAMPLE_COUNT: u32 = 4;
+
+/// The direction of the sun light that casts the terrain shadows.
+const LIGHT_DIRECTION: [f32; 3] = [-0.4, -1.0, -0.3];
+
+const CLEAR_COLOR: wgpu::Color = wgpu::Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0, };
 
 // Redefine needed features for this application.
 struct MyFeatures {}
@@ -43,52 +51,58 @@ impl WGPUFeatures for MyFeatures {
 
 // State for this application.
 struct HelloApp {
-    textures: HashMap<String, JTexture>, 
-    buffers: HashMap<String, wgpu::Buffer>,
+    resources: ResourceRegistry,
+    mc_output: BufferHandle,
+    mc_output_slime: BufferHandle,
+    noise3d_slime: BufferHandle,
+    future_usage1_noise3d: BufferHandle,
     //two_triangles: TwoTriangles,
     //two_triangles_bind_group: wgpu::BindGroup,
-    depth_texture: JTexture,
+    transient_textures: TransientTextures,
     camera: Camera,
     mc_slime: MarchingCubes,
     test_layout: TestLayoutEntry,
-    bind: Vec<wgpu::BindGroup>,
-    bind_slime: Vec<wgpu::BindGroup>,
-    draw_count_mc: u32,
-    draw_count_mc_slime: u32,
+    bind: BindGroupHandle,
+    bind_slime: BindGroupHandle,
+    mc_params: McParams,
     mc_params_slime: McParams,
-    slime_texture3d_bindgroups: Vec<wgpu::BindGroup>,
+    slime_texture3d_bindgroups: BindGroupHandle,
     custom_3d: Custom3DTexture,
+    shadow_map: ShadowMap,
+    shadow_pipeline: ShadowPipeline,
+    shadow_bind_groups: Vec<Vec<wgpu::BindGroup>>,
     //shaders: HashMap<String, ShaderModule>,
     //render_passes: HashMap<String, RenderPass>,
 }
 
 impl HelloApp {
 
-    fn create_textures(configuration: &WGPUConfiguration) -> (JTexture, JTexture, JTexture, JTexture) {
+    /// Load the textures to the registry. The wgpu textures get the registry labels.
+    fn create_textures(configuration: &WGPUConfiguration, resources: &mut ResourceRegistry) -> (TextureHandle, TextureHandle, TextureHandle, TextureHandle) {
         log::info!("Creating textures.");
-        let grass_texture = JTexture::create_from_bytes(
+        let grass_texture = resources.insert_texture("grass", |label| JTexture::create_from_bytes(
             &configuration.queue,
             &configuration.device,
             &configuration.sc_desc,
             1,
             &include_bytes!("../../assets/textures/grass2.png")[..],
-            None);
-        let rock_texture = JTexture::create_from_bytes(
+            label));
+        let rock_texture = resources.insert_texture("rock", |label| JTexture::create_from_bytes(
             &configuration.queue,
             &configuration.device,
             &configuration.sc_desc,
             1,
             &include_bytes!("../../assets/textures/rock.png")[..],
-            None);
-        let slime_texture = JTexture::create_from_bytes(
+            label));
+        let slime_texture = resources.insert_texture("slime", |label| JTexture::create_from_bytes(
             &configuration.queue,
             &configuration.device,
             &configuration.sc_desc,
             1,
             &include_bytes!("../../assets/textures/lava.png")[..],
             //&include_bytes!("../../assets/textures/slime.png")[..],
-            None);
-        let slime_texture2 = JTexture::create_from_bytes(
+            label));
+        let slime_texture2 = resources.insert_texture("slime2", |label| JTexture::create_from_bytes(
             &configuration.queue,
             &configuration.device,
             &configuration.sc_desc,
@@ -96,7 +110,7 @@ impl HelloApp {
             //&include_bytes!("../../assets/textures/slime2.png")[..],
             //&include_bytes!("../../assets/textures/xXqQP0.png")[..],
             &include_bytes!("../../assets/textures/luava.png")[..],
-            None);
+            label));
         log::info!("Textures created OK.");
         (grass_texture, rock_texture, slime_texture, slime_texture2)
     }
@@ -106,20 +120,14 @@ impl Application for HelloApp {
 
     fn init(configuration: &WGPUConfiguration) -> Self {
         
-        // Create buffer container.
-        let mut buffers: HashMap<String, wgpu::Buffer> = HashMap::new();
-        let mut textures: HashMap<String, JTexture> = HashMap::new();
+        // Create resource container.
+        let mut resources = ResourceRegistry::init();
 
         //let screen_buffer = create_screen_texture_buffer(&configuration.device);
         //buffers.insert("screen".to_string(),screen_buffer);
 
         //let two_triangles = TwoTriangles::init(&configuration.device, &configuration.sc_desc);
-        let (grass_texture, rock_texture, slime, slime2) = HelloApp::create_textures(&configuration); 
-        let depth_texture = JTexture::create_depth_texture(
-            &configuration.device,
-            &configuration.sc_desc,
-            Some("depth_texture")
-        ); 
+        let (grass, rock, slime, slime2) = HelloApp::create_textures(&configuration, &mut resources);
         // let bind_group = TwoTriangles::create_bind_group(
         //     &configuration.device,
         //     &grass_texture
@@ -141,11 +149,6 @@ impl Application for HelloApp {
         //     )
         // );
 
-        textures.insert("grass".to_string(), grass_texture); 
-        textures.insert("rock".to_string(), rock_texture); 
-        textures.insert("slime".to_string(), slime); 
-        textures.insert("slime2".to_string(), slime2); 
-
         let mut camera = Camera::new(configuration.size.width as f32, configuration.size.height as f32);
         camera.set_rotation_sensitivity(0.2);
         //camera.set_movement_sensitivity(0.0001);
@@ -153,55 +156,47 @@ impl Application for HelloApp {
         // let vertex_shader_src = wgpu::include_spirv!("../../shaders/spirv/renderer_4v4n.vert.spv");
         // let fragment_shader_src = wgpu::include_spirv!("../../shaders/spirv/renderer_4v4n.frag.spv");
 
+        // The terrain shadows.
+        let mut shadow_map = ShadowMap::init(&configuration.device, &mut resources, 2048, 4);
+        shadow_map.set_shadow_distance(200.0);
+        let shadow_pipeline = ShadowPipeline::init(&configuration.device, &[wgpu::VertexFormat::Float32x4, wgpu::VertexFormat::Float32x4]);
+        let shadow_bind_groups = shadow_pipeline.create_bind_groups(&configuration.device, &shadow_map).unwrap();
+        let shadow_resources = shadow_bindings(&shadow_map, &resources, 2);
+
         // Render pipeline...
         let t = TestLayoutEntry::init(
                     &configuration.device,
                     &configuration.sc_desc,
-                    &configuration.device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-                        label: Some("renderer_v4n4_module"),
-                        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../../shaders_wgsl/renderer_v4n4.wgsl"))),
-                    // &configuration.device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-                    //     label: Some("renderer_v4n4_module"),
-                    //     source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../../shaders_wgsl/renderer_v4n4.wgsl"))),
-                    //     flags: wgpu::ShaderFlags::VALIDATION | wgpu::ShaderFlags::EXPERIMENTAL_TRANSLATION,
-                    })
+                    SAMPLE_COUNT,
+                    &with_shadows(include_str!("../../shaders_wgsl/renderer_v4n4.wgsl"), 2),
+                    true,
 //                    &configuration.device.create_shader_module(&vertex_shader_src),
 //                    &configuration.device.create_shader_module(&fragment_shader_src)
         );
 
         // Create bind groups for basic render pipeline and grass/rock textures. 
-        let t_bindgroups = create_bind_groups(
-                                &configuration.device, 
-                                &t.layout_entries,
-                                &vec![
-                                    vec![&wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                                            buffer: &camera.get_camera_uniform(&configuration.device),
-                                            offset: 0,
-                                            size: None,
-                                    })], 
-                                    vec![&wgpu::BindingResource::TextureView(&textures.get("grass").unwrap().view),
-                                         &wgpu::BindingResource::Sampler(&textures.get("grass").unwrap().sampler),
-                                         &wgpu::BindingResource::TextureView(&textures.get("rock").unwrap().view),
-                                         &wgpu::BindingResource::Sampler(&textures.get("rock").unwrap().sampler)]
-                                ]
-        );
+        let t_bindgroups = {
+            let mut bindings = vec![((0, 0), camera.get_camera_uniform(&configuration.device).as_entire_binding()),
+                                    ((1, 0), wgpu::BindingResource::TextureView(&resources.get_texture(grass).view)),
+                                    ((1, 1), wgpu::BindingResource::Sampler(&resources.get_texture(grass).sampler)),
+                                    ((1, 2), wgpu::BindingResource::TextureView(&resources.get_texture(rock).view)),
+                                    ((1, 3), wgpu::BindingResource::Sampler(&resources.get_texture(rock).sampler))];
+            bindings.extend(shadow_resources.iter().cloned());
+            t.create_bind_groups(&configuration.device, &bindings).unwrap()
+        };
 
         // Create bind groups for basic render pipeline and slime/slime2 textures. 
-        let t_slime_bindgroups = create_bind_groups(
-                                     &configuration.device, 
-                                     &t.layout_entries,
-                                     &vec![
-                                         vec![&wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                                                 buffer: &camera.get_camera_uniform(&configuration.device),
-                                                 offset: 0,
-                                                 size: None,
-                                         })], 
-                                         vec![&wgpu::BindingResource::TextureView(&textures.get("slime").unwrap().view),
-                                              &wgpu::BindingResource::Sampler(&textures.get("slime").unwrap().sampler),
-                                              &wgpu::BindingResource::TextureView(&textures.get("slime2").unwrap().view),
-                                              &wgpu::BindingResource::Sampler(&textures.get("slime2").unwrap().sampler)]
-                                     ]
-        );
+        let t_slime_bindgroups = {
+            let mut bindings = vec![((0, 0), camera.get_camera_uniform(&configuration.device).as_entire_binding()),
+                                    ((1, 0), wgpu::BindingResource::TextureView(&resources.get_texture(slime).view)),
+                                    ((1, 1), wgpu::BindingResource::Sampler(&resources.get_texture(slime).sampler)),
+                                    ((1, 2), wgpu::BindingResource::TextureView(&resources.get_texture(slime2).view)),
+                                    ((1, 3), wgpu::BindingResource::Sampler(&resources.get_texture(slime2).sampler))];
+            bindings.extend(shadow_resources.iter().cloned());
+            t.create_bind_groups(&configuration.device, &bindings).unwrap()
+        };
+        let t_bindgroups = resources.insert_bind_groups("grass_rock_bind_groups", t_bindgroups, &[grass.into(), rock.into(), shadow_map.get_texture().into()]);
+        let t_slime_bindgroups = resources.insert_bind_groups("slime_bind_groups", t_slime_bindgroups, &[slime.into(), slime2.into(), shadow_map.get_texture().into()]);
 
 
         // The environment (mountains marching cubes). We need to disable shader validation because atomic counter are not supported yet.
@@ -235,13 +230,11 @@ impl Application for HelloApp {
         ); 
 
         // Create output buffer for "mountains", the output of mc.
-        buffers.insert(
-            "mc_output".to_string(),
-            buffer_from_data::<f32>(
+        let mc_output = resources.create_buffer_from_data::<f32>(
             &configuration.device,
+            "mc_output",
             &vec![0 as f32 ; 128*128*64*24],
-            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-            None)
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST
         );
 
         // Create parameters for "mountain" marching cubes.
@@ -256,9 +249,9 @@ impl Application for HelloApp {
         let mc_bind_groups = mc.create_bind_groups(
             &configuration.device,
             &mc_params,
-            &buffers.get("mc_output").unwrap(),
+            resources.get_buffer(mc_output),
             None,
-        );
+        ).unwrap();
 
         // Add create bind groups to the mc_params.
         mc_params.bind_groups = Some(mc_bind_groups); 
@@ -283,14 +276,12 @@ impl Application for HelloApp {
         );
 
         // Create output buffer for slime triangle mesh (the output of slime mc).
-        buffers.insert(
-            "mc_output_slime".to_string(),
-            buffer_from_data::<f32>(
+        let mc_output_slime = resources.create_buffer_from_data::<f32>(
             &configuration.device,
+            "mc_output_slime",
             //&vec![0 as f32 ; 128*128*64*24],
             &vec![0 as f32 ; 128*128*80*24],
-            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-            None)
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST
         );
 
         // Create parameters for "slime" marching cubes.
@@ -302,33 +293,29 @@ impl Application for HelloApp {
         );
 
         // Create density values buffer for slime.
-        buffers.insert(
-            "3dnoise_slime".to_string(),
-            buffer_from_data::<f32>(
+        let noise3d_slime = resources.create_buffer_from_data::<f32>(
             &configuration.device,
+            "3dnoise_slime",
             //&vec![0 as f32 ; 64*2*64*16*4],
             &vec![0 as f32 ; 256*8*256],
             //&vec![0 as f32 ; 256*12*256],
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
-            None)
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC
         );
         // Future usage1
-        buffers.insert(
-            "future_usage1_noise3d".to_string(), 
-            buffer_from_data::<f32>(
+        let future_usage1_noise3d = resources.create_buffer_from_data::<f32>(
             &configuration.device,
+            "future_usage1_noise3d",
             &vec![0.3,0.3,0.3,0.3],
-            wgpu::BufferUsages::COPY_DST |wgpu::BufferUsages::STORAGE,
-            None)
+            wgpu::BufferUsages::COPY_DST |wgpu::BufferUsages::STORAGE
         );
 
         // Create bind groups for slime.
         let mc_bind_groups_slime = mc_slime.create_bind_groups(
             &configuration.device,
             &mc_params_slime,
-            &buffers.get("mc_output_slime").unwrap(),
-            Some(&buffers.get("3dnoise_slime").unwrap())
-        );
+            resources.get_buffer(mc_output_slime),
+            Some(resources.get_buffer(noise3d_slime))
+        ).unwrap();
 
         // Add create bind groups to the mc_slime.
         mc_params_slime.bind_groups = Some(mc_bind_groups_slime); 
@@ -351,53 +338,37 @@ impl Application for HelloApp {
         );
 
         // Create uniform buffer uvec3 for number of invocations.
-        buffers.insert(
-            "slime_invocations".to_string(),
-            buffer_from_data::<u32>(
+        let slime_invocations = resources.create_buffer_from_data::<u32>(
             &configuration.device,
+            "slime_invocations",
             //&vec![64,3,64],
             &vec![64,2,64],
-            wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE,
-            None)
+            wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE
         );
         // Create uniform buffer uvec3 for area dimensions.
-        buffers.insert(
-            "slime_dimensions".to_string(),
-            buffer_from_data::<u32>(
+        let slime_dimensions = resources.create_buffer_from_data::<u32>(
             &configuration.device,
+            "slime_dimensions",
             &vec![256,8,256],
             //&vec![256,12,256],
             //&vec![256,24,256],
-            wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE,
-            None)
+            wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE
         );
 
         let slime_texture3d_bindgroups =
-                create_bind_groups(
-                    &configuration.device, 
-                    &texture3_d.layout_entries,
-                    &vec![
-                        vec![&wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                                buffer: &buffers.get("slime_invocations").unwrap(),
-                                offset: 0,
-                                size: None,
-                        }), 
-                        &wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                                buffer: &buffers.get("slime_dimensions").unwrap(),
-                                offset: 0,
-                                size: None,
-                        }), 
-                        &wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                                buffer: &buffers.get("future_usage1_noise3d").unwrap(),
-                                offset: 0,
-                                size: None,
-                        }), 
-                        &wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                                buffer: &buffers.get("3dnoise_slime").unwrap(),
-                                offset: 0,
-                                size: None,
-                        })]
+                texture3_d.create_bind_groups(
+                    &configuration.device,
+                    &[
+                        ((0, 0), resources.get_buffer(slime_invocations).as_entire_binding()),
+                        ((0, 1), resources.get_buffer(slime_dimensions).as_entire_binding()),
+                        ((0, 2), resources.get_buffer(future_usage1_noise3d).as_entire_binding()),
+                        ((0, 3), resources.get_buffer(noise3d_slime).as_entire_binding()),
                     ]
+        ).unwrap();
+        let slime_texture3d_bindgroups = resources.insert_bind_groups(
+            "slime_texture3d_bind_groups",
+            slime_texture3d_bindgroups,
+            &[slime_invocations.into(), slime_dimensions.into(), future_usage1_noise3d.into(), noise3d_slime.into()]
         );
         log::info!("Create mountain and first slime");
 
@@ -411,7 +382,7 @@ impl Application for HelloApp {
                     64
         ); 
 
-        texture3_d.dispatch(&slime_texture3d_bindgroups,
+        texture3_d.dispatch(resources.get_bind_groups(slime_texture3d_bindgroups),
                     &mut encoder,
                     64 * 2 * 64,
                     //64 * 3 * 64,
@@ -428,41 +399,37 @@ impl Application for HelloApp {
                     64
         );
 
+        // The vertex counts stay on the gpu as draw indirect arguments.
+        mc_params.write_draw_indirect(&mut encoder);
+        mc_params_slime.write_draw_indirect(&mut encoder);
+
         configuration.queue.submit(Some(encoder.finish()));
         log::info!("Dispatch finished.");
 
-        // The number of mountain vertices (from marching cubes).
-        let k = to_vec::<u32>(&configuration.device,
-                              &configuration.queue,
-                              &mc_params.counter_buffer,
-                              0 as wgpu::BufferAddress,
-                              4 as wgpu::BufferAddress);
-
-        // The number of initial slime vertices (from marching cubes).
-        let k_slime = to_vec::<u32>(&configuration.device,
-                                    &configuration.queue,
-                                    &mc_params_slime.counter_buffer,
-                                    0 as wgpu::BufferAddress,
-                                    4 as wgpu::BufferAddress);
-
         log::info!("Application data initialized.");
+        log::info!("{}", resources.report());
 
         HelloApp {
-            textures: textures,
-            buffers: buffers,
+            resources: resources,
+            mc_output: mc_output,
+            mc_output_slime: mc_output_slime,
+            noise3d_slime: noise3d_slime,
+            future_usage1_noise3d: future_usage1_noise3d,
             //two_triangles: two_triangles,
             //two_triangles_bind_group: bind_group,
-            depth_texture: depth_texture,
+            transient_textures: TransientTextures::init(),
             camera: camera,
             mc_slime: mc_slime,
             test_layout: t,
             bind: t_bindgroups,
             bind_slime: t_slime_bindgroups,
-            draw_count_mc: k[0],
-            draw_count_mc_slime: k_slime[0],
+            mc_params: mc_params,
             mc_params_slime: mc_params_slime,
             slime_texture3d_bindgroups: slime_texture3d_bindgroups,
             custom_3d: texture3_d,
+            shadow_map: shadow_map,
+            shadow_pipeline: shadow_pipeline,
+            shadow_bind_groups: shadow_bind_groups,
             //shaders: load_shaders(&configuration.device),
             //render_passes: HashMap::<String, RenderPass>::new(),
         }
@@ -483,38 +450,91 @@ impl Application for HelloApp {
             },
         };
 
-        let mut encoder = device.create_command_encoder(
-            &wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-        });
-
         let view = frame.texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        // The mountain.
-        draw(&mut encoder,
-             //&frame,
-             &view,
-             &self.depth_texture,
-             &self.bind,
-             &self.test_layout.pipeline,
-             &self.buffers.get("mc_output").unwrap(),
-             0..self.draw_count_mc, 
-             true
+        let mut graph = FrameGraph::<HelloApp>::init("hello frame");
+        let color_texture = graph.create_texture(
+            "msaa color texture",
+            TransientTextureDescriptor::attachment(&sc_desc, sc_desc.format).with_sample_count(SAMPLE_COUNT)
         );
-
-        // The slime.
-        draw(&mut encoder,
-             //&frame,
-             &view,
-             &self.depth_texture,
-             &self.bind_slime,
-             &self.test_layout.pipeline,
-             &self.buffers.get("mc_output_slime").unwrap(),
-             0..self.draw_count_mc_slime, 
-             false
+        let depth_texture = graph.create_texture(
+            "depth_texture",
+            TransientTextureDescriptor::attachment(&sc_desc, JTexture::DEPTH_FORMAT).with_sample_count(SAMPLE_COUNT)
         );
 
-        queue.submit(Some(encoder.finish()));
+        // Create a new density scalar field for marching cubes slime.
+        graph.add_compute_pass("slime density")
+             .write(self.noise3d_slime)
+             .execute(|app, encoder| {
+                 app.custom_3d.dispatch(app.resources.get_bind_groups(app.slime_texture3d_bindgroups),
+                             encoder,
+                             64 * 2 * 64,
+                             //64 * 3 * 64,
+                             1,
+                             1
+                 );
+             });
+
+        // Create slime. The vertex count stays on the gpu as draw indirect arguments.
+        graph.add_compute_pass("slime marching cubes")
+             .read(self.noise3d_slime)
+             .write(self.mc_output_slime)
+             .execute(|app, encoder| {
+                 app.mc_slime.dispatch(&app.mc_params_slime.bind_groups.as_ref().unwrap(),
+                             encoder,
+                             64,
+                             2,
+                             //3,
+                             64
+                 );
+                 app.mc_params_slime.write_draw_indirect(encoder);
+             });
+
+        // The shadow cascades. The frame graph doesn't know the cascade layers so the passes are
+        // recorded straight to the encoder. The cascades are cleared by begin_cascade_pass.
+        graph.add_compute_pass("shadows")
+             .read(self.mc_output)
+             .read(self.mc_output_slime)
+             .write(self.shadow_map.get_texture())
+             .execute(|app, encoder| {
+                 for cascade in 0..app.shadow_map.get_cascade_count() {
+                     let mut pass = app.shadow_map.begin_cascade_pass(encoder, cascade);
+                     let bind_groups = &app.shadow_bind_groups[cascade as usize];
+                     DrawCall::init(app.shadow_pipeline.get_pipeline(), bind_groups)
+                         .vertex_buffer(app.resources.get_buffer(app.mc_output))
+                         .draw_indirect(&mut pass, &app.mc_params.draw_indirect_buffer, 0);
+                     DrawCall::init(app.shadow_pipeline.get_pipeline(), bind_groups)
+                         .vertex_buffer(app.resources.get_buffer(app.mc_output_slime))
+                         .draw_indirect(&mut pass, &app.mc_params_slime.draw_indirect_buffer, 0);
+                 }
+             });
+
+        // The mountain.
+        graph.add_render_pass("mountain")
+             .color_resolve(color_texture, GraphResource::Surface, CLEAR_COLOR)
+             .depth(depth_texture, 1.0)
+             .read(self.mc_output)
+             .read(self.shadow_map.get_texture())
+             .execute(|app, pass| {
+                 DrawCall::init(&app.test_layout.pipeline, app.resources.get_bind_groups(app.bind))
+                     .vertex_buffer(app.resources.get_buffer(app.mc_output))
+                     .draw_indirect(pass, &app.mc_params.draw_indirect_buffer, 0);
+             });
+
+        // The slime.
+        graph.add_render_pass("slime")
+             .color_resolve(color_texture, GraphResource::Surface, CLEAR_COLOR)
+             .depth(depth_texture, 1.0)
+             .read(self.mc_output_slime)
+             .read(self.shadow_map.get_texture())
+             .execute(|app, pass| {
+                 DrawCall::init(&app.test_layout.pipeline, app.resources.get_bind_groups(app.bind_slime))
+                     .vertex_buffer(app.resources.get_buffer(app.mc_output_slime))
+                     .draw_indirect(pass, &app.mc_params_slime.draw_indirect_buffer, 0);
+             });
+
+        graph.allocate(&device, &mut self.transient_textures);
+        graph.execute(&device, &queue, &self.resources, Some(&view), &self.transient_textures, self);
         frame.present();
     }
 
@@ -522,37 +542,26 @@ impl Application for HelloApp {
         // self.camera.update_from_input(&queue, &input_cache);
     }
 
-    fn resize(&mut self, device: &wgpu::Device, sc_desc: &wgpu::SurfaceConfiguration, _new_size: winit::dpi::PhysicalSize<u32>) {
-        self.depth_texture = JTexture::create_depth_texture(&device, &sc_desc, Some("depth-texture"));
+    fn resize(&mut self, _device: &wgpu::Device, sc_desc: &wgpu::SurfaceConfiguration, _new_size: winit::dpi::PhysicalSize<u32>) {
         self.camera.resize(sc_desc.width as f32, sc_desc.height as f32);
     }
 
-    fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, input: &InputCache) {
+    fn update(&mut self, _device: &wgpu::Device, queue: &wgpu::Queue, input: &InputCache) {
 
         let val = ((input.get_time() / 5000000) as f32) * 0.0015;
 
         //log::info!("val == {}", val);
 
         queue.write_buffer(
-            &self.buffers.get("future_usage1_noise3d").unwrap(),
+            self.resources.get_buffer(self.future_usage1_noise3d),
             0,
             bytemuck::cast_slice(&vec![val, 0.0, 0.0, 0.0])
         );
 
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Compute encoder (noise/slime).") });
-
         self.camera.update_from_input(&queue, &input);
+        self.shadow_map.update(&queue, &self.camera, cgmath::Vector3::from(LIGHT_DIRECTION));
 
-        // Create a new density scalar field for marching cubes slime.
-        self.custom_3d.dispatch(&self.slime_texture3d_bindgroups,
-                    &mut encoder,
-                    64 * 2 * 64,
-                    //64 * 3 * 64,
-                    1,
-                    1
-        );
-        
-        // Create slime.
+        // The slime parameters of the next frame. The slime is created by the frame graph.
         self.mc_params_slime.reset_counter(&queue);
         self.mc_params_slime.update_params(
             &queue,
@@ -566,25 +575,6 @@ impl Application for HelloApp {
         //        cgmath::Vector4::<f32>::new(0.0,0.5,0.0,1.0),
         //        0.0,
         //        0.05
-
-        self.mc_slime.dispatch(&self.mc_params_slime.bind_groups.as_ref().unwrap(),
-                    &mut encoder,
-                    64,
-                    2,
-                    //3,
-                    64
-        );
-
-        queue.submit(Some(encoder.finish()));
-
-        let k_slime = to_vec::<u32>(&device,
-                                    &queue,
-                                    &self.mc_params_slime.counter_buffer,
-                                    0 as wgpu::BufferAddress,
-                                    4 as wgpu::BufferAddress);
-
-        self.draw_count_mc_slime = k_slime[0];
-        //log::info!("k_slime[0] == {}", k_slime[0]);
     }
 }
 
@@ -0,0 +1,537 @@
+use std::collections::HashMap;
+use std::fmt;
+use winit::event::MouseButton;
+use crate::input::{InputCache, InputState, Key};
+use crate::gamepad::{GamepadButton, GamepadAxis};
+
+/// A keyboard key, a mouse button, a gamepad button or a gamepad axis. The gamepad inputs are
+/// read from any connected gamepad.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(Key),
+    Mouse(MouseButton),
+    GamepadButton(GamepadButton),
+    GamepadAxis(GamepadAxis),
+}
+
+impl Input {
+
+    /// Parse a VirtualKeyCode name (W, Key1, LShift, Space, ...), a mouse button name
+    /// (MouseLeft, MouseMiddle, MouseRight, Mouse<n>) or a gamepad button or axis name prefixed
+    /// with Gamepad (GamepadSouth, GamepadDPadUp, GamepadLeftStickX, ...). The names are case
+    /// insensitive.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        let name = name.trim();
+        let lower = name.to_ascii_lowercase();
+        match lower.as_str() {
+            "mouseleft" => return Ok(Input::Mouse(MouseButton::Left)),
+            "mousemiddle" => return Ok(Input::Mouse(MouseButton::Middle)),
+            "mouseright" => return Ok(Input::Mouse(MouseButton::Right)),
+            _ => {}
+        }
+        if let Some(n) = lower.strip_prefix("mouse") {
+            if let Ok(n) = n.parse::<u16>() { return Ok(Input::Mouse(MouseButton::Other(n))); }
+        }
+        if let Some(n) = lower.strip_prefix("gamepad") {
+            if let Some((_, b)) = GAMEPAD_BUTTONS.iter().find(|(b, _)| b.eq_ignore_ascii_case(n)) {
+                return Ok(Input::GamepadButton(*b));
+            }
+            if let Some((_, a)) = GAMEPAD_AXES.iter().find(|(a, _)| a.eq_ignore_ascii_case(n)) {
+                return Ok(Input::GamepadAxis(*a));
+            }
+        }
+        match KEYS.iter().find(|(key_name, _)| key_name.eq_ignore_ascii_case(name)) {
+            Some((_, key)) => Ok(Input::Key(*key)),
+            None => Err(format!("Unknown key, mouse button or gamepad input '{}'.", name)),
+        }
+    }
+
+    /// Check if the input is pressed or down. The gamepad axes are down over half way.
+    fn is_down(&self, input: &InputCache) -> bool {
+        match self {
+            Input::GamepadAxis(_) => self.value(input) > 0.5,
+            _ => is_down(&self.state(input)),
+        }
+    }
+
+    /// The state of the input. Always None for the gamepad axes.
+    fn state(&self, input: &InputCache) -> Option<InputState> {
+        match self {
+            Input::Key(key) => input.key_state(key),
+            Input::Mouse(button) => input.mouse_button_state(button),
+            Input::GamepadButton(button) => input.get_gamepads().any_button_state(button),
+            Input::GamepadAxis(_) => None,
+        }
+    }
+
+    /// The value of the input. The value of an axis or 1.0 if the button is down.
+    fn value(&self, input: &InputCache) -> f32 {
+        match self {
+            Input::GamepadAxis(axis) => input.get_gamepads().any_axis(axis),
+            _ => if is_down(&self.state(input)) { 1.0 } else { 0.0 },
+        }
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Input::Key(key) => write!(f, "{:?}", key),
+            Input::Mouse(MouseButton::Left) => write!(f, "MouseLeft"),
+            Input::Mouse(MouseButton::Middle) => write!(f, "MouseMiddle"),
+            Input::Mouse(MouseButton::Right) => write!(f, "MouseRight"),
+            Input::Mouse(MouseButton::Other(n)) => write!(f, "Mouse{}", n),
+            Input::GamepadButton(button) => write!(f, "Gamepad{:?}", button),
+            Input::GamepadAxis(axis) => write!(f, "Gamepad{:?}", axis),
+        }
+    }
+}
+
+/// A single input or a chord. The binding follows the state of the trigger while all the held
+/// inputs are down, e.g. LControl+S.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Binding {
+    pub held: Vec<Input>,
+    pub trigger: Input,
+}
+
+impl Binding {
+
+    pub fn key(key: Key) -> Self {
+        Self { held: Vec::new(), trigger: Input::Key(key) }
+    }
+
+    pub fn mouse(button: MouseButton) -> Self {
+        Self { held: Vec::new(), trigger: Input::Mouse(button) }
+    }
+
+    pub fn chord(held: &[Input], trigger: Input) -> Self {
+        Self { held: held.to_vec(), trigger: trigger }
+    }
+
+    /// Parse a binding. The inputs of a chord are joined with '+' and the last one is the trigger.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut inputs = text.split('+')
+                             .map(Input::parse)
+                             .collect::<Result<Vec<Input>, String>>()?;
+        let trigger = inputs.pop().unwrap();
+        Ok(Self { held: inputs, trigger: trigger })
+    }
+
+    /// The state of the binding. None if the binding isn't active or a more specific chord with
+    /// the same trigger is active (LControl+S hides S).
+    fn state(&self, input: &InputCache, all_bindings: &[&Binding]) -> Option<InputState> {
+        if !self.held.iter().all(|i| i.is_down(input)) { return None; }
+        let hidden = all_bindings.iter().any(|b| {
+            b.trigger == self.trigger &&
+            b.held.len() > self.held.len() &&
+            self.held.iter().all(|i| b.held.contains(i)) &&
+            b.held.iter().all(|i| i.is_down(input))
+        });
+        if hidden { None } else { self.trigger.state(input) }
+    }
+
+    /// The value of the binding. 0.0 if the binding isn't active.
+    fn value(&self, input: &InputCache, all_bindings: &[&Binding]) -> f32 {
+        match self.trigger {
+            Input::GamepadAxis(_) => {
+                if self.held.iter().all(|i| i.is_down(input)) { self.trigger.value(input) } else { 0.0 }
+            }
+            _ => if is_down(&self.state(input, all_bindings)) { 1.0 } else { 0.0 },
+        }
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in self.held.iter() { write!(f, "{}+", i)?; }
+        write!(f, "{}", self.trigger)
+    }
+}
+
+/// A named action.
+/// Button: the state of the first active binding. The gamepad axes are ignored.
+/// Axis: a value in [-1, 1]. The value of the strongest positive binding minus the value of the
+/// strongest negative binding. The buttons have the value 1.0 when down and the gamepad axes their
+/// analog value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Button(Vec<Binding>),
+    Axis { positive: Vec<Binding>, negative: Vec<Binding> },
+}
+
+impl Action {
+    fn bindings(&self) -> Vec<&Binding> {
+        match self {
+            Action::Button(bindings) => bindings.iter().collect(),
+            Action::Axis { positive, negative } => positive.iter().chain(negative.iter()).collect(),
+        }
+    }
+}
+
+/// Named actions bound to keys, mouse buttons and chords. Applications query the actions instead
+/// of the raw keys, so the keys can be rebound from a config file.
+///
+/// The config file has one action per line:
+///
+/// ```text
+/// # Comment.
+/// button quit = Q
+/// button save = LControl+S, F2
+/// axis move_forward = W / S
+/// ```
+///
+/// The bindings are separated with ',' and the positive and negative bindings of an axis with '/'.
+#[derive(Clone, Debug)]
+pub struct ActionMap {
+    actions: HashMap<String, Action>,
+}
+
+impl ActionMap {
+
+    pub fn init() -> Self {
+        Self {
+            actions: HashMap::new(),
+        }
+    }
+
+    /// Parse actions from a config string.
+    pub fn parse(text: &str) -> Result<Self, String> {
+
+        let mut result = ActionMap::init();
+
+        for (line_number, line) in text.lines().enumerate() {
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+
+            let error = |message: String| -> String { format!("line {}: {}", line_number + 1, message) };
+
+            let (declaration, bindings) = match line.split_once('=') {
+                Some(d) => d,
+                None => return Err(error(format!("expected '=' in '{}'.", line))),
+            };
+
+            let parse_bindings = |text: &str| -> Result<Vec<Binding>, String> {
+                text.split(',')
+                    .filter(|b| !b.trim().is_empty())
+                    .map(Binding::parse)
+                    .collect::<Result<Vec<Binding>, String>>()
+                    .map_err(error)
+            };
+
+            let declaration: Vec<&str> = declaration.split_whitespace().collect();
+            let action = match declaration.as_slice() {
+                ["button", _] => Action::Button(parse_bindings(bindings)?),
+                ["axis", _] => {
+                    let (positive, negative) = match bindings.split_once('/') {
+                        Some(pn) => pn,
+                        None => return Err(error("expected 'positive / negative' bindings for an axis.".to_string())),
+                    };
+                    Action::Axis { positive: parse_bindings(positive)?, negative: parse_bindings(negative)? }
+                }
+                _ => return Err(error(format!("expected 'button <name>' or 'axis <name>', found '{}'.", declaration.join(" ")))),
+            };
+
+            let _ = result.actions.insert(declaration[1].to_string(), action);
+        }
+
+        Ok(result)
+    }
+
+    /// Load actions from a config file.
+    pub fn load(file_name: &str) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(file_name)?;
+        ActionMap::parse(&text).map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", file_name, e))
+        })
+    }
+
+    /// Save the actions to a config file.
+    pub fn save(&self, file_name: &str) -> std::io::Result<()> {
+        std::fs::write(file_name, self.to_config())
+    }
+
+    /// The actions in the config file format.
+    pub fn to_config(&self) -> String {
+        let join = |bindings: &Vec<Binding>| -> String {
+            bindings.iter().map(|b| b.to_string()).collect::<Vec<String>>().join(", ")
+        };
+        let mut names: Vec<&String> = self.actions.keys().collect();
+        names.sort();
+        let mut result = String::new();
+        for name in names {
+            match &self.actions[name] {
+                Action::Button(bindings) => {
+                    result.push_str(&format!("button {} = {}\n", name, join(bindings)));
+                }
+                Action::Axis { positive, negative } => {
+                    result.push_str(&format!("axis {} = {} / {}\n", name, join(positive), join(negative)));
+                }
+            }
+        }
+        result
+    }
+
+    /// Add the actions of other. The actions with the same name are replaced. Use this to apply
+    /// the user config over the default actions.
+    pub fn merge(&mut self, other: &ActionMap) {
+        for (name, action) in other.actions.iter() {
+            let _ = self.actions.insert(name.clone(), action.clone());
+        }
+    }
+
+    /// Add or replace the action.
+    pub fn set_action(&mut self, name: &str, action: Action) {
+        let _ = self.actions.insert(name.to_string(), action);
+    }
+
+    /// Add a binding to a button action. Creates the action if it doesn't exist.
+    pub fn bind_button(&mut self, name: &str, binding: Binding) {
+        match self.actions.get_mut(name) {
+            Some(Action::Button(bindings)) => { bindings.push(binding); }
+            _ => { let _ = self.actions.insert(name.to_string(), Action::Button(vec![binding])); }
+        }
+    }
+
+    /// Add bindings to an axis action. Creates the action if it doesn't exist.
+    pub fn bind_axis(&mut self, name: &str, positive: Binding, negative: Binding) {
+        match self.actions.get_mut(name) {
+            Some(Action::Axis { positive: p, negative: n }) => { p.push(positive); n.push(negative); }
+            _ => { let _ = self.actions.insert(name.to_string(), Action::Axis { positive: vec![positive], negative: vec![negative] }); }
+        }
+    }
+
+    /// Remove the action.
+    pub fn unbind(&mut self, name: &str) {
+        let _ = self.actions.remove(name);
+    }
+
+    pub fn get_action(&self, name: &str) -> Option<&Action> {
+        self.actions.get(name)
+    }
+
+    /// The pairs of actions that share a binding.
+    pub fn conflicts(&self) -> Vec<(String, String, Binding)> {
+        let mut names: Vec<&String> = self.actions.keys().collect();
+        names.sort();
+        let mut result = Vec::new();
+        for (i, a) in names.iter().enumerate() {
+            for b in names[i+1..].iter() {
+                let b_bindings = self.actions[*b].bindings();
+                for binding in self.actions[*a].bindings() {
+                    if b_bindings.contains(&binding) {
+                        result.push(((*a).clone(), (*b).clone(), binding.clone()));
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// The state of a button action. None if the action isn't active or doesn't exist.
+    pub fn button_state(&self, input: &InputCache, name: &str) -> Option<InputState> {
+        match self.actions.get(name) {
+            Some(Action::Button(bindings)) => {
+                let all_bindings = self.all_bindings();
+                bindings.iter().find_map(|b| b.state(input, &all_bindings))
+            }
+            _ => None,
+        }
+    }
+
+    /// Check if the button action is pressed or down.
+    pub fn is_down(&self, input: &InputCache, name: &str) -> bool {
+        is_down(&self.button_state(input, name))
+    }
+
+    /// Check if the button action is pressed, down or was released during this tick.
+    pub fn is_active(&self, input: &InputCache, name: &str) -> bool {
+        self.button_state(input, name).is_some()
+    }
+
+    /// Check if the button action was pressed during this tick.
+    pub fn is_pressed(&self, input: &InputCache, name: &str) -> bool {
+        matches!(self.button_state(input, name), Some(InputState::Pressed(_)))
+    }
+
+    /// The value of an axis action in [-1, 1]. 0.0 if the action doesn't exist.
+    pub fn axis(&self, input: &InputCache, name: &str) -> f32 {
+        match self.actions.get(name) {
+            Some(Action::Axis { positive, negative }) => {
+                let all_bindings = self.all_bindings();
+                let strongest = |bindings: &Vec<Binding>| -> f32 {
+                    bindings.iter()
+                            .map(|b| b.value(input, &all_bindings))
+                            .fold(0.0, |a, b| if b.abs() > a.abs() { b } else { a })
+                };
+                (strongest(positive) - strongest(negative)).max(-1.0).min(1.0)
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn all_bindings(&self) -> Vec<&Binding> {
+        self.actions.values().flat_map(|a| a.bindings()).collect()
+    }
+}
+
+fn is_down(state: &Option<InputState>) -> bool {
+    matches!(state, Some(InputState::Pressed(_)) | Some(InputState::Down(_,_)))
+}
+
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        /// The names of the keys in the config files. All the VirtualKeyCode variants are listed,
+        /// so every key can be bound and recorded.
+        const KEYS: &[(&str, Key)] = &[$((stringify!($key), Key::$key)),*];
+    };
+}
+
+key_names!(
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Snapshot, Scroll, Pause, Insert, Home, Delete, End, PageDown, PageUp,
+    Left, Up, Right, Down, Back, Return, Space,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    NumpadAdd, NumpadDivide, NumpadDecimal, NumpadMultiply, NumpadSubtract,
+    Apostrophe, Asterisk, Backslash, Comma, Equals, Grave, LBracket, Minus, Period, Plus, RBracket,
+    Semicolon, Slash, Tab,
+    LAlt, LControl, LShift, LWin, RAlt, RControl, RShift, RWin,
+    F13, F14, F15, F16, F17, F18, F19, F20, F21, F22, F23, F24,
+    Compose, Caret, Numlock, NumpadComma, NumpadEnter, NumpadEquals,
+    AbntC1, AbntC2, Apps, At, Ax, Calculator, Capital, Colon, Convert, Kana, Kanji, Mail,
+    MediaSelect, MediaStop, Mute, MyComputer, NavigateForward, NavigateBackward, NextTrack,
+    NoConvert, OEM102, PlayPause, Power, PrevTrack, Sleep, Stop, Sysrq, Underline, Unlabeled,
+    VolumeDown, VolumeUp, Wake, WebBack, WebFavorites, WebForward, WebHome, WebRefresh,
+    WebSearch, WebStop, Yen, Copy, Paste, Cut,
+);
+
+/// The names of the gamepad buttons in the config files (without the Gamepad prefix).
+const GAMEPAD_BUTTONS: &[(&str, GamepadButton)] = &[
+    ("South", GamepadButton::South),
+    ("East", GamepadButton::East),
+    ("North", GamepadButton::North),
+    ("West", GamepadButton::West),
+    ("LeftBumper", GamepadButton::LeftBumper),
+    ("RightBumper", GamepadButton::RightBumper),
+    ("Select", GamepadButton::Select),
+    ("Start", GamepadButton::Start),
+    ("Mode", GamepadButton::Mode),
+    ("LeftStick", GamepadButton::LeftStick),
+    ("RightStick", GamepadButton::RightStick),
+    ("DPadUp", GamepadButton::DPadUp),
+    ("DPadDown", GamepadButton::DPadDown),
+    ("DPadLeft", GamepadButton::DPadLeft),
+    ("DPadRight", GamepadButton::DPadRight),
+];
+
+/// The names of the gamepad axes in the config files (without the Gamepad prefix).
+const GAMEPAD_AXES: &[(&str, GamepadAxis)] = &[
+    ("LeftStickX", GamepadAxis::LeftStickX),
+    ("LeftStickY", GamepadAxis::LeftStickY),
+    ("RightStickX", GamepadAxis::RightStickX),
+    ("RightStickY", GamepadAxis::RightStickY),
+    ("LeftTrigger", GamepadAxis::LeftTrigger),
+    ("RightTrigger", GamepadAxis::RightTrigger),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::ElementState;
+    use crate::input::{InputEvent, Clock};
+
+    const CONFIG: &str = "
+# Comment.
+button quit = Q
+button save = LControl+S, F2
+button pick = MouseLeft
+axis move_forward = W, GamepadLeftStickY / S
+";
+
+    /// An input cache where the keys were pressed during the last tick.
+    fn press(keys: &[Key]) -> InputCache {
+        let mut input = InputCache::init_with_clock(Clock::Manual(0));
+        input.get_clock_mut().set(16);
+        input.pre_update();
+        for key in keys.iter() { input.apply(&InputEvent::Key(*key, ElementState::Pressed)); }
+        input
+    }
+
+    #[test]
+    fn binding_lookup() {
+        let actions = ActionMap::parse(CONFIG).unwrap();
+        assert_eq!(actions.get_action("save"),
+                   Some(&Action::Button(vec![Binding::chord(&[Input::Key(Key::LControl)], Input::Key(Key::S)),
+                                             Binding::key(Key::F2)])));
+        assert_eq!(actions.get_action("pick"), Some(&Action::Button(vec![Binding::mouse(MouseButton::Left)])));
+        assert!(actions.get_action("jump").is_none());
+
+        let input = press(&[Key::Q, Key::W]);
+        assert!(actions.is_pressed(&input, "quit") && actions.is_down(&input, "quit"));
+        assert!(!actions.is_active(&input, "save"));
+        assert_eq!(actions.axis(&input, "move_forward"), 1.0);
+        assert_eq!(actions.axis(&input, "quit"), 0.0);
+        assert!(!actions.is_pressed(&input, "move_forward") && !actions.is_pressed(&input, "jump"));
+
+        // Both directions cancel out.
+        assert_eq!(actions.axis(&press(&[Key::W, Key::S]), "move_forward"), 0.0);
+
+        // The chord hides the plain S of the axis.
+        let input = press(&[Key::LControl, Key::S]);
+        assert!(actions.is_pressed(&input, "save"));
+        assert_eq!(actions.axis(&input, "move_forward"), 0.0);
+        assert!(!actions.is_active(&press(&[Key::S]), "save"));
+    }
+
+    #[test]
+    fn override_binding() {
+        let mut actions = ActionMap::parse(CONFIG).unwrap();
+        actions.merge(&ActionMap::parse("button quit = Escape\naxis move_forward = Up / Down").unwrap());
+
+        let input = press(&[Key::Q, Key::W]);
+        assert!(!actions.is_active(&input, "quit"));
+        assert_eq!(actions.axis(&input, "move_forward"), 0.0);
+        let input = press(&[Key::Escape, Key::Down]);
+        assert!(actions.is_pressed(&input, "quit"));
+        assert_eq!(actions.axis(&input, "move_forward"), -1.0);
+
+        // The other actions are kept.
+        assert!(actions.is_pressed(&press(&[Key::F2]), "save"));
+
+        actions.bind_button("quit", Binding::key(Key::Q));
+        assert!(actions.is_pressed(&press(&[Key::Q]), "quit"));
+        actions.set_action("quit", Action::Button(vec![Binding::key(Key::F2)]));
+        assert_eq!(actions.conflicts(), vec![("quit".to_string(), "save".to_string(), Binding::key(Key::F2))]);
+        actions.unbind("quit");
+        assert!(actions.get_action("quit").is_none() && actions.conflicts().is_empty());
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let actions = ActionMap::parse(CONFIG).unwrap();
+        let text = actions.to_config();
+        assert!(text.contains("button save = LControl+S, F2\n"), "{}", text);
+        let parsed = ActionMap::parse(&text).unwrap();
+        for name in ["quit", "save", "pick", "move_forward"] {
+            assert_eq!(parsed.get_action(name), actions.get_action(name));
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(ActionMap::parse("button quit = Q\nbutton quit Q").unwrap_err(),
+                   "line 2: expected '=' in 'button quit Q'.");
+        assert_eq!(ActionMap::parse("button quit = Qq").unwrap_err(),
+                   "line 1: Unknown key, mouse button or gamepad input 'Qq'.");
+        assert_eq!(ActionMap::parse("axis move = W").unwrap_err(),
+                   "line 1: expected 'positive / negative' bindings for an axis.");
+        assert_eq!(ActionMap::parse("trigger quit = Q").unwrap_err(),
+                   "line 1: expected 'button <name>' or 'axis <name>', found 'trigger quit'.");
+        assert!(ActionMap::parse("button = Q").unwrap_err().starts_with("line 1:"));
+        assert!(ActionMap::parse("button save = LControl+").is_err());
+        assert_eq!(ActionMap::load("no_such_actions.cfg").unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    }
+}
@@ -1,11 +1,22 @@
 use crate::misc::clamp;
-use crate::input::{InputCache, InputState};
+use crate::input::InputCache;
+use crate::actions::ActionMap;
 use crate::buffer::buffer_from_data;
-use cgmath::{prelude::*, Vector3, Vector4, Point3};
+use cgmath::{prelude::*, Vector3, Vector4, Point3, Quaternion, Rad};
+use geometry::ray::Ray;
+use geometry::aabb::BBox;
+use geometry::frustum::Frustum;
 
 pub use winit::event::VirtualKeyCode as Key;
 pub use winit::event::MouseButton as MouseButton;
 
+/// The default camera actions. The keys can be rebound with Camera::set_actions.
+pub const DEFAULT_CAMERA_ACTIONS: &str = include_str!("../camera_actions.cfg");
+
+/// The rotation speed of camera_yaw and camera_pitch actions (the same as mouse movement of
+/// this many pixels per millisecond).
+const STICK_ROTATION_SPEED: f32 = 0.5;
+
 /// Opengl to wgpu matrix
 //#[cfg_attr(rustfmt, surtfmt_skip)]
 #[rustfmt::skip]
@@ -35,7 +46,7 @@ pub struct RayCameraUniform {
     pos: cgmath::Vector4<f32>,
     view: cgmath::Vector4<f32>,
     up: cgmath::Vector4<f32>,
-    fov: cgmath::Vector2<f32>,
+    fov: cgmath::Vector2<f32>, // Horizontal and vertical field of view in radians.
     aperture_radius: f32,
     focal_distance: f32,
 }
@@ -43,13 +54,49 @@ pub struct RayCameraUniform {
 unsafe impl bytemuck::Zeroable for RayCameraUniform {}
 unsafe impl bytemuck::Pod for RayCameraUniform {}
 
+/// The camera control modes.
+/// Fly: W/S/A/D/E/C (or the left stick and the triggers) moves the camera and left mouse drag
+/// (or the right stick) turns it.
+/// Orbit: left mouse drag rotates the camera around the target keeping the up vector fixed, right
+/// mouse drag pans the target and the mouse wheel zooms.
+/// Arcball: like orbit, but the rotation is free (trackball) and the up vector rotates too.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraMode {
+    Fly,
+    Orbit,
+    Arcball,
+}
+
+/// The axis of the stored field of view. The field of view of the other axis depends on the
+/// aspect ratio.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FovAxis {
+    Vertical,
+    Horizontal,
+}
+
+/// The projection of the camera.
+/// Perspective: perspective projection between znear and zfar.
+/// ReverseZInfinite: perspective projection with infinite far plane. The depth is 1.0 at znear and
+/// goes towards 0.0 at infinity, so the depth test must be CompareFunction::Greater and the depth
+/// buffer must be cleared to 0.0 (see depth_compare and depth_clear_value).
+/// Orthographic: orthographic projection. The height of the view volume is given in world units.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    ReverseZInfinite,
+    Orthographic { height: f32 },
+}
+
 /// A camera for basic rendering and ray tracing purposes.
 pub struct Camera {
     pos: cgmath::Vector3<f32>,
     view: cgmath::Vector3<f32>,
     up: cgmath::Vector3<f32>,
     aspect: f32,
-    fov: cgmath::Vector2<f32>,
+    fov: f32, // The field of view of fov_axis in degrees.
+    fov_axis: FovAxis,
+    projection: Projection,
     znear: f32,
     zfar: f32,
     movement_sensitivity: f32,
@@ -58,6 +105,11 @@ pub struct Camera {
     yaw: f32,
     aperture_radius: f32, // For ray tracer camera.
     focal_distance: f32, // For ray tracer camera.
+    mode: CameraMode,
+    target: cgmath::Vector3<f32>, // For orbit and arcball modes.
+    distance: f32, // The distance to the target.
+    zoom_sensitivity: f32,
+    actions: ActionMap,
     camera_buffer: Option<wgpu::Buffer>, // A buffer to basic camera uniform buffer.
     ray_camera_buffer: Option<wgpu::Buffer>, // A buffer to ray tracing camear uniform buffer.
 }
@@ -74,14 +126,200 @@ impl Camera {
         self.rotation_sensitivity = sensitivity;
     }
 
+    pub fn set_zoom_sensitivity(&mut self, sensitivity: f32) {
+        assert!(sensitivity > 0.0 && sensitivity < 1.0, "Zoom sensitivity must be in range ]0, 1[.");
+        self.zoom_sensitivity = sensitivity;
+    }
+
+    /// Rebind the camera actions (camera_forward, camera_right, camera_up, camera_yaw,
+    /// camera_pitch, camera_slow, camera_rotate and camera_pan). Only the actions included in
+    /// actions are replaced.
+    pub fn set_actions(&mut self, actions: &ActionMap) {
+        for name in ["camera_forward", "camera_right", "camera_up", "camera_yaw", "camera_pitch",
+                     "camera_slow", "camera_rotate", "camera_pan"].iter() {
+            if let Some(action) = actions.get_action(name) {
+                self.actions.set_action(name, action.clone());
+            }
+        }
+    }
+
+    /// Set the field of view (degrees) of the given axis.
+    pub fn set_fov(&mut self, degrees: f32, axis: FovAxis) {
+        assert!(degrees > 0.0 && degrees < 180.0, "Field of view must be in range ]0, 180[.");
+        self.fov = degrees;
+        self.fov_axis = axis;
+    }
+
+    /// Get the horizontal and vertical field of view (degrees) of the perspective projection.
+    pub fn get_fov(&self) -> cgmath::Vector2<f32> {
+        let vertical = self.vertical_fov();
+        let horizontal = 2.0 * ((0.5 * vertical).tan() * self.aspect).atan();
+        cgmath::Vector2::<f32>::new(horizontal.to_degrees(), vertical.to_degrees())
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        if let Projection::Orthographic { height } = projection {
+            assert!(height > 0.0, "Orthographic height must be > 0.");
+        }
+        self.projection = projection;
+    }
+
+    pub fn get_projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_near_far(&mut self, znear: f32, zfar: f32) {
+        assert!(znear > 0.0 && zfar > znear, "0 < znear < zfar must hold.");
+        self.znear = znear;
+        self.zfar = zfar;
+    }
+
+    /// The near and far plane distances. ReverseZInfinite doesn't use the far plane.
+    pub fn get_near_far(&self) -> (f32, f32) {
+        (self.znear, self.zfar)
+    }
+
+    /// The world space corners of the view frustum between the distances near and far along the
+    /// view direction. The four near corners come first, both in the order bottom left, bottom
+    /// right, top right, top left. Used for fitting shadow cascades (see shadow::ShadowMap).
+    pub fn frustum_corners(&self, near: f32, far: f32) -> [Vector3<f32>; 8] {
+
+        let forward = self.view.normalize();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward);
+
+        let mut corners = [Vector3::<f32>::zero(); 8];
+        for (i, &distance) in [near, far].iter().enumerate() {
+            let half_height = match self.projection {
+                Projection::Orthographic { height } => 0.5 * height,
+                _ => distance * (0.5 * self.vertical_fov()).tan(),
+            };
+            let half_width = half_height * self.aspect;
+            let center = self.pos + forward * distance;
+            corners[i * 4]     = center - right * half_width - up * half_height;
+            corners[i * 4 + 1] = center + right * half_width - up * half_height;
+            corners[i * 4 + 2] = center + right * half_width + up * half_height;
+            corners[i * 4 + 3] = center - right * half_width + up * half_height;
+        }
+        corners
+    }
+
+    /// The depth compare function that must be used with the projection.
+    pub fn depth_compare(&self) -> wgpu::CompareFunction {
+        match self.projection {
+            Projection::ReverseZInfinite => wgpu::CompareFunction::Greater,
+            _ => wgpu::CompareFunction::Less,
+        }
+    }
+
+    /// The value that the depth buffer must be cleared to with the projection.
+    pub fn depth_clear_value(&self) -> f32 {
+        match self.projection {
+            Projection::ReverseZInfinite => 0.0,
+            _ => 1.0,
+        }
+    }
+
+    pub fn get_position(&self) -> Vector3<f32> {
+        self.pos
+    }
+
+    pub fn get_view(&self) -> Vector3<f32> {
+        self.view
+    }
+
+    pub fn get_up(&self) -> Vector3<f32> {
+        self.up
+    }
+
+    /// Set the position, view direction and up vector of the camera. The up vector must not be
+    /// parallel to the view direction. The orbit target is moved in front of the camera.
+    pub fn set_pos_view_up(&mut self, pos: &Vector3<f32>, view: &Vector3<f32>, up: &Vector3<f32>) {
+        assert!(view.cross(*up).magnitude2() > 0.0, "View direction and up vector must not be parallel.");
+        self.pos = *pos;
+        self.view = view.normalize();
+        self.up = up.normalize();
+        self.pitch = clamp(self.view.y.asin().to_degrees(), -89.0, 89.0);
+        self.yaw = self.view.z.atan2(self.view.x).to_degrees();
+        self.target = self.pos + self.distance * self.view;
+    }
+
+    /// Set the view direction. In orbit and arcball modes the camera moves around the target.
+    pub fn set_view(&mut self, view: &Vector3<f32>) {
+        assert!(view.magnitude2() > 0.0, "View direction must be non zero.");
+        self.view = view.normalize();
+        self.reset_up();
+        if self.mode != CameraMode::Fly {
+            self.pos = self.target - self.distance * self.view;
+        }
+    }
+
+    /// Change the camera control mode. The camera position and view direction are kept. In orbit
+    /// and arcball modes the target is the point at the current target distance in front of the
+    /// camera.
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        if mode == self.mode { return; }
+        if mode != CameraMode::Arcball { self.reset_up(); }
+        self.target = self.pos + self.distance * self.view;
+        self.mode = mode;
+    }
+
+    pub fn get_mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    /// Set the orbit target. The camera position is kept and the camera turns towards the target.
+    pub fn set_target(&mut self, target: &Vector3<f32>) {
+        let to_target = target - self.pos;
+        if to_target.magnitude2() == 0.0 { return; }
+        self.target = *target;
+        self.distance = to_target.magnitude();
+        self.view = to_target.normalize();
+        self.reset_up();
+    }
+
+    pub fn get_target(&self) -> Vector3<f32> {
+        self.target
+    }
+
+    /// Move towards (positive amount) or away from the target in orbit and arcball modes. Each unit
+    /// scales the distance to the target by 1 - zoom sensitivity.
+    pub fn zoom(&mut self, amount: f32) {
+        if self.mode == CameraMode::Fly || amount == 0.0 { return; }
+        let factor = (1.0 - self.zoom_sensitivity).powf(amount);
+        self.distance = (self.distance * factor).max(self.znear);
+        if let Projection::Orthographic { height } = self.projection {
+            self.projection = Projection::Orthographic { height: height * factor };
+        }
+        self.pos = self.target - self.distance * self.view;
+    }
+
+    /// Move the camera so that the whole aabb is visible. The view direction is kept and the
+    /// center of the aabb becomes the orbit target.
+    pub fn frame_aabb(&mut self, aabb: &BBox) {
+
+        if aabb.is_empty() { return; }
+
+        // The bounding sphere must fit into the smaller field of view.
+        let radius = (0.5 * aabb.extent().magnitude()).max(self.znear);
+        let half_fov_y = 0.5 * self.vertical_fov();
+        let half_fov_x = (half_fov_y.tan() * self.aspect).atan();
+        let half_fov = half_fov_x.min(half_fov_y);
+
+        self.target = aabb.center();
+        self.distance = radius / half_fov.sin();
+        self.pos = self.target - self.distance * self.view;
+
+        if let Projection::Orthographic { .. } = self.projection {
+            self.projection = Projection::Orthographic { height: 2.0 * radius * (1.0 as f32).max(1.0 / self.aspect) };
+        }
+    }
+
     /// Get a reference to camera uniform buffer. Creates the buffer is it doens't already exist.
     pub fn get_camera_uniform(&mut self, device: &wgpu::Device) -> &wgpu::Buffer {
 
         // Create camera uniform data.
-        let camera_uniform = CameraUniform {
-            view_proj: self.build_projection_matrix(),
-            pos: Vector4::new(self.pos.x, self.pos.y, self.pos.z, 1.0),
-        };
+        let camera_uniform = self.create_camera_uniform();
 
         // The camera uniform buffer doesn't exist. Create camera buffer.
         if self.camera_buffer.is_none() {
@@ -107,14 +345,7 @@ impl Camera {
     pub fn get_ray_camera_uniform(&mut self, device: &wgpu::Device) -> &wgpu::Buffer {
 
         // Create ray camera uniform data.
-        let ray_camera_uniform = RayCameraUniform {
-            pos: cgmath::Vector4::<f32>::new(self.pos.x, self.pos.y, self.pos.z, 1.0),
-            view: cgmath::Vector4::<f32>::new(self.view.x, self.view.y, self.view.z, 0.0),
-            up: cgmath::Vector4::<f32>::new(self.up.x, self.up.y, self.up.z, 0.0),
-            fov: self.fov,
-            aperture_radius: self.aperture_radius,
-            focal_distance: self.focal_distance,
-        };
+        let ray_camera_uniform = self.create_ray_camera_uniform();
 
         // The ray camera uniform buffer doesn't exist. Create ray camera buffer.
         if self.ray_camera_buffer.is_none() {
@@ -141,7 +372,9 @@ impl Camera {
             view: Vector3::new(0.0, 0.0, -1.0).normalize(),
             up: cgmath::Vector3::unit_y(),
             aspect: aspect_width / aspect_height as f32,
-            fov: (45.0,45.0).into(),
+            fov: 45.0,
+            fov_axis: FovAxis::Vertical,
+            projection: Projection::Perspective,
             znear: 0.01,
             zfar: 1000.0,
             movement_sensitivity: 0.003,
@@ -150,24 +383,57 @@ impl Camera {
             yaw: -50.5,
             aperture_radius: 0.01,
             focal_distance: 1.0,
+            mode: CameraMode::Fly,
+            target: (3.0, 4.0, 0.0).into(),
+            distance: 1.0,
+            zoom_sensitivity: 0.1,
+            actions: ActionMap::parse(DEFAULT_CAMERA_ACTIONS).unwrap(),
             camera_buffer: None,
             ray_camera_buffer: None,
         }
     }
 
-    /// Update camera from user input. TODO: create a method for 
-    /// Bezier-curvers and B-splines.
+    /// Update camera from user input. The input is handled according to the camera mode. Updates
+    /// the camera uniform buffers. TODO: create a method for Bezier-curvers and B-splines.
     pub fn update_from_input(&mut self, queue: &wgpu::Queue, input_cache: &InputCache) {
 
-        // Get the keyboard state (camera movement).
-        let state_forward = input_cache.key_state(&Key::W);
-        let state_backward = input_cache.key_state(&Key::S);
-        let state_right = input_cache.key_state(&Key::D);
-        let state_left = input_cache.key_state(&Key::A);
-        let state_up = input_cache.key_state(&Key::E);
-        let state_down = input_cache.key_state(&Key::C);
-        let left_mouse_button = input_cache.mouse_button_state(&MouseButton::Left);
-        let left_shift = input_cache.key_state(&Key::LShift);
+        match self.mode {
+            CameraMode::Fly => self.update_fly(input_cache),
+            CameraMode::Orbit => self.update_orbit(input_cache),
+            CameraMode::Arcball => self.update_arcball(input_cache),
+        }
+
+        self.update_uniform_buffers(queue);
+    }
+
+    /// Write the camera state to the camera uniform buffer and the ray camera uniform buffer (if
+    /// created).
+    pub fn update_uniform_buffers(&self, queue: &wgpu::Queue) {
+
+        if let Some(buffer) = self.camera_buffer.as_ref() {
+            queue.write_buffer(
+                buffer,
+                0,
+                bytemuck::cast_slice(&[self.create_camera_uniform()])
+            );
+        }
+
+        if let Some(buffer) = self.ray_camera_buffer.as_ref() {
+            queue.write_buffer(
+                buffer,
+                0,
+                bytemuck::cast_slice(&[self.create_ray_camera_uniform()])
+            );
+        }
+    }
+
+    /// Fly camera. W/S/A/D/E/C moves the camera and left mouse drag turns it.
+    fn update_fly(&mut self, input_cache: &InputCache) {
+
+        // Get the action states (camera movement).
+        let forward = self.actions.axis(input_cache, "camera_forward");
+        let right_axis = self.actions.axis(input_cache, "camera_right");
+        let up_axis = self.actions.axis(input_cache, "camera_up");
 
         // Get the delta time between previous and current tick.
         let time_delta_nanos = input_cache.get_time_delta();
@@ -180,17 +446,14 @@ impl Camera {
 
         let mut movement = cgmath::Vector3::new(0.0, 0.0, 0.0);
 
-        // 1/10 speed if left shift is down.
+        // 1/10 speed if the slow action (left shift) is down.
         let mut movement_factor = 1.0;
-        if !left_shift.is_none() { movement_factor = 0.1; }
+        if self.actions.is_down(input_cache, "camera_slow") { movement_factor = 0.1; }
 
         // Calculate the amount of movement based on user input.
-        if !state_forward.is_none() { movement += movement_factor * time_delta_milli_f32 * self.view; }
-        if !state_backward.is_none() { movement -= movement_factor * time_delta_milli_f32 * self.view; }
-        if !state_right.is_none() { movement += movement_factor * time_delta_milli_f32 * right; }
-        if !state_left.is_none() { movement -= movement_factor * time_delta_milli_f32 * right; }
-        if !state_up.is_none() { movement += movement_factor * time_delta_milli_f32 * self.up; }
-        if !state_down.is_none() { movement -= movement_factor * time_delta_milli_f32 * self.up; }
+        movement += forward * movement_factor * time_delta_milli_f32 * self.view;
+        movement += right_axis * movement_factor * time_delta_milli_f32 * right;
+        movement += up_axis * movement_factor * time_delta_milli_f32 * self.up;
 
         // Update the camera position.
         self.pos += self.movement_sensitivity * movement;
@@ -199,45 +462,227 @@ impl Camera {
           
         let md = input_cache.get_mouse_delta();
 
-        // If left mouse is down update pitch, yaw and view.
-        if let Some(InputState::Down(_,_)) = left_mouse_button {
+        // If the rotate action (left mouse) is down update pitch, yaw and view.
+        if self.actions.is_down(input_cache, "camera_rotate") {
+            self.rotate_pitch_yaw(md.x as f32, md.y as f32);
+        }
+
+        // Rotation with the gamepad stick.
+        let yaw = self.actions.axis(input_cache, "camera_yaw");
+        let pitch = self.actions.axis(input_cache, "camera_pitch");
+        if yaw != 0.0 || pitch != 0.0 {
+            let speed = STICK_ROTATION_SPEED * time_delta_milli_f32;
+            self.rotate_pitch_yaw(yaw * speed, -pitch * speed);
+        }
+    }
+
+    /// Orbit camera. Left mouse drag rotates around the target, right mouse drag pans and the
+    /// mouse wheel zooms.
+    fn update_orbit(&mut self, input_cache: &InputCache) {
+
+        let md = input_cache.get_mouse_delta();
+
+        if self.actions.is_down(input_cache, "camera_rotate") {
+            self.rotate_pitch_yaw(md.x as f32, md.y as f32);
+        }
 
-            self.pitch = clamp(
-                self.pitch + (self.rotation_sensitivity as f32 * (md.y * (-1.0)) as f32),
-                -89.0,89.0);
-            self.yaw = self.yaw + self.rotation_sensitivity * md.x as f32 ;
+        self.update_pan_and_zoom(input_cache);
+        self.pos = self.target - self.distance * self.view;
+    }
+
+    /// Arcball camera. Left mouse drag rotates the camera freely around the target, right mouse
+    /// drag pans and the mouse wheel zooms.
+    fn update_arcball(&mut self, input_cache: &InputCache) {
 
-            self.view = Vector3::new(
-                self.pitch.to_radians().cos() * self.yaw.to_radians().cos(),
-                self.pitch.to_radians().sin(),
-                self.pitch.to_radians().cos() * self.yaw.to_radians().sin()
-            ).normalize_to(1.0);
+        let md = input_cache.get_mouse_delta();
+
+        if self.actions.is_down(input_cache, "camera_rotate") {
+
+            let right = self.view.cross(self.up).normalize();
+
+            // The rotation axis is perpendicular to the mouse movement on the screen.
+            let axis = md.x as f32 * self.up + md.y as f32 * right;
+            let angle = self.rotation_sensitivity * axis.magnitude();
+
+            if axis.magnitude2() > 0.0 {
+                let rotation = Quaternion::from_axis_angle(axis.normalize(), Rad(-angle.to_radians()));
+                self.view = (rotation * self.view).normalize();
+                self.up = (rotation * self.up).normalize();
+            }
         }
 
-        // Update the camera uniform and the camera uniform buffer.
-        if !self.camera_buffer.is_none() {
+        self.update_pan_and_zoom(input_cache);
+        self.pos = self.target - self.distance * self.view;
+    }
 
-            // Create camera uniform data. TODO: refactor.
-            let camera_uniform = CameraUniform {
-                view_proj: self.build_projection_matrix(),
-                pos: Vector4::new(self.pos.x, self.pos.y, self.pos.z, 1.0),
-            };
-            queue.write_buffer(
-                &self.camera_buffer.as_ref().unwrap(),
-                0,
-                bytemuck::cast_slice(&[camera_uniform])
-            );
+    /// Move the target with right mouse drag and the distance to the target with mouse wheel.
+    fn update_pan_and_zoom(&mut self, input_cache: &InputCache) {
+
+        let md = input_cache.get_mouse_delta();
+
+        if self.actions.is_down(input_cache, "camera_pan") {
+            let right = self.view.cross(self.up).normalize();
+            let up = right.cross(self.view);
+            let scale = self.movement_sensitivity * self.distance;
+            self.target += scale * (-(md.x as f32) * right + md.y as f32 * up);
+        }
+
+        self.zoom(input_cache.get_scroll_delta());
+    }
+
+    /// Turn the view direction by mouse movement (dx, dy).
+    fn rotate_pitch_yaw(&mut self, dx: f32, dy: f32) {
+
+        self.pitch = clamp(
+            self.pitch + (self.rotation_sensitivity as f32 * (dy * (-1.0)) as f32),
+            -89.0,89.0);
+        self.yaw = self.yaw + self.rotation_sensitivity * dx as f32 ;
+
+        self.view = Vector3::new(
+            self.pitch.to_radians().cos() * self.yaw.to_radians().cos(),
+            self.pitch.to_radians().sin(),
+            self.pitch.to_radians().cos() * self.yaw.to_radians().sin()
+        ).normalize_to(1.0);
+    }
+
+    /// Set the up vector to y-axis and update pitch and yaw from the view direction.
+    fn reset_up(&mut self) {
+        // Looking along y-axis. Use z-axis as up vector.
+        if self.view.y.abs() > 0.999 {
+            self.up = -self.view.y.signum() * cgmath::Vector3::unit_z();
+        }
+        else {
+            self.up = cgmath::Vector3::unit_y();
+        }
+        self.pitch = clamp(self.view.y.asin().to_degrees(), -89.0, 89.0);
+        self.yaw = self.view.z.atan2(self.view.x).to_degrees();
+    }
+
+    /// The vertical field of view of the perspective projection (radians).
+    fn vertical_fov(&self) -> f32 {
+        match self.fov_axis {
+            FovAxis::Vertical => self.fov.to_radians(),
+            FovAxis::Horizontal => 2.0 * ((0.5 * self.fov.to_radians()).tan() / self.aspect).atan(),
+        }
+    }
+
+    fn create_camera_uniform(&self) -> CameraUniform {
+        CameraUniform {
+            view_proj: self.build_projection_matrix(),
+            pos: Vector4::new(self.pos.x, self.pos.y, self.pos.z, 1.0),
+        }
+    }
+
+    fn create_ray_camera_uniform(&self) -> RayCameraUniform {
+        RayCameraUniform {
+            pos: cgmath::Vector4::<f32>::new(self.pos.x, self.pos.y, self.pos.z, 1.0),
+            view: cgmath::Vector4::<f32>::new(self.view.x, self.view.y, self.view.z, 0.0),
+            up: cgmath::Vector4::<f32>::new(self.up.x, self.up.y, self.up.z, 0.0),
+            fov: self.get_fov().map(|degrees| degrees.to_radians()),
+            aperture_radius: self.aperture_radius,
+            focal_distance: self.focal_distance,
         }
     }
 
     /// Creates a pv matrix for wgpu.
     pub fn build_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        self.build_proj_matrix() * self.build_view_matrix()
+    }
+
+    /// Creates a projection matrix for wgpu.
+    pub fn build_proj_matrix(&self) -> cgmath::Matrix4<f32> {
+        match self.projection {
+            Projection::Perspective => {
+                let proj = cgmath::perspective(cgmath::Rad(self.vertical_fov()), self.aspect, self.znear, self.zfar);
+
+                // Convert "opengl" matrix to wgpu matris.
+                OPENGL_TO_WGPU_MATRIX * proj
+            }
+            Projection::ReverseZInfinite => {
+                let f = 1.0 / (0.5 * self.vertical_fov()).tan();
+                #[rustfmt::skip]
+                let proj = cgmath::Matrix4::new(
+                    f / self.aspect, 0.0, 0.0,        0.0,
+                    0.0,             f,   0.0,        0.0,
+                    0.0,             0.0, 0.0,        -1.0,
+                    0.0,             0.0, self.znear, 0.0,
+                );
+                proj
+            }
+            Projection::Orthographic { height } => {
+                let half_height = 0.5 * height;
+                let half_width = half_height * self.aspect;
+                let proj = cgmath::ortho(-half_width, half_width, -half_height, half_height, self.znear, self.zfar);
+
+                // Convert "opengl" matrix to wgpu matris.
+                OPENGL_TO_WGPU_MATRIX * proj
+            }
+        }
+    }
+
+    /// Get the view frustum of the camera.
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&self.build_projection_matrix())
+    }
+
+    /// Create a world space ray from the screen coordinates (x, y) through the camera. Width and
+    /// height are the dimensions of the screen. The ray starts from the near plane and ends to the
+    /// far plane (no end with infinite projection). This can be used for mouse picking.
+    pub fn create_ray(&self, x: f32, y: f32, width: f32, height: f32) -> Ray {
 
-        let view = self.build_view_matrix();
-        let proj = cgmath::perspective(cgmath::Rad(std::f32::consts::PI/2.0), self.aspect, self.znear, self.zfar);
+        let inv_view_proj = self.build_projection_matrix().invert().expect("Camera matrix isn't invertible.");
 
-        // Convert "opengl" matrix to wgpu matris.
-        OPENGL_TO_WGPU_MATRIX * proj * view
+        // Screen coordinates to normalized device coordinates.
+        let ndc_x = 2.0 * x / width - 1.0;
+        let ndc_y = 1.0 - 2.0 * y / height;
+
+        let unproject = |ndc_z: f32| -> Vector3<f32> {
+            let p = inv_view_proj * Vector4::new(ndc_x, ndc_y, ndc_z, 1.0);
+            p.truncate() / p.w
+        };
+
+        match self.projection {
+            Projection::ReverseZInfinite => {
+                let near = unproject(1.0);
+                Ray::from_points(&near, &unproject(0.5))
+            }
+            _ => {
+                let near = unproject(0.0);
+                let far = unproject(1.0);
+                let mut ray = Ray::from_points(&near, &far);
+                ray.r_max = near.distance(far);
+                ray
+            }
+        }
+    }
+
+    /// Create a world space ray from the cursor position. Width and height are the dimensions of
+    /// the window. Returns None if the cursor position isn't known yet.
+    pub fn create_cursor_ray(&self, input: &InputCache, width: f32, height: f32) -> Option<Ray> {
+        input.cursor_position().map(|p| self.create_ray(p.x as f32, p.y as f32, width, height))
+    }
+
+    /// Create the same ray as sphere tracer shader creates for the pixel (x, y). Width and height
+    /// are the dimensions of the sphere tracer output.
+    pub fn create_ray_tracer_ray(&self, x: u32, y: u32, width: u32, height: u32) -> Ray {
+
+        let right = self.view.cross(self.up).normalize();
+        let y_axis = self.view.cross(right).normalize();
+
+        let d = self.focal_distance;
+        let fov = self.get_fov().map(|degrees| degrees.to_radians());
+
+        let u = (d * (fov.x * 0.5).tan()) * right;
+        let v = (d * (fov.y * 0.5).tan()) * y_axis;
+
+        let alpha = 2.0 * (x as f32 + 0.5) / width as f32 - 1.0;
+        let beta  = 1.0 - 2.0 * (y as f32 + 0.5) / height as f32;
+
+        let point_on_plane = alpha * u + beta * v;
+
+        let mut ray = Ray::new(&(point_on_plane + self.pos), &(point_on_plane + d * self.view));
+        ray.r_max = 100.0;
+        ray
     }
 
     /// Build view projection matrix.
@@ -248,3 +693,176 @@ impl Camera {
         view
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3<f32> {
+        Vector3::<f32>::new(x, y, z)
+    }
+
+    /// Transform p by m and divide by w.
+    fn project(m: &cgmath::Matrix4<f32>, p: Vector3<f32>) -> Vector3<f32> {
+        let q = m * p.extend(1.0);
+        q.truncate() / q.w
+    }
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    fn camera() -> Camera {
+        let mut camera = Camera::new(200.0, 100.0);
+        camera.set_pos_view_up(&v(1.0, 2.0, 3.0), &v(0.0, 0.0, -1.0), &v(0.0, 1.0, 0.0));
+        camera.set_near_far(1.0, 10.0);
+        camera
+    }
+
+    #[test]
+    fn default_actions() {
+        let camera = Camera::new(1.0, 1.0);
+        for name in ["camera_forward", "camera_right", "camera_up", "camera_yaw", "camera_pitch",
+                     "camera_slow", "camera_rotate", "camera_pan"].iter() {
+            assert!(camera.actions.get_action(name).is_some(), "{} isn't in DEFAULT_CAMERA_ACTIONS", name);
+        }
+    }
+
+    #[test]
+    fn view_matrix() {
+        let camera = camera();
+        let view = camera.build_view_matrix();
+
+        // The camera is at the origin of the view space looking along -z.
+        assert_near(project(&view, v(1.0, 2.0, 3.0)), v(0.0, 0.0, 0.0));
+        assert_near(project(&view, v(1.0, 2.0, 1.0)), v(0.0, 0.0, -2.0));
+        assert_near(project(&view, v(2.0, 3.0, 3.0)), v(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn perspective_projection() {
+        let mut camera = camera();
+        camera.set_fov(90.0, FovAxis::Vertical);
+        let m = camera.build_projection_matrix();
+
+        // The depth is 0 at the near plane and 1 at the far plane.
+        assert_near(project(&m, v(1.0, 2.0, 2.0)), v(0.0, 0.0, 0.0));
+        assert_near(project(&m, v(1.0, 2.0, -7.0)), v(0.0, 0.0, 1.0));
+
+        // The top right corner of the view at distance 2 (tan(45) == 1, aspect == 2).
+        let corner = project(&m, v(1.0 + 4.0, 2.0 + 2.0, 1.0));
+        assert!((corner.x - 1.0).abs() < 1e-4 && (corner.y - 1.0).abs() < 1e-4, "{:?}", corner);
+        assert_eq!(camera.depth_compare(), wgpu::CompareFunction::Less);
+        assert_eq!(camera.depth_clear_value(), 1.0);
+    }
+
+    #[test]
+    fn reverse_z_and_orthographic_projection() {
+        let mut camera = camera();
+        camera.set_projection(Projection::ReverseZInfinite);
+        let m = camera.build_projection_matrix();
+        assert_near(project(&m, v(1.0, 2.0, 2.0)), v(0.0, 0.0, 1.0));
+        assert!(project(&m, v(1.0, 2.0, -1000.0)).z < 0.001);
+        assert_eq!(camera.depth_compare(), wgpu::CompareFunction::Greater);
+        assert_eq!(camera.depth_clear_value(), 0.0);
+
+        camera.set_projection(Projection::Orthographic { height: 4.0 });
+        let m = camera.build_projection_matrix();
+        assert_near(project(&m, v(1.0 + 4.0, 2.0 + 2.0, 2.0)), v(1.0, 1.0, 0.0));
+        assert_near(project(&m, v(1.0 - 4.0, 2.0 - 2.0, -7.0)), v(-1.0, -1.0, 1.0));
+    }
+
+    #[test]
+    fn frustum_corners() {
+        let mut camera = camera();
+        camera.set_fov(90.0, FovAxis::Vertical);
+
+        // The half height is the distance (tan(45) == 1) and the half width twice that (aspect == 2).
+        let corners = camera.frustum_corners(1.0, 4.0);
+        let expected = [v(-1.0, 1.0, 2.0), v(3.0, 1.0, 2.0), v(3.0, 3.0, 2.0), v(-1.0, 3.0, 2.0),
+                        v(-7.0, -2.0, -1.0), v(9.0, -2.0, -1.0), v(9.0, 6.0, -1.0), v(-7.0, 6.0, -1.0)];
+        for (c, e) in corners.iter().zip(expected.iter()) {
+            assert_near(*c, *e);
+        }
+
+        // The corners are on the edges of the view.
+        let m = camera.build_projection_matrix();
+        for (c, e) in corners.iter().zip([v(-1.0, -1.0, 0.0), v(1.0, -1.0, 0.0), v(1.0, 1.0, 0.0), v(-1.0, 1.0, 0.0)].iter().cycle()) {
+            let p = project(&m, *c);
+            assert_near(v(p.x, p.y, 0.0), *e);
+        }
+
+        // The orthographic frustum is a box.
+        camera.set_projection(Projection::Orthographic { height: 4.0 });
+        let corners = camera.frustum_corners(1.0, 4.0);
+        assert_near(corners[0], v(-3.0, 0.0, 2.0));
+        assert_near(corners[6], v(5.0, 4.0, -1.0));
+    }
+
+    #[test]
+    fn fov_and_aspect() {
+        let mut camera = camera();
+        camera.set_fov(90.0, FovAxis::Vertical);
+        let fov = camera.get_fov();
+        assert!((fov.y - 90.0).abs() < 1e-4 && (fov.x - 2.0 * 2.0_f32.atan().to_degrees()).abs() < 1e-4, "{:?}", fov);
+
+        // The horizontal field of view is kept when the aspect ratio changes.
+        camera.set_fov(90.0, FovAxis::Horizontal);
+        let fov = camera.get_fov();
+        assert!((fov.x - 90.0).abs() < 1e-4 && (fov.y - 2.0 * 0.5_f32.atan().to_degrees()).abs() < 1e-4, "{:?}", fov);
+        camera.resize(100.0, 100.0);
+        let fov = camera.get_fov();
+        assert!((fov.x - 90.0).abs() < 1e-4 && (fov.y - 90.0).abs() < 1e-4, "{:?}", fov);
+
+        // The vertical field of view is kept when the aspect ratio changes.
+        camera.set_fov(60.0, FovAxis::Vertical);
+        camera.resize(300.0, 100.0);
+        assert!((camera.get_fov().y - 60.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn mode_switching() {
+        let mut camera = camera();
+        camera.distance = 5.0;
+
+        // Zooming doesn't move the fly camera.
+        camera.zoom(1.0);
+        assert_near(camera.get_position(), v(1.0, 2.0, 3.0));
+
+        // The orbit target is in front of the camera at the target distance.
+        camera.set_mode(CameraMode::Orbit);
+        assert_eq!(camera.get_mode(), CameraMode::Orbit);
+        assert_near(camera.get_target(), v(1.0, 2.0, -2.0));
+        assert_near(camera.get_position(), v(1.0, 2.0, 3.0));
+
+        camera.zoom(1.0);
+        assert_near(camera.get_position(), v(1.0, 2.0, -2.0 + 4.5));
+
+        // The orbit camera moves around the target.
+        camera.set_view(&v(1.0, 0.0, 0.0));
+        assert_near(camera.get_position(), v(1.0 - 4.5, 2.0, -2.0));
+        assert_near(camera.get_view(), v(1.0, 0.0, 0.0));
+
+        // Switching back to fly mode keeps the position and the view direction.
+        camera.set_mode(CameraMode::Fly);
+        assert_near(camera.get_position(), v(1.0 - 4.5, 2.0, -2.0));
+        camera.set_view(&v(0.0, 0.0, 1.0));
+        assert_near(camera.get_position(), v(1.0 - 4.5, 2.0, -2.0));
+        assert_near(camera.get_up(), v(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn frame_aabb() {
+        let mut camera = camera();
+        camera.set_mode(CameraMode::Orbit);
+        let aabb = BBox { min: v(-1.0, -2.0, -3.0), max: v(3.0, 2.0, 1.0) };
+        camera.frame_aabb(&aabb);
+        assert_near(camera.get_target(), v(1.0, 0.0, -1.0));
+
+        let m = camera.build_projection_matrix();
+        for c in aabb.corners().iter() {
+            let p = project(&m, *c);
+            assert!(p.x.abs() <= 1.0 && p.y.abs() <= 1.0, "corner {:?} is outside the view: {:?}", c, p);
+        }
+    }
+}
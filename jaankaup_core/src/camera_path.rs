@@ -0,0 +1,401 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use cgmath::{prelude::*, Vector3, Matrix3, Quaternion};
+use geometry::curve::catmull_rom;
+use crate::camera::Camera;
+
+/// The state of the camera at the given time (seconds).
+#[derive(Clone, Copy, Debug)]
+pub struct CameraKeyframe {
+    pub time: f32,
+    pub pos: Vector3<f32>,
+    pub view: Vector3<f32>,
+    pub up: Vector3<f32>,
+}
+
+impl CameraKeyframe {
+
+    /// Check that view and up are non zero and not parallel.
+    fn has_valid_orientation(&self) -> bool {
+        self.view.cross(self.up).magnitude2() > 0.0
+    }
+
+    /// The orientation of the keyframe. Rotates -z to view and y to up.
+    fn orientation(&self) -> Quaternion<f32> {
+        let right = self.view.cross(self.up).normalize();
+        let up = right.cross(self.view).normalize();
+        Quaternion::from(Matrix3::from_cols(right, up, -self.view.normalize()))
+    }
+}
+
+/// A camera path. The positions are interpolated with Catmull-Rom splines and the orientations
+/// with slerp.
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+    keyframes: Vec<CameraKeyframe>,
+}
+
+impl CameraPath {
+
+    pub fn init() -> Self {
+        Self {
+            keyframes: Vec::new(),
+        }
+    }
+
+    /// Add a keyframe. The keyframes must be added in time order.
+    pub fn add_keyframe(&mut self, keyframe: CameraKeyframe) {
+        assert!(keyframe.time.is_finite(), "{}", format!("keyframe.time == {} must be finite.", keyframe.time));
+        if let Some(last) = self.keyframes.last() {
+            assert!(keyframe.time > last.time, "{}", format!("keyframe.time == {} > {}", keyframe.time, last.time));
+        }
+        assert!(keyframe.has_valid_orientation(), "View direction and up vector must be non zero and not parallel.");
+        self.keyframes.push(keyframe);
+    }
+
+    pub fn get_keyframes(&self) -> &Vec<CameraKeyframe> {
+        &self.keyframes
+    }
+
+    /// The time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        match self.keyframes.last() {
+            Some(k) => k.time,
+            None => 0.0,
+        }
+    }
+
+    /// Get the interpolated camera state at the given time. The time is clamped to the path.
+    /// Returns None if the path is empty or the time is NaN.
+    pub fn sample(&self, time: f32) -> Option<CameraKeyframe> {
+
+        let n = self.keyframes.len();
+        if n == 0 || time.is_nan() { return None; }
+
+        let first = &self.keyframes[0];
+        let last = &self.keyframes[n - 1];
+        if n == 1 || time <= first.time { return Some(CameraKeyframe { time: time, ..*first }); }
+        if time >= last.time { return Some(CameraKeyframe { time: time, ..*last }); }
+
+        // The segment (i, i + 1) that includes time.
+        let i = match self.keyframes.binary_search_by(|k| k.time.total_cmp(&time)) {
+            Ok(i) => i.min(n - 2),
+            Err(i) => i - 1,
+        };
+
+        let k1 = &self.keyframes[i];
+        let k2 = &self.keyframes[i + 1];
+        let k0 = &self.keyframes[if i > 0 { i - 1 } else { i }];
+        let k3 = &self.keyframes[(i + 2).min(n - 1)];
+
+        let t = (time - k1.time) / (k2.time - k1.time);
+
+        let pos = catmull_rom(t, k0.pos, k1.pos, k2.pos, k3.pos);
+
+        // Take the shortest rotation.
+        let q1 = k1.orientation();
+        let mut q2 = k2.orientation();
+        if q1.dot(q2) < 0.0 { q2 = -q2; }
+        let q = q1.slerp(q2, t).normalize();
+
+        Some(CameraKeyframe {
+            time: time,
+            pos: pos,
+            view: q.rotate_vector(-Vector3::unit_z()),
+            up: q.rotate_vector(Vector3::unit_y()),
+        })
+    }
+
+    /// Save the path to a text file. One keyframe per line:
+    /// time pos.x pos.y pos.z view.x view.y view.z up.x up.y up.z
+    pub fn save(&self, file_name: &str) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(file_name)?);
+        writeln!(writer, "# time pos.x pos.y pos.z view.x view.y view.z up.x up.y up.z")?;
+        for k in self.keyframes.iter() {
+            writeln!(writer, "{} {} {} {} {} {} {} {} {} {}",
+                     k.time,
+                     k.pos.x, k.pos.y, k.pos.z,
+                     k.view.x, k.view.y, k.view.z,
+                     k.up.x, k.up.y, k.up.z)?;
+        }
+        Ok(())
+    }
+
+    /// Load a path saved with save. Fails if a line doesn't have 10 finite numbers, the times
+    /// aren't increasing or the view and up vectors of a keyframe are zero or parallel.
+    pub fn load(file_name: &str) -> std::io::Result<Self> {
+
+        let invalid = |line_number: usize, reason: &str| -> std::io::Error {
+            std::io::Error::new(std::io::ErrorKind::InvalidData,
+                                format!("{}:{}: {}", file_name, line_number + 1, reason))
+        };
+        let numbers = "expected 10 finite numbers.";
+
+        let mut result = CameraPath::init();
+        let reader = BufReader::new(File::open(file_name)?);
+
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+
+            let values: Vec<f32> = line.split_whitespace()
+                                       .map(|v| v.parse::<f32>())
+                                       .collect::<Result<Vec<f32>, _>>()
+                                       .map_err(|_| invalid(line_number, numbers))?;
+            if values.len() != 10 || !values.iter().all(|v| v.is_finite()) { return Err(invalid(line_number, numbers)); }
+            if let Some(last) = result.keyframes.last() {
+                if values[0] <= last.time { return Err(invalid(line_number, "the keyframes must be in time order.")); }
+            }
+
+            let keyframe = CameraKeyframe {
+                time: values[0],
+                pos: Vector3::new(values[1], values[2], values[3]),
+                view: Vector3::new(values[4], values[5], values[6]),
+                up: Vector3::new(values[7], values[8], values[9]),
+            };
+            if !keyframe.has_valid_orientation() {
+                return Err(invalid(line_number, "the view and up vectors must be non zero and not parallel."));
+            }
+            result.keyframes.push(keyframe);
+        }
+
+        Ok(result)
+    }
+}
+
+/// Records the camera state once per frame.
+pub struct CameraRecorder {
+    path: CameraPath,
+    start_time: Option<u128>,
+}
+
+impl CameraRecorder {
+
+    pub fn init() -> Self {
+        Self {
+            path: CameraPath::init(),
+            start_time: None,
+        }
+    }
+
+    /// Record the camera state. time_now is the current time in nanoseconds
+    /// (InputCache::get_time). The first recorded frame is at time 0.
+    pub fn record(&mut self, camera: &Camera, time_now: u128) {
+
+        let start_time = *self.start_time.get_or_insert(time_now);
+        let time = time_now.saturating_sub(start_time) as f32 / 1000000000.0;
+
+        // Skip frames with the same time.
+        if let Some(last) = self.path.keyframes.last() {
+            if time <= last.time { return; }
+        }
+
+        self.path.keyframes.push(CameraKeyframe {
+            time: time,
+            pos: camera.get_position(),
+            view: camera.get_view(),
+            up: camera.get_up(),
+        });
+    }
+
+    pub fn get_path(&self) -> &CameraPath {
+        &self.path
+    }
+
+    /// Save the recorded path to a file.
+    pub fn save(&self, file_name: &str) -> std::io::Result<()> {
+        self.path.save(file_name)
+    }
+}
+
+/// The time source of the playback.
+/// RealTime: the path is played in the recorded speed.
+/// FixedStep: the time advances the given amount of seconds each frame. Every run renders
+/// exactly the same frames.
+#[derive(Clone, Copy, Debug)]
+pub enum PlaybackClock {
+    RealTime,
+    FixedStep(f32),
+}
+
+/// Drives the camera along a camera path. Use update instead of Camera::update_from_input.
+pub struct CameraPlayback {
+    path: CameraPath,
+    clock: PlaybackClock,
+    looping: bool,
+    time: f32,
+    previous_time: Option<u128>,
+}
+
+impl CameraPlayback {
+
+    pub fn init(path: CameraPath, clock: PlaybackClock, looping: bool) -> Self {
+        Self {
+            path: path,
+            clock: clock,
+            looping: looping,
+            time: 0.0,
+            previous_time: None,
+        }
+    }
+
+    /// Start from the beginning.
+    pub fn restart(&mut self) {
+        self.time = 0.0;
+        self.previous_time = None;
+    }
+
+    /// The current playback time (seconds).
+    pub fn get_time(&self) -> f32 {
+        self.time
+    }
+
+    /// Check if the playback has reached the end of the path. Never true for looping playback.
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.time >= self.path.duration()
+    }
+
+    /// Move the camera to the current point of the path, advance the time and update the camera
+    /// uniform buffers. time_now is the current time in nanoseconds (InputCache::get_time).
+    /// Returns false if the playback is finished.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &mut Camera, time_now: u128) -> bool {
+
+        if let Some(k) = self.path.sample(self.time) {
+            camera.set_pos_view_up(&k.pos, &k.view, &k.up);
+            camera.update_uniform_buffers(queue);
+        }
+
+        let finished = self.is_finished();
+
+        // Advance the time.
+        let delta = match self.clock {
+            PlaybackClock::RealTime => match self.previous_time {
+                Some(previous) => time_now.saturating_sub(previous) as f32 / 1000000000.0,
+                None => 0.0,
+            },
+            PlaybackClock::FixedStep(step) => step,
+        };
+        self.previous_time = Some(time_now);
+        self.time += delta;
+
+        let duration = self.path.duration();
+        if self.looping && duration > 0.0 && self.time > duration { self.time %= duration; }
+        else if !self.looping { self.time = self.time.min(duration); }
+
+        !finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, pos: Vector3<f32>, view: Vector3<f32>) -> CameraKeyframe {
+        CameraKeyframe { time: time, pos: pos, view: view, up: Vector3::unit_y() }
+    }
+
+    fn assert_vector(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    /// A path along the x axis. The camera turns from -z to x.
+    fn path() -> CameraPath {
+        let mut path = CameraPath::init();
+        path.add_keyframe(keyframe(0.0, Vector3::new(0.0, 0.0, 0.0), -Vector3::unit_z()));
+        path.add_keyframe(keyframe(1.0, Vector3::new(1.0, 0.0, 0.0), -Vector3::unit_z()));
+        path.add_keyframe(keyframe(2.0, Vector3::new(2.0, 0.0, 0.0), Vector3::unit_x()));
+        path.add_keyframe(keyframe(3.0, Vector3::new(3.0, 0.0, 0.0), Vector3::unit_x()));
+        path
+    }
+
+    /// A file in the temp directory with the given content. Removed when dropped.
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, content: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("camera_path_{}_{}.txt", name, std::process::id()));
+            std::fs::write(&path, content).unwrap();
+            TempFile(path)
+        }
+
+        fn name(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn sample_at_keyframes() {
+        let path = path();
+        assert_eq!(path.duration(), 3.0);
+        for k in path.get_keyframes().iter() {
+            let sample = path.sample(k.time).unwrap();
+            assert_eq!(sample.time, k.time);
+            assert_vector(sample.pos, k.pos);
+            assert_vector(sample.view, k.view);
+            assert_vector(sample.up, k.up);
+        }
+
+        // The time is clamped to the path.
+        assert_vector(path.sample(-1.0).unwrap().pos, Vector3::new(0.0, 0.0, 0.0));
+        assert_vector(path.sample(4.0).unwrap().pos, Vector3::new(3.0, 0.0, 0.0));
+        assert!(path.sample(f32::NAN).is_none());
+        assert!(CameraPath::init().sample(0.0).is_none());
+    }
+
+    #[test]
+    fn sample_between_keyframes() {
+        let path = path();
+
+        // Evenly spaced points on a line stay on the line. The inner segments are linear.
+        assert_vector(path.sample(1.25).unwrap().pos, Vector3::new(1.25, 0.0, 0.0));
+        let pos = path.sample(0.5).unwrap().pos;
+        assert!(pos.x > 0.0 && pos.x < 1.0 && pos.y == 0.0 && pos.z == 0.0, "{:?}", pos);
+
+        // Same orientations.
+        assert_vector(path.sample(0.5).unwrap().view, -Vector3::unit_z());
+
+        // Halfway between -z and x.
+        let s = 0.5_f32.sqrt();
+        let sample = path.sample(1.5).unwrap();
+        assert_vector(sample.view, Vector3::new(s, 0.0, -s));
+        assert_vector(sample.up, Vector3::unit_y());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = path();
+        let file = TempFile::new("save", "");
+        path.save(file.name()).unwrap();
+        let loaded = CameraPath::load(file.name()).unwrap();
+        assert_eq!(loaded.get_keyframes().len(), 4);
+        for (a, b) in loaded.get_keyframes().iter().zip(path.get_keyframes().iter()) {
+            assert_eq!(a.time, b.time);
+            assert_vector(a.pos, b.pos);
+            assert_vector(a.view, b.view);
+        }
+    }
+
+    #[test]
+    fn load_errors() {
+        let error = |name: &str, content: &str| -> String {
+            let file = TempFile::new(name, content);
+            CameraPath::load(file.name()).unwrap_err().to_string()
+        };
+
+        let valid = "0 0 0 0 0 0 -1 0 1 0\n";
+        assert!(error("count", &format!("{}\n1 0 0 0 0 0 -1 0 1\n", valid)).ends_with(":3: expected 10 finite numbers."));
+        assert!(error("number", "0 0 0 0 0 0 -1 0 1 x\n").ends_with(":1: expected 10 finite numbers."));
+        assert!(error("nan", "0 0 0 NaN 0 0 -1 0 1 0\n").ends_with(":1: expected 10 finite numbers."));
+        assert!(error("order", &format!("# comment\n{}{}", valid, valid)).ends_with(":3: the keyframes must be in time order."));
+        assert!(error("zero", "0 0 0 0 0 0 0 0 1 0\n").ends_with(":1: the view and up vectors must be non zero and not parallel."));
+        assert!(error("parallel", "0 0 0 0 0 2 0 0 1 0\n").ends_with(":1: the view and up vectors must be non zero and not parallel."));
+        assert_eq!(CameraPath::load("no_such_camera_path.txt").unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    }
+}
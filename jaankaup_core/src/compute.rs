@@ -1,4 +1,6 @@
+use bytemuck::{Zeroable, Pod};
 use crate::buffer::{to_vec, buffer_from_data};
+use crate::render_pipelines::{create_bind_group_layouts, create_checked_bind_groups, ShaderBindings};
 //use crate::wgpu_system::*;
 /// Information about the invocation counts and data dimensions sizes.
 /// TODO: add items_per_thread?
@@ -113,3 +115,207 @@ impl Histogram {
         );
     }
 }
+
+/// The arguments of dispatch_indirect (the workgroup counts). The same layout as wgpu expects.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DispatchIndirectArgs {
+    pub x: u32,
+    pub y: u32,
+    pub z: u32,
+}
+
+unsafe impl Pod for DispatchIndirectArgs {}
+unsafe impl Zeroable for DispatchIndirectArgs {}
+
+/// The number of workgroups needed to cover dimensions items with workgroups of workgroup_size.
+pub fn workgroup_count(dimensions: [u32; 3], workgroup_size: [u32; 3]) -> [u32; 3] {
+    assert!(workgroup_size.iter().all(|s| *s > 0), "{}", format!("workgroup_size == {:?} must be > 0.", workgroup_size));
+    [(dimensions[0] + workgroup_size[0] - 1) / workgroup_size[0],
+     (dimensions[1] + workgroup_size[1] - 1) / workgroup_size[1],
+     (dimensions[2] + workgroup_size[2] - 1) / workgroup_size[2]]
+}
+
+/// The workgroup size of a compute entry point of a spirv binary. Only parses the binary, so the
+/// hand written layout entries can still be used with it.
+pub fn spirv_workgroup_size(data: &[u8], entry_point: &str) -> Result<[u32; 3], String> {
+    let module = naga::front::spv::parse_u8_slice(data, &naga::front::spv::Options::default())
+        .map_err(|e| format!("Failed to parse spirv: {:?}", e))?;
+    module.entry_points.iter()
+                       .find(|e| e.stage == naga::ShaderStage::Compute && e.name == entry_point)
+                       .map(|e| e.workgroup_size)
+                       .ok_or_else(|| format!("The spirv has no compute entry point '{}'.", entry_point))
+}
+
+/// A compute pipeline and the bind group layouts of it. The workgroup size is used for computing
+/// the workgroup counts in dispatch_items.
+///
+/// ```ignore
+/// let bindings = ShaderBindings::from_wgsl(source).unwrap();
+/// let kernel = ComputeKernel::from_bindings(&device, &module, "main", &bindings, Some("my kernel")).unwrap();
+/// let bind_groups = kernel.create_bind_groups(&device, &[((0, 0), params.as_entire_binding()),
+///                                                      ((0, 1), output.as_entire_binding())]).unwrap();
+/// kernel.dispatch_items(&bind_groups, &mut encoder, [count, 1, 1]);
+/// ```
+///
+/// Use from_bindings when naga can parse the shader. The layout entries and the workgroup size
+/// are then reflected from it. naga can't parse the spirv shaders with atomics, so init takes
+/// them by hand.
+pub struct ComputeKernel {
+    label: Option<String>,
+    layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    pipeline: wgpu::ComputePipeline,
+    workgroup_size: [u32; 3],
+}
+
+impl ComputeKernel {
+
+    /// Create a compute kernel with the layout entries and the workgroup size reflected from the
+    /// shader.
+    pub fn from_bindings(device: &wgpu::Device,
+                         module: &wgpu::ShaderModule,
+                         entry_point: &str,
+                         bindings: &ShaderBindings,
+                         label: Option<&str>) -> Result<Self, String> {
+
+        let workgroup_size = bindings.workgroup_size(entry_point)
+                                     .ok_or_else(|| format!("The shader has no compute entry point '{}'.", entry_point))?;
+        Ok(ComputeKernel::init(&device, &module, entry_point, bindings.layout_entries(), workgroup_size, label))
+    }
+
+    /// Create a compute kernel. The layout entries are given per set. The workgroup size must be
+    /// the same as in the shader (see spirv_workgroup_size).
+    pub fn init(device: &wgpu::Device,
+                module: &wgpu::ShaderModule,
+                entry_point: &str,
+                layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+                workgroup_size: [u32; 3],
+                label: Option<&str>) -> Self {
+
+        let bind_group_layouts = create_bind_group_layouts(&device, &layout_entries);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: label,
+            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: label,
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: entry_point,
+        });
+
+        Self {
+            label: label.map(|l| l.to_string()),
+            layout_entries: layout_entries,
+            bind_group_layouts: bind_group_layouts,
+            pipeline: pipeline,
+            workgroup_size: workgroup_size,
+        }
+    }
+
+    pub fn get_pipeline(&self) -> &wgpu::ComputePipeline {
+        &self.pipeline
+    }
+
+    pub fn get_bind_group_layouts(&self) -> &Vec<wgpu::BindGroupLayout> {
+        &self.bind_group_layouts
+    }
+
+    pub fn get_bind_group_layout_entries(&self) -> &Vec<Vec<wgpu::BindGroupLayoutEntry>> {
+        &self.layout_entries
+    }
+
+    pub fn get_workgroup_size(&self) -> [u32; 3] {
+        self.workgroup_size
+    }
+
+    /// Create the bind groups (one per set) from the resources keyed by (group, binding). See
+    /// create_checked_bind_groups.
+    pub fn create_bind_groups(&self, device: &wgpu::Device, bindings: &[((u32, u32), wgpu::BindingResource)]) -> Result<Vec<wgpu::BindGroup>, String> {
+        create_checked_bind_groups(&device, &self.layout_entries, &self.bind_group_layouts, bindings)
+    }
+
+    /// Dispatch x * y * z workgroups.
+    pub fn dispatch(&self, bind_groups: &Vec<wgpu::BindGroup>,
+                    encoder: &mut wgpu::CommandEncoder,
+                    x: u32, y: u32, z: u32) {
+
+        let mut pass = self.begin_pass(bind_groups, encoder);
+        pass.dispatch(x, y, z)
+    }
+
+    /// Dispatch enough workgroups to cover dimensions items (one invocation per item).
+    pub fn dispatch_items(&self, bind_groups: &Vec<wgpu::BindGroup>,
+                          encoder: &mut wgpu::CommandEncoder,
+                          dimensions: [u32; 3]) {
+
+        let count = workgroup_count(dimensions, self.workgroup_size);
+        self.dispatch(bind_groups, encoder, count[0], count[1], count[2])
+    }
+
+    /// Dispatch the workgroup counts (DispatchIndirectArgs) stored in indirect_buffer at offset.
+    /// See indirect::IndirectArgsKernel for computing them from a counter.
+    pub fn dispatch_indirect(&self, bind_groups: &Vec<wgpu::BindGroup>,
+                             encoder: &mut wgpu::CommandEncoder,
+                             indirect_buffer: &wgpu::Buffer,
+                             offset: wgpu::BufferAddress) {
+
+        let mut pass = self.begin_pass(bind_groups, encoder);
+        pass.dispatch_indirect(indirect_buffer, offset)
+    }
+
+    fn begin_pass<'a>(&'a self, bind_groups: &'a Vec<wgpu::BindGroup>, encoder: &'a mut wgpu::CommandEncoder) -> wgpu::ComputePass<'a> {
+
+        let mut pass = encoder.begin_compute_pass(
+            &wgpu::ComputePassDescriptor { label: self.label.as_deref() }
+        );
+        pass.set_pipeline(&self.pipeline);
+        for (e, bgs) in bind_groups.iter().enumerate() {
+            pass.set_bind_group(e as u32, &bgs, &[]);
+        }
+        pass
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shadow::with_shadows;
+
+    #[test]
+    fn workgroup_counts() {
+        assert_eq!(workgroup_count([0, 1, 1], [64, 1, 1]), [0, 1, 1]);
+        assert_eq!(workgroup_count([1, 1, 1], [64, 1, 1]), [1, 1, 1]);
+        assert_eq!(workgroup_count([64, 1, 1], [64, 1, 1]), [1, 1, 1]);
+        assert_eq!(workgroup_count([65, 1, 1], [64, 1, 1]), [2, 1, 1]);
+        assert_eq!(workgroup_count([16, 17, 33], [4, 4, 4]), [4, 5, 9]);
+        assert_eq!(workgroup_count([1000, 480, 1], [8, 8, 1]), [125, 60, 1]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_workgroup_size() {
+        let _ = workgroup_count([64, 1, 1], [64, 0, 1]);
+    }
+
+    #[test]
+    fn reflected_workgroup_sizes() {
+        let size = |source: &str| ShaderBindings::from_wgsl(source).unwrap().workgroup_size("main");
+        assert_eq!(size(include_str!("../../shaders_wgsl/indirect_args.wgsl")), Some([1, 1, 1]));
+        assert_eq!(size(include_str!("../../shaders_wgsl/frustum_cull.wgsl")), Some([64, 1, 1]));
+        assert_eq!(size(include_str!("../../shaders_wgsl/voxelize.wgsl")), Some([64, 1, 1]));
+        assert_eq!(size(include_str!("../../shaders_wgsl/mc_test.wgsl")), Some([4, 4, 4]));
+
+        // Only the compute entry points have a workgroup size.
+        let pbr = ShaderBindings::from_wgsl(&with_shadows(include_str!("../../shaders_wgsl/pbr_v4n4.wgsl"), 2)).unwrap();
+        assert_eq!(pbr.workgroup_size("vs_main"), None);
+
+        assert_eq!(spirv_workgroup_size(include_bytes!("../../shaders/spirv/fmm_data_generator.comp.spv"), "main"), Ok([64, 6, 1]));
+        assert_eq!(spirv_workgroup_size(include_bytes!("../../shaders/spirv/numbers.comp.spv"), "main"), Ok([64, 1, 1]));
+        assert!(spirv_workgroup_size(include_bytes!("../../shaders/spirv/numbers.comp.spv"), "other").is_err());
+    }
+}
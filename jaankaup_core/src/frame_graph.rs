@@ -0,0 +1,795 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use crate::resources::{BufferHandle, TextureHandle, ResourceRegistry};
+use crate::misc::multisampled;
+
+/// A handle to a transient texture of a FrameGraph. Valid only for the graph that created it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TransientHandle(usize);
+
+/// A resource that the passes of a FrameGraph read or write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GraphResource {
+    Buffer(BufferHandle),
+    Texture(TextureHandle),
+    Transient(TransientHandle),
+    /// The surface texture view given to FrameGraph::execute.
+    Surface,
+}
+
+impl From<BufferHandle> for GraphResource {
+    fn from(handle: BufferHandle) -> Self { GraphResource::Buffer(handle) }
+}
+
+impl From<TextureHandle> for GraphResource {
+    fn from(handle: TextureHandle) -> Self { GraphResource::Texture(handle) }
+}
+
+impl From<TransientHandle> for GraphResource {
+    fn from(handle: TransientHandle) -> Self { GraphResource::Transient(handle) }
+}
+
+/// The description of a transient 2d texture. Textures with equal descriptors are shared
+/// between frames and between passes whose lifetimes don't overlap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TransientTextureDescriptor {
+    pub size: wgpu::Extent3d,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+}
+
+impl TransientTextureDescriptor {
+
+    /// A render attachment with the size of the surface. Because the descriptor is given every
+    /// frame, the texture is recreated after the surface is resized.
+    pub fn attachment(sc_desc: &wgpu::SurfaceConfiguration, format: wgpu::TextureFormat) -> Self {
+        Self {
+            size: wgpu::Extent3d {
+                width: sc_desc.width,
+                height: sc_desc.height,
+                depth_or_array_layers: 1,
+            },
+            format: format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            sample_count: 1,
+        }
+    }
+
+    /// Use a multisampled texture. Panics if the count isn't 1, 2, 4, 8 or 16.
+    pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+        multisampled(sample_count);
+        self.sample_count = sample_count;
+        self
+    }
+}
+
+struct PoolTexture {
+    descriptor: TransientTextureDescriptor,
+    #[allow(dead_code)]
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+/// The textures allocated for the transient textures of frame graphs. Keep this over frames
+/// so the textures are reused. Textures that the latest frame didn't need are released.
+pub struct TransientTextures {
+    textures: Vec<PoolTexture>,
+    assignment: Vec<Option<usize>>,
+}
+
+impl TransientTextures {
+    pub fn init() -> Self {
+        Self {
+            textures: Vec::new(),
+            assignment: Vec::new(),
+        }
+    }
+
+    /// The number of allocated textures.
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+
+    fn get(&self, handle: TransientHandle) -> &PoolTexture {
+        let index = self.assignment.get(handle.0).copied().flatten();
+        assert!(index.is_some(), "{}", format!("TransientTextures::get: transient texture {} is not allocated. Call FrameGraph::allocate first.", handle.0));
+        &self.textures[index.unwrap()]
+    }
+}
+
+/// The result of FrameGraph::assign. The pool textures are the given ones followed by the
+/// created ones.
+struct Assignment {
+    /// The pool texture of each transient texture. None if no pass uses the transient texture.
+    transients: Vec<Option<usize>>,
+    /// The transient textures that got a new pool texture, in pool order.
+    created: Vec<usize>,
+    /// Is the pool texture used in this frame.
+    used: Vec<bool>,
+}
+
+type RenderFn<'a, C> = Box<dyn for<'p> FnOnce(&'p C, &mut wgpu::RenderPass<'p>) + 'a>;
+type ComputeFn<'a, C> = Box<dyn FnOnce(&C, &mut wgpu::CommandEncoder) + 'a>;
+
+/// A color attachment of a render pass and the single sampled texture it is resolved to.
+#[derive(Clone, Copy)]
+struct ColorAttachment {
+    resource: GraphResource,
+    resolve_target: Option<GraphResource>,
+    clear: wgpu::Color,
+}
+
+enum PassKind<'a, C> {
+    Render {
+        colors: Vec<ColorAttachment>,
+        depth: Option<(GraphResource, f32)>,
+        execute: RenderFn<'a, C>,
+    },
+    Compute {
+        execute: ComputeFn<'a, C>,
+    },
+}
+
+struct Pass<'a, C> {
+    label: String,
+    reads: Vec<GraphResource>,
+    writes: Vec<GraphResource>,
+    kind: PassKind<'a, C>,
+}
+
+impl<'a, C> Pass<'a, C> {
+    fn accesses(&self, resource: GraphResource) -> bool {
+        self.reads.contains(&resource) || self.writes.contains(&resource)
+    }
+}
+
+/// The passes of a single frame. Each pass declares the resources it reads and writes and the
+/// graph runs the passes so that every reader of a resource comes after its writers. The writers
+/// of a resource run in the order they were added. The first pass that renders to an
+/// attachment clears it, the later ones load it. A multisampled attachment is resolved only by
+/// the last pass that renders to it. All passes are recorded into one encoder that is
+/// submitted once.
+///
+/// The pass closures get the context given to execute (usually the application) so they only
+/// need to capture handles and draw counts.
+pub struct FrameGraph<'a, C> {
+    label: String,
+    passes: Vec<Pass<'a, C>>,
+    transients: Vec<(String, TransientTextureDescriptor)>,
+    order: Option<Vec<usize>>,
+}
+
+impl<'a, C> FrameGraph<'a, C> {
+    pub fn init(label: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            passes: Vec::new(),
+            transients: Vec::new(),
+            order: None,
+        }
+    }
+
+    /// Declare a texture that lives only during this frame.
+    pub fn create_texture(&mut self, label: &str, descriptor: TransientTextureDescriptor) -> TransientHandle {
+        self.transients.push((label.to_string(), descriptor));
+        TransientHandle(self.transients.len() - 1)
+    }
+
+    /// Start a render pass. The pass is added to the graph by RenderPassBuilder::execute.
+    pub fn add_render_pass(&mut self, label: &str) -> RenderPassBuilder<'_, 'a, C> {
+        RenderPassBuilder {
+            graph: self,
+            label: label.to_string(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+            colors: Vec::new(),
+            depth: None,
+        }
+    }
+
+    /// Start a compute pass. The pass is added to the graph by ComputePassBuilder::execute.
+    pub fn add_compute_pass(&mut self, label: &str) -> ComputePassBuilder<'_, 'a, C> {
+        ComputePassBuilder {
+            graph: self,
+            label: label.to_string(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+        }
+    }
+
+    /// The labels of the passes in execution order.
+    pub fn get_order(&self) -> Vec<&str> {
+        let order = match &self.order {
+            Some(order) => order.clone(),
+            None => self.sort(),
+        };
+        order.iter().map(|&i| self.passes[i].label.as_str()).collect()
+    }
+
+    /// Order the passes and assign a pool texture to each transient texture. Transient textures
+    /// with the same descriptor share a texture if their first and last uses don't overlap.
+    pub fn allocate(&mut self, device: &wgpu::Device, pool: &mut TransientTextures) {
+
+        let order = self.sort();
+        let descriptors: Vec<TransientTextureDescriptor> = pool.textures.iter().map(|t| t.descriptor).collect();
+        let assignment = self.assign(&order, &descriptors);
+
+        for &t in assignment.created.iter() {
+            let (label, descriptor) = &self.transients[t];
+            pool.textures.push(create_pool_texture(device, label, descriptor));
+        }
+
+        // Release the textures this frame didn't need.
+        let mut remap: Vec<Option<usize>> = vec![None; pool.textures.len()];
+        let mut kept = Vec::new();
+        for (i, texture) in pool.textures.drain(..).enumerate() {
+            if assignment.used[i] {
+                remap[i] = Some(kept.len());
+                kept.push(texture);
+            }
+        }
+        pool.textures = kept;
+        pool.assignment = assignment.transients.iter().map(|a| a.and_then(|i| remap[i])).collect();
+
+        self.order = Some(order);
+    }
+
+    /// Assign the transient textures to the pool textures with the given descriptors in the
+    /// order of their first use. A pool texture is free after the last use of the transient
+    /// texture it was assigned to.
+    fn assign(&self, order: &[usize], pool: &[TransientTextureDescriptor]) -> Assignment {
+
+        let mut descriptors = pool.to_vec();
+
+        // Reserved until this position of the execution order, for each pool texture.
+        let mut busy_until: Vec<Option<usize>> = vec![None; pool.len()];
+        let mut transients: Vec<Option<usize>> = vec![None; self.transients.len()];
+        let mut created = Vec::new();
+
+        let mut lifetimes: Vec<(usize, usize, usize)> = Vec::new();
+        for t in 0..self.transients.len() {
+            let resource = GraphResource::Transient(TransientHandle(t));
+            let uses: Vec<usize> = order.iter().enumerate()
+                                        .filter(|&(_, &p)| self.passes[p].accesses(resource))
+                                        .map(|(position, _)| position)
+                                        .collect();
+            if let (Some(&first), Some(&last)) = (uses.first(), uses.last()) {
+                lifetimes.push((first, last, t));
+            }
+        }
+        lifetimes.sort();
+
+        for (first, last, t) in lifetimes {
+            let descriptor = &self.transients[t].1;
+            let free = (0..descriptors.len()).find(|&i| {
+                descriptors[i] == *descriptor && busy_until[i].map_or(true, |until| until < first)
+            });
+            let index = match free {
+                Some(index) => index,
+                None => {
+                    descriptors.push(*descriptor);
+                    busy_until.push(None);
+                    created.push(t);
+                    descriptors.len() - 1
+                }
+            };
+            busy_until[index] = Some(last);
+            transients[t] = Some(index);
+        }
+
+        Assignment {
+            transients: transients,
+            created: created,
+            used: busy_until.iter().map(|b| b.is_some()).collect(),
+        }
+    }
+
+    /// Record all passes into one encoder and submit it. Call allocate first if the graph
+    /// has transient textures.
+    pub fn execute(mut self,
+                   device: &wgpu::Device,
+                   queue: &wgpu::Queue,
+                   registry: &ResourceRegistry,
+                   surface: Option<&wgpu::TextureView>,
+                   pool: &TransientTextures,
+                   context: &C) {
+
+        let order = match self.order.take() {
+            Some(order) => order,
+            None => {
+                assert!(self.transients.is_empty(), "{}", format!("FrameGraph::execute: '{}' has transient textures. Call allocate before execute.", self.label));
+                self.sort()
+            }
+        };
+        assert!(pool.assignment.len() == self.transients.len(), "{}", format!("FrameGraph::execute: the transient textures of '{}' are allocated for another graph.", self.label));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some(&self.label),
+        });
+
+        let last_resolve = self.last_resolves(&order);
+
+        let mut passes: Vec<Option<Pass<'a, C>>> = self.passes.drain(..).map(Some).collect();
+        let mut cleared: HashSet<GraphResource> = HashSet::new();
+
+        for index in order {
+            let Pass { label, kind, .. } = passes[index].take().unwrap();
+            match kind {
+                PassKind::Render { colors, depth, execute } => {
+                    check_sample_counts(&label, &colors, depth, |resource| sample_count(resource, registry, pool));
+
+                    let color_attachments: Vec<wgpu::RenderPassColorAttachment> = colors.iter().map(|c| {
+                        let resolve_target = match c.resolve_target {
+                            Some(target) if last_resolve.get(&c.resource) == Some(&index) => {
+                                cleared.insert(target);
+                                Some(attachment_view(target, registry, pool, surface, &label))
+                            }
+                            _ => None,
+                        };
+                        wgpu::RenderPassColorAttachment {
+                            view: attachment_view(c.resource, registry, pool, surface, &label),
+                            resolve_target: resolve_target,
+                            ops: wgpu::Operations {
+                                load: if cleared.insert(c.resource) { wgpu::LoadOp::Clear(c.clear) } else { wgpu::LoadOp::Load },
+                                store: true,
+                            },
+                        }
+                    }).collect();
+
+                    let depth_stencil_attachment = depth.map(|(resource, clear_depth)| {
+                        wgpu::RenderPassDepthStencilAttachment {
+                            view: attachment_view(resource, registry, pool, surface, &label),
+                            depth_ops: Some(wgpu::Operations {
+                                load: if cleared.insert(resource) { wgpu::LoadOp::Clear(clear_depth) } else { wgpu::LoadOp::Load },
+                                store: true,
+                            }),
+                            stencil_ops: None,
+                        }
+                    });
+
+                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some(&label),
+                        color_attachments: &color_attachments,
+                        depth_stencil_attachment: depth_stencil_attachment,
+                    });
+                    execute(context, &mut render_pass);
+                }
+                PassKind::Compute { execute } => {
+                    execute(context, &mut encoder);
+                }
+            }
+        }
+
+        queue.submit(Some(encoder.finish()));
+    }
+
+    /// The pass that resolves each multisampled attachment, i.e. the last pass in the order that
+    /// renders to it.
+    fn last_resolves(&self, order: &[usize]) -> HashMap<GraphResource, usize> {
+        let mut last_resolve: HashMap<GraphResource, usize> = HashMap::new();
+        for &index in order.iter() {
+            if let PassKind::Render { colors, .. } = &self.passes[index].kind {
+                for c in colors.iter().filter(|c| c.resolve_target.is_some()) {
+                    last_resolve.insert(c.resource, index);
+                }
+            }
+        }
+        last_resolve
+    }
+
+    /// Kahn's algorithm. Ties are broken by the order the passes were added.
+    fn sort(&self) -> Vec<usize> {
+
+        let n = self.passes.len();
+        let mut edges: Vec<Vec<usize>> = vec![Vec::new(); n];
+        let mut in_degree = vec![0; n];
+
+        let mut resources: Vec<GraphResource> = Vec::new();
+        for pass in self.passes.iter() {
+            for r in pass.reads.iter().chain(pass.writes.iter()) {
+                if !resources.contains(r) { resources.push(*r); }
+            }
+        }
+
+        for resource in resources {
+            let writers: Vec<usize> = (0..n).filter(|&i| self.passes[i].writes.contains(&resource)).collect();
+            let readers: Vec<usize> = (0..n).filter(|&i| self.passes[i].reads.contains(&resource) && !writers.contains(&i)).collect();
+            for w in writers.windows(2) {
+                edges[w[0]].push(w[1]);
+            }
+            for &w in writers.iter() {
+                for &r in readers.iter() {
+                    edges[w].push(r);
+                }
+            }
+        }
+
+        for targets in edges.iter() {
+            for &t in targets.iter() { in_degree[t] += 1; }
+        }
+
+        let mut ready: BinaryHeap<Reverse<usize>> = (0..n).filter(|&i| in_degree[i] == 0).map(Reverse).collect();
+        let mut order = Vec::with_capacity(n);
+
+        while let Some(Reverse(i)) = ready.pop() {
+            order.push(i);
+            for &t in edges[i].iter() {
+                in_degree[t] -= 1;
+                if in_degree[t] == 0 { ready.push(Reverse(t)); }
+            }
+        }
+
+        if order.len() != n {
+            let cycle: Vec<&str> = (0..n).filter(|i| !order.contains(i)).map(|i| self.passes[i].label.as_str()).collect();
+            panic!("FrameGraph '{}': the passes {:?} depend on each other.", self.label, cycle);
+        }
+
+        order
+    }
+}
+
+/// Declares the attachments and the resources of a render pass.
+pub struct RenderPassBuilder<'g, 'a, C> {
+    graph: &'g mut FrameGraph<'a, C>,
+    label: String,
+    reads: Vec<GraphResource>,
+    writes: Vec<GraphResource>,
+    colors: Vec<ColorAttachment>,
+    depth: Option<(GraphResource, f32)>,
+}
+
+impl<'g, 'a, C> RenderPassBuilder<'g, 'a, C> {
+
+    /// Render to a color attachment. The color is used if this is the first pass that writes the attachment.
+    pub fn color<R: Into<GraphResource>>(mut self, resource: R, clear: wgpu::Color) -> Self {
+        let resource = resource.into();
+        assert_attachment(resource, &self.label);
+        self.colors.push(ColorAttachment { resource: resource, resolve_target: None, clear: clear });
+        self.writes.push(resource);
+        self
+    }
+
+    /// Render to a multisampled color attachment and resolve it to resolve_target (e.g. the
+    /// surface). If several passes render to the attachment, only the last one resolves it.
+    pub fn color_resolve<R: Into<GraphResource>, T: Into<GraphResource>>(mut self, resource: R, resolve_target: T, clear: wgpu::Color) -> Self {
+        let resource = resource.into();
+        let resolve_target = resolve_target.into();
+        assert_attachment(resource, &self.label);
+        assert_attachment(resolve_target, &self.label);
+        self.colors.push(ColorAttachment { resource: resource, resolve_target: Some(resolve_target), clear: clear });
+        self.writes.push(resource);
+        self.writes.push(resolve_target);
+        self
+    }
+
+    /// Use a depth attachment. The depth is used if this is the first pass that writes the attachment.
+    pub fn depth<R: Into<GraphResource>>(mut self, resource: R, clear_depth: f32) -> Self {
+        let resource = resource.into();
+        assert_attachment(resource, &self.label);
+        self.depth = Some((resource, clear_depth));
+        self.writes.push(resource);
+        self
+    }
+
+    pub fn read<R: Into<GraphResource>>(mut self, resource: R) -> Self {
+        self.reads.push(resource.into());
+        self
+    }
+
+    pub fn write<R: Into<GraphResource>>(mut self, resource: R) -> Self {
+        self.writes.push(resource.into());
+        self
+    }
+
+    /// Add the pass to the graph.
+    pub fn execute<F>(self, execute: F)
+        where F: for<'p> FnOnce(&'p C, &mut wgpu::RenderPass<'p>) + 'a {
+        assert!(!self.colors.is_empty() || self.depth.is_some(), "{}", format!("FrameGraph: render pass '{}' has no attachments.", self.label));
+        self.graph.passes.push(Pass {
+            label: self.label,
+            reads: self.reads,
+            writes: self.writes,
+            kind: PassKind::Render {
+                colors: self.colors,
+                depth: self.depth,
+                execute: Box::new(execute),
+            },
+        });
+    }
+}
+
+/// Declares the resources of a compute pass.
+pub struct ComputePassBuilder<'g, 'a, C> {
+    graph: &'g mut FrameGraph<'a, C>,
+    label: String,
+    reads: Vec<GraphResource>,
+    writes: Vec<GraphResource>,
+}
+
+impl<'g, 'a, C> ComputePassBuilder<'g, 'a, C> {
+
+    pub fn read<R: Into<GraphResource>>(mut self, resource: R) -> Self {
+        self.reads.push(resource.into());
+        self
+    }
+
+    pub fn write<R: Into<GraphResource>>(mut self, resource: R) -> Self {
+        self.writes.push(resource.into());
+        self
+    }
+
+    /// Add the pass to the graph. The closure records into the frame encoder, e.g. with ComputeKernel::dispatch.
+    /// Any encoder commands are fine, e.g. the render passes of ShadowMap::begin_cascade_pass.
+    pub fn execute<F>(self, execute: F)
+        where F: FnOnce(&C, &mut wgpu::CommandEncoder) + 'a {
+        self.graph.passes.push(Pass {
+            label: self.label,
+            reads: self.reads,
+            writes: self.writes,
+            kind: PassKind::Compute {
+                execute: Box::new(execute),
+            },
+        });
+    }
+}
+
+fn assert_attachment(resource: GraphResource, label: &str) {
+    if let GraphResource::Buffer(_) = resource {
+        panic!("FrameGraph: render pass '{}' uses a buffer as an attachment.", label);
+    }
+}
+
+fn attachment_view<'r>(resource: GraphResource,
+                       registry: &'r ResourceRegistry,
+                       pool: &'r TransientTextures,
+                       surface: Option<&'r wgpu::TextureView>,
+                       label: &str) -> &'r wgpu::TextureView {
+    match resource {
+        GraphResource::Texture(handle) => &registry.get_texture(handle).view,
+        GraphResource::Transient(handle) => &pool.get(handle).view,
+        GraphResource::Surface => {
+            match surface {
+                Some(view) => view,
+                None => panic!("FrameGraph: render pass '{}' renders to the surface but no surface view was given.", label),
+            }
+        }
+        GraphResource::Buffer(_) => unreachable!(),
+    }
+}
+
+fn sample_count(resource: GraphResource, registry: &ResourceRegistry, pool: &TransientTextures) -> u32 {
+    match resource {
+        GraphResource::Texture(handle) => registry.get_texture(handle).get_sample_count(),
+        GraphResource::Transient(handle) => pool.get(handle).descriptor.sample_count,
+        GraphResource::Surface => 1,
+        GraphResource::Buffer(_) => unreachable!(),
+    }
+}
+
+/// The attachments of a pass must have the same sample count and only multisampled
+/// attachments can be resolved to single sampled textures.
+fn check_sample_counts<F: Fn(GraphResource) -> u32>(label: &str,
+                                                    colors: &[ColorAttachment],
+                                                    depth: Option<(GraphResource, f32)>,
+                                                    sample_count: F) {
+
+    let counts: Vec<u32> = colors.iter().map(|c| c.resource)
+                                 .chain(depth.map(|(resource, _)| resource))
+                                 .map(|resource| sample_count(resource))
+                                 .collect();
+    assert!(counts.windows(2).all(|w| w[0] == w[1]), "{}", format!("FrameGraph: the attachments of render pass '{}' have different sample counts {:?}.", label, counts));
+
+    for c in colors.iter() {
+        if let Some(target) = c.resolve_target {
+            let from = sample_count(c.resource);
+            let to = sample_count(target);
+            assert!(from > 1 && to == 1, "{}", format!("FrameGraph: render pass '{}' resolves a texture with {} samples to a texture with {} samples.", label, from, to));
+        }
+    }
+}
+
+fn create_pool_texture(device: &wgpu::Device, label: &str, descriptor: &TransientTextureDescriptor) -> PoolTexture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: descriptor.size,
+        mip_level_count: 1,
+        sample_count: descriptor.sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: descriptor.format,
+        usage: descriptor.usage,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    PoolTexture {
+        descriptor: *descriptor,
+        texture: texture,
+        view: view,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(format: wgpu::TextureFormat) -> TransientTextureDescriptor {
+        TransientTextureDescriptor {
+            size: wgpu::Extent3d { width: 64, height: 64, depth_or_array_layers: 1, },
+            format: format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            sample_count: 1,
+        }
+    }
+
+    fn compute_pass(graph: &mut FrameGraph<()>, label: &str, reads: &[TransientHandle], writes: &[TransientHandle]) {
+        let mut pass = graph.add_compute_pass(label);
+        for &r in reads.iter() { pass = pass.read(r); }
+        for &w in writes.iter() { pass = pass.write(w); }
+        pass.execute(|_, _| {});
+    }
+
+    #[test]
+    fn order_readers_after_writers() {
+        let mut graph = FrameGraph::<()>::init("test");
+        let a = graph.create_texture("a", descriptor(wgpu::TextureFormat::Depth32Float));
+        let b = graph.create_texture("b", descriptor(wgpu::TextureFormat::Depth32Float));
+
+        compute_pass(&mut graph, "read b", &[b], &[]);
+        compute_pass(&mut graph, "read a write b", &[a], &[b]);
+        compute_pass(&mut graph, "independent", &[], &[]);
+        compute_pass(&mut graph, "write a", &[], &[a]);
+
+        assert_eq!(graph.get_order(), vec!["independent", "write a", "read a write b", "read b"]);
+    }
+
+    #[test]
+    fn order_writers_in_added_order() {
+        let mut graph = FrameGraph::<()>::init("test");
+        let a = graph.create_texture("a", descriptor(wgpu::TextureFormat::Depth32Float));
+
+        compute_pass(&mut graph, "read", &[a], &[]);
+        compute_pass(&mut graph, "first write", &[], &[a]);
+        compute_pass(&mut graph, "second write", &[a], &[a]);
+
+        assert_eq!(graph.get_order(), vec!["first write", "second write", "read"]);
+    }
+
+    #[test]
+    fn order_render_attachments() {
+        let mut graph = FrameGraph::<()>::init("test");
+        let depth = graph.create_texture("depth", descriptor(wgpu::TextureFormat::Depth32Float));
+
+        graph.add_render_pass("draw")
+             .color(GraphResource::Surface, wgpu::Color::BLACK)
+             .depth(depth, 1.0)
+             .execute(|_, _| {});
+        graph.add_render_pass("overlay")
+             .color(GraphResource::Surface, wgpu::Color::BLACK)
+             .execute(|_, _| {});
+        compute_pass(&mut graph, "read depth", &[depth], &[]);
+
+        assert_eq!(graph.get_order(), vec!["draw", "overlay", "read depth"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "depend on each other")]
+    fn order_cycle() {
+        let mut graph = FrameGraph::<()>::init("test");
+        let a = graph.create_texture("a", descriptor(wgpu::TextureFormat::Depth32Float));
+        let b = graph.create_texture("b", descriptor(wgpu::TextureFormat::Depth32Float));
+
+        compute_pass(&mut graph, "read a write b", &[a], &[b]);
+        compute_pass(&mut graph, "read b write a", &[b], &[a]);
+
+        graph.get_order();
+    }
+
+    /// Check the sample counts of a render pass with the sample counts of the transient descriptors.
+    fn check_pass(graph: &FrameGraph<()>, index: usize) {
+        let pass = &graph.passes[index];
+        if let PassKind::Render { colors, depth, .. } = &pass.kind {
+            check_sample_counts(&pass.label, colors, *depth, |resource| match resource {
+                GraphResource::Transient(TransientHandle(i)) => graph.transients[i].1.sample_count,
+                _ => 1,
+            });
+        }
+    }
+
+    #[test]
+    fn resolve_in_last_pass() {
+        let mut graph = FrameGraph::<()>::init("test");
+        let color = graph.create_texture("color", descriptor(wgpu::TextureFormat::Rgba8Unorm).with_sample_count(4));
+        let depth = graph.create_texture("depth", descriptor(wgpu::TextureFormat::Depth32Float).with_sample_count(4));
+
+        graph.add_render_pass("draw")
+             .color_resolve(color, GraphResource::Surface, wgpu::Color::BLACK)
+             .depth(depth, 1.0)
+             .execute(|_, _| {});
+        graph.add_render_pass("overlay")
+             .color_resolve(color, GraphResource::Surface, wgpu::Color::BLACK)
+             .execute(|_, _| {});
+
+        // Only the overlay resolves the color to the surface.
+        let order = graph.sort();
+        assert_eq!(graph.get_order(), vec!["draw", "overlay"]);
+        let last_resolve = graph.last_resolves(&order);
+        assert_eq!(last_resolve.len(), 1);
+        assert_eq!(last_resolve.get(&GraphResource::Transient(color)), Some(&1));
+
+        check_pass(&graph, 0);
+        check_pass(&graph, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "different sample counts [4, 1]")]
+    fn mismatched_sample_counts() {
+        let mut graph = FrameGraph::<()>::init("test");
+        let color = graph.create_texture("color", descriptor(wgpu::TextureFormat::Rgba8Unorm).with_sample_count(4));
+        let depth = graph.create_texture("depth", descriptor(wgpu::TextureFormat::Depth32Float));
+
+        graph.add_render_pass("draw")
+             .color_resolve(color, GraphResource::Surface, wgpu::Color::BLACK)
+             .depth(depth, 1.0)
+             .execute(|_, _| {});
+
+        check_pass(&graph, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "resolves a texture with 1 samples")]
+    fn resolve_single_sampled() {
+        let mut graph = FrameGraph::<()>::init("test");
+        let color = graph.create_texture("color", descriptor(wgpu::TextureFormat::Rgba8Unorm));
+
+        graph.add_render_pass("draw")
+             .color_resolve(color, GraphResource::Surface, wgpu::Color::BLACK)
+             .execute(|_, _| {});
+
+        check_pass(&graph, 0);
+    }
+
+    #[test]
+    fn assign_aliases_disjoint_lifetimes() {
+        let mut graph = FrameGraph::<()>::init("test");
+        let a = graph.create_texture("a", descriptor(wgpu::TextureFormat::Depth32Float));
+        let b = graph.create_texture("b", descriptor(wgpu::TextureFormat::Depth32Float));
+        let c = graph.create_texture("c", descriptor(wgpu::TextureFormat::Depth32Float));
+        let d = graph.create_texture("d", descriptor(wgpu::TextureFormat::Rgba8Unorm));
+        let _unused = graph.create_texture("unused", descriptor(wgpu::TextureFormat::Depth32Float));
+
+        // a lives in the passes 0..1, b in 1..2 and c in 2..3. d has another descriptor.
+        compute_pass(&mut graph, "0", &[], &[a]);
+        compute_pass(&mut graph, "1", &[a], &[b]);
+        compute_pass(&mut graph, "2", &[b], &[c, d]);
+        compute_pass(&mut graph, "3", &[c, d], &[]);
+
+        let order = graph.sort();
+        let assignment = graph.assign(&order, &[]);
+
+        // a and b overlap in pass 1, so c is the first one that can reuse the texture of a.
+        assert_eq!(assignment.transients, vec![Some(0), Some(1), Some(0), Some(2), None]);
+        assert_eq!(assignment.created, vec![0, 1, 3]);
+        assert_eq!(assignment.used, vec![true, true, true]);
+    }
+
+    #[test]
+    fn assign_reuses_pool() {
+        let mut graph = FrameGraph::<()>::init("test");
+        let a = graph.create_texture("a", descriptor(wgpu::TextureFormat::Depth32Float));
+        let b = graph.create_texture("b", descriptor(wgpu::TextureFormat::Depth32Float));
+
+        compute_pass(&mut graph, "0", &[], &[a, b]);
+
+        let order = graph.sort();
+        let pool = [descriptor(wgpu::TextureFormat::Rgba8Unorm), descriptor(wgpu::TextureFormat::Depth32Float)];
+        let assignment = graph.assign(&order, &pool);
+
+        // a gets the matching pool texture, b needs a new one and the Rgba8Unorm texture is released.
+        assert_eq!(assignment.transients, vec![Some(1), Some(2)]);
+        assert_eq!(assignment.created, vec![1]);
+        assert_eq!(assignment.used, vec![false, true, true]);
+    }
+}
@@ -0,0 +1,149 @@
+use std::borrow::Cow;
+use bytemuck::{Zeroable, Pod};
+use geometry::frustum::Frustum;
+use crate::buffer::buffer_from_data;
+use crate::compute::ComputeKernel;
+use crate::render_pipelines::{DrawIndirectArgs, ShaderBindings};
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CullUniform {
+    pub planes: [cgmath::Vector4<f32>; 6],
+    pub aabb_count: u32,
+    pub future_usage1: u32,
+    pub future_usage2: u32,
+    pub future_usage3: u32,
+}
+
+unsafe impl Pod for CullUniform {}
+unsafe impl Zeroable for CullUniform {}
+
+/// Uniform data for frustum culling (set=0, binding=0), the draw indirect arguments
+/// (set=0, binding=2) and the indices of the visible aabbs (set=0, binding=3).
+pub struct CullParams {
+    params: CullUniform,
+    buffer: wgpu::Buffer,
+    pub draw_indirect_buffer: wgpu::Buffer,
+    pub visible_buffer: wgpu::Buffer,
+}
+
+impl CullParams {
+
+    /// Create an instance of CullParams. The visible aabbs are drawn with
+    /// draw_indirect(draw_indirect_buffer, 0) as instances of vertex_count vertices. The
+    /// visible_buffer can be used as an instance buffer (u32 aabb index per instance).
+    pub fn init(device: &wgpu::Device, aabb_count: u32, vertex_count: u32) -> Self {
+
+        assert!(aabb_count > 0, "{}", format!("aabb_count == {} > 0", aabb_count));
+
+        let uniform = CullUniform {
+            planes: [cgmath::Vector4::<f32>::new(0.0, 0.0, 0.0, 0.0); 6],
+            aabb_count: aabb_count,
+            future_usage1: 0,
+            future_usage2: 0,
+            future_usage3: 0,
+        };
+
+        let args = DrawIndirectArgs {
+            vertex_count: vertex_count,
+            instance_count: 0,
+            base_vertex: 0,
+            base_instance: 0,
+        };
+
+        Self {
+            params: uniform,
+            buffer: buffer_from_data::<CullUniform>(
+                &device,
+                &[uniform],
+                wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+                None),
+            draw_indirect_buffer: buffer_from_data::<DrawIndirectArgs>(
+                &device,
+                &[args],
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+                None),
+            visible_buffer: buffer_from_data::<u32>(
+                &device,
+                &vec![0 as u32 ; aabb_count as usize],
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+                None),
+        }
+    }
+
+    pub fn get_uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn get_params(&self) -> &CullUniform {
+        &self.params
+    }
+
+    /// Update the frustum planes and reset the instance count. Call this before each dispatch.
+    pub fn update_frustum(&mut self, queue: &wgpu::Queue, frustum: &Frustum) {
+
+        self.params.planes = frustum.to_vec4();
+
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[self.params])
+        );
+
+        // Reset instance_count.
+        queue.write_buffer(
+            &self.draw_indirect_buffer,
+            std::mem::size_of::<u32>() as wgpu::BufferAddress,
+            bytemuck::cast_slice(&[0 as u32])
+        );
+    }
+}
+
+/// Frustum culling of aabbs on the gpu. The aabbs are BBox4:s. The same test as in
+/// geometry::frustum::Frustum::intersects_aabb.
+pub struct FrustumCuller {
+    kernel: ComputeKernel,
+    bindings: ShaderBindings,
+}
+
+impl FrustumCuller {
+
+    pub fn init(device: &wgpu::Device) -> Self {
+
+        let source = include_str!("../../shaders_wgsl/frustum_cull.wgsl");
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("frustum_cull.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+        });
+        let bindings = ShaderBindings::from_wgsl(source).unwrap();
+
+        Self {
+            kernel: ComputeKernel::from_bindings(&device, &module, "main", &bindings, Some("frustum_culler")).unwrap(),
+            bindings: bindings,
+        }
+    }
+
+    /// Creates the bind groups for frustum culling.
+    pub fn create_bind_groups(&self,
+                              device: &wgpu::Device,
+                              params: &CullParams,
+                              aabb_buffer: &wgpu::Buffer) -> Result<Vec<wgpu::BindGroup>, String> {
+
+        let resources = self.bindings.resolve(
+            &[("params", params.get_uniform_buffer().as_entire_binding()),
+              ("aabbs", aabb_buffer.as_entire_binding()),
+              ("draw_indirect", params.draw_indirect_buffer.as_entire_binding()),
+              ("visible", params.visible_buffer.as_entire_binding())]
+        )?;
+        self.kernel.create_bind_groups(&device, &resources)
+    }
+
+    /// Cull aabb_count aabbs. One invocation per aabb.
+    pub fn dispatch(&self,
+                    bind_groups: &Vec<wgpu::BindGroup>,
+                    encoder: &mut wgpu::CommandEncoder,
+                    aabb_count: u32) {
+
+        self.kernel.dispatch_items(bind_groups, encoder, [aabb_count, 1, 1])
+    }
+}
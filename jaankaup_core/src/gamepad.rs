@@ -0,0 +1,479 @@
+use std::collections::{HashMap, VecDeque};
+use winit::event::ElementState;
+use crate::input::InputState;
+
+/// Gamepad buttons. The face buttons are named by their position (South is A on Xbox and Cross
+/// on PlayStation controllers).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    Select,
+    Start,
+    Mode,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// Gamepad axes. The sticks are in range [-1, 1] (up and right are positive) and the triggers in
+/// range [0, 1].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+/// An event from a gamepad backend.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GamepadEvent {
+    Connected(u32),
+    Disconnected(u32),
+    Button { id: u32, button: GamepadButton, state: ElementState },
+    Axis { id: u32, axis: GamepadAxis, value: f32 },
+}
+
+/// A source of gamepad events. InputCache::update_gamepads polls the backend once per tick.
+pub trait GamepadBackend {
+    fn poll(&mut self) -> Vec<GamepadEvent>;
+}
+
+/// A backend without hardware. The events are pushed by hand. Use this for tests and when there
+/// is no gamepad support.
+#[derive(Clone, Debug, Default)]
+pub struct MockGamepadBackend {
+    events: VecDeque<GamepadEvent>,
+}
+
+impl MockGamepadBackend {
+
+    pub fn init() -> Self {
+        Self {
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Add an event for the next poll.
+    pub fn push(&mut self, event: GamepadEvent) {
+        self.events.push_back(event);
+    }
+
+    pub fn connect(&mut self, id: u32) {
+        self.push(GamepadEvent::Connected(id));
+    }
+
+    pub fn disconnect(&mut self, id: u32) {
+        self.push(GamepadEvent::Disconnected(id));
+    }
+
+    pub fn press(&mut self, id: u32, button: GamepadButton) {
+        self.push(GamepadEvent::Button { id: id, button: button, state: ElementState::Pressed });
+    }
+
+    pub fn release(&mut self, id: u32, button: GamepadButton) {
+        self.push(GamepadEvent::Button { id: id, button: button, state: ElementState::Released });
+    }
+
+    pub fn set_axis(&mut self, id: u32, axis: GamepadAxis, value: f32) {
+        self.push(GamepadEvent::Axis { id: id, axis: axis, value: value });
+    }
+}
+
+impl GamepadBackend for MockGamepadBackend {
+    fn poll(&mut self) -> Vec<GamepadEvent> {
+        self.events.drain(..).collect()
+    }
+}
+
+/// A backend for real devices (gilrs). Requires the "gamepad" feature.
+#[cfg(feature = "gamepad")]
+pub struct GilrsBackend {
+    gilrs: gilrs::Gilrs,
+}
+
+#[cfg(feature = "gamepad")]
+impl GilrsBackend {
+
+    pub fn init() -> Result<Self, String> {
+        match gilrs::Gilrs::new() {
+            Ok(gilrs) => Ok(Self { gilrs: gilrs }),
+            Err(e) => Err(format!("Failed to initialize gilrs: {}", e)),
+        }
+    }
+}
+
+#[cfg(feature = "gamepad")]
+impl GamepadBackend for GilrsBackend {
+    fn poll(&mut self) -> Vec<GamepadEvent> {
+
+        use gilrs::{Axis, Button, EventType};
+
+        let button = |b: Button| -> Option<GamepadButton> {
+            match b {
+                Button::South => Some(GamepadButton::South),
+                Button::East => Some(GamepadButton::East),
+                Button::North => Some(GamepadButton::North),
+                Button::West => Some(GamepadButton::West),
+                Button::LeftTrigger => Some(GamepadButton::LeftBumper),
+                Button::RightTrigger => Some(GamepadButton::RightBumper),
+                Button::Select => Some(GamepadButton::Select),
+                Button::Start => Some(GamepadButton::Start),
+                Button::Mode => Some(GamepadButton::Mode),
+                Button::LeftThumb => Some(GamepadButton::LeftStick),
+                Button::RightThumb => Some(GamepadButton::RightStick),
+                Button::DPadUp => Some(GamepadButton::DPadUp),
+                Button::DPadDown => Some(GamepadButton::DPadDown),
+                Button::DPadLeft => Some(GamepadButton::DPadLeft),
+                Button::DPadRight => Some(GamepadButton::DPadRight),
+                _ => None,
+            }
+        };
+
+        let mut result = Vec::new();
+
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            let id: usize = id.into();
+            let id = id as u32;
+            match event {
+                EventType::Connected => result.push(GamepadEvent::Connected(id)),
+                EventType::Disconnected => result.push(GamepadEvent::Disconnected(id)),
+                EventType::ButtonPressed(b, _) => if let Some(b) = button(b) {
+                    result.push(GamepadEvent::Button { id: id, button: b, state: ElementState::Pressed });
+                },
+                EventType::ButtonReleased(b, _) => if let Some(b) = button(b) {
+                    result.push(GamepadEvent::Button { id: id, button: b, state: ElementState::Released });
+                },
+                // The analog triggers are reported as buttons.
+                EventType::ButtonChanged(Button::LeftTrigger2, value, _) => {
+                    result.push(GamepadEvent::Axis { id: id, axis: GamepadAxis::LeftTrigger, value: value });
+                }
+                EventType::ButtonChanged(Button::RightTrigger2, value, _) => {
+                    result.push(GamepadEvent::Axis { id: id, axis: GamepadAxis::RightTrigger, value: value });
+                }
+                EventType::AxisChanged(a, value, _) => {
+                    let axis = match a {
+                        Axis::LeftStickX => Some(GamepadAxis::LeftStickX),
+                        Axis::LeftStickY => Some(GamepadAxis::LeftStickY),
+                        Axis::RightStickX => Some(GamepadAxis::RightStickX),
+                        Axis::RightStickY => Some(GamepadAxis::RightStickY),
+                        _ => None,
+                    };
+                    if let Some(axis) = axis {
+                        result.push(GamepadEvent::Axis { id: id, axis: axis, value: value });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        result
+    }
+}
+
+/// Create the default gamepad backend. GilrsBackend with the "gamepad" feature, otherwise a
+/// backend without devices.
+pub fn default_backend() -> Box<dyn GamepadBackend> {
+    #[cfg(feature = "gamepad")]
+    {
+        match GilrsBackend::init() {
+            Ok(backend) => return Box::new(backend),
+            Err(e) => log::info!("{}", e),
+        }
+    }
+    Box::new(MockGamepadBackend::init())
+}
+
+/// The deadzones of the sticks and the triggers. The values inside the deadzone are 0.0 and the
+/// rest of the range is scaled to [0, 1].
+/// stick: the radial deadzone of the sticks.
+/// trigger: the deadzone of the triggers.
+#[derive(Clone, Copy, Debug)]
+pub struct GamepadDeadzones {
+    pub stick: f32,
+    pub trigger: f32,
+}
+
+impl Default for GamepadDeadzones {
+    fn default() -> Self {
+        Self {
+            stick: 0.15,
+            trigger: 0.05,
+        }
+    }
+}
+
+/// The state of a single gamepad.
+#[derive(Clone, Debug, Default)]
+struct Gamepad {
+    buttons: HashMap<GamepadButton, InputState>,
+    axes: HashMap<GamepadAxis, f32>,
+}
+
+/// The state of the connected gamepads.
+#[derive(Clone, Debug, Default)]
+pub struct Gamepads {
+    gamepads: HashMap<u32, Gamepad>,
+    deadzones: GamepadDeadzones,
+}
+
+impl Gamepads {
+
+    pub fn init() -> Self {
+        Self {
+            gamepads: HashMap::new(),
+            deadzones: GamepadDeadzones::default(),
+        }
+    }
+
+    pub fn set_deadzones(&mut self, deadzones: GamepadDeadzones) {
+        assert!(deadzones.stick >= 0.0 && deadzones.stick < 1.0, "Stick deadzone must be in range [0, 1[.");
+        assert!(deadzones.trigger >= 0.0 && deadzones.trigger < 1.0, "Trigger deadzone must be in range [0, 1[.");
+        self.deadzones = deadzones;
+    }
+
+    pub fn get_deadzones(&self) -> GamepadDeadzones {
+        self.deadzones
+    }
+
+    /// The ids of the connected gamepads in ascending order.
+    pub fn get_ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.gamepads.keys().cloned().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Apply a gamepad event. Events from unknown gamepads connect them.
+    pub fn update(&mut self, event: &GamepadEvent, time_now: u128) {
+        match *event {
+            GamepadEvent::Connected(id) => { let _ = self.gamepads.entry(id).or_default(); }
+            GamepadEvent::Disconnected(id) => { let _ = self.gamepads.remove(&id); }
+            GamepadEvent::Button { id, button, state } => {
+                let buttons = &mut self.gamepads.entry(id).or_default().buttons;
+                match (buttons.get_mut(&button), state) {
+                    (Some(s), _) => s.update(&state, time_now),
+                    (None, ElementState::Pressed) => { let _ = buttons.insert(button, InputState::Pressed(time_now)); }
+                    (None, ElementState::Released) => {}
+                }
+            }
+            GamepadEvent::Axis { id, axis, value } => {
+                let _ = self.gamepads.entry(id).or_default().axes.insert(axis, value);
+            }
+        }
+    }
+
+    /// Move the button states to the next tick. The released buttons are removed and the pressed
+    /// buttons become down.
+    pub fn pre_update(&mut self, time_now: u128) {
+        for gamepad in self.gamepads.values_mut() {
+            gamepad.buttons.retain(|_, state| match state { InputState::Released(_,_) => false, _ => true });
+            for state in gamepad.buttons.values_mut() {
+                if let InputState::Pressed(start_time) | InputState::Down(start_time, _) = *state {
+                    *state = InputState::Down(start_time, time_now);
+                }
+            }
+        }
+    }
+
+    /// The state of the button of the gamepad id.
+    pub fn button_state(&self, id: u32, button: &GamepadButton) -> Option<InputState> {
+        self.gamepads.get(&id).and_then(|g| g.buttons.get(button).cloned())
+    }
+
+    /// The state of the button on any gamepad.
+    pub fn any_button_state(&self, button: &GamepadButton) -> Option<InputState> {
+        self.get_ids().iter().find_map(|id| self.button_state(*id, button))
+    }
+
+    /// The value of the axis of the gamepad id with the deadzones applied.
+    pub fn axis(&self, id: u32, axis: &GamepadAxis) -> f32 {
+        let gamepad = match self.gamepads.get(&id) {
+            Some(g) => g,
+            None => return 0.0,
+        };
+        let raw = |a: GamepadAxis| -> f32 { *gamepad.axes.get(&a).unwrap_or(&0.0) };
+        match axis {
+            GamepadAxis::LeftStickX => self.stick(raw(GamepadAxis::LeftStickX), raw(GamepadAxis::LeftStickY)).0,
+            GamepadAxis::LeftStickY => self.stick(raw(GamepadAxis::LeftStickX), raw(GamepadAxis::LeftStickY)).1,
+            GamepadAxis::RightStickX => self.stick(raw(GamepadAxis::RightStickX), raw(GamepadAxis::RightStickY)).0,
+            GamepadAxis::RightStickY => self.stick(raw(GamepadAxis::RightStickX), raw(GamepadAxis::RightStickY)).1,
+            GamepadAxis::LeftTrigger | GamepadAxis::RightTrigger => {
+                rescale(raw(*axis).max(0.0).min(1.0), self.deadzones.trigger)
+            }
+        }
+    }
+
+    /// The value of the axis on any gamepad. The value with the largest magnitude is returned.
+    pub fn any_axis(&self, axis: &GamepadAxis) -> f32 {
+        self.get_ids().iter()
+                      .map(|id| self.axis(*id, axis))
+                      .fold(0.0, |a, b| if b.abs() > a.abs() { b } else { a })
+    }
+
+    /// Apply the radial deadzone to the stick (x, y).
+    fn stick(&self, x: f32, y: f32) -> (f32, f32) {
+        let magnitude = (x * x + y * y).sqrt();
+        if magnitude <= self.deadzones.stick { return (0.0, 0.0); }
+        let scale = rescale(magnitude.min(1.0), self.deadzones.stick) / magnitude;
+        (x * scale, y * scale)
+    }
+}
+
+/// Map the value in [deadzone, 1] to [0, 1]. Values below deadzone are 0.0.
+fn rescale(value: f32, deadzone: f32) -> f32 {
+    if value <= deadzone { 0.0 }
+    else { (value - deadzone) / (1.0 - deadzone) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::ActionMap;
+    use crate::input::{Clock, InputCache, InputEvent, Key};
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn stick_deadzone_is_radial() {
+        let mut gamepads = Gamepads::init();
+        gamepads.set_deadzones(GamepadDeadzones { stick: 0.2, trigger: 0.1 });
+
+        // Inside the deadzone.
+        gamepads.update(&GamepadEvent::Axis { id: 0, axis: GamepadAxis::LeftStickX, value: 0.1 }, 0);
+        gamepads.update(&GamepadEvent::Axis { id: 0, axis: GamepadAxis::LeftStickY, value: -0.1 }, 0);
+        assert_eq!(gamepads.axis(0, &GamepadAxis::LeftStickX), 0.0);
+        assert_eq!(gamepads.axis(0, &GamepadAxis::LeftStickY), 0.0);
+
+        // The magnitude 0.6 is rescaled to (0.6 - 0.2) / 0.8 = 0.5 and the direction is kept.
+        gamepads.update(&GamepadEvent::Axis { id: 0, axis: GamepadAxis::LeftStickX, value: 0.36 }, 0);
+        gamepads.update(&GamepadEvent::Axis { id: 0, axis: GamepadAxis::LeftStickY, value: -0.48 }, 0);
+        assert!(approx(gamepads.axis(0, &GamepadAxis::LeftStickX), 0.3));
+        assert!(approx(gamepads.axis(0, &GamepadAxis::LeftStickY), -0.4));
+
+        // The full range is reached and the values outside the unit circle are clamped.
+        gamepads.update(&GamepadEvent::Axis { id: 0, axis: GamepadAxis::LeftStickX, value: 1.0 }, 0);
+        gamepads.update(&GamepadEvent::Axis { id: 0, axis: GamepadAxis::LeftStickY, value: 0.0 }, 0);
+        assert!(approx(gamepads.axis(0, &GamepadAxis::LeftStickX), 1.0));
+        gamepads.update(&GamepadEvent::Axis { id: 0, axis: GamepadAxis::LeftStickY, value: 1.0 }, 0);
+        assert!(approx(gamepads.axis(0, &GamepadAxis::LeftStickX), std::f32::consts::FRAC_1_SQRT_2));
+
+        // A single axis past the deadzone is enough.
+        gamepads.update(&GamepadEvent::Axis { id: 0, axis: GamepadAxis::RightStickY, value: 0.25 }, 0);
+        assert!(gamepads.axis(0, &GamepadAxis::RightStickY) > 0.0);
+        assert_eq!(gamepads.axis(0, &GamepadAxis::RightStickX), 0.0);
+    }
+
+    #[test]
+    fn trigger_deadzone() {
+        let mut gamepads = Gamepads::init();
+        gamepads.set_deadzones(GamepadDeadzones { stick: 0.2, trigger: 0.1 });
+
+        gamepads.update(&GamepadEvent::Axis { id: 0, axis: GamepadAxis::LeftTrigger, value: 0.05 }, 0);
+        assert_eq!(gamepads.axis(0, &GamepadAxis::LeftTrigger), 0.0);
+        gamepads.update(&GamepadEvent::Axis { id: 0, axis: GamepadAxis::LeftTrigger, value: 0.55 }, 0);
+        assert!(approx(gamepads.axis(0, &GamepadAxis::LeftTrigger), 0.5));
+        gamepads.update(&GamepadEvent::Axis { id: 0, axis: GamepadAxis::LeftTrigger, value: 1.0 }, 0);
+        assert!(approx(gamepads.axis(0, &GamepadAxis::LeftTrigger), 1.0));
+
+        // Unknown gamepads have no axes.
+        assert_eq!(gamepads.axis(1, &GamepadAxis::LeftTrigger), 0.0);
+    }
+
+    #[test]
+    fn button_states_across_ticks() {
+        let mut input = InputCache::init_with_clock(Clock::Manual(0));
+        let mut backend = MockGamepadBackend::init();
+        let south = GamepadButton::South;
+
+        backend.connect(3);
+        input.get_clock_mut().set(10);
+        input.pre_update();
+        input.update_gamepads(&mut backend);
+        assert_eq!(input.get_gamepads().get_ids(), vec![3]);
+        assert!(input.get_gamepads().button_state(3, &south).is_none());
+
+        backend.press(3, south);
+        input.get_clock_mut().set(20);
+        input.pre_update();
+        input.update_gamepads(&mut backend);
+        assert!(matches!(input.get_gamepads().button_state(3, &south), Some(InputState::Pressed(20))));
+
+        input.get_clock_mut().set(30);
+        input.pre_update();
+        input.update_gamepads(&mut backend);
+        assert!(matches!(input.get_gamepads().button_state(3, &south), Some(InputState::Down(20, 30))));
+        assert!(matches!(input.get_gamepads().any_button_state(&south), Some(InputState::Down(20, 30))));
+
+        backend.release(3, south);
+        input.get_clock_mut().set(40);
+        input.pre_update();
+        input.update_gamepads(&mut backend);
+        assert!(matches!(input.get_gamepads().button_state(3, &south), Some(InputState::Released(20, 40))));
+
+        input.get_clock_mut().set(50);
+        input.pre_update();
+        input.update_gamepads(&mut backend);
+        assert!(input.get_gamepads().button_state(3, &south).is_none());
+
+        // The state of a disconnected gamepad is removed.
+        backend.press(3, south);
+        backend.disconnect(3);
+        input.pre_update();
+        input.update_gamepads(&mut backend);
+        assert!(input.get_gamepads().get_ids().is_empty());
+        assert!(input.get_gamepads().any_button_state(&south).is_none());
+    }
+
+    #[test]
+    fn axis_action_combines_positive_and_negative_bindings() {
+        let actions = ActionMap::parse("axis right = D, GamepadLeftStickX / A, GamepadDPadLeft").unwrap();
+        let mut input = InputCache::init_with_clock(Clock::Manual(0));
+        let mut backend = MockGamepadBackend::init();
+        backend.connect(0);
+        input.update_gamepads(&mut backend);
+        assert_eq!(actions.axis(&input, "right"), 0.0);
+
+        // The stick is a positive binding, so left on the stick is negative.
+        backend.set_axis(0, GamepadAxis::LeftStickX, -1.0);
+        input.update_gamepads(&mut backend);
+        assert!(approx(actions.axis(&input, "right"), -1.0));
+
+        // The strongest positive binding minus the strongest negative binding, clamped to [-1, 1].
+        backend.set_axis(0, GamepadAxis::LeftStickX, 0.0);
+        input.apply(&InputEvent::Key(Key::D, ElementState::Pressed));
+        input.update_gamepads(&mut backend);
+        assert!(approx(actions.axis(&input, "right"), 1.0));
+        backend.press(0, GamepadButton::DPadLeft);
+        input.update_gamepads(&mut backend);
+        assert!(approx(actions.axis(&input, "right"), 0.0));
+
+        backend.set_axis(0, GamepadAxis::LeftStickX, 1.0);
+        input.update_gamepads(&mut backend);
+        assert!(approx(actions.axis(&input, "right"), 0.0));
+
+        backend.release(0, GamepadButton::DPadLeft);
+        input.apply(&InputEvent::Key(Key::D, ElementState::Released));
+        input.pre_update();
+        input.update_gamepads(&mut backend);
+        assert!(approx(actions.axis(&input, "right"), 1.0));
+
+        // Half the stick past the deadzone against a key.
+        let deadzone = input.get_gamepads().get_deadzones().stick;
+        backend.set_axis(0, GamepadAxis::LeftStickX, deadzone + 0.5 * (1.0 - deadzone));
+        input.apply(&InputEvent::Key(Key::A, ElementState::Pressed));
+        input.update_gamepads(&mut backend);
+        assert!(approx(actions.axis(&input, "right"), -0.5));
+    }
+}
@@ -0,0 +1,219 @@
+use std::borrow::Cow;
+use bytemuck::{Zeroable, Pod};
+use crate::buffer::buffer_from_data;
+use crate::compute::{ComputeKernel, DispatchIndirectArgs};
+use crate::render_pipelines::ShaderBindings;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct IndirectUniform {
+    counter_index: u32,
+    kind: u32,
+    first_vertex: u32,
+    instance_count: u32,
+    items_per_workgroup: u32,
+    future_usage1: u32,
+    future_usage2: u32,
+    future_usage3: u32,
+}
+
+unsafe impl Pod for IndirectUniform {}
+unsafe impl Zeroable for IndirectUniform {}
+
+const KIND_DRAW: u32 = 0;
+const KIND_DISPATCH: u32 = 1;
+
+/// The largest workgroup count of a single dispatch dimension.
+pub const MAX_WORKGROUPS_PER_DIMENSION: u32 = 65535;
+
+/// The dispatch arguments that indirect_args.wgsl computes for counter items. The workgroups that
+/// don't fit in x are split over y, so x * y can be larger than the needed workgroups. The kernel
+/// gets the index of the workgroup as workgroup_id.x + workgroup_id.y * MAX_WORKGROUPS_PER_DIMENSION
+/// and must skip the indices past the count.
+pub fn dispatch_args(counter: u32, items_per_workgroup: u32) -> DispatchIndirectArgs {
+    assert!(items_per_workgroup > 0, "items_per_workgroup == {} > 0", items_per_workgroup);
+    let workgroups = counter / items_per_workgroup + if counter % items_per_workgroup != 0 { 1 } else { 0 };
+    DispatchIndirectArgs {
+        x: workgroups.min(MAX_WORKGROUPS_PER_DIMENSION),
+        y: workgroups / MAX_WORKGROUPS_PER_DIMENSION + if workgroups % MAX_WORKGROUPS_PER_DIMENSION != 0 { 1 } else { 0 },
+        z: 1,
+    }
+}
+
+/// Indirect arguments computed from a gpu counter. Created with IndirectArgsKernel and updated
+/// with IndirectArgsKernel::write.
+pub struct IndirectArgs {
+    #[allow(dead_code)]
+    params: wgpu::Buffer,
+    buffer: wgpu::Buffer,
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl IndirectArgs {
+
+    /// The DrawIndirectArgs or DispatchIndirectArgs at offset 0. Use with
+    /// DrawCall::draw_indirect or ComputeKernel::dispatch_indirect.
+    pub fn get_buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+}
+
+/// A single invocation kernel that turns a counter (e.g. McParams::counter_buffer or a
+/// Histogram) into draw or dispatch arguments, so the count never leaves the gpu.
+///
+/// ```ignore
+/// let indirect = IndirectArgsKernel::init(&device);
+/// let points = indirect.create_draw_args(&device, histogram.get_histogram(), 0, 0, 1);
+/// // After the kernel that writes the counter.
+/// indirect.write(&points, &mut encoder);
+/// DrawCall::init(&pipeline, &bind_groups).vertex_buffer(&vertices).draw_indirect(pass, points.get_buffer(), 0);
+/// ```
+pub struct IndirectArgsKernel {
+    kernel: ComputeKernel,
+    bindings: ShaderBindings,
+}
+
+impl IndirectArgsKernel {
+
+    pub fn init(device: &wgpu::Device) -> Self {
+
+        let source = include_str!("../../shaders_wgsl/indirect_args.wgsl");
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("indirect_args.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+        });
+        let bindings = ShaderBindings::from_wgsl(source).unwrap();
+
+        Self {
+            kernel: ComputeKernel::from_bindings(&device, &module, "main", &bindings, Some("indirect_args")).unwrap(),
+            bindings: bindings,
+        }
+    }
+
+    /// DrawIndirectArgs for the vertices first_vertex..counter, where counter is the u32 at
+    /// counter_index of counter_buffer.
+    pub fn create_draw_args(&self,
+                            device: &wgpu::Device,
+                            counter_buffer: &wgpu::Buffer,
+                            counter_index: u32,
+                            first_vertex: u32,
+                            instance_count: u32) -> IndirectArgs {
+
+        self.create_args(device, counter_buffer, IndirectUniform {
+            counter_index: counter_index,
+            kind: KIND_DRAW,
+            first_vertex: first_vertex,
+            instance_count: instance_count,
+            items_per_workgroup: 1,
+            future_usage1: 0,
+            future_usage2: 0,
+            future_usage3: 0,
+        })
+    }
+
+    /// DispatchIndirectArgs with enough workgroups to cover counter items, where counter is the
+    /// u32 at counter_index of counter_buffer. More than MAX_WORKGROUPS_PER_DIMENSION workgroups
+    /// are split over y (see dispatch_args).
+    pub fn create_dispatch_args(&self,
+                                device: &wgpu::Device,
+                                counter_buffer: &wgpu::Buffer,
+                                counter_index: u32,
+                                items_per_workgroup: u32) -> IndirectArgs {
+
+        assert!(items_per_workgroup > 0, "{}", format!("items_per_workgroup == {} > 0", items_per_workgroup));
+
+        self.create_args(device, counter_buffer, IndirectUniform {
+            counter_index: counter_index,
+            kind: KIND_DISPATCH,
+            first_vertex: 0,
+            instance_count: 0,
+            items_per_workgroup: items_per_workgroup,
+            future_usage1: 0,
+            future_usage2: 0,
+            future_usage3: 0,
+        })
+    }
+
+    /// Compute the arguments from the current counter value. Record this after the kernel that
+    /// writes the counter and before the indirect draw or dispatch.
+    pub fn write(&self, args: &IndirectArgs, encoder: &mut wgpu::CommandEncoder) {
+        self.kernel.dispatch(&args.bind_groups, encoder, 1, 1, 1);
+    }
+
+    fn create_args(&self, device: &wgpu::Device, counter_buffer: &wgpu::Buffer, uniform: IndirectUniform) -> IndirectArgs {
+
+        let params = buffer_from_data::<IndirectUniform>(
+            &device,
+            &[uniform],
+            wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            None);
+
+        // Large enough for both DrawIndirectArgs and DispatchIndirectArgs.
+        let buffer = buffer_from_data::<u32>(
+            &device,
+            &[0, 0, 0, 0],
+            wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            None);
+
+        let resources = self.bindings.resolve(
+            &[("params", params.as_entire_binding()),
+              ("counters", counter_buffer.as_entire_binding()),
+              ("args", buffer.as_entire_binding())]
+        ).unwrap();
+        let bind_groups = self.kernel.create_bind_groups(&device, &resources).unwrap();
+
+        IndirectArgs {
+            params: params,
+            buffer: buffer,
+            bind_groups: bind_groups,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_pipelines::{DrawIndirectArgs, wgsl_struct_layout};
+
+    #[test]
+    fn args_layout() {
+        let params = wgsl_struct_layout(include_str!("../../shaders_wgsl/indirect_args.wgsl"), "IndirectParams").unwrap();
+        assert_eq!(params.size as usize, std::mem::size_of::<IndirectUniform>());
+        assert_eq!(params.offsets.iter().map(|(_, offset)| *offset).collect::<Vec<u32>>(), vec![0, 4, 8, 12, 16, 20, 24, 28]);
+
+        // The shader writes the u32s in the field order of the wgpu arguments.
+        let draw: DrawIndirectArgs = bytemuck::cast([10u32, 1, 2, 0]);
+        assert_eq!((draw.vertex_count, draw.instance_count, draw.base_vertex, draw.base_instance), (10, 1, 2, 0));
+        let dispatch: DispatchIndirectArgs = bytemuck::cast([3u32, 2, 1]);
+        assert_eq!(dispatch, DispatchIndirectArgs { x: 3, y: 2, z: 1 });
+    }
+
+    #[test]
+    fn dispatch_workgroups() {
+        let args = |x, y| DispatchIndirectArgs { x: x, y: y, z: 1 };
+        assert_eq!(dispatch_args(0, 64), args(0, 0));
+        assert_eq!(dispatch_args(1, 64), args(1, 1));
+        assert_eq!(dispatch_args(64, 64), args(1, 1));
+        assert_eq!(dispatch_args(65, 64), args(2, 1));
+        assert_eq!(dispatch_args(MAX_WORKGROUPS_PER_DIMENSION, 1), args(MAX_WORKGROUPS_PER_DIMENSION, 1));
+
+        // The workgroups past 65535 are split over y.
+        assert_eq!(dispatch_args(MAX_WORKGROUPS_PER_DIMENSION + 1, 1), args(MAX_WORKGROUPS_PER_DIMENSION, 2));
+        assert_eq!(dispatch_args(200000, 1), args(MAX_WORKGROUPS_PER_DIMENSION, 4));
+        assert_eq!(dispatch_args(u32::MAX, 1), args(MAX_WORKGROUPS_PER_DIMENSION, 65537));
+        assert_eq!(dispatch_args(u32::MAX, 64), args(MAX_WORKGROUPS_PER_DIMENSION, 1025));
+
+        // Every item gets a workgroup.
+        for &(counter, items) in [(200000u32, 3u32), (u32::MAX, 7), (12345678, 1)].iter() {
+            let a = dispatch_args(counter, items);
+            assert!(a.x as u64 * a.y as u64 * items as u64 >= counter as u64);
+            assert!((a.x as u64 * (a.y as u64 - 1)) * (items as u64) < counter as u64);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "items_per_workgroup == 0 > 0")]
+    fn dispatch_zero_items() {
+        dispatch_args(1, 0);
+    }
+}
@@ -5,7 +5,13 @@ use instant;
 use winit::event as ev;
 
 pub use ev::VirtualKeyCode as Key;
+pub use ev::ModifiersState;
 use winit::dpi::PhysicalPosition;
+use crate::gamepad::{Gamepads, GamepadBackend, GamepadEvent};
+use crate::input_record::InputRecording;
+
+/// The number of pixels in one scroll line (touchpads report the scroll amount in pixels).
+const PIXELS_PER_LINE: f64 = 20.0;
 
 /// An enum for mouse and keyboard button states.
 #[derive(Clone,Debug)]
@@ -24,8 +30,8 @@ impl InputState {
                     InputState::Pressed(start_time) => {
                         *self = InputState::Down(start_time,time_now)
                     }
-                    // This is updated in InputCache::pre_update function for mouse buttons.
-                    // This won't never happen for mouse events.
+                    // This is updated in InputCache::pre_update function. This happens for
+                    // keyboard key repeats.
                     InputState::Down(start_time, _) => {
                         //log::info!("start_time == {}", start_time);
                         *self = InputState::Down(start_time,time_now);
@@ -59,12 +65,13 @@ pub struct MouseButton {
     tag: ev::MouseButton,
 }
 
-/// A struct for mouse buttons (left, middle, right).
+/// A struct for mouse buttons (left, middle, right and the other buttons).
 #[derive(Clone)]
 pub struct MouseButtons {
     left: MouseButton,
     middle: MouseButton,
     right: MouseButton,
+    other: HashMap<u16, InputState>,
 }
 
 impl MouseButtons {
@@ -73,49 +80,21 @@ impl MouseButtons {
             left: MouseButton   { state: None , tag: ev::MouseButton::Left},
             middle: MouseButton { state: None , tag: ev::MouseButton::Middle},
             right: MouseButton  { state: None , tag: ev::MouseButton::Right},
+            other: HashMap::<u16, InputState>::new(),
         }
     }
 
     /// Update mouse button.
     pub fn update(&mut self, button: &ev::MouseButton, state: &ev::ElementState, time_now: u128) {
         match button {
-            ev::MouseButton::Left => {
-                match &mut self.left.state {
-                    Some(s) => {
-                        s.update(&state, time_now);
-                        //log::info!("Left mouse : {:?}", self.left.state.as_ref());
-                    }
-                    None => {
-                        self.left.state = Some(InputState::Pressed(time_now));
-                        //log::info!("Left mouse : {:?}", self.left.state.as_ref());
-                    }
-                }
+            ev::MouseButton::Left => { update_button_state(&mut self.left.state, state, time_now); }
+            ev::MouseButton::Middle => { update_button_state(&mut self.middle.state, state, time_now); }
+            ev::MouseButton::Right => { update_button_state(&mut self.right.state, state, time_now); }
+            ev::MouseButton::Other(b) => {
+                let mut button_state = self.other.remove(b);
+                update_button_state(&mut button_state, state, time_now);
+                if let Some(s) = button_state { let _ = self.other.insert(*b, s); }
             }
-            ev::MouseButton::Middle => {
-                match &mut self.middle.state {
-                    Some(s) => {
-                        s.update(&state, time_now);
-                        //log::info!("Middle mouse : {:?}", self.middle.state.as_ref());
-                    }
-                    None => {
-                        self.middle.state = Some(InputState::Pressed(time_now));
-                        //log::info!("Middle mouse : {:?}", self.middle.state.as_ref());
-                    }
-                }
-            }
-            ev::MouseButton::Right => {
-                match &mut self.right.state {
-                    Some(s) => {
-                        s.update(&state, time_now);
-                        //log::info!("Right mouse : {:?}", self.right.state.as_ref());
-                    }
-                    None => {
-                        self.right.state = Some(InputState::Pressed(time_now));
-                        //log::info!("Right mouse : {:?}", self.right.state.as_ref());
-                    }
-                }
-            }
-            _ => { /* Some other mouse button clicked. */ }
         }
     }
 
@@ -133,6 +112,122 @@ impl MouseButtons {
     pub fn get_right(&self) -> Option<InputState> {
         self.right.state.clone()
     }
+
+    /// Get the state of the other mouse button (back, forward, etc).
+    pub fn get_other(&self, button: u16) -> Option<InputState> {
+        self.other.get(&button).cloned()
+    }
+
+    /// Move the button states to the next tick. The released buttons are removed and the pressed
+    /// buttons become down.
+    fn pre_update(&mut self, time_now: u128) {
+        advance_button_state(&mut self.left.state, time_now);
+        advance_button_state(&mut self.middle.state, time_now);
+        advance_button_state(&mut self.right.state, time_now);
+        self.other.retain(|_, state| match state { InputState::Released(_,_) => false, _ => true }); 
+        for state in self.other.values_mut() {
+            if let InputState::Pressed(start_time) | InputState::Down(start_time, _) = *state {
+                *state = InputState::Down(start_time, time_now);
+            }
+        }
+    }
+}
+
+/// Update the state of a single mouse button. A release without a press is ignored.
+fn update_button_state(button_state: &mut Option<InputState>, state: &ev::ElementState, time_now: u128) {
+    match button_state {
+        Some(s) => { s.update(&state, time_now); }
+        None => {
+            if let ev::ElementState::Pressed = state {
+                *button_state = Some(InputState::Pressed(time_now));
+            }
+        }
+    }
+}
+
+/// Move the state of a single mouse button to the next tick.
+/// Released -> None, Pressed -> Down, Down -> Down.
+fn advance_button_state(button_state: &mut Option<InputState>, time_now: u128) {
+    *button_state = match button_state.take() {
+        Some(InputState::Pressed(start_time)) | Some(InputState::Down(start_time, _)) => {
+            Some(InputState::Down(start_time, time_now))
+        }
+        Some(InputState::Released(_,_)) | None => None,
+    };
+}
+
+/// The time source of InputCache.
+/// Real: the time elapsed since the instant.
+/// Manual: the time (nanoseconds) is set by hand. Use this for tests and replays.
+#[derive(Clone, Debug)]
+pub enum Clock {
+    Real(instant::Instant),
+    Manual(u128),
+}
+
+impl Clock {
+
+    /// A real time clock starting now.
+    pub fn real() -> Self {
+        Clock::Real(instant::Instant::now())
+    }
+
+    /// The current time in nanoseconds.
+    pub fn now(&self) -> u128 {
+        match self {
+            Clock::Real(start) => start.elapsed().as_nanos(),
+            Clock::Manual(time) => *time,
+        }
+    }
+
+    /// Set the time of a manual clock. Panics for a real time clock.
+    pub fn set(&mut self, nanos: u128) {
+        match self {
+            Clock::Real(_) => panic!("Can't set the time of a real time clock."),
+            Clock::Manual(time) => *time = nanos,
+        }
+    }
+
+    /// Advance the time of a manual clock. Panics for a real time clock.
+    pub fn advance(&mut self, nanos: u128) {
+        let now = self.now();
+        self.set(now + nanos);
+    }
+}
+
+/// An input event that InputCache handles. The window events are converted to input events, so
+/// they can be recorded and replayed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+    Key(Key, ev::ElementState),
+    MouseButton(ev::MouseButton, ev::ElementState),
+    MouseWheel(ev::MouseScrollDelta),
+    CursorMoved(PhysicalPosition<f64>),
+    CursorEntered,
+    CursorLeft,
+    Modifiers(ModifiersState),
+    Focused(bool),
+    Gamepad(GamepadEvent),
+}
+
+impl InputEvent {
+
+    /// Convert the window event to an input event. None if InputCache doesn't handle the event.
+    pub fn from_window_event(event: &ev::WindowEvent) -> Option<Self> {
+        use ev::WindowEvent::*;
+
+        match event {
+            KeyboardInput { input, ..} => input.virtual_keycode.map(|key| InputEvent::Key(key, input.state)),
+            MouseInput { button, state, ..} => Some(InputEvent::MouseButton(*button, *state)),
+            MouseWheel { delta, ..} => Some(InputEvent::MouseWheel(*delta)),
+            CursorMoved { position, ..} => Some(InputEvent::CursorMoved(*position)),
+            CursorEntered { ..} => Some(InputEvent::CursorEntered),
+            CursorLeft { ..} => Some(InputEvent::CursorLeft),
+            ModifiersChanged(state) => Some(InputEvent::Modifiers(*state)),
+            Focused(focused) => Some(InputEvent::Focused(*focused)),
+            _ => None,
+        }
+    }
 }
 
 /// A stuct for keep track on mouse cursor position.
@@ -160,7 +255,7 @@ pub struct InputCache {
     /// HashMap for keyboard keys/states.
     keyboard: HashMap<Key, InputState>,
 
-    /// Left, middle and right mouse buttons.
+    /// Left, middle, right and the other mouse buttons.
     mouse_buttons: MouseButtons,
 
     /// The current mouse_position.
@@ -169,41 +264,61 @@ pub struct InputCache {
     /// The delta for the current and previous mouse position.
     mouse_delta: PhysicalPosition::<f64>,
 
-    /// The delta for the mouse scroll.
-    scroll_delta: f32,
+    /// The mouse scroll amount in lines (mouse wheels).
+    scroll_line_delta: (f32, f32),
+
+    /// The mouse scroll amount in pixels (touchpads).
+    scroll_pixel_delta: PhysicalPosition::<f64>,
+
+    /// The state of shift, ctrl, alt and logo keys.
+    modifiers: ModifiersState,
 
-    /// Time now in micro seconds.
+    /// The connected gamepads.
+    gamepads: Gamepads,
+
+    /// Time now in nano seconds.
     time_now: u128,
 
     /// Delta for the current time and previous tick.
     time_delta: u128,
 
-    /// The timer instance.
-    timer: instant::Instant,
+    /// The time source.
+    clock: Clock,
 
     /// Mouse move event happened.
     mouse_moved: bool,
+
+    /// The events and the ticks are recorded here if the recording is on.
+    recording: Option<InputRecording>,
 }
 
 impl InputCache {
 
     /// Initialize InputCache.
     pub fn init() -> Self {
+        InputCache::init_with_clock(Clock::real())
+    }
+
+    /// Initialize InputCache with the given time source.
+    pub fn init_with_clock(clock: Clock) -> Self {
         let keyboard = HashMap::<Key, InputState>::with_capacity(128);
         let mouse_buttons = MouseButtons::init();
         let mouse_position = CursorPosition::init();
-        let timer = instant::Instant::now();
 
         Self {
             keyboard: keyboard,
             mouse_buttons: mouse_buttons,
             mouse_position: mouse_position,
             mouse_delta: PhysicalPosition::<f64>::new(0.0, 0.0),
-            scroll_delta: 0.0,
+            scroll_line_delta: (0.0, 0.0),
+            scroll_pixel_delta: PhysicalPosition::<f64>::new(0.0, 0.0),
+            modifiers: ModifiersState::empty(),
+            gamepads: Gamepads::init(),
             time_now: 0,
             time_delta: 0,
-            timer: timer,
+            clock: clock,
             mouse_moved: false,
+            recording: None,
         }
     }
 
@@ -224,62 +339,129 @@ impl InputCache {
         else { PhysicalPosition::<f64>::new(0.0, 0.0) }
     }
 
+    /// Get the mouse scroll amount (in lines) since the previous tick. Positive values are scrolled
+    /// away from the user.
+    pub fn get_scroll_delta(&self) -> f32 {
+        self.scroll_line_delta.1 + (self.scroll_pixel_delta.y / PIXELS_PER_LINE) as f32
+    }
+
+    /// Get the horizontal and vertical scroll amount of line based scroll events (mouse wheels)
+    /// since the previous tick.
+    pub fn get_scroll_line_delta(&self) -> (f32, f32) {
+        self.scroll_line_delta
+    }
+
+    /// Get the scroll amount of pixel based scroll events (touchpads) since the previous tick.
+    pub fn get_scroll_pixel_delta(&self) -> PhysicalPosition::<f64> {
+        self.scroll_pixel_delta
+    }
+
+    /// Get the current state of the modifier keys.
+    pub fn get_modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    /// Get the cursor position in physical pixels. None if the cursor hasn't moved over the window.
+    pub fn cursor_position(&self) -> Option<PhysicalPosition::<f64>> {
+        self.mouse_position.pos
+    }
+
+    /// Check if the cursor is inside the window.
+    pub fn cursor_inside(&self) -> bool {
+        self.mouse_position.inside
+    }
+
+    /// Get the state of the gamepads.
+    pub fn get_gamepads(&self) -> &Gamepads {
+        &self.gamepads
+    }
+
+    /// Get the state of the gamepads for changing the deadzones.
+    pub fn get_gamepads_mut(&mut self) -> &mut Gamepads {
+        &mut self.gamepads
+    }
+
+    /// Process the new gamepad events. This should be called once per tick before the
+    /// application handles the input.
+    pub fn update_gamepads(&mut self, backend: &mut dyn GamepadBackend) {
+        for event in backend.poll().iter() {
+            self.apply(&InputEvent::Gamepad(*event));
+        }
+    }
+
+    /// Get the time source.
+    pub fn get_clock_mut(&mut self) -> &mut Clock {
+        &mut self.clock
+    }
+
+    /// Start recording the input events and the ticks. A previous recording is discarded.
+    pub fn start_recording(&mut self) {
+        self.recording = Some(InputRecording::init(self.time_now));
+    }
+
+    /// Stop recording and return the recording. None if the recording wasn't on.
+    pub fn stop_recording(&mut self) -> Option<InputRecording> {
+        self.recording.take()
+    }
+
+    /// Check if the recording is on.
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
     /// This should be called before the actual update to ensure the all events takes effect even
     /// winit doesn't produce any events.
     pub fn pre_update(&mut self) {
         
         self.mouse_moved = false;
+        self.scroll_line_delta = (0.0, 0.0);
+        self.scroll_pixel_delta = PhysicalPosition::<f64>::new(0.0, 0.0);
 
         // Update timer.
-        let now = self.timer.elapsed().as_nanos();
-        self.time_delta = now - self.time_now;
+        let now = self.clock.now();
+        self.time_delta = now.saturating_sub(self.time_now);
         self.time_now = now;
 
-        // If mouse buttons were released previously, apply None to those states.
-        // TODO: loop.
-        if let Some(InputState::Released(_,_)) = self.mouse_buttons.left.state   { self.mouse_buttons.left.state = None }
-        if let Some(InputState::Released(_,_)) = self.mouse_buttons.middle.state { self.mouse_buttons.middle.state = None }
-        if let Some(InputState::Released(_,_)) = self.mouse_buttons.right.state  { self.mouse_buttons.right.state = None }
+        if let Some(recording) = self.recording.as_mut() { recording.push_tick(now); }
 
-        // If left mouse button was pressed in previous tick, change the state to down.
-        if let Some(InputState::Pressed(start_time)) = self.mouse_buttons.left.state {
-            self.mouse_buttons.left.state = Some(InputState::Down(start_time,self.time_now));
-        }
-        if let Some(InputState::Pressed(start_time)) = self.mouse_buttons.middle.state {
-            self.mouse_buttons.middle.state = Some(InputState::Down(start_time,self.time_now));
-        }
-        if let Some(InputState::Pressed(start_time)) = self.mouse_buttons.right.state {
-            self.mouse_buttons.right.state = Some(InputState::Down(start_time,self.time_now));
-        }
-
-        // If the buttons are down, they stay down.
-        // TODO: loop
-        if let Some(InputState::Down(start_time, _)) = self.mouse_buttons.left.state {
-            self.mouse_buttons.left.state = Some(InputState::Down(start_time,self.time_now));
-        }
-        if let Some(InputState::Down(start_time, _)) = self.mouse_buttons.middle.state {
-            self.mouse_buttons.middle.state = Some(InputState::Down(start_time,self.time_now));
-        }
-        if let Some(InputState::Down(start_time, _)) = self.mouse_buttons.right.state {
-            self.mouse_buttons.right.state = Some(InputState::Down(start_time,self.time_now));
-        }
+        // Released mouse buttons are removed and pressed buttons become down.
+        self.mouse_buttons.pre_update(self.time_now);
+        self.gamepads.pre_update(self.time_now);
 
         // Remove key from hashmap if its previous state was 'released'.
         self.keyboard.retain(|_, state| match state { InputState::Released(_,_) => false, _ => true }); 
+
+        // Pressed keys become down, so a key is pressed only for one tick.
+        for state in self.keyboard.values_mut() {
+            if let InputState::Pressed(start_time) | InputState::Down(start_time, _) = *state {
+                *state = InputState::Down(start_time, self.time_now);
+            }
+        }
     }
 
     /// Process the new inputs.
     pub fn update(&mut self, event: &ev::WindowEvent) {
-        use ev::WindowEvent::*;
+        if let Some(e) = InputEvent::from_window_event(event) {
+            self.apply(&e);
+        }
+    }
 
-        match event {
-            KeyboardInput { input, ..} => self.track_keyboard(*input),
-            MouseInput { button, state, ..} => self.track_mouse_button(*button, *state),
-            MouseWheel { delta, ..} => self.track_mouse_wheel(*delta),
-            CursorMoved { position, ..} => self.track_cursor_movement(*position),
-            CursorEntered { ..} => self.track_cursor_enter(),
-            CursorLeft { ..} => self.track_cursor_leave(),
-            _ => (),
+    /// Process an input event.
+    pub fn apply(&mut self, event: &InputEvent) {
+
+        if let Some(recording) = self.recording.as_mut() { recording.push_event(*event); }
+
+        match *event {
+            InputEvent::Key(key, state) => self.track_keyboard(key, state),
+            InputEvent::MouseButton(button, state) => self.track_mouse_button(button, state),
+            InputEvent::MouseWheel(delta) => self.track_mouse_wheel(delta),
+            InputEvent::CursorMoved(position) => self.track_cursor_movement(position),
+            InputEvent::CursorEntered => self.track_cursor_enter(),
+            InputEvent::CursorLeft => self.track_cursor_leave(),
+            InputEvent::Modifiers(state) => self.modifiers = state,
+            InputEvent::Focused(false) => self.modifiers = ModifiersState::empty(),
+            InputEvent::Focused(true) => (),
+            InputEvent::Gamepad(e) => self.gamepads.update(&e, self.time_now),
         }
     }
     /// Get the InputState of keyboard key.
@@ -296,21 +478,19 @@ impl InputCache {
             ev::MouseButton::Left => { self.mouse_buttons.left.state.clone() } 
             ev::MouseButton::Middle => { self.mouse_buttons.middle.state.clone() } 
             ev::MouseButton::Right => { self.mouse_buttons.right.state.clone() } 
-            _ => None
+            ev::MouseButton::Other(b) => { self.mouse_buttons.get_other(*b) } 
         }
     }
     /// Update the state of keyboard.
-    fn track_keyboard(&mut self, evt: ev::KeyboardInput) {
-        if let Some(key) = evt.virtual_keycode {
-            match self.keyboard.get_mut(&key) {
-                Some(state) => {
-                    // Update the key time value.
-                    state.update(&evt.state, self.time_now) 
-                }
-                None => {
-                    // The key doesn't have any state. Add a new pressed state for this key.
-                    let _ = self.keyboard.insert(key, InputState::Pressed(self.time_now));
-                }
+    fn track_keyboard(&mut self, key: Key, element_state: ev::ElementState) {
+        match self.keyboard.get_mut(&key) {
+            Some(state) => {
+                // Update the key time value.
+                state.update(&element_state, self.time_now) 
+            }
+            None => {
+                // The key doesn't have any state. Add a new pressed state for this key.
+                let _ = self.keyboard.insert(key, InputState::Pressed(self.time_now));
             }
         }
     }
@@ -319,8 +499,17 @@ impl InputCache {
         self.mouse_buttons.update(&button, &state, self.time_now);
     }
     /// Update the state of mouse wheel.
-    fn track_mouse_wheel(&mut self, _delta: ev::MouseScrollDelta) {
-        //log::info!("track_mouse_wheel");
+    fn track_mouse_wheel(&mut self, delta: ev::MouseScrollDelta) {
+        match delta {
+            ev::MouseScrollDelta::LineDelta(x, y) => {
+                self.scroll_line_delta.0 += x;
+                self.scroll_line_delta.1 += y;
+            }
+            ev::MouseScrollDelta::PixelDelta(p) => {
+                self.scroll_pixel_delta.x += p.x;
+                self.scroll_pixel_delta.y += p.y;
+            }
+        }
     }
     /// Update the state of mouse movement.
     fn track_cursor_movement(&mut self, new_pos: PhysicalPosition<f64>) {
@@ -0,0 +1,362 @@
+use winit::event::{ElementState, MouseScrollDelta};
+use winit::dpi::PhysicalPosition;
+use crate::input::{InputCache, InputEvent, Clock, Key, ModifiersState};
+use crate::actions::Input;
+use crate::gamepad::GamepadEvent;
+
+/// A recorded tick. The time of InputCache::pre_update and the events after it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedTick {
+    pub time: u128,
+    pub events: Vec<InputEvent>,
+}
+
+/// The input events and the ticks of a session. Created by InputCache::start_recording and
+/// InputCache::stop_recording.
+///
+/// The file format is plain text with one tick or event per line:
+///
+/// ```text
+/// tick <nanoseconds>
+/// key <name> pressed|released
+/// mouse <name> pressed|released
+/// wheel_lines <x> <y>
+/// wheel_pixels <x> <y>
+/// cursor <x> <y>
+/// cursor_entered
+/// cursor_left
+/// modifiers <bits>
+/// focused true|false
+/// gamepad_connected <id>
+/// gamepad_disconnected <id>
+/// gamepad_button <id> <name> pressed|released
+/// gamepad_axis <id> <name> <value>
+/// ```
+///
+/// The names are the same as in the action config files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputRecording {
+    ticks: Vec<RecordedTick>,
+}
+
+impl InputRecording {
+
+    /// Create a recording. The first tick starts at time_now.
+    pub fn init(time_now: u128) -> Self {
+        Self {
+            ticks: vec![RecordedTick { time: time_now, events: Vec::new() }],
+        }
+    }
+
+    pub fn get_ticks(&self) -> &Vec<RecordedTick> {
+        &self.ticks
+    }
+
+    /// Start a new tick.
+    pub fn push_tick(&mut self, time: u128) {
+        self.ticks.push(RecordedTick { time: time, events: Vec::new() });
+    }
+
+    /// Add an event to the current tick.
+    pub fn push_event(&mut self, event: InputEvent) {
+        self.ticks.last_mut().unwrap().events.push(event);
+    }
+
+    /// Save the recording to a file.
+    pub fn save(&self, file_name: &str) -> std::io::Result<()> {
+        std::fs::write(file_name, self.to_text())
+    }
+
+    /// Load a recording from a file.
+    pub fn load(file_name: &str) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(file_name)?;
+        InputRecording::parse(&text).map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", file_name, e))
+        })
+    }
+
+    /// The recording in the file format.
+    pub fn to_text(&self) -> String {
+
+        let state = |s: &ElementState| -> &str {
+            match s {
+                ElementState::Pressed => "pressed",
+                ElementState::Released => "released",
+            }
+        };
+
+        let mut result = String::new();
+
+        for tick in self.ticks.iter() {
+            result.push_str(&format!("tick {}\n", tick.time));
+            for event in tick.events.iter() {
+                let line = match event {
+                    InputEvent::Key(key, s) => format!("key {} {}", Input::Key(*key), state(s)),
+                    InputEvent::MouseButton(button, s) => format!("mouse {} {}", Input::Mouse(*button), state(s)),
+                    InputEvent::MouseWheel(MouseScrollDelta::LineDelta(x, y)) => format!("wheel_lines {} {}", x, y),
+                    InputEvent::MouseWheel(MouseScrollDelta::PixelDelta(p)) => format!("wheel_pixels {} {}", p.x, p.y),
+                    InputEvent::CursorMoved(p) => format!("cursor {} {}", p.x, p.y),
+                    InputEvent::CursorEntered => "cursor_entered".to_string(),
+                    InputEvent::CursorLeft => "cursor_left".to_string(),
+                    InputEvent::Modifiers(m) => format!("modifiers {}", m.bits()),
+                    InputEvent::Focused(f) => format!("focused {}", f),
+                    InputEvent::Gamepad(GamepadEvent::Connected(id)) => format!("gamepad_connected {}", id),
+                    InputEvent::Gamepad(GamepadEvent::Disconnected(id)) => format!("gamepad_disconnected {}", id),
+                    InputEvent::Gamepad(GamepadEvent::Button { id, button, state: s }) => {
+                        format!("gamepad_button {} {} {}", id, Input::GamepadButton(*button), state(s))
+                    }
+                    InputEvent::Gamepad(GamepadEvent::Axis { id, axis, value }) => {
+                        format!("gamepad_axis {} {} {}", id, Input::GamepadAxis(*axis), value)
+                    }
+                };
+                result.push_str(&line);
+                result.push('\n');
+            }
+        }
+
+        result
+    }
+
+    /// Parse a recording from the file format.
+    pub fn parse(text: &str) -> Result<Self, String> {
+
+        let mut ticks: Vec<RecordedTick> = Vec::new();
+
+        for (line_number, line) in text.lines().enumerate() {
+
+            let line = line.trim();
+            if line.is_empty() { continue; }
+
+            let error = |message: &str| -> String { format!("line {}: {} ('{}').", line_number + 1, message, line) };
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            let number = |i: usize| -> Result<f64, String> {
+                words.get(i).and_then(|w| w.parse::<f64>().ok()).ok_or_else(|| error("expected a number"))
+            };
+            let id = |i: usize| -> Result<u32, String> {
+                words.get(i).and_then(|w| w.parse::<u32>().ok()).ok_or_else(|| error("expected a gamepad id"))
+            };
+            let state = |i: usize| -> Result<ElementState, String> {
+                match words.get(i) {
+                    Some(&"pressed") => Ok(ElementState::Pressed),
+                    Some(&"released") => Ok(ElementState::Released),
+                    _ => Err(error("expected pressed or released")),
+                }
+            };
+            let input = |i: usize| -> Result<Input, String> {
+                words.get(i).ok_or_else(|| error("expected a name")).and_then(|w| Input::parse(w).map_err(|e| error(&e)))
+            };
+            let key = |i: usize| -> Result<Key, String> {
+                match input(i)? { Input::Key(k) => Ok(k), _ => Err(error("expected a key")) }
+            };
+
+            if words[0] == "tick" {
+                let time = words.get(1).and_then(|w| w.parse::<u128>().ok()).ok_or_else(|| error("expected a time"))?;
+                ticks.push(RecordedTick { time: time, events: Vec::new() });
+                continue;
+            }
+
+            let event = match words[0] {
+                "key" => InputEvent::Key(key(1)?, state(2)?),
+                "mouse" => match input(1)? {
+                    Input::Mouse(button) => InputEvent::MouseButton(button, state(2)?),
+                    _ => return Err(error("expected a mouse button")),
+                },
+                "wheel_lines" => InputEvent::MouseWheel(MouseScrollDelta::LineDelta(number(1)? as f32, number(2)? as f32)),
+                "wheel_pixels" => InputEvent::MouseWheel(MouseScrollDelta::PixelDelta(PhysicalPosition::new(number(1)?, number(2)?))),
+                "cursor" => InputEvent::CursorMoved(PhysicalPosition::new(number(1)?, number(2)?)),
+                "cursor_entered" => InputEvent::CursorEntered,
+                "cursor_left" => InputEvent::CursorLeft,
+                "modifiers" => InputEvent::Modifiers(ModifiersState::from_bits_truncate(id(1)?)),
+                "focused" => InputEvent::Focused(words.get(1) == Some(&"true")),
+                "gamepad_connected" => InputEvent::Gamepad(GamepadEvent::Connected(id(1)?)),
+                "gamepad_disconnected" => InputEvent::Gamepad(GamepadEvent::Disconnected(id(1)?)),
+                "gamepad_button" => match input(2)? {
+                    Input::GamepadButton(button) => InputEvent::Gamepad(GamepadEvent::Button { id: id(1)?, button: button, state: state(3)? }),
+                    _ => return Err(error("expected a gamepad button")),
+                },
+                "gamepad_axis" => match input(2)? {
+                    Input::GamepadAxis(axis) => InputEvent::Gamepad(GamepadEvent::Axis { id: id(1)?, axis: axis, value: number(3)? as f32 }),
+                    _ => return Err(error("expected a gamepad axis")),
+                },
+                _ => return Err(error("unknown event")),
+            };
+
+            match ticks.last_mut() {
+                Some(tick) => tick.events.push(event),
+                None => return Err(error("an event before the first tick")),
+            }
+        }
+
+        if ticks.is_empty() { return Err("The recording has no ticks.".to_string()); }
+
+        Ok(Self { ticks: ticks })
+    }
+}
+
+/// Replays a recording through an InputCache with a manual clock. Each call of next_tick
+/// reproduces one tick of the recorded session: the time, the pre_update and the events.
+pub struct InputReplay {
+    recording: InputRecording,
+    next: usize,
+}
+
+impl InputReplay {
+
+    pub fn init(recording: InputRecording) -> Self {
+        Self {
+            recording: recording,
+            next: 0,
+        }
+    }
+
+    /// Create an InputCache with a manual clock for the replay.
+    pub fn create_input_cache(&self) -> InputCache {
+        InputCache::init_with_clock(Clock::Manual(self.recording.ticks[0].time))
+    }
+
+    /// Check if all ticks are replayed.
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.recording.ticks.len()
+    }
+
+    /// Replay the next tick. Call this instead of InputCache::pre_update and window event
+    /// handling. Returns false if the replay was already finished. The clock of the input must
+    /// be a manual clock.
+    pub fn next_tick(&mut self, input: &mut InputCache) -> bool {
+
+        if self.is_finished() { return false; }
+
+        let tick = &self.recording.ticks[self.next];
+        input.get_clock_mut().set(tick.time);
+        input.pre_update();
+        for event in tick.events.iter() {
+            input.apply(event);
+        }
+
+        self.next += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::MouseButton;
+    use crate::gamepad::{GamepadAxis, GamepadButton};
+
+    const KEYS: [Key; 5] = [Key::W, Key::LShift, Key::Copy, Key::Paste, Key::Cut];
+    const BUTTONS: [GamepadButton; 2] = [GamepadButton::South, GamepadButton::DPadUp];
+    const AXES: [GamepadAxis; 2] = [GamepadAxis::LeftStickX, GamepadAxis::RightTrigger];
+
+    /// The events of each tick and the time of the tick.
+    fn session() -> Vec<(u128, Vec<InputEvent>)> {
+        let gamepad = |e: GamepadEvent| InputEvent::Gamepad(e);
+        vec![
+            (16, vec![InputEvent::CursorEntered,
+                      InputEvent::CursorMoved(PhysicalPosition::new(100.0, 50.0)),
+                      InputEvent::Key(Key::W, ElementState::Pressed),
+                      InputEvent::Key(Key::Copy, ElementState::Pressed)]),
+            (33, vec![InputEvent::CursorMoved(PhysicalPosition::new(110.5, 40.25)),
+                      InputEvent::MouseButton(MouseButton::Left, ElementState::Pressed),
+                      InputEvent::MouseWheel(MouseScrollDelta::LineDelta(0.0, 2.0)),
+                      InputEvent::Key(Key::Copy, ElementState::Released)]),
+            (50, vec![InputEvent::Modifiers(ModifiersState::SHIFT),
+                      InputEvent::Key(Key::LShift, ElementState::Pressed),
+                      InputEvent::MouseWheel(MouseScrollDelta::PixelDelta(PhysicalPosition::new(0.0, -30.0))),
+                      gamepad(GamepadEvent::Connected(1)),
+                      gamepad(GamepadEvent::Button { id: 1, button: GamepadButton::South, state: ElementState::Pressed }),
+                      gamepad(GamepadEvent::Axis { id: 1, axis: GamepadAxis::LeftStickX, value: -0.75 })]),
+            (66, vec![InputEvent::Key(Key::Paste, ElementState::Pressed),
+                      InputEvent::Key(Key::Cut, ElementState::Pressed),
+                      gamepad(GamepadEvent::Axis { id: 1, axis: GamepadAxis::RightTrigger, value: 0.5 })]),
+            (83, vec![]),
+            (100, vec![InputEvent::Focused(false),
+                       InputEvent::Key(Key::W, ElementState::Released),
+                       InputEvent::MouseButton(MouseButton::Left, ElementState::Released),
+                       gamepad(GamepadEvent::Button { id: 1, button: GamepadButton::South, state: ElementState::Released }),
+                       InputEvent::CursorLeft]),
+            (116, vec![InputEvent::Focused(true),
+                       gamepad(GamepadEvent::Disconnected(1))]),
+        ]
+    }
+
+    /// The observable state of the input.
+    fn snapshot(input: &InputCache) -> String {
+        let mut result = format!("{} {} {:?} {:?} {:?} {:?} {:?} {:?} {}",
+                                 input.get_time(),
+                                 input.get_time_delta(),
+                                 input.get_mouse_delta(),
+                                 input.get_scroll_line_delta(),
+                                 input.get_scroll_pixel_delta(),
+                                 input.get_modifiers(),
+                                 input.cursor_position(),
+                                 input.mouse_button_state(&MouseButton::Left),
+                                 input.cursor_inside());
+        for key in KEYS.iter() { result.push_str(&format!(" {:?}", input.key_state(key))); }
+        let gamepads = input.get_gamepads();
+        result.push_str(&format!(" {:?}", gamepads.get_ids()));
+        for button in BUTTONS.iter() { result.push_str(&format!(" {:?}", gamepads.any_button_state(button))); }
+        for axis in AXES.iter() { result.push_str(&format!(" {:?}", gamepads.any_axis(axis))); }
+        result
+    }
+
+    /// Run the session on a manual clock. Returns the recording and the snapshots of the input
+    /// after the start and after each tick.
+    fn record() -> (InputRecording, Vec<String>) {
+        let mut input = InputCache::init_with_clock(Clock::Manual(0));
+        input.start_recording();
+        let mut snapshots = vec![snapshot(&input)];
+        for (time, events) in session().iter() {
+            input.get_clock_mut().set(*time);
+            input.pre_update();
+            for event in events.iter() { input.apply(event); }
+            snapshots.push(snapshot(&input));
+        }
+        (input.stop_recording().unwrap(), snapshots)
+    }
+
+    #[test]
+    fn all_events_are_recorded() {
+        let (recording, _) = record();
+        let ticks = recording.get_ticks();
+        assert_eq!(ticks.len(), session().len() + 1);
+        assert_eq!(ticks[0], RecordedTick { time: 0, events: Vec::new() });
+        for (tick, (time, events)) in ticks[1..].iter().zip(session().into_iter()) {
+            assert_eq!(tick.time, time);
+            assert_eq!(tick.events, events);
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let (recording, _) = record();
+        let text = recording.to_text();
+        assert!(text.contains("key Copy pressed"), "{}", text);
+        assert_eq!(InputRecording::parse(&text).unwrap(), recording);
+    }
+
+    #[test]
+    fn replay_reproduces_the_input() {
+        let (recording, snapshots) = record();
+        let mut replay = InputReplay::init(InputRecording::parse(&recording.to_text()).unwrap());
+        let mut input = replay.create_input_cache();
+
+        for (i, expected) in snapshots.iter().enumerate() {
+            assert!(replay.next_tick(&mut input));
+            assert_eq!(&snapshot(&input), expected, "{}", format!("The state differs at tick {}.", i));
+        }
+        assert!(replay.is_finished());
+        assert!(!replay.next_tick(&mut input));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(InputRecording::parse("").is_err());
+        assert!(InputRecording::parse("key W pressed\ntick 0").unwrap_err().starts_with("line 1:"));
+        assert!(InputRecording::parse("tick 0\nkey W down").unwrap_err().starts_with("line 2:"));
+        assert!(InputRecording::parse("tick 0\nkey MouseLeft pressed").is_err());
+        assert!(InputRecording::parse("tick 0\nteleport 1 2").is_err());
+    }
+}
@@ -1,5 +1,9 @@
 pub mod wgpu_system; 
 pub mod input; 
+pub mod actions; 
+pub mod gamepad; 
+pub mod input_record; 
+pub mod timestep; 
 pub mod shader; 
 pub mod misc; 
 pub mod buffer; 
@@ -8,10 +12,16 @@ pub mod assets;
 pub mod camera; 
 pub mod two_triangles; 
 pub mod mc; 
-pub mod temp; 
 pub mod render_pipelines; 
+pub mod resources; 
+pub mod frame_graph; 
+pub mod shadow; 
 pub mod noise3d; 
 pub mod compute; 
+pub mod indirect; 
+pub mod voxelize; 
+pub mod frustum_culling; 
+pub mod camera_path; 
 pub use wgpu;
 //pub use rand;
 
@@ -1,5 +1,7 @@
 use bytemuck::{Zeroable, Pod};
 use crate::buffer::buffer_from_data;
+use crate::compute::ComputeKernel;
+use crate::render_pipelines::{uniform_entry, storage_entry, DrawIndirectArgs};
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -19,6 +21,8 @@ pub struct McParams {
     params: McUniform,
     buffer: wgpu::Buffer,
     pub counter_buffer: wgpu::Buffer,
+    /// DrawIndirectArgs for the output vertices. See write_draw_indirect.
+    pub draw_indirect_buffer: wgpu::Buffer,
     pub bind_groups: Option<Vec<wgpu::BindGroup>>,
 }
 
@@ -49,6 +53,11 @@ impl McParams {
                 &[0 as u32],
                 wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST |wgpu::BufferUsages::COPY_SRC,
                 None),
+            draw_indirect_buffer: buffer_from_data::<DrawIndirectArgs>(
+                &device,
+                &[DrawIndirectArgs { vertex_count: 0, instance_count: 1, base_vertex: 0, base_instance: 0, }],
+                wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                None),
             bind_groups: None,
         }
     }
@@ -61,6 +70,19 @@ impl McParams {
         &self.params
     }
 
+    /// Copy the vertex count of the counter to the vertex_count of draw_indirect_buffer. Record
+    /// this after the marching cubes dispatch and draw with DrawCall::draw_indirect. The count
+    /// isn't read back to the cpu.
+    pub fn write_draw_indirect(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_buffer_to_buffer(
+            &self.counter_buffer,
+            0,
+            &self.draw_indirect_buffer,
+            0,
+            std::mem::size_of::<u32>() as wgpu::BufferAddress
+        );
+    }
+
     pub fn reset_counter(&self, queue: &wgpu::Queue) {
         queue.write_buffer(
             &self.counter_buffer,
@@ -102,22 +124,31 @@ impl McParams {
     }
 }
 
+/// The workgroup size of the marching cubes shaders.
+const MC_WORKGROUP_SIZE: [u32; 3] = [4, 4, 4];
+
 /// A struct for marching cubes algorithm purposes.
 /// Includes the counter buffer. Should it be 'a part of' or outside MarchingCubes?
 pub struct MarchingCubes {
-    // The mc pipeline.
-    pipeline: wgpu::ComputePipeline,
+    // The mc kernel.
+    kernel: ComputeKernel,
 }
 
 impl MarchingCubes {
 
     pub fn init(device: &wgpu::Device, mc_shader: &wgpu::ShaderModule, has_3d_texture: bool) -> Self {
         Self {
-            pipeline: MarchingCubes::create_pipeline(&device, &mc_shader, has_3d_texture),
+            kernel: ComputeKernel::init(&device,
+                                        &mc_shader,
+                                        "main",
+                                        MarchingCubes::get_layout_entries(has_3d_texture),
+                                        MC_WORKGROUP_SIZE,
+                                        Some("mc_pipeline")),
         }
     }
 
-    /// Creates bind groups for marching cubes.
+    /// Creates bind groups for marching cubes. The texture_3d must be given if and only if the
+    /// MarchingCubes was created with has_3d_texture.
     pub fn create_bind_groups(
         &self,
         device: &wgpu::Device,
@@ -125,215 +156,37 @@ impl MarchingCubes {
         output_buffer: &wgpu::Buffer,
         texture_3d: Option<&wgpu::Buffer>
         )
-        -> Vec<wgpu::BindGroup> {
-
-        let has_3d_texture = !texture_3d.is_none();
-
-        let bind_group_layouts = MarchingCubes::get_bind_group_layout(&device, has_3d_texture);
-        
-        // if let Some(tex) = texture_3d.as_ref() {
-        //     MarchingCubes::get_bind_group_layout(&device, has_3d_texture)
-        // }
-        // else {
-        //     MarchingCubes::get_bind_group_layout(&device, false)
-        // };
-
-        // Create bindings. Group 0.
-        let mc_param_buffer = wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: params.get_uniform_buffer(),
-                    offset: 0,
-                    size: None,
-                }),
-        };
-
-        let mc_counter_buffer = wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &params.counter_buffer,    
-                    offset: 0,
-                    size: None,
-                }),
-        };
-
-        // Create bindings. Group 1.
-        let mc_output_buffer = wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &output_buffer,    
-                    offset: 0,
-                    size: None,
-                }),
-        };
+        -> Result<Vec<wgpu::BindGroup>, String> {
 
-        let mc_texture_buffer = if let Some(tex3d) = texture_3d.as_ref() {
-                Some(wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: tex3d,    
-                        offset: 0,
-                        size: None,
-                    }),
-                })
-            }
-            else {
-                None
-            };
+        let mut bindings = vec![
+            ((0, 0), params.get_uniform_buffer().as_entire_binding()),
+            ((0, 1), params.counter_buffer.as_entire_binding()),
+            ((1, 0), output_buffer.as_entire_binding()),
+        ];
+        if let Some(t) = texture_3d { bindings.push(((1, 1), t.as_entire_binding())); }
 
-        //let mc_texture3d = if let Some(tex3d) = texture_3d.as_ref() {
-        //    device.create_bind_group(&wgpu::BindGroupDescriptor {
-        //        layout: &bind_group_layouts[0],
-        //        entries: &[mc_param_buffer, mc_counter_buffer],
-        //        label: None,
-        //    });
-        //};
-
-        let bind_group_0 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layouts[0],
-            entries: &[mc_param_buffer, mc_counter_buffer],
-            label: None,
-        });
-
-        println!("match mc_texture_buffer.is_none() == {}", mc_texture_buffer.is_none());
-        let bind_group_1 = match mc_texture_buffer.is_none() {
-            true => device.create_bind_group(&wgpu::BindGroupDescriptor {
-                        layout: &bind_group_layouts[1],
-                        entries: &[mc_output_buffer],
-                        label: None,
-                    }),
-            false => device.create_bind_group(&wgpu::BindGroupDescriptor {
-                        layout: &bind_group_layouts[1],
-                        entries: &[mc_output_buffer, mc_texture_buffer.unwrap()],
-                        label: None,
-                    }),
-        };
-
-        vec![bind_group_0, bind_group_1]
+        self.kernel.create_bind_groups(&device, &bindings)
     }
 
     pub fn dispatch(&self, bind_groups: &Vec<wgpu::BindGroup>,
                     encoder: &mut wgpu::CommandEncoder,
                     x: u32, y: u32, z: u32) {
-
-        let mut pass = encoder.begin_compute_pass(
-            &wgpu::ComputePassDescriptor { label: None}
-        );
-        pass.set_pipeline(&self.pipeline);
-        for (e, bgs) in bind_groups.iter().enumerate() {
-            pass.set_bind_group(e as u32, &bgs, &[]);
-        }
-        pass.dispatch(x, y, z)
+        self.kernel.dispatch(bind_groups, encoder, x, y, z)
     }
 
-    /// Creates the BindGroupLayout for marching cubes pipeline and bindgroup.
-    fn get_bind_group_layout(device: &wgpu::Device, has_3d_texture: bool) -> Vec<wgpu::BindGroupLayout> {
-
-        let mut layouts = Vec::<wgpu::BindGroupLayout>::new();
+    /// The layout entries of marching cubes. Set 0: the uniform params and the counter. Set 1: the
+    /// output vertices and the optional 3d texture.
+    fn get_layout_entries(has_3d_texture: bool) -> Vec<Vec<wgpu::BindGroupLayoutEntry>> {
 
-        layouts.push(
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("Mc_test bind group layout 0"),
-            })
-        );
-        if !has_3d_texture {
-        layouts.push(
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("Mc_test bind group layout 1"),
-            })
-        )
-        }
-        else {
-            println!("Does it has 3d_texture :: {}", has_3d_texture);
-            layouts.push(
-                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: false },
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: true },
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        }
-                    ],
-                    label: Some("Mc_test bind group layout 2"),
-                })
-            );
+        let mut set_1 = vec![storage_entry(0, wgpu::ShaderStages::COMPUTE, false)];
+        if has_3d_texture {
+            set_1.push(storage_entry(1, wgpu::ShaderStages::COMPUTE, true));
         }
-        layouts
-    }
-
-    /// Create the pipeline for marching cubes.
-    fn create_pipeline(
-        device: &wgpu::Device,
-        shader_module: &wgpu::ShaderModule,
-        has_3d_texture: bool)
-    -> wgpu::ComputePipeline {
-    
-        let bind_group_layouts = MarchingCubes::get_bind_group_layout(&device, has_3d_texture);
-        
-        // Create pipeline layout.
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Mc cubes test layout"),
-            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(), // &[&bind_group_layout],
-            push_constant_ranges: &[],
-        });
-        
-        // Create the pipeline.
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("mc_pipeline"),
-            layout: Some(&pipeline_layout),
-            module: &shader_module, // : wgpu::ProgrammableStageDescriptor {
-            entry_point: "main",
-        });
 
-        pipeline
+        vec![
+            vec![uniform_entry(0, wgpu::ShaderStages::COMPUTE),
+                 storage_entry(1, wgpu::ShaderStages::COMPUTE, false)],
+            set_1
+        ]
     }
 }
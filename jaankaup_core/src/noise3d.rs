@@ -1,9 +1,8 @@
-use crate::render_pipelines::*;
+use crate::render_pipelines::storage_entry;
+use crate::compute::ComputeKernel;
 
 pub struct Custom3DTexture {
-    pub layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>, 
-    pub bind_group_layouts: Vec<wgpu::BindGroupLayout>, 
-    pub pipeline: wgpu::ComputePipeline,
+    kernel: ComputeKernel,
 }
 
 impl Custom3DTexture {
@@ -11,84 +10,29 @@ impl Custom3DTexture {
 
         // Define bind grout entries. Output buffer.
         let layout_entries = vec![
-                    vec![wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: true },
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: true },
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 2,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: true },
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 3,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Storage { read_only: false },
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        }
-                ]
+                vec![storage_entry(0, wgpu::ShaderStages::COMPUTE, true),
+                     storage_entry(1, wgpu::ShaderStages::COMPUTE, true),
+                     storage_entry(2, wgpu::ShaderStages::COMPUTE, true),
+                     storage_entry(3, wgpu::ShaderStages::COMPUTE, false)]
         ];
 
-        let bind_group_layouts = create_bind_group_layouts(&device, &layout_entries);
-
-        // Create pipeline layout.
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
-            push_constant_ranges: &[],
-        });
-
-        // Create the pipeline.
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: None,
-            layout: Some(&pipeline_layout),
-            module: &comp_module,
-            entry_point: "main",
-        });
-
         Self {
-            layout_entries: layout_entries, 
-            bind_group_layouts: bind_group_layouts, 
-            pipeline: pipeline,
+            kernel: ComputeKernel::init(&device, &comp_module, "main", layout_entries, [64, 1, 1], Some("custom_3d_texture")),
         }
     }
 
+    pub fn get_bind_group_layout_entries(&self) -> &Vec<Vec<wgpu::BindGroupLayoutEntry>> {
+        self.kernel.get_bind_group_layout_entries()
+    }
+
+    /// The resources are keyed by (group, binding), see ComputeKernel::create_bind_groups.
+    pub fn create_bind_groups(&self, device: &wgpu::Device, bindings: &[((u32, u32), wgpu::BindingResource)]) -> Result<Vec<wgpu::BindGroup>, String> {
+        self.kernel.create_bind_groups(&device, bindings)
+    }
+
     pub fn dispatch(&self, bind_groups: &Vec<wgpu::BindGroup>,
                     encoder: &mut wgpu::CommandEncoder,
                     x: u32, y: u32, z: u32) {
-
-        let mut pass = encoder.begin_compute_pass(
-            &wgpu::ComputePassDescriptor { label: None}
-        );
-        pass.set_pipeline(&self.pipeline);
-        for (e, bgs) in bind_groups.iter().enumerate() {
-            pass.set_bind_group(e as u32, &bgs, &[]);
-        }
-        pass.dispatch(x, y, z)
+        self.kernel.dispatch(bind_groups, encoder, x, y, z)
     }
 }
@@ -1,6 +1,7 @@
 use core::ops::Range;
-use crate::misc::create_vb_descriptor;
-use crate::texture as jaankaup;
+use std::borrow::Cow;
+use bytemuck::{Zeroable, Pod};
+use crate::misc::{create_vb_descriptor, multisampled};
 
 //trait RenderPipelineInfo {
 //    pub fn get_render_pipeline(&self) -> Option<&wgpu::RenderPipeline>;
@@ -11,71 +12,143 @@ use crate::texture as jaankaup;
 //
 //}
 
-pub fn draw(encoder: &mut wgpu::CommandEncoder,
-            //frame: &wgpu::SurfaceFrame,
-            view: &wgpu::TextureView,
-            depth_texture: &jaankaup::Texture,
-            bind_groups: &Vec<wgpu::BindGroup>,
-            pipeline: &wgpu::RenderPipeline,
-            draw_buffer: &wgpu::Buffer,
-            range: Range<u32>, 
-            clear: bool) {
-
-        // println!("yhhyyy!");
-        // let view = frame.output.texture.create_view(&wgpu::TextureViewDescriptor::default());
-        // println!("yhhyyy2!");
-        let mut render_pass = encoder.begin_render_pass(
-                &wgpu::RenderPassDescriptor {
-                    label: Some("two_triangles_rendes_pass_descriptor"),
-                    color_attachments: &[
-                        wgpu::RenderPassColorAttachment {
-                                view: &view,
-                                resolve_target: None,
-                                ops: wgpu::Operations {
-                                    load: match clear {
-                                        true => {
-                                            wgpu::LoadOp::Clear(wgpu::Color {
-                                                r: 0.0,
-                                                g: 0.0,
-                                                b: 0.0,
-                                                a: 1.0,
-                                            })
-                                        }
-                                        false => {
-                                            wgpu::LoadOp::Load
-                                        }
-                                    },
-                                    store: true,
-                                },
-                        }
-                    ],
-                //depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    //attachment: &depth_texture.view,
-                    view: &depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                            load: match clear { true => wgpu::LoadOp::Clear(1.0), false => wgpu::LoadOp::Load },
-                            store: true,
-                    }),
-                    stencil_ops: None,
-                    }),
-            });
+/// The arguments of draw_indirect. The same layout as wgpu expects.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DrawIndirectArgs {
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    pub base_vertex: u32,
+    pub base_instance: u32,
+}
 
-            render_pass.set_pipeline(&pipeline);
-            // Set bind groups.
-            for (e, bgs) in bind_groups.iter().enumerate() {
-                render_pass.set_bind_group(e as u32, &bgs, &[]);
-            }
+unsafe impl Pod for DrawIndirectArgs {}
+unsafe impl Zeroable for DrawIndirectArgs {}
+
+/// The arguments of draw_indexed_indirect. The same layout as wgpu expects.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DrawIndexedIndirectArgs {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub base_index: u32,
+    pub vertex_offset: i32,
+    pub base_instance: u32,
+}
+
+unsafe impl Pod for DrawIndexedIndirectArgs {}
+unsafe impl Zeroable for DrawIndexedIndirectArgs {}
+
+/// A draw call. The vertex buffers are bound to the slots in the order they are added (the
+/// pipeline decides which of them are stepped per instance, see
+/// RenderPipelineBuilder::instance_formats). With an index buffer the draw is indexed.
+pub struct DrawCall<'a> {
+    pipeline: &'a wgpu::RenderPipeline,
+    bind_groups: &'a [wgpu::BindGroup],
+    vertex_buffers: Vec<wgpu::BufferSlice<'a>>,
+    index_buffer: Option<(wgpu::BufferSlice<'a>, wgpu::IndexFormat)>,
+    instances: Range<u32>,
+    base_vertex: i32,
+}
+
+impl<'a> DrawCall<'a> {
+
+    /// A single instance draw without buffers.
+    pub fn init(pipeline: &'a wgpu::RenderPipeline, bind_groups: &'a [wgpu::BindGroup]) -> Self {
+        Self {
+            pipeline: pipeline,
+            bind_groups: bind_groups,
+            vertex_buffers: Vec::new(),
+            index_buffer: None,
+            instances: 0..1,
+            base_vertex: 0,
+        }
+    }
+
+    /// Bind the buffer to the next vertex buffer slot.
+    pub fn vertex_buffer(self, buffer: &'a wgpu::Buffer) -> Self {
+        self.vertex_buffer_slice(buffer.slice(..))
+    }
 
-            // Set vertex buffer.
-            render_pass.set_vertex_buffer(
-                0,
-                draw_buffer.slice(..)
-            );
+    /// Bind a part of a buffer to the next vertex buffer slot.
+    pub fn vertex_buffer_slice(mut self, slice: wgpu::BufferSlice<'a>) -> Self {
+        self.vertex_buffers.push(slice);
+        self
+    }
 
-            render_pass.draw(range, 0..1);
+    /// Use an index buffer of u16 (IndexFormat::Uint16) or u32 (IndexFormat::Uint32) indices.
+    pub fn index_buffer(mut self, buffer: &'a wgpu::Buffer, format: wgpu::IndexFormat) -> Self {
+        self.index_buffer = Some((buffer.slice(..), format));
+        self
     }
 
+    /// The range of instances to draw (0..1 by default). The buffers of the instance slots are
+    /// read at the instance index, e.g. instances(i..i + 1) draws only the i:th instance.
+    pub fn instances(mut self, instances: Range<u32>) -> Self {
+        self.instances = instances;
+        self
+    }
+
+    /// The value added to each index before reading the vertex buffers. Only for indexed draws.
+    pub fn base_vertex(mut self, base_vertex: i32) -> Self {
+        self.base_vertex = base_vertex;
+        self
+    }
+
+    /// Draw the vertices in range, or the indices in range if there is an index buffer.
+    pub fn draw(self, render_pass: &mut wgpu::RenderPass<'a>, range: Range<u32>) {
+        self.bind(render_pass);
+        if self.index_buffer.is_some() {
+            render_pass.draw_indexed(range, self.base_vertex, self.instances);
+        }
+        else {
+            assert!(self.base_vertex == 0, "{}", format!("DrawCall::draw: base_vertex == {} needs an index buffer.", self.base_vertex));
+            render_pass.draw(range, self.instances);
+        }
+    }
+
+    /// Draw with the arguments stored in indirect_buffer at offset: DrawIndirectArgs, or
+    /// DrawIndexedIndirectArgs if there is an index buffer. The counts stay on the gpu so they
+    /// can be written by a compute shader. The instances and base_vertex of the DrawCall are not
+    /// used.
+    pub fn draw_indirect(self, render_pass: &mut wgpu::RenderPass<'a>, indirect_buffer: &'a wgpu::Buffer, offset: wgpu::BufferAddress) {
+        self.bind(render_pass);
+        if self.index_buffer.is_some() {
+            render_pass.draw_indexed_indirect(indirect_buffer, offset);
+        }
+        else {
+            render_pass.draw_indirect(indirect_buffer, offset);
+        }
+    }
+
+    fn bind(&self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(self.pipeline);
+        for (e, bgs) in self.bind_groups.iter().enumerate() {
+            render_pass.set_bind_group(e as u32, bgs, &[]);
+        }
+        for (slot, slice) in self.vertex_buffers.iter().enumerate() {
+            render_pass.set_vertex_buffer(slot as u32, *slice);
+        }
+        if let Some((slice, format)) = self.index_buffer {
+            render_pass.set_index_buffer(slice, format);
+        }
+    }
+}
+
+/// Record a non-indexed draw of one vertex buffer into a render pass. The attachments and their
+/// load operations belong to the pass (see frame_graph::FrameGraph). See DrawCall for
+/// indexed, instanced and indirect draws.
+pub fn draw<'a>(render_pass: &mut wgpu::RenderPass<'a>,
+                bind_groups: &'a Vec<wgpu::BindGroup>,
+                pipeline: &'a wgpu::RenderPipeline,
+                draw_buffer: &'a wgpu::Buffer,
+                range: Range<u32>) {
+
+        DrawCall::init(pipeline, bind_groups)
+            .vertex_buffer(draw_buffer)
+            .draw(render_pass, range);
+}
+
 /// 1. Create BindGroupLayouts from the BindGroupLayoutEntries.
 /// BindGroupLayouts can be used to create pipelines and BindGroups.
 pub fn create_bind_group_layouts(device: &wgpu::Device, layout_entries: &Vec<Vec<wgpu::BindGroupLayoutEntry>>) -> Vec<wgpu::BindGroupLayout> {
@@ -92,292 +165,816 @@ pub fn create_bind_group_layouts(device: &wgpu::Device, layout_entries: &Vec<Vec
     bind_group_layouts
 }
 
-/// A debug helper function to check that the given binding resources match the bind group layout.
-/// Function will panic if the entries and binding resources mismatch.
-pub fn check_correspondence(
-    bind_group_layout_entries: &Vec<Vec<wgpu::BindGroupLayoutEntry>>,
-    binding_resources: &Vec<Vec<&wgpu::BindingResource>>) -> bool {
-
-    // Check that the number of groups are the same.
-    assert!(bind_group_layout_entries.len() == binding_resources.len(),
-        "The length of bind_group_layout_entries mismatches with binding_resources. {} != {}", 
-        bind_group_layout_entries.len(), binding_resources.len()
-    );
-
-    // Check that the number of entries match.
-    for i in 0..bind_group_layout_entries.len() {
-
-        assert!(bind_group_layout_entries[i].len() == binding_resources[i].len(),
-            "The length of bind_group_layout_entries[{}] mismatches with binding_resources[{}]. {} != {}", 
-            i, i, bind_group_layout_entries[i].len(), binding_resources[i].len());
-
-        // Check the correspondence of entries and resourced.
-        for j in 0..bind_group_layout_entries[i].len() {
-
-            match bind_group_layout_entries[i][j] {
-                 // If the entry is a buffer => resource must be a buffer.
-                 wgpu::BindGroupLayoutEntry {
-                     binding: _,
-                     visibility: _,
-                     ty: wgpu::BindingType::Buffer { .. },
-                     count: _,
-                 } => {
-                     // Check if the corresponding resource is a buffer.
-                     match binding_resources[i][j] {
-                             wgpu::BindingResource::Buffer { .. } => { /* Ok. */ },                              
-                             _ => panic!("Entry layout and binding mismatch at index [{}][{}] (Buffer).", i,j),
-                     }
-                 },
-                 // If the entry is a texture => resource must be a texture.
-                 wgpu::BindGroupLayoutEntry {
-                     binding: _,
-                     visibility: _,
-                     ty: wgpu::BindingType::Texture { .. }, 
-                     count: _,
-                 } => {
-                     match binding_resources[i][j] {
-                             wgpu::BindingResource::TextureView(_) => { /* Ok. */ },
-                             _ => panic!("Entry layout and binding mismatch at index [{}][{}] (Texture).", i, j),
-                         }
-                     },
-                 // If the entry is a sampler => resource must be a sampler.
-                 wgpu::BindGroupLayoutEntry {
-                     binding: _,
-                     visibility: _,
-                     ty: wgpu::BindingType::Sampler { .. }, 
-                     count: _,
-                 } => {
-                     match binding_resources[i][j] {
-                             wgpu::BindingResource::Sampler(_) => { /* Ok. */ },
-                             _ => panic!("Entry layout and binding mismatch (Sampler)."),
-                         }
-                     },
-                 _ => { unimplemented!("Now implemented yet or something error.") },
+/// A bind group layout entry for a uniform buffer.
+pub fn uniform_entry(binding: u32, visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding: binding,
+        visibility: visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// A bind group layout entry for a storage buffer.
+pub fn storage_entry(binding: u32, visibility: wgpu::ShaderStages, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding: binding,
+        visibility: visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// A bind group layout entry for a filterable 2d float texture.
+pub fn texture_entry(binding: u32, visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding: binding,
+        visibility: visibility,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+/// A bind group layout entry for a filtering sampler.
+pub fn sampler_entry(binding: u32, visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding: binding,
+        visibility: visibility,
+        ty: wgpu::BindingType::Sampler {
+            filtering: true,
+            comparison: false,
+        },
+        count: None,
+    }
+}
+
+/// A bind group layout entry for a depth texture array (e.g. Texture::create_shadow_map).
+pub fn depth_texture_array_entry(binding: u32, visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding: binding,
+        visibility: visibility,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Depth,
+            view_dimension: wgpu::TextureViewDimension::D2Array,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+/// A bind group layout entry for a comparison sampler.
+pub fn comparison_sampler_entry(binding: u32, visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding: binding,
+        visibility: visibility,
+        ty: wgpu::BindingType::Sampler {
+            filtering: true,
+            comparison: true,
+        },
+        count: None,
+    }
+}
+
+/// A resource binding of a shader: a global variable with [[group(g), binding(b)]].
+/// The visibility contains the stages of the entry points that use the variable.
+#[derive(Clone, Debug)]
+pub struct ShaderBinding {
+    pub name: String,
+    pub group: u32,
+    pub binding: u32,
+    pub ty: wgpu::BindingType,
+    pub visibility: wgpu::ShaderStages,
+}
+
+/// The resource bindings of a shader module, reflected from wgsl or spirv with naga. Use
+/// layout_entries instead of writing the bind group layout entries by hand and resolve for
+/// binding the resources by name.
+///
+/// ```ignore
+/// let bindings = ShaderBindings::from_wgsl(include_str!("../../shaders_wgsl/mc_test.wgsl")).unwrap();
+/// let kernel = ComputeKernel::from_bindings(&device, &module, "main", &bindings, None).unwrap();
+/// let resources = bindings.resolve(&[("mc_uniform", params.as_entire_binding()),
+///                                    ("counter", counter.as_entire_binding()),
+///                                    ("output", output.as_entire_binding())]).unwrap();
+/// let bind_groups = kernel.create_bind_groups(&device, &resources).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct ShaderBindings {
+    bindings: Vec<ShaderBinding>,
+    workgroup_sizes: Vec<(String, [u32; 3])>,
+}
+
+impl ShaderBindings {
+
+    /// Reflect the bindings of a wgsl source. Returns the parse or validation error as a string.
+    pub fn from_wgsl(source: &str) -> Result<Self, String> {
+        let module = naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string(source))?;
+        ShaderBindings::from_module(&module)
+    }
+
+    /// Reflect only the bindings used by the given entry points of a wgsl source. Use this when
+    /// the pipeline doesn't use all entry points of the module, e.g. the shadow and non-shadow
+    /// fragment shaders of pbr_v4n4.wgsl.
+    pub fn from_wgsl_entry_points(source: &str, entry_points: &[&str]) -> Result<Self, String> {
+        let module = naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string(source))?;
+        ShaderBindings::reflect(&module, Some(entry_points))
+    }
+
+    /// Reflect the bindings of a spirv binary.
+    pub fn from_spirv(data: &[u8]) -> Result<Self, String> {
+        let module = naga::front::spv::parse_u8_slice(data, &naga::front::spv::Options::default())
+            .map_err(|e| format!("Failed to parse spirv: {:?}", e))?;
+        ShaderBindings::from_module(&module)
+    }
+
+    /// Reflect the bindings of a naga module.
+    pub fn from_module(module: &naga::Module) -> Result<Self, String> {
+        ShaderBindings::reflect(&module, None)
+    }
+
+    /// Reflect the bindings of the given entry points (all if None).
+    fn reflect(module: &naga::Module, entry_points: Option<&[&str]>) -> Result<Self, String> {
+
+        if let Some(names) = entry_points {
+            for name in names.iter() {
+                if !module.entry_points.iter().any(|e| e.name == *name) {
+                    let all: Vec<&str> = module.entry_points.iter().map(|e| e.name.as_str()).collect();
+                    return Err(format!("The shader has no entry point '{}'. The entry points are {:?}.", name, all));
+                }
+            }
+        }
+        let selected = |e: &naga::EntryPoint| entry_points.map_or(true, |names| names.contains(&e.name.as_str()));
+
+        // The validator is needed for finding out which entry points use the globals.
+        let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+            .validate(module)
+            .map_err(|e| format!("Shader validation failed: {}", e))?;
+
+        let mut bindings: Vec<ShaderBinding> = Vec::new();
+
+        for (handle, global) in module.global_variables.iter() {
+
+            let resource_binding = match &global.binding {
+                Some(b) => b,
+                None => continue,
+            };
+            let name = global.name.clone().unwrap_or_else(|| format!("<unnamed {}:{}>", resource_binding.group, resource_binding.binding));
+
+            let ty = match global.class {
+                naga::StorageClass::Uniform => buffer_binding_type(wgpu::BufferBindingType::Uniform),
+                naga::StorageClass::Storage { access } => {
+                    buffer_binding_type(wgpu::BufferBindingType::Storage { read_only: !access.contains(naga::StorageAccess::STORE) })
+                }
+                naga::StorageClass::Handle => handle_binding_type(&module.types[global.ty].inner)
+                    .map_err(|e| format!("Binding '{}' (group {}, binding {}): {}", name, resource_binding.group, resource_binding.binding, e))?,
+                _ => continue,
+            };
+
+            let mut visibility = wgpu::ShaderStages::NONE;
+            for (i, entry_point) in module.entry_points.iter().enumerate() {
+                if !selected(entry_point) || info.get_entry_point(i)[handle].is_empty() { continue; }
+                visibility |= match entry_point.stage {
+                    naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                    naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                    naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                };
             }
+
+            // The bindings of the other entry points are not part of the pipeline layout.
+            if entry_points.is_some() && visibility.is_empty() { continue; }
+
+            bindings.push(ShaderBinding {
+                name: name,
+                group: resource_binding.group,
+                binding: resource_binding.binding,
+                ty: ty,
+                visibility: visibility,
+            });
         }
+
+        bindings.sort_by_key(|b| (b.group, b.binding));
+
+        let workgroup_sizes = module.entry_points.iter()
+                                                 .filter(|e| e.stage == naga::ShaderStage::Compute)
+                                                 .map(|e| (e.name.clone(), e.workgroup_size))
+                                                 .collect();
+
+        Ok(Self {
+            bindings: bindings,
+            workgroup_sizes: workgroup_sizes,
+        })
+    }
+
+    /// The workgroup size of a compute entry point. None if there is no such compute entry point.
+    pub fn workgroup_size(&self, entry_point: &str) -> Option<[u32; 3]> {
+        self.workgroup_sizes.iter().find(|(name, _)| name == entry_point).map(|(_, size)| *size)
     }
-    true
-}
 
-/// Create bindgroups from bind group layout entries and binding resources.
-/// This function assemes that binding resources correspond to bind group layout enties.
-/// The correspondence can be checked with check_correspondence function.
-pub fn create_bind_groups(device: &wgpu::Device, 
-                          entry_layouts: &Vec<Vec<wgpu::BindGroupLayoutEntry>>,
-                          bindings: &Vec<Vec<&wgpu::BindingResource>>)
-                        -> Vec<wgpu::BindGroup> {
+    /// All bindings sorted by group and binding.
+    pub fn get_bindings(&self) -> &Vec<ShaderBinding> {
+        &self.bindings
+    }
 
-    // The created bindgroups.
-    let mut result: Vec<wgpu::BindGroup> = Vec::new();
+    /// Find a binding by the name of the global variable.
+    pub fn find(&self, name: &str) -> Option<&ShaderBinding> {
+        self.bindings.iter().find(|b| b.name == name)
+    }
 
-    // Add Binding resources to the bind group.
-    for i in 0..entry_layouts.len() {
+    /// The bind group layout entries per group. Groups without bindings are empty. Unused bindings
+    /// get the visibility of all stages of the module, because wgpu doesn't accept empty visibility.
+    pub fn layout_entries(&self) -> Vec<Vec<wgpu::BindGroupLayoutEntry>> {
 
-        let mut inner_group: Vec<wgpu::BindGroupEntry> = Vec::new();
+        let group_count = self.bindings.iter().map(|b| b.group + 1).max().unwrap_or(0);
+        let all_stages = self.bindings.iter().fold(wgpu::ShaderStages::NONE, |acc, b| acc | b.visibility);
 
-        // Create the bind groups. TODO: this should be created only once (add to struct
-        // attribute).
-        let layouts = create_bind_group_layouts(&device, &entry_layouts);
+        let mut result: Vec<Vec<wgpu::BindGroupLayoutEntry>> = vec![Vec::new(); group_count as usize];
+        for b in self.bindings.iter() {
+            result[b.group as usize].push(wgpu::BindGroupLayoutEntry {
+                binding: b.binding,
+                visibility: if b.visibility.is_empty() { all_stages } else { b.visibility },
+                ty: b.ty,
+                count: None,
+            });
+        }
+        result
+    }
 
-        for j in 0..entry_layouts[i].len() {
+    /// Check that hand written layout entries match the shader. Visibilities are not compared.
+    pub fn check_layout(&self, layout_entries: &Vec<Vec<wgpu::BindGroupLayoutEntry>>) -> Result<(), String> {
 
-            // Create bind group entry from rresource.
-            inner_group.push(
-                wgpu::BindGroupEntry {
-                    binding: j as u32,
-                    resource: bindings[i][j].clone(),
+        for b in self.bindings.iter() {
+            let entry = layout_entries.get(b.group as usize)
+                                      .and_then(|entries| entries.iter().find(|e| e.binding == b.binding));
+            match entry {
+                None => return Err(format!("'{}' (group {}, binding {}) is missing from the layout entries.", b.name, b.group, b.binding)),
+                Some(e) if !same_binding_type(&e.ty, &b.ty) => {
+                    return Err(format!("'{}' (group {}, binding {}) is a {} in the shader but a {} in the layout entries.",
+                                       b.name, b.group, b.binding, describe_binding_type(&b.ty), describe_binding_type(&e.ty)));
                 }
-            );
-
-            // If all bind group entries has been created, create BindGroup.
-            if j == entry_layouts[i].len() - 1 {
-                result.push(device.create_bind_group(
-                    &wgpu::BindGroupDescriptor {
-                        label: None,
-                        layout: &layouts[i],
-                        entries: &inner_group,
-                    })
-                );
+                _ => {}
             }
-        } // j
-    } // i
-    result
+        }
+        Ok(())
+    }
+
+    /// Key the named resources by (group, binding). Each binding of the shader must be given
+    /// exactly once and the resource must be of the right kind (buffer, texture view or sampler).
+    /// The result can be given to create_checked_bind_groups, RenderPipeline::create_bind_groups or
+    /// ComputeKernel::create_bind_groups.
+    pub fn resolve<'r>(&self, resources: &[(&str, wgpu::BindingResource<'r>)]) -> Result<Vec<((u32, u32), wgpu::BindingResource<'r>)>, String> {
+
+        for (i, (name, _)) in resources.iter().enumerate() {
+            if self.find(name).is_none() {
+                let names: Vec<&str> = self.bindings.iter().map(|b| b.name.as_str()).collect();
+                return Err(format!("The shader has no binding '{}'. The bindings are {:?}.", name, names));
+            }
+            if resources[..i].iter().any(|(n, _)| n == name) {
+                return Err(format!("The binding '{}' is given more than once.", name));
+            }
+        }
+
+        let mut result: Vec<((u32, u32), wgpu::BindingResource<'r>)> = Vec::new();
+
+        for b in self.bindings.iter() {
+            let resource = match resources.iter().find(|(name, _)| *name == b.name) {
+                Some((_, r)) => r,
+                None => return Err(format!("No resource given for '{}' (group {}, binding {}, {}).",
+                                           b.name, b.group, b.binding, describe_binding_type(&b.ty))),
+            };
+            if !accepts_resource(&b.ty, resource) {
+                return Err(format!("'{}' (group {}, binding {}) is a {} but a {} was given.",
+                                   b.name, b.group, b.binding, describe_binding_type(&b.ty), describe_resource(resource)));
+            }
+            result.push(((b.group, b.binding), resource.clone()));
+        }
+        Ok(result)
+    }
 }
 
-#[derive(Clone)]
-pub struct RenderDescriptor {
-    vertex_descriptors: Vec<wgpu::VertexFormat>,
-    primitive_topology: wgpu::PrimitiveTopology,
-    has_depth_buffer: bool,
+/// The layout of a struct of a wgsl shader with the wgsl layout rules.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WgslStructLayout {
+    pub size: u32,
+    pub alignment: u32,
+    /// The offsets of the members in the declaration order.
+    pub offsets: Vec<(String, u32)>,
 }
 
-pub struct TestLayoutEntry {
+/// The layout of the struct name of a wgsl source. Use it for checking that the #[repr(C)] structs
+/// written to the buffers match the structs of the shader.
+pub fn wgsl_struct_layout(source: &str, name: &str) -> Result<WgslStructLayout, String> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string(source))?;
 
-    /// BindGroupEntries in descending set number order.
-    /// This is the interface to the pipeline. Create actual binding using 
-    /// wgpu::BindGroupEntry and wgpu::BindGroupDescriptor.
-    pub layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>, 
-    pub bind_group_layouts: Vec<wgpu::BindGroupLayout>, 
-    pub pipeline: wgpu::RenderPipeline,
+    let mut layouter = naga::proc::Layouter::default();
+    layouter.update(&module.types, &module.constants).map_err(|e| format!("{}", e))?;
+
+    for (handle, ty) in module.types.iter() {
+        if ty.name.as_deref() != Some(name) { continue; }
+        if let naga::TypeInner::Struct { members, span, .. } = &ty.inner {
+            return Ok(WgslStructLayout {
+                size: *span,
+                alignment: layouter[handle].alignment.get(),
+                offsets: members.iter().map(|m| (m.name.clone().unwrap_or_default(), m.offset)).collect(),
+            });
+        }
+    }
+    Err(format!("The shader has no struct '{}'.", name))
 }
 
-impl TestLayoutEntry {
-    pub fn init(device: &wgpu::Device,
-                sc_desc: &wgpu::SurfaceConfiguration,               
-                wgsl_module: &wgpu::ShaderModule,
-                // vs_module: &wgpu::ShaderModule,
-                // fs_module: &wgpu::ShaderModule,
-                ) -> Self {
+fn buffer_binding_type(ty: wgpu::BufferBindingType) -> wgpu::BindingType {
+    wgpu::BindingType::Buffer {
+        ty: ty,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    }
+}
 
-        // Define all bind grout entries for pipeline and bind groups.
-        log::info!("TestLayoutEntry::init");
-        let layout_entries = vec![
-                // Set 0
-                vec![wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                            },
-                        count: None,
-                    },
-                ],
-                // Set 1
-                vec![wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler {
-                            filtering: true,
-                            comparison: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler {
-                            filtering: true,
-                            comparison: false,
-                        },
-                        count: None,
-                    }
-                ] // Set 1
-        ];
-
-        // Create stride and vertex attribute descriptors.
-        let (stride, attributes) =  create_vb_descriptor(
-            &vec![wgpu::VertexFormat::Float32x4, wgpu::VertexFormat::Float32x4]
-        );
-
-        let mut bind_group_layouts: Vec<wgpu::BindGroupLayout> = Vec::new();
-
-        // Create bind group layout descriptors. This is used to create bind group layout.
-        for ve in layout_entries.iter() {
-            bind_group_layouts.push(
-                device.create_bind_group_layout(
-                    &wgpu::BindGroupLayoutDescriptor {
-                        entries: &ve,
-                        label: None,
-                    }
-                )
-            );
+/// The binding type of a texture or a sampler.
+fn handle_binding_type(inner: &naga::TypeInner) -> Result<wgpu::BindingType, String> {
+    match *inner {
+        naga::TypeInner::Sampler { comparison } => Ok(wgpu::BindingType::Sampler { filtering: true, comparison: comparison }),
+        naga::TypeInner::Image { dim, arrayed, class } => {
+            let view_dimension = match (dim, arrayed) {
+                (naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+                (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                (naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+                (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+            };
+            match class {
+                naga::ImageClass::Sampled { kind, multi } => {
+                    let sample_type = match kind {
+                        naga::ScalarKind::Float => wgpu::TextureSampleType::Float { filterable: true },
+                        naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                        naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                        naga::ScalarKind::Bool => return Err("bool textures are not supported.".to_string()),
+                    };
+                    Ok(wgpu::BindingType::Texture { sample_type: sample_type, view_dimension: view_dimension, multisampled: multi })
+                }
+                naga::ImageClass::Depth { .. } => {
+                    Ok(wgpu::BindingType::Texture { sample_type: wgpu::TextureSampleType::Depth, view_dimension: view_dimension, multisampled: false })
+                }
+                naga::ImageClass::Storage { format, access } => {
+                    let access = if !access.contains(naga::StorageAccess::STORE) { wgpu::StorageTextureAccess::ReadOnly }
+                                 else if !access.contains(naga::StorageAccess::LOAD) { wgpu::StorageTextureAccess::WriteOnly }
+                                 else { wgpu::StorageTextureAccess::ReadWrite };
+                    Ok(wgpu::BindingType::StorageTexture { access: access, format: storage_format(format)?, view_dimension: view_dimension })
+                }
+            }
         }
+        _ => Err(format!("unsupported handle type {:?}.", inner)),
+    }
+}
 
-        // Create pipeline layout.
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+/// The storage texture formats of WebGPU.
+fn storage_format(format: naga::StorageFormat) -> Result<wgpu::TextureFormat, String> {
+    match format {
+        naga::StorageFormat::Rgba8Unorm => Ok(wgpu::TextureFormat::Rgba8Unorm),
+        naga::StorageFormat::Rgba8Snorm => Ok(wgpu::TextureFormat::Rgba8Snorm),
+        naga::StorageFormat::Rgba8Uint => Ok(wgpu::TextureFormat::Rgba8Uint),
+        naga::StorageFormat::Rgba8Sint => Ok(wgpu::TextureFormat::Rgba8Sint),
+        naga::StorageFormat::Rgba16Uint => Ok(wgpu::TextureFormat::Rgba16Uint),
+        naga::StorageFormat::Rgba16Sint => Ok(wgpu::TextureFormat::Rgba16Sint),
+        naga::StorageFormat::Rgba16Float => Ok(wgpu::TextureFormat::Rgba16Float),
+        naga::StorageFormat::R32Uint => Ok(wgpu::TextureFormat::R32Uint),
+        naga::StorageFormat::R32Sint => Ok(wgpu::TextureFormat::R32Sint),
+        naga::StorageFormat::R32Float => Ok(wgpu::TextureFormat::R32Float),
+        naga::StorageFormat::Rg32Uint => Ok(wgpu::TextureFormat::Rg32Uint),
+        naga::StorageFormat::Rg32Sint => Ok(wgpu::TextureFormat::Rg32Sint),
+        naga::StorageFormat::Rg32Float => Ok(wgpu::TextureFormat::Rg32Float),
+        naga::StorageFormat::Rgba32Uint => Ok(wgpu::TextureFormat::Rgba32Uint),
+        naga::StorageFormat::Rgba32Sint => Ok(wgpu::TextureFormat::Rgba32Sint),
+        naga::StorageFormat::Rgba32Float => Ok(wgpu::TextureFormat::Rgba32Float),
+        _ => Err(format!("{:?} is not a storage texture format.", format)),
+    }
+}
+
+/// Compare binding types ignoring the details that the shader doesn't know (filtering,
+/// min_binding_size, dynamic offsets).
+fn same_binding_type(a: &wgpu::BindingType, b: &wgpu::BindingType) -> bool {
+    match (a, b) {
+        (wgpu::BindingType::Buffer { ty: ta, .. }, wgpu::BindingType::Buffer { ty: tb, .. }) => ta == tb,
+        (wgpu::BindingType::Sampler { comparison: ca, .. }, wgpu::BindingType::Sampler { comparison: cb, .. }) => ca == cb,
+        (wgpu::BindingType::Texture { sample_type: sa, view_dimension: va, multisampled: ma },
+         wgpu::BindingType::Texture { sample_type: sb, view_dimension: vb, multisampled: mb }) => {
+            let float = |s: &wgpu::TextureSampleType| matches!(s, wgpu::TextureSampleType::Float { .. });
+            (sa == sb || (float(sa) && float(sb))) && va == vb && ma == mb
+        }
+        (wgpu::BindingType::StorageTexture { .. }, wgpu::BindingType::StorageTexture { .. }) => a == b,
+        _ => false,
+    }
+}
+
+/// Can the resource be bound to a binding of type ty.
+fn accepts_resource(ty: &wgpu::BindingType, resource: &wgpu::BindingResource) -> bool {
+    match ty {
+        wgpu::BindingType::Buffer { .. } => matches!(resource, wgpu::BindingResource::Buffer { .. }),
+        wgpu::BindingType::Sampler { .. } => matches!(resource, wgpu::BindingResource::Sampler { .. }),
+        wgpu::BindingType::Texture { .. } |
+        wgpu::BindingType::StorageTexture { .. } => matches!(resource, wgpu::BindingResource::TextureView { .. }),
+    }
+}
+
+fn describe_binding_type(ty: &wgpu::BindingType) -> String {
+    match ty {
+        wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, .. } => "uniform buffer".to_string(),
+        wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: true }, .. } => "read only storage buffer".to_string(),
+        wgpu::BindingType::Buffer { .. } => "storage buffer".to_string(),
+        wgpu::BindingType::Sampler { comparison: true, .. } => "comparison sampler".to_string(),
+        wgpu::BindingType::Sampler { .. } => "sampler".to_string(),
+        wgpu::BindingType::Texture { sample_type, view_dimension, .. } => format!("texture ({:?}, {:?})", view_dimension, sample_type),
+        wgpu::BindingType::StorageTexture { format, view_dimension, .. } => format!("storage texture ({:?}, {:?})", view_dimension, format),
+    }
+}
+
+fn describe_resource(resource: &wgpu::BindingResource) -> &'static str {
+    match resource {
+        wgpu::BindingResource::Buffer { .. } => "buffer",
+        wgpu::BindingResource::Sampler { .. } => "sampler",
+        wgpu::BindingResource::TextureView { .. } => "texture view",
+        _ => "resource array",
+    }
+}
+
+/// A render pipeline and the bind group layouts of it. Created with RenderPipelineBuilder.
+pub struct RenderPipeline {
+    layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    pipeline: wgpu::RenderPipeline,
+    sample_count: u32,
+}
+
+impl RenderPipeline {
+
+    pub fn get_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn get_bind_group_layouts(&self) -> &Vec<wgpu::BindGroupLayout> {
+        &self.bind_group_layouts
+    }
+
+    pub fn get_bind_group_layout_entries(&self) -> &Vec<Vec<wgpu::BindGroupLayoutEntry>> {
+        &self.layout_entries
+    }
+
+    pub fn get_sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Create the bind groups (one per set) from the binding resources keyed by (group, binding).
+    /// See create_checked_bind_groups.
+    pub fn create_bind_groups(&self, device: &wgpu::Device, bindings: &[((u32, u32), wgpu::BindingResource)]) -> Result<Vec<wgpu::BindGroup>, String> {
+        create_checked_bind_groups(&device, &self.layout_entries, &self.bind_group_layouts, bindings)
+    }
+}
+
+/// Check that the resources keyed by (group, binding) match the layout entries: every entry has
+/// exactly one resource of the right kind (buffer, texture view or sampler) and there are no
+/// resources without an entry.
+pub fn check_bindings(layout_entries: &Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+                      bindings: &[((u32, u32), wgpu::BindingResource)]) -> Result<(), String> {
+
+    for (i, ((group, binding), resource)) in bindings.iter().enumerate() {
+        if bindings[..i].iter().any(|(key, _)| *key == (*group, *binding)) {
+            return Err(format!("Group {}, binding {} is given more than once.", group, binding));
+        }
+        let entry = layout_entries.get(*group as usize)
+                                  .and_then(|entries| entries.iter().find(|e| e.binding == *binding));
+        match entry {
+            None => return Err(format!("There is no layout entry for group {}, binding {}.", group, binding)),
+            Some(e) if !accepts_resource(&e.ty, resource) => {
+                return Err(format!("Group {}, binding {} is a {} but a {} was given.",
+                                   group, binding, describe_binding_type(&e.ty), describe_resource(resource)));
+            }
+            _ => {}
+        }
+    }
+
+    for (group, entries) in layout_entries.iter().enumerate() {
+        for e in entries.iter() {
+            if !bindings.iter().any(|(key, _)| *key == (group as u32, e.binding)) {
+                return Err(format!("No resource given for group {}, binding {} ({}).", group, e.binding, describe_binding_type(&e.ty)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Create bind groups using existing bind group layouts. The resources are keyed by
+/// (group, binding) and checked with check_bindings, so a resource in a wrong slot is an error
+/// instead of a wgpu validation panic.
+pub fn create_checked_bind_groups(device: &wgpu::Device,
+                                  layout_entries: &Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+                                  bind_group_layouts: &Vec<wgpu::BindGroupLayout>,
+                                  bindings: &[((u32, u32), wgpu::BindingResource)]) -> Result<Vec<wgpu::BindGroup>, String> {
+
+    check_bindings(&layout_entries, bindings)?;
+
+    Ok(layout_entries.iter().zip(bind_group_layouts.iter()).enumerate().map(|(group, (entries, layout))| {
+        let bind_group_entries: Vec<wgpu::BindGroupEntry> = entries.iter().map(|e| {
+            let (_, resource) = bindings.iter().find(|(key, _)| *key == (group as u32, e.binding)).unwrap();
+            wgpu::BindGroupEntry {
+                binding: e.binding,
+                resource: resource.clone(),
+            }
+        }).collect();
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
-            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(), // &[&bind_group_layout],
+            layout: layout,
+            entries: &bind_group_entries,
+        })
+    }).collect())
+}
+
+/// A builder for RenderPipeline. The default pipeline has entry points vs_main and fs_main, no
+/// vertex buffer, triangle list topology, ccw front face, no culling, Depth32Float depth test with
+/// CompareFunction::Less, no blending and no multisampling.
+pub struct RenderPipelineBuilder<'a> {
+    label: Option<&'a str>,
+    module: &'a wgpu::ShaderModule,
+    vs_entry_point: &'a str,
+    fs_entry_point: Option<&'a str>,
+    vertex_formats: Vec<wgpu::VertexFormat>,
+    step_mode: wgpu::VertexStepMode,
+    instance_formats: Vec<Vec<wgpu::VertexFormat>>,
+    topology: wgpu::PrimitiveTopology,
+    front_face: wgpu::FrontFace,
+    cull_mode: Option<wgpu::Face>,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    color_format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+    write_mask: wgpu::ColorWrites,
+    sample_count: u32,
+    layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+}
+
+impl<'a> RenderPipelineBuilder<'a> {
+
+    /// Start building a pipeline from a wgsl module that renders to color_format.
+    pub fn init(module: &'a wgpu::ShaderModule, color_format: wgpu::TextureFormat) -> Self {
+        Self {
+            label: None,
+            module: module,
+            vs_entry_point: "vs_main",
+            fs_entry_point: Some("fs_main"),
+            vertex_formats: Vec::new(),
+            step_mode: wgpu::VertexStepMode::Vertex,
+            instance_formats: Vec::new(),
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            depth_stencil: Some(RenderPipelineBuilder::depth_state(wgpu::TextureFormat::Depth32Float, wgpu::CompareFunction::Less, true)),
+            color_format: color_format,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+            sample_count: 1,
+            layout_entries: Vec::new(),
+        }
+    }
+
+    pub fn label(mut self, label: &'a str) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    /// Set the vertex and fragment entry points. A pipeline without fragment entry point only
+    /// writes depth (e.g. shadow maps).
+    pub fn entry_points(mut self, vs_entry_point: &'a str, fs_entry_point: Option<&'a str>) -> Self {
+        self.vs_entry_point = vs_entry_point;
+        self.fs_entry_point = fs_entry_point;
+        self
+    }
+
+    /// The attributes of the vertex buffer. See create_vb_descriptor.
+    pub fn vertex_formats(mut self, formats: &[wgpu::VertexFormat]) -> Self {
+        self.vertex_formats = formats.to_vec();
+        self
+    }
+
+    /// Step the vertex buffer per instance instead of per vertex.
+    pub fn step_mode(mut self, step_mode: wgpu::VertexStepMode) -> Self {
+        self.step_mode = step_mode;
+        self
+    }
+
+    /// Add an instance buffer to the next slot (see DrawCall::vertex_buffer). The shader
+    /// locations of the attributes continue from the previous buffers.
+    pub fn instance_formats(mut self, formats: &[wgpu::VertexFormat]) -> Self {
+        assert!(!formats.is_empty(), "RenderPipelineBuilder::instance_formats: an instance buffer without attributes.");
+        self.instance_formats.push(formats.to_vec());
+        self
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: wgpu::FrontFace) -> Self {
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    /// Set the depth test. Use Camera::depth_compare with reversed z.
+    pub fn depth(mut self, format: wgpu::TextureFormat, compare: wgpu::CompareFunction, write_enabled: bool) -> Self {
+        self.depth_stencil = Some(RenderPipelineBuilder::depth_state(format, compare, write_enabled));
+        self
+    }
+
+    /// Offset the depth of the fragments by constant (in the smallest depth units) plus
+    /// slope_scale times the depth slope of the triangle. Used against shadow acne in shadow
+    /// maps. Panics if the pipeline has no depth buffer.
+    pub fn depth_bias(mut self, constant: i32, slope_scale: f32) -> Self {
+        let depth_stencil = self.depth_stencil.as_mut().expect("RenderPipelineBuilder::depth_bias: the pipeline has no depth buffer.");
+        depth_stencil.bias.constant = constant;
+        depth_stencil.bias.slope_scale = slope_scale;
+        self
+    }
+
+    /// Render without depth buffer.
+    pub fn no_depth(mut self) -> Self {
+        self.depth_stencil = None;
+        self
+    }
+
+    pub fn blend(mut self, blend: Option<wgpu::BlendState>) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn write_mask(mut self, write_mask: wgpu::ColorWrites) -> Self {
+        self.write_mask = write_mask;
+        self
+    }
+
+    /// The number of samples per pixel (MSAA). The color and depth targets must have the same
+    /// sample count and a multisampled color target must be resolved (see
+    /// RenderPassBuilder::color_resolve). Panics if the count isn't 1, 2, 4, 8 or 16.
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        multisampled(sample_count);
+        self.sample_count = sample_count;
+        self
+    }
+
+    /// Add the next bind group (set) layout.
+    pub fn bind_group(mut self, entries: Vec<wgpu::BindGroupLayoutEntry>) -> Self {
+        self.layout_entries.push(entries);
+        self
+    }
+
+    /// Use the bind group layouts reflected from the shader (see ShaderBindings::layout_entries).
+    /// Replaces the bind groups added so far.
+    pub fn bind_groups_from(mut self, bindings: &ShaderBindings) -> Self {
+        self.layout_entries = bindings.layout_entries();
+        self
+    }
+
+    /// Create the pipeline.
+    pub fn build(self, device: &wgpu::Device) -> RenderPipeline {
+
+        let bind_group_layouts = create_bind_group_layouts(&device, &self.layout_entries);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: self.label,
+            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
             push_constant_ranges: &[],
         });
 
+        let layouts = vertex_buffer_layouts(&self.vertex_formats, self.step_mode, &self.instance_formats);
+
+        let vertex_buffers: Vec<wgpu::VertexBufferLayout> = layouts.iter().map(|(stride, attributes, step_mode)| {
+            wgpu::VertexBufferLayout {
+                array_stride: *stride,
+                step_mode: *step_mode,
+                attributes: attributes,
+            }
+        }).collect();
+
+        let targets = [wgpu::ColorTargetState {
+            format: self.color_format,
+            blend: self.blend,
+            write_mask: self.write_mask,
+        }];
+
         let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
+            label: self.label,
             layout: Some(&pipeline_layout),
             vertex: wgpu::VertexState {
-                module: &wgsl_module,
-                entry_point: "vs_main",
-                buffers: &[
-                    wgpu::VertexBufferLayout {
-                        array_stride: stride,
-                        step_mode: wgpu::VertexStepMode::Vertex, 
-                        attributes: &attributes,
-                    }],
+                module: self.module,
+                entry_point: self.vs_entry_point,
+                buffers: &vertex_buffers,
             },
             primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
+                topology: self.topology,
                 strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Front),
+                front_face: self.front_face,
+                cull_mode: self.cull_mode,
                 polygon_mode: wgpu::PolygonMode::Fill,
                 conservative: false,
                 clamp_depth: false,
             },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState {
-                    front: wgpu::StencilFaceState::IGNORE,
-                    back: wgpu::StencilFaceState::IGNORE,
-                    read_mask: 0,
-                    write_mask: 0,
-                },
-                bias: wgpu::DepthBiasState {
-                    constant: 0,
-                    slope_scale: 0.0,
-                    clamp: 0.0,
-                },
-            }),
+            depth_stencil: self.depth_stencil,
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: self.sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
-            fragment: Some(wgpu::FragmentState {
-                module: &wgsl_module,
-                entry_point: "fs_main",
-                targets: &[wgpu::ColorTargetState {
-                    format: sc_desc.format,
-                    blend: None, //Some(wgpu::BlendState {
-                           //     color: wgpu::BlendComponent {
-                           //          src_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                           //          dst_factor: wgpu::BlendFactor::OneMinusDstAlpha,
-                           //          operation: wgpu::BlendOperation::Max, 
-                           //     },
-                           //     alpha: wgpu::BlendComponent {
-                           //          src_factor: wgpu::BlendFactor::SrcAlpha,
-                           //          dst_factor: wgpu::BlendFactor::One,
-                           //          operation: wgpu::BlendOperation::Add, 
-                           //     },
-                           // }),
-                    // alpha_blend: wgpu::BlendState::REPLACE,
-                    // color_blend: wgpu::BlendState::REPLACE,
-                    write_mask: wgpu::ColorWrites::COLOR,
-                }],
+            fragment: self.fs_entry_point.map(|entry_point| wgpu::FragmentState {
+                module: self.module,
+                entry_point: entry_point,
+                targets: &targets,
             }),
         });
+
+        RenderPipeline {
+            layout_entries: self.layout_entries,
+            bind_group_layouts: bind_group_layouts,
+            pipeline: pipeline,
+            sample_count: self.sample_count,
+        }
+    }
+
+    fn depth_state(format: wgpu::TextureFormat, compare: wgpu::CompareFunction, write_enabled: bool) -> wgpu::DepthStencilState {
+        wgpu::DepthStencilState {
+            format: format,
+            depth_write_enabled: write_enabled,
+            depth_compare: compare,
+            stencil: wgpu::StencilState {
+                front: wgpu::StencilFaceState::IGNORE,
+                back: wgpu::StencilFaceState::IGNORE,
+                read_mask: 0,
+                write_mask: 0,
+            },
+            bias: wgpu::DepthBiasState {
+                constant: 0,
+                slope_scale: 0.0,
+                clamp: 0.0,
+            },
+        }
+    }
+}
+
+pub struct TestLayoutEntry {
+
+    /// BindGroupEntries in descending set number order.
+    /// This is the interface to the pipeline. Create actual binding using 
+    /// wgpu::BindGroupEntry and wgpu::BindGroupDescriptor.
+    pub layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>, 
+    pub bind_group_layouts: Vec<wgpu::BindGroupLayout>, 
+    pub pipeline: wgpu::RenderPipeline,
+}
+
+impl TestLayoutEntry {
+    /// The layout is reflected from the wgsl source. With shadows the pipeline uses the
+    /// fs_main_shadow entry point and set 2 has the shadow bindings, e.g.
+    /// shadow::with_shadows(renderer_v4n4.wgsl, 2).
+    pub fn init(device: &wgpu::Device,
+                sc_desc: &wgpu::SurfaceConfiguration,               
+                sample_count: u32,
+                wgsl_source: &str,
+                shadows: bool,
+                ) -> Self {
+
+        log::info!("TestLayoutEntry::init");
+
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("test_layout_entry_module"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(wgsl_source)),
+        });
+
+        // The shadow bindings are only used by fs_main_shadow.
+        let fs_entry_point = if shadows { "fs_main_shadow" } else { "fs_main" };
+        let bindings = ShaderBindings::from_wgsl_entry_points(wgsl_source, &["vs_main", fs_entry_point]).unwrap();
+
+        let RenderPipeline { layout_entries, bind_group_layouts, pipeline, .. } =
+            RenderPipelineBuilder::init(&module, sc_desc.format)
+                .entry_points("vs_main", Some(fs_entry_point))
+                .vertex_formats(&[wgpu::VertexFormat::Float32x4, wgpu::VertexFormat::Float32x4])
+                .cull_mode(Some(wgpu::Face::Front))
+                .write_mask(wgpu::ColorWrites::COLOR)
+                .bind_groups_from(&bindings)
+                .sample_count(sample_count)
+                .build(&device);
+
         log::info!("TestLayoutEntry::init == OK");
 
         Self {
@@ -386,4 +983,218 @@ impl TestLayoutEntry {
             pipeline: pipeline,
         }
     }
+
+    /// Create the bind groups. The resources are keyed by (group, binding) and checked against
+    /// the layout entries.
+    pub fn create_bind_groups(&self, device: &wgpu::Device, bindings: &[((u32, u32), wgpu::BindingResource)]) -> Result<Vec<wgpu::BindGroup>, String> {
+        create_checked_bind_groups(&device, &self.layout_entries, &self.bind_group_layouts, bindings)
+    }
+}
+
+/// The stride, attributes and step mode of the vertex buffer (slot 0) and the instance buffers
+/// (the next slots). The locations of the instance attributes continue after the vertex attributes.
+fn vertex_buffer_layouts(vertex_formats: &Vec<wgpu::VertexFormat>,
+                         step_mode: wgpu::VertexStepMode,
+                         instance_formats: &[Vec<wgpu::VertexFormat>]) -> Vec<(u64, Vec<wgpu::VertexAttribute>, wgpu::VertexStepMode)> {
+
+    let mut layouts = Vec::new();
+    if !vertex_formats.is_empty() {
+        let (stride, attributes) = create_vb_descriptor(vertex_formats);
+        layouts.push((stride, attributes, step_mode));
+    }
+    let mut location = vertex_formats.len() as u32;
+    for formats in instance_formats.iter() {
+        let (stride, mut attributes) = create_vb_descriptor(formats);
+        for a in attributes.iter_mut() { a.shader_location += location; }
+        location += formats.len() as u32;
+        layouts.push((stride, attributes, wgpu::VertexStepMode::Instance));
+    }
+    layouts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shadow::{shadow_layout_entries, with_shadows};
+
+    const COMPUTE: wgpu::ShaderStages = wgpu::ShaderStages::COMPUTE;
+    const FRAGMENT: wgpu::ShaderStages = wgpu::ShaderStages::FRAGMENT;
+    const VERTEX: wgpu::ShaderStages = wgpu::ShaderStages::VERTEX;
+
+    const INDIRECT_ARGS: &str = include_str!("../../shaders_wgsl/indirect_args.wgsl");
+
+    fn pbr_v4n4() -> String {
+        with_shadows(include_str!("../../shaders_wgsl/pbr_v4n4.wgsl"), 2)
+    }
+
+    /// A resource that is not accepted by any of the bindings. Real resources need a device.
+    fn dummy<'a>() -> wgpu::BindingResource<'a> {
+        wgpu::BindingResource::TextureViewArray(&[])
+    }
+
+    fn names(bindings: &ShaderBindings) -> Vec<(&str, u32, u32)> {
+        bindings.get_bindings().iter().map(|b| (b.name.as_str(), b.group, b.binding)).collect()
+    }
+
+    #[test]
+    fn reflect_compute_shaders() {
+        let indirect = ShaderBindings::from_wgsl(INDIRECT_ARGS).unwrap();
+        assert_eq!(names(&indirect), vec![("params", 0, 0), ("counters", 0, 1), ("args", 0, 2)]);
+        assert_eq!(indirect.layout_entries(), vec![vec![uniform_entry(0, COMPUTE),
+                                                        storage_entry(1, COMPUTE, true),
+                                                        storage_entry(2, COMPUTE, false)]]);
+
+        let voxelize = ShaderBindings::from_wgsl(include_str!("../../shaders_wgsl/voxelize.wgsl")).unwrap();
+        assert_eq!(names(&voxelize), vec![("params", 0, 0), ("triangles", 0, 1), ("voxels", 0, 2)]);
+        assert_eq!(voxelize.layout_entries(), vec![vec![uniform_entry(0, COMPUTE),
+                                                        storage_entry(1, COMPUTE, true),
+                                                        storage_entry(2, COMPUTE, false)]]);
+
+        let cull = ShaderBindings::from_wgsl(include_str!("../../shaders_wgsl/frustum_cull.wgsl")).unwrap();
+        assert_eq!(names(&cull), vec![("params", 0, 0), ("aabbs", 0, 1), ("draw_indirect", 0, 2), ("visible", 0, 3)]);
+        assert_eq!(cull.layout_entries(), vec![vec![uniform_entry(0, COMPUTE),
+                                                    storage_entry(1, COMPUTE, true),
+                                                    storage_entry(2, COMPUTE, false),
+                                                    storage_entry(3, COMPUTE, false)]]);
+    }
+
+    #[test]
+    fn reflect_shadow_depth() {
+        let bindings = ShaderBindings::from_wgsl(include_str!("../../shaders_wgsl/shadow_depth.wgsl")).unwrap();
+        assert_eq!(names(&bindings), vec![("cascade", 0, 0)]);
+        assert_eq!(bindings.layout_entries(), vec![vec![uniform_entry(0, VERTEX)]]);
+    }
+
+    #[test]
+    fn reflect_pbr_entry_points() {
+        let material = vec![uniform_entry(0, FRAGMENT),
+                            texture_entry(1, FRAGMENT),
+                            texture_entry(2, FRAGMENT),
+                            texture_entry(3, FRAGMENT),
+                            sampler_entry(4, FRAGMENT)];
+        let camera_and_lights = vec![uniform_entry(0, VERTEX | FRAGMENT), uniform_entry(1, FRAGMENT)];
+
+        for fs in ["fs_main", "fs_main_gamma"].iter().cloned() {
+            let bindings = ShaderBindings::from_wgsl_entry_points(&pbr_v4n4(), &["vs_main", fs]).unwrap();
+            assert_eq!(bindings.layout_entries(), vec![camera_and_lights.clone(), material.clone()], "{}", fs);
+        }
+
+        for fs in ["fs_main_shadow", "fs_main_gamma_shadow"].iter().cloned() {
+            let bindings = ShaderBindings::from_wgsl_entry_points(&pbr_v4n4(), &["vs_main", fs]).unwrap();
+            assert_eq!(bindings.layout_entries(), vec![camera_and_lights.clone(), material.clone(), shadow_layout_entries(FRAGMENT)], "{}", fs);
+            assert_eq!(names(&bindings)[7..].to_vec(), vec![("shadows", 2, 0), ("t_shadow", 2, 1), ("s_shadow", 2, 2)]);
+        }
+
+        // Without the filtering the shadows are in the layout.
+        assert_eq!(ShaderBindings::from_wgsl(&pbr_v4n4()).unwrap().layout_entries().len(), 3);
+
+        assert_eq!(ShaderBindings::from_wgsl_entry_points(&pbr_v4n4(), &["vs_main", "fs_shadow"]).unwrap_err(),
+                   "The shader has no entry point 'fs_shadow'. The entry points are [\"vs_main\", \"fs_main\", \"fs_main_gamma\", \"fs_main_shadow\", \"fs_main_gamma_shadow\"].");
+    }
+
+    #[test]
+    fn reflect_render_shaders() {
+        // The hand written layouts that were used before the layouts were reflected.
+        let camera = vec![uniform_entry(0, VERTEX | FRAGMENT)];
+        let textures = vec![texture_entry(0, FRAGMENT), sampler_entry(1, FRAGMENT), texture_entry(2, FRAGMENT), sampler_entry(3, FRAGMENT)];
+
+        // TestLayoutEntry.
+        let v4n4 = with_shadows(include_str!("../../shaders_wgsl/renderer_v4n4.wgsl"), 2);
+        let bindings = ShaderBindings::from_wgsl_entry_points(&v4n4, &["vs_main", "fs_main"]).unwrap();
+        assert_eq!(bindings.layout_entries().len(), 2);
+        assert_eq!(bindings.check_layout(&vec![camera.clone(), textures.clone()]), Ok(()));
+        let bindings = ShaderBindings::from_wgsl_entry_points(&v4n4, &["vs_main", "fs_main_shadow"]).unwrap();
+        assert_eq!(bindings.layout_entries().len(), 3);
+        assert_eq!(bindings.check_layout(&vec![camera.clone(), textures.clone(), shadow_layout_entries(FRAGMENT)]), Ok(()));
+
+        // Render_vvvvnnnn.
+        let plain = with_shadows(include_str!("../../shaders_wgsl/renderer_v4n4_plain.wgsl"), 1);
+        let bindings = ShaderBindings::from_wgsl_entry_points(&plain, &["vs_main", "fs_main"]).unwrap();
+        assert_eq!(bindings.layout_entries().len(), 1);
+        assert_eq!(bindings.check_layout(&vec![camera.clone()]), Ok(()));
+        let bindings = ShaderBindings::from_wgsl_entry_points(&plain, &["vs_main", "fs_main_shadow"]).unwrap();
+        assert_eq!(bindings.layout_entries().len(), 2);
+        assert_eq!(bindings.check_layout(&vec![camera.clone(), shadow_layout_entries(FRAGMENT)]), Ok(()));
+
+        // Render_vvvc.
+        let bindings = ShaderBindings::from_wgsl(include_str!("../../shaders_wgsl/renderer_v3c1.wgsl")).unwrap();
+        assert_eq!(bindings.layout_entries().len(), 1);
+        assert_eq!(bindings.check_layout(&vec![vec![uniform_entry(0, VERTEX)]]), Ok(()));
+
+        // TwoTriangles.
+        let bindings = ShaderBindings::from_wgsl(include_str!("../../shaders_wgsl/two_triangles.wgsl")).unwrap();
+        assert_eq!(bindings.layout_entries().len(), 1);
+        assert_eq!(bindings.check_layout(&vec![vec![texture_entry(0, FRAGMENT), sampler_entry(1, FRAGMENT)]]), Ok(()));
+    }
+
+    #[test]
+    fn instance_buffer_layouts() {
+        let attribute = |format, offset, shader_location| wgpu::VertexAttribute { format: format, offset: offset, shader_location: shader_location };
+
+        // The aabb lines: the corners per vertex and the aabb index per instance.
+        let layouts = vertex_buffer_layouts(&vec![wgpu::VertexFormat::Float32x3],
+                                            wgpu::VertexStepMode::Vertex,
+                                            &[vec![wgpu::VertexFormat::Uint32]]);
+        assert_eq!(layouts, vec![(12, vec![attribute(wgpu::VertexFormat::Float32x3, 0, 0)], wgpu::VertexStepMode::Vertex),
+                                 (4, vec![attribute(wgpu::VertexFormat::Uint32, 0, 1)], wgpu::VertexStepMode::Instance)]);
+
+        // The locations continue over several instance buffers.
+        let layouts = vertex_buffer_layouts(&vec![wgpu::VertexFormat::Float32x4, wgpu::VertexFormat::Float32x4],
+                                            wgpu::VertexStepMode::Vertex,
+                                            &[vec![wgpu::VertexFormat::Float32x4, wgpu::VertexFormat::Float32x4],
+                                              vec![wgpu::VertexFormat::Uint32]]);
+        assert_eq!(layouts.len(), 3);
+        assert_eq!(layouts[1], (32, vec![attribute(wgpu::VertexFormat::Float32x4, 0, 2), attribute(wgpu::VertexFormat::Float32x4, 16, 3)], wgpu::VertexStepMode::Instance));
+        assert_eq!(layouts[2], (4, vec![attribute(wgpu::VertexFormat::Uint32, 0, 4)], wgpu::VertexStepMode::Instance));
+
+        // Only instance data.
+        let layouts = vertex_buffer_layouts(&vec![], wgpu::VertexStepMode::Vertex, &[vec![wgpu::VertexFormat::Float32x2]]);
+        assert_eq!(layouts, vec![(8, vec![attribute(wgpu::VertexFormat::Float32x2, 0, 0)], wgpu::VertexStepMode::Instance)]);
+    }
+
+    #[test]
+    fn invalid_source() {
+        assert!(ShaderBindings::from_wgsl("fn main( {").is_err());
+    }
+
+    #[test]
+    fn check_layout_errors() {
+        let bindings = ShaderBindings::from_wgsl(INDIRECT_ARGS).unwrap();
+        assert_eq!(bindings.check_layout(&bindings.layout_entries()), Ok(()));
+
+        assert_eq!(bindings.check_layout(&vec![vec![uniform_entry(0, COMPUTE), storage_entry(1, COMPUTE, true)]]).unwrap_err(),
+                   "'args' (group 0, binding 2) is missing from the layout entries.");
+        assert_eq!(bindings.check_layout(&vec![vec![uniform_entry(0, COMPUTE), storage_entry(1, COMPUTE, false), storage_entry(2, COMPUTE, false)]]).unwrap_err(),
+                   "'counters' (group 0, binding 1) is a read only storage buffer in the shader but a storage buffer in the layout entries.");
+    }
+
+    #[test]
+    fn resolve_errors() {
+        let bindings = ShaderBindings::from_wgsl(INDIRECT_ARGS).unwrap();
+
+        assert_eq!(bindings.resolve(&[("counter", dummy())]).unwrap_err(),
+                   "The shader has no binding 'counter'. The bindings are [\"params\", \"counters\", \"args\"].");
+        assert_eq!(bindings.resolve(&[("args", dummy()), ("args", dummy())]).unwrap_err(),
+                   "The binding 'args' is given more than once.");
+        assert_eq!(bindings.resolve(&[]).unwrap_err(),
+                   "No resource given for 'params' (group 0, binding 0, uniform buffer).");
+        assert_eq!(bindings.resolve(&[("params", dummy())]).unwrap_err(),
+                   "'params' (group 0, binding 0) is a uniform buffer but a resource array was given.");
+    }
+
+    #[test]
+    fn check_bindings_errors() {
+        let layout_entries = ShaderBindings::from_wgsl(INDIRECT_ARGS).unwrap().layout_entries();
+
+        assert_eq!(check_bindings(&layout_entries, &[]).unwrap_err(),
+                   "No resource given for group 0, binding 0 (uniform buffer).");
+        assert_eq!(check_bindings(&layout_entries, &[((1, 0), dummy())]).unwrap_err(),
+                   "There is no layout entry for group 1, binding 0.");
+        assert_eq!(check_bindings(&layout_entries, &[((0, 3), dummy())]).unwrap_err(),
+                   "There is no layout entry for group 0, binding 3.");
+        assert_eq!(check_bindings(&layout_entries, &[((0, 2), dummy()), ((0, 2), dummy())]).unwrap_err(),
+                   "Group 0, binding 2 is given more than once.");
+        assert_eq!(check_bindings(&layout_entries, &[((0, 1), dummy())]).unwrap_err(),
+                   "Group 0, binding 1 is a read only storage buffer but a resource array was given.");
+    }
 }
@@ -0,0 +1,436 @@
+use bytemuck::Pod;
+use wgpu::util::DeviceExt;
+use crate::texture::Texture as JTexture;
+
+/// A handle to a buffer of ResourceRegistry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferHandle(usize);
+
+/// A handle to a texture of ResourceRegistry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(usize);
+
+/// A handle to a sampler of ResourceRegistry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerHandle(usize);
+
+/// A handle to the bind groups (one per set) of ResourceRegistry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BindGroupHandle(usize);
+
+/// A resource that bind groups can depend on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceHandle {
+    Buffer(BufferHandle),
+    Texture(TextureHandle),
+    Sampler(SamplerHandle),
+}
+
+impl From<BufferHandle> for ResourceHandle {
+    fn from(handle: BufferHandle) -> Self { ResourceHandle::Buffer(handle) }
+}
+
+impl From<TextureHandle> for ResourceHandle {
+    fn from(handle: TextureHandle) -> Self { ResourceHandle::Texture(handle) }
+}
+
+impl From<SamplerHandle> for ResourceHandle {
+    fn from(handle: SamplerHandle) -> Self { ResourceHandle::Sampler(handle) }
+}
+
+/// The metadata of a buffer.
+#[derive(Clone, Debug)]
+pub struct BufferInfo {
+    pub label: String,
+    pub size: wgpu::BufferAddress,
+    pub usage: wgpu::BufferUsages,
+}
+
+/// The metadata of a texture.
+#[derive(Clone, Debug)]
+pub struct TextureInfo {
+    pub label: String,
+    pub size: [u32; 3],
+    pub format: wgpu::TextureFormat,
+    pub byte_size: u64,
+}
+
+/// The memory used by the resources of the registry in bytes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoryUsage {
+    pub buffers: u64,
+    pub textures: u64,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> u64 {
+        self.buffers + self.textures
+    }
+}
+
+struct Slot<T, I> {
+    resource: T,
+    info: I,
+}
+
+struct BindGroupSlot {
+    label: String,
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+/// The handle and version bookkeeping of ResourceRegistry. The version of a resource is
+/// increased every time the resource is recreated. The bind groups remember the versions of their
+/// dependencies at the time they were created.
+#[derive(Default)]
+struct Versions {
+    buffers: Vec<u32>,
+    textures: Vec<u32>,
+    samplers: Vec<u32>,
+    bind_groups: Vec<Vec<(ResourceHandle, u32)>>,
+}
+
+impl Versions {
+
+    fn add_buffer(&mut self) -> BufferHandle {
+        self.buffers.push(0);
+        BufferHandle(self.buffers.len() - 1)
+    }
+
+    fn add_texture(&mut self) -> TextureHandle {
+        self.textures.push(0);
+        TextureHandle(self.textures.len() - 1)
+    }
+
+    fn add_sampler(&mut self) -> SamplerHandle {
+        self.samplers.push(0);
+        SamplerHandle(self.samplers.len() - 1)
+    }
+
+    fn add_bind_groups(&mut self, dependencies: &[ResourceHandle]) -> BindGroupHandle {
+        let dependencies = dependencies.iter().map(|d| (*d, self.get(*d))).collect();
+        self.bind_groups.push(dependencies);
+        BindGroupHandle(self.bind_groups.len() - 1)
+    }
+
+    fn get(&self, resource: ResourceHandle) -> u32 {
+        match resource {
+            ResourceHandle::Buffer(h) => self.buffers[h.0],
+            ResourceHandle::Texture(h) => self.textures[h.0],
+            ResourceHandle::Sampler(h) => self.samplers[h.0],
+        }
+    }
+
+    /// The resource has been recreated.
+    fn increase(&mut self, resource: ResourceHandle) {
+        match resource {
+            ResourceHandle::Buffer(h) => self.buffers[h.0] += 1,
+            ResourceHandle::Texture(h) => self.textures[h.0] += 1,
+            ResourceHandle::Sampler(h) => self.samplers[h.0] += 1,
+        }
+    }
+
+    /// The bind groups have been recreated from the current resources.
+    fn update_bind_groups(&mut self, handle: BindGroupHandle) {
+        let dependencies = self.bind_groups[handle.0].iter().map(|(d, _)| (*d, self.get(*d))).collect();
+        self.bind_groups[handle.0] = dependencies;
+    }
+
+    fn find_stale_dependency(&self, handle: BindGroupHandle) -> Option<ResourceHandle> {
+        self.bind_groups[handle.0].iter()
+            .find(|(d, version)| self.get(*d) != *version)
+            .map(|(d, _)| *d)
+    }
+
+    fn invalid_bind_groups(&self) -> Vec<BindGroupHandle> {
+        (0..self.bind_groups.len()).map(BindGroupHandle).filter(|h| self.find_stale_dependency(*h).is_some()).collect()
+    }
+}
+
+/// The gpu resources of an application behind typed handles. Buffers and samplers created by the
+/// registry are labeled for wgpu debugging. Recreating a resource (e.g. on resize) keeps the
+/// handle valid but invalidates the bind groups that depend on it. Those must be replaced with
+/// replace_bind_groups before they are used again.
+///
+/// ```ignore
+/// let mut resources = ResourceRegistry::init();
+/// let nodes = resources.create_buffer_from_data::<FMM_Node>(&device, "fmm_nodes", &data, wgpu::BufferUsages::STORAGE);
+/// let bind_groups = kernel.create_bind_groups(&device, &[((0, 0), resources.get_buffer(nodes).as_entire_binding())]).unwrap();
+/// let fmm_bind_groups = resources.insert_bind_groups("fmm", bind_groups, &[nodes.into()]);
+/// kernel.dispatch(resources.get_bind_groups(fmm_bind_groups), &mut encoder, 1, 1, 1);
+/// ```
+pub struct ResourceRegistry {
+    buffers: Vec<Slot<wgpu::Buffer, BufferInfo>>,
+    textures: Vec<Slot<JTexture, TextureInfo>>,
+    samplers: Vec<Slot<wgpu::Sampler, String>>,
+    bind_groups: Vec<BindGroupSlot>,
+    versions: Versions,
+}
+
+impl ResourceRegistry {
+
+    pub fn init() -> Self {
+        Self {
+            buffers: Vec::new(),
+            textures: Vec::new(),
+            samplers: Vec::new(),
+            bind_groups: Vec::new(),
+            versions: Versions::default(),
+        }
+    }
+
+    /// Create an uninitialized buffer.
+    pub fn create_buffer(&mut self, device: &wgpu::Device, label: &str, size: wgpu::BufferAddress, usage: wgpu::BufferUsages) -> BufferHandle {
+        let buffer = ResourceRegistry::new_buffer(&device, label, size, usage);
+        self.buffers.push(Slot {
+            resource: buffer,
+            info: BufferInfo { label: label.to_string(), size: size, usage: usage },
+        });
+        self.versions.add_buffer()
+    }
+
+    /// Create a buffer from data.
+    pub fn create_buffer_from_data<T: Pod>(&mut self, device: &wgpu::Device, label: &str, data: &[T], usage: wgpu::BufferUsages) -> BufferHandle {
+        let buffer = ResourceRegistry::new_buffer_from_data(&device, label, data, usage);
+        self.buffers.push(Slot {
+            resource: buffer,
+            info: BufferInfo { label: label.to_string(), size: std::mem::size_of_val(data) as wgpu::BufferAddress, usage: usage },
+        });
+        self.versions.add_buffer()
+    }
+
+    /// Recreate an uninitialized buffer with a new size. The label and the usage are kept.
+    pub fn recreate_buffer(&mut self, device: &wgpu::Device, handle: BufferHandle, size: wgpu::BufferAddress) {
+        let slot = &mut self.buffers[handle.0];
+        slot.resource = ResourceRegistry::new_buffer(&device, &slot.info.label, size, slot.info.usage);
+        slot.info.size = size;
+        self.versions.increase(handle.into());
+    }
+
+    /// Recreate a buffer from data. The label and the usage are kept.
+    pub fn recreate_buffer_from_data<T: Pod>(&mut self, device: &wgpu::Device, handle: BufferHandle, data: &[T]) {
+        let slot = &mut self.buffers[handle.0];
+        slot.resource = ResourceRegistry::new_buffer_from_data(&device, &slot.info.label, data, slot.info.usage);
+        slot.info.size = std::mem::size_of_val(data) as wgpu::BufferAddress;
+        self.versions.increase(handle.into());
+    }
+
+    /// Add a texture created by create. The label is given to create, so the wgpu texture has
+    /// the same label as the registry.
+    ///
+    /// ```ignore
+    /// let grass = resources.insert_texture("grass", |label| JTexture::create_from_bytes(&queue, &device, &sc_desc, 1, GRASS, label));
+    /// ```
+    pub fn insert_texture<F: FnOnce(Option<&str>) -> JTexture>(&mut self, label: &str, create: F) -> TextureHandle {
+        let texture = create(Some(label));
+        let info = ResourceRegistry::texture_info(label, &texture);
+        self.textures.push(Slot {
+            resource: texture,
+            info: info,
+        });
+        self.versions.add_texture()
+    }
+
+    /// Replace the texture with a texture created by create, e.g. a screen sized texture after
+    /// resize. The label of the texture is kept.
+    pub fn recreate_texture<F: FnOnce(Option<&str>) -> JTexture>(&mut self, handle: TextureHandle, create: F) {
+        let slot = &mut self.textures[handle.0];
+        let texture = create(Some(&slot.info.label));
+        slot.info = ResourceRegistry::texture_info(&slot.info.label, &texture);
+        slot.resource = texture;
+        self.versions.increase(handle.into());
+    }
+
+    /// Create a sampler. The label of the descriptor is replaced with label.
+    pub fn create_sampler(&mut self, device: &wgpu::Device, label: &str, descriptor: &wgpu::SamplerDescriptor) -> SamplerHandle {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor { label: Some(label), ..descriptor.clone() });
+        self.samplers.push(Slot {
+            resource: sampler,
+            info: label.to_string(),
+        });
+        self.versions.add_sampler()
+    }
+
+    /// Add bind groups that were created from the dependencies. The bind groups are invalidated
+    /// when any of the dependencies is recreated.
+    pub fn insert_bind_groups(&mut self, label: &str, bind_groups: Vec<wgpu::BindGroup>, dependencies: &[ResourceHandle]) -> BindGroupHandle {
+        self.bind_groups.push(BindGroupSlot {
+            label: label.to_string(),
+            bind_groups: bind_groups,
+        });
+        self.versions.add_bind_groups(dependencies)
+    }
+
+    /// Replace invalidated bind groups with bind groups created from the current resources.
+    pub fn replace_bind_groups(&mut self, handle: BindGroupHandle, bind_groups: Vec<wgpu::BindGroup>) {
+        self.bind_groups[handle.0].bind_groups = bind_groups;
+        self.versions.update_bind_groups(handle);
+    }
+
+    pub fn get_buffer(&self, handle: BufferHandle) -> &wgpu::Buffer {
+        &self.buffers[handle.0].resource
+    }
+
+    pub fn get_buffer_info(&self, handle: BufferHandle) -> &BufferInfo {
+        &self.buffers[handle.0].info
+    }
+
+    pub fn get_texture(&self, handle: TextureHandle) -> &JTexture {
+        &self.textures[handle.0].resource
+    }
+
+    pub fn get_texture_info(&self, handle: TextureHandle) -> &TextureInfo {
+        &self.textures[handle.0].info
+    }
+
+    pub fn get_sampler(&self, handle: SamplerHandle) -> &wgpu::Sampler {
+        &self.samplers[handle.0].resource
+    }
+
+    /// Get the bind groups. Panics if a dependency has been recreated after the bind groups were
+    /// created (see is_valid).
+    pub fn get_bind_groups(&self, handle: BindGroupHandle) -> &Vec<wgpu::BindGroup> {
+        let slot = &self.bind_groups[handle.0];
+        if let Some(d) = self.versions.find_stale_dependency(handle) {
+            panic!("The bind groups '{}' use the recreated resource '{}'. Replace them with replace_bind_groups.", slot.label, self.get_label(d));
+        }
+        &slot.bind_groups
+    }
+
+    /// Are the bind groups up to date with their dependencies.
+    pub fn is_valid(&self, handle: BindGroupHandle) -> bool {
+        self.versions.find_stale_dependency(handle).is_none()
+    }
+
+    /// The bind groups that must be replaced.
+    pub fn invalid_bind_groups(&self) -> Vec<BindGroupHandle> {
+        self.versions.invalid_bind_groups()
+    }
+
+    /// Find a buffer by label.
+    pub fn find_buffer(&self, label: &str) -> Option<BufferHandle> {
+        self.buffers.iter().position(|s| s.info.label == label).map(BufferHandle)
+    }
+
+    /// Find a texture by label.
+    pub fn find_texture(&self, label: &str) -> Option<TextureHandle> {
+        self.textures.iter().position(|s| s.info.label == label).map(TextureHandle)
+    }
+
+    /// The memory used by buffers and textures.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            buffers: self.buffers.iter().map(|s| s.info.size).sum(),
+            textures: self.textures.iter().map(|s| s.info.byte_size).sum(),
+        }
+    }
+
+    /// A human readable list of the resources and their sizes.
+    pub fn report(&self) -> String {
+        let mut result = String::new();
+        for s in self.buffers.iter() {
+            result.push_str(&format!("buffer  {:<32} {:>12} bytes {:?}\n", s.info.label, s.info.size, s.info.usage));
+        }
+        for s in self.textures.iter() {
+            result.push_str(&format!("texture {:<32} {:>12} bytes {:?} {:?}\n", s.info.label, s.info.byte_size, s.info.size, s.info.format));
+        }
+        let usage = self.memory_usage();
+        result.push_str(&format!("total: buffers {} bytes, textures {} bytes, all {} bytes", usage.buffers, usage.textures, usage.total()));
+        result
+    }
+
+    fn new_buffer(device: &wgpu::Device, label: &str, size: wgpu::BufferAddress, usage: wgpu::BufferUsages) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: size,
+            usage: usage,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn new_buffer_from_data<T: Pod>(device: &wgpu::Device, label: &str, data: &[T], usage: wgpu::BufferUsages) -> wgpu::Buffer {
+        device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(&data),
+                usage: usage,
+            }
+        )
+    }
+
+    fn texture_info(label: &str, texture: &JTexture) -> TextureInfo {
+        TextureInfo {
+            label: label.to_string(),
+            size: texture.get_size(),
+            format: texture.get_format(),
+            byte_size: texture.get_byte_size(),
+        }
+    }
+
+    fn get_label(&self, resource: ResourceHandle) -> &str {
+        match resource {
+            ResourceHandle::Buffer(h) => &self.buffers[h.0].info.label,
+            ResourceHandle::Texture(h) => &self.textures[h.0].info.label,
+            ResourceHandle::Sampler(h) => &self.samplers[h.0].info,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles() {
+        let mut versions = Versions::default();
+        assert_eq!(versions.add_buffer(), BufferHandle(0));
+        assert_eq!(versions.add_buffer(), BufferHandle(1));
+        assert_eq!(versions.add_texture(), TextureHandle(0));
+        assert_eq!(versions.add_sampler(), SamplerHandle(0));
+        assert_eq!(versions.add_bind_groups(&[]), BindGroupHandle(0));
+        assert_eq!(versions.add_bind_groups(&[]), BindGroupHandle(1));
+    }
+
+    #[test]
+    fn recreate_invalidates_dependent_bind_groups() {
+        let mut versions = Versions::default();
+        let buffer = versions.add_buffer();
+        let texture = versions.add_texture();
+        let sampler = versions.add_sampler();
+        let a = versions.add_bind_groups(&[buffer.into()]);
+        let b = versions.add_bind_groups(&[texture.into(), sampler.into()]);
+        let c = versions.add_bind_groups(&[buffer.into(), texture.into()]);
+        assert!(versions.invalid_bind_groups().is_empty());
+
+        versions.increase(texture.into());
+        assert_eq!(versions.find_stale_dependency(a), None);
+        assert_eq!(versions.find_stale_dependency(b), Some(texture.into()));
+        assert_eq!(versions.find_stale_dependency(c), Some(texture.into()));
+        assert_eq!(versions.invalid_bind_groups(), vec![b, c]);
+
+        versions.update_bind_groups(b);
+        assert_eq!(versions.invalid_bind_groups(), vec![c]);
+
+        versions.increase(sampler.into());
+        assert_eq!(versions.invalid_bind_groups(), vec![b, c]);
+    }
+
+    #[test]
+    fn update_after_many_recreations() {
+        let mut versions = Versions::default();
+        let buffer = versions.add_buffer();
+        let a = versions.add_bind_groups(&[buffer.into()]);
+        versions.increase(buffer.into());
+        versions.increase(buffer.into());
+        assert_eq!(versions.get(buffer.into()), 2);
+        assert_eq!(versions.find_stale_dependency(a), Some(buffer.into()));
+
+        versions.update_bind_groups(a);
+        assert_eq!(versions.find_stale_dependency(a), None);
+
+        // Bind groups created after a recreation use the current version.
+        let b = versions.add_bind_groups(&[buffer.into()]);
+        assert_eq!(versions.find_stale_dependency(b), None);
+        versions.increase(buffer.into());
+        assert_eq!(versions.invalid_bind_groups(), vec![a, b]);
+    }
+}
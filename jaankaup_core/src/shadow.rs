@@ -0,0 +1,420 @@
+use std::borrow::Cow;
+use bytemuck::{Zeroable, Pod};
+use cgmath::{prelude::*, Vector3, Vector4, Matrix4, Point3};
+use crate::buffer::buffer_from_data;
+use crate::camera::{Camera, Projection, OPENGL_TO_WGPU_MATRIX};
+use crate::texture::Texture as JTexture;
+use crate::resources::{ResourceRegistry, TextureHandle};
+use crate::render_pipelines::{
+    RenderPipeline,
+    RenderPipelineBuilder,
+    ShaderBindings,
+    uniform_entry,
+    depth_texture_array_entry,
+    comparison_sampler_entry,
+};
+
+/// The maximum number of shadow cascades.
+pub const MAX_CASCADES: usize = 4;
+
+/// The Shadows struct, the shadow bindings and shadow_factor of the shaders that sample the
+/// shadow map. The bind group is SHADOW_GROUP (see with_shadows).
+pub const SHADOWS_WGSL: &str = include_str!("../../shaders_wgsl/shadows.wgsl");
+
+/// The wgsl source with the shadows (SHADOWS_WGSL) in front of it, bound to the given group.
+/// The shaders that call shadow_factor (e.g. pbr_v4n4.wgsl) must be built with this.
+pub fn with_shadows(source: &str, group: u32) -> String {
+    format!("{}\n{}", SHADOWS_WGSL.replace("SHADOW_GROUP", &group.to_string()), source)
+}
+
+/// The shadow uniform of the shaders that sample the shadow map (the Shadows struct of
+/// shadows.wgsl).
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ShadowUniform {
+    light_view_proj: [[[f32; 4]; 4]; MAX_CASCADES],
+    splits: [f32; 4],
+    camera_pos: [f32; 4],
+    view_dir: [f32; 4],
+    texel_world_size: [f32; 4],
+    cascade_count: u32,
+    texel_size: f32,
+    depth_bias: f32,
+    normal_bias: f32,
+}
+
+unsafe impl Pod for ShadowUniform {}
+unsafe impl Zeroable for ShadowUniform {}
+
+/// Cascaded shadow maps of a directional light. The view frustum of the camera is split into
+/// cascades along the view direction and each cascade has an orthographic light projection that
+/// covers its part of the frustum. The cascades are layers of one depth texture array (see
+/// Texture::create_shadow_map) that is kept in a ResourceRegistry, so frame graph passes can
+/// declare it with write and read.
+///
+/// ```ignore
+/// let mut shadow_map = ShadowMap::init(&device, &mut resources, 2048, 4);
+/// let shadow_pipeline = ShadowPipeline::init(&device, &[wgpu::VertexFormat::Float32x4, wgpu::VertexFormat::Float32x4]);
+/// let cascade_bind_groups = shadow_pipeline.create_bind_groups(&device, &shadow_map).unwrap();
+/// // Every frame after the camera has moved.
+/// shadow_map.update(&queue, &camera, light_direction);
+/// graph.add_compute_pass("shadows")
+///      .write(shadow_map.get_texture())
+///      .execute(|app, encoder| {
+///          for cascade in 0..app.shadow_map.get_cascade_count() {
+///              let mut pass = app.shadow_map.begin_cascade_pass(encoder, cascade);
+///              DrawCall::init(app.shadow_pipeline.get_pipeline(), &app.cascade_bind_groups[cascade as usize])
+///                  .vertex_buffer(&app.vertices)
+///                  .draw(&mut pass, 0..app.vertex_count);
+///          }
+///      });
+/// ```
+pub struct ShadowMap {
+    texture: TextureHandle,
+    cascade_views: Vec<wgpu::TextureView>,
+    cascade_buffers: Vec<wgpu::Buffer>,
+    uniform_buffer: wgpu::Buffer,
+    uniform: ShadowUniform,
+    size: u32,
+    cascade_count: u32,
+    shadow_distance: f32,
+    split_lambda: f32,
+}
+
+impl ShadowMap {
+
+    /// Create size x size shadow maps for cascade_count cascades. The depth texture array is
+    /// added to resources with the label "shadow map".
+    pub fn init(device: &wgpu::Device, resources: &mut ResourceRegistry, size: u32, cascade_count: u32) -> Self {
+
+        assert!(cascade_count > 0 && cascade_count as usize <= MAX_CASCADES, "{}", format!("ShadowMap::init: cascade_count == {} isn't in range [1, {}].", cascade_count, MAX_CASCADES));
+
+        let texture = resources.insert_texture("shadow map", |label| JTexture::create_shadow_map(device, size, cascade_count, label));
+
+        let cascade_views = (0..cascade_count).map(|cascade| {
+            resources.get_texture(texture).texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("shadow cascade"),
+                format: Some(JTexture::DEPTH_FORMAT),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                aspect: wgpu::TextureAspect::DepthOnly,
+                base_mip_level: 0,
+                mip_level_count: std::num::NonZeroU32::new(1),
+                base_array_layer: cascade,
+                array_layer_count: std::num::NonZeroU32::new(1),
+            })
+        }).collect();
+
+        let cascade_buffers = (0..cascade_count).map(|_| {
+            buffer_from_data::<[[f32; 4]; 4]>(
+                device,
+                &[Matrix4::<f32>::identity().into()],
+                wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                None)
+        }).collect();
+
+        let uniform = ShadowUniform {
+            light_view_proj: [Matrix4::<f32>::identity().into(); MAX_CASCADES],
+            splits: [0.0; 4],
+            camera_pos: [0.0; 4],
+            view_dir: [0.0, 0.0, -1.0, 0.0],
+            texel_world_size: [0.0; 4],
+            cascade_count: cascade_count,
+            texel_size: 1.0 / size as f32,
+            depth_bias: 0.0005,
+            normal_bias: 1.5,
+        };
+
+        let uniform_buffer = buffer_from_data::<ShadowUniform>(
+            device,
+            &[uniform],
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            None);
+
+        Self {
+            texture: texture,
+            cascade_views: cascade_views,
+            cascade_buffers: cascade_buffers,
+            uniform_buffer: uniform_buffer,
+            uniform: uniform,
+            size: size,
+            cascade_count: cascade_count,
+            shadow_distance: 100.0,
+            split_lambda: 0.75,
+        }
+    }
+
+    /// The depth texture array in the registry. Its view and comparison sampler are bound to the
+    /// shaders that sample the shadows (see shadow_bindings).
+    pub fn get_texture(&self) -> TextureHandle {
+        self.texture
+    }
+
+    pub fn get_uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.uniform_buffer
+    }
+
+    /// The light view projection matrix of the cascade. Used by ShadowPipeline.
+    pub fn get_cascade_buffer(&self, cascade: u32) -> &wgpu::Buffer {
+        &self.cascade_buffers[cascade as usize]
+    }
+
+    pub fn get_cascade_count(&self) -> u32 {
+        self.cascade_count
+    }
+
+    pub fn get_size(&self) -> u32 {
+        self.size
+    }
+
+    /// The far distances of the cascades along the view direction.
+    pub fn get_splits(&self) -> [f32; 4] {
+        self.uniform.splits
+    }
+
+    /// The distance from the camera where the shadows end. The far plane of the camera is used
+    /// if it is closer.
+    pub fn set_shadow_distance(&mut self, distance: f32) {
+        assert!(distance > 0.0, "ShadowMap::set_shadow_distance: the distance must be > 0.");
+        self.shadow_distance = distance;
+    }
+
+    /// The blend between uniform (0.0) and logarithmic (1.0) cascade splits.
+    pub fn set_split_lambda(&mut self, lambda: f32) {
+        assert!((0.0..=1.0).contains(&lambda), "ShadowMap::set_split_lambda: lambda must be in range [0, 1].");
+        self.split_lambda = lambda;
+    }
+
+    /// depth_bias is subtracted from the depth of the receiver (in the depth range of the
+    /// cascade) and the receiver is moved along its normal by normal_bias shadow map texels.
+    pub fn set_bias(&mut self, depth_bias: f32, normal_bias: f32) {
+        self.uniform.depth_bias = depth_bias;
+        self.uniform.normal_bias = normal_bias;
+    }
+
+    /// Fit the cascades to the view frustum of the camera and upload the matrices.
+    /// light_direction is the direction where the light travels.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera, light_direction: Vector3<f32>) {
+
+        assert!(light_direction.magnitude2() > 0.0, "ShadowMap::update: light_direction is a zero vector.");
+
+        let light_direction = light_direction.normalize();
+        let (znear, zfar) = camera.get_near_far();
+        let far = match camera.get_projection() {
+            Projection::ReverseZInfinite => self.shadow_distance,
+            _ => zfar.min(self.shadow_distance),
+        };
+
+        // The light up vector must not be parallel to the light direction.
+        let up = if light_direction.y.abs() > 0.99 { Vector3::unit_x() } else { Vector3::unit_y() };
+
+        let mut near = znear;
+        for cascade in 0..self.cascade_count as usize {
+
+            let split = cascade_split(znear, far, self.split_lambda, cascade as u32, self.cascade_count);
+
+            // A bounding sphere of the cascade frustum. The size of the projection doesn't
+            // change when the camera rotates, which keeps the shadow edges stable.
+            let corners = camera.frustum_corners(near, split);
+            let center = corners.iter().fold(Vector3::<f32>::zero(), |acc, c| acc + *c) / 8.0;
+            let radius = corners.iter().map(|c| (*c - center).magnitude()).fold(0.0, f32::max);
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            // The casters between the light and the cascade are in the depth range too.
+            let eye = center - light_direction * (radius + self.shadow_distance);
+            let view = Matrix4::look_at_rh(Point3::from_vec(eye), Point3::from_vec(center), up);
+            let proj = OPENGL_TO_WGPU_MATRIX * cgmath::ortho(-radius, radius, -radius, radius, 0.0, 2.0 * radius + self.shadow_distance);
+            let proj = snap_to_texels(proj, &view, self.size);
+
+            let light_view_proj: [[f32; 4]; 4] = (proj * view).into();
+            self.uniform.light_view_proj[cascade] = light_view_proj;
+            self.uniform.splits[cascade] = split;
+            self.uniform.texel_world_size[cascade] = 2.0 * radius / self.size as f32;
+            queue.write_buffer(&self.cascade_buffers[cascade], 0, bytemuck::cast_slice(&[light_view_proj]));
+
+            near = split;
+        }
+
+        let position = camera.get_position();
+        let view_dir = camera.get_view().normalize();
+        self.uniform.camera_pos = [position.x, position.y, position.z, 1.0];
+        self.uniform.view_dir = [view_dir.x, view_dir.y, view_dir.z, 0.0];
+
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    /// Begin a depth only render pass that clears the cascade and renders to it. Draw the shadow
+    /// casters with ShadowPipeline and the bind groups of the cascade.
+    pub fn begin_cascade_pass<'a>(&'a self, encoder: &'a mut wgpu::CommandEncoder, cascade: u32) -> wgpu::RenderPass<'a> {
+        assert!(cascade < self.cascade_count, "{}", format!("ShadowMap::begin_cascade_pass: cascade {} doesn't exist (cascade count {}).", cascade, self.cascade_count));
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("shadow cascade"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.cascade_views[cascade as usize],
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+}
+
+/// The far distance of the cascade with the practical split scheme: a blend of the logarithmic
+/// (lambda == 1.0) and uniform (lambda == 0.0) splits of the range znear..far.
+fn cascade_split(znear: f32, far: f32, lambda: f32, cascade: u32, cascade_count: u32) -> f32 {
+    let p = (cascade + 1) as f32 / cascade_count as f32;
+    let log_split = znear * (far / znear).powf(p);
+    let uniform_split = znear + (far - znear) * p;
+    lambda * log_split + (1.0 - lambda) * uniform_split
+}
+
+/// Move the orthographic light projection in whole texels of a size * size shadow map, so the
+/// world origin is at a texel corner and the shadow edges don't shimmer when the camera moves.
+fn snap_to_texels(mut proj: Matrix4<f32>, view: &Matrix4<f32>, size: u32) -> Matrix4<f32> {
+    let half_size = 0.5 * size as f32;
+    let origin = (proj * view) * Vector4::new(0.0, 0.0, 0.0, 1.0);
+    proj.w.x += ((origin.x * half_size).round() - origin.x * half_size) / half_size;
+    proj.w.y += ((origin.y * half_size).round() - origin.y * half_size) / half_size;
+    proj
+}
+
+/// The bind group layout entries for sampling the shadows: the shadow uniform, the shadow map
+/// and its comparison sampler. Add these as a set of a pipeline and bind the resources of
+/// shadow_bindings to the same group.
+pub fn shadow_layout_entries(visibility: wgpu::ShaderStages) -> Vec<wgpu::BindGroupLayoutEntry> {
+    vec![uniform_entry(0, visibility),
+         depth_texture_array_entry(1, visibility),
+         comparison_sampler_entry(2, visibility)]
+}
+
+/// The resources for shadow_layout_entries in the given group, keyed by (group, binding).
+/// resources is the registry that was given to ShadowMap::init.
+pub fn shadow_bindings<'a>(shadow_map: &'a ShadowMap, resources: &'a ResourceRegistry, group: u32) -> Vec<((u32, u32), wgpu::BindingResource<'a>)> {
+    let texture = resources.get_texture(shadow_map.get_texture());
+    vec![((group, 0), shadow_map.get_uniform_buffer().as_entire_binding()),
+         ((group, 1), wgpu::BindingResource::TextureView(&texture.view)),
+         ((group, 2), wgpu::BindingResource::Sampler(&texture.sampler))]
+}
+
+/// A depth only pipeline that renders shadow casters to the cascades of a ShadowMap. Both sides
+/// of the triangles cast shadows.
+pub struct ShadowPipeline {
+    pipeline: RenderPipeline,
+}
+
+impl ShadowPipeline {
+
+    /// vertex_formats is the layout of the vertex buffer. The first attribute must be the
+    /// Float32x4 position, the others are not used.
+    pub fn init(device: &wgpu::Device, vertex_formats: &[wgpu::VertexFormat]) -> Self {
+
+        assert!(vertex_formats.first() == Some(&wgpu::VertexFormat::Float32x4), "ShadowPipeline::init: the first vertex attribute must be a Float32x4 position.");
+
+        let source = include_str!("../../shaders_wgsl/shadow_depth.wgsl");
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("shadow_depth.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+        });
+
+        // There is no fragment stage, so the color format isn't used.
+        let pipeline = RenderPipelineBuilder::init(&module, JTexture::DEPTH_FORMAT)
+            .label("shadow depth")
+            .entry_points("vs_main", None)
+            .vertex_formats(vertex_formats)
+            .depth(JTexture::DEPTH_FORMAT, wgpu::CompareFunction::Less, true)
+            .depth_bias(2, 2.0)
+            .bind_groups_from(&ShaderBindings::from_wgsl(source).unwrap())
+            .build(&device);
+
+        Self {
+            pipeline: pipeline,
+        }
+    }
+
+    pub fn get_pipeline(&self) -> &wgpu::RenderPipeline {
+        self.pipeline.get_pipeline()
+    }
+
+    /// The bind groups of each cascade.
+    pub fn create_bind_groups(&self, device: &wgpu::Device, shadow_map: &ShadowMap) -> Result<Vec<Vec<wgpu::BindGroup>>, String> {
+        (0..shadow_map.get_cascade_count()).map(|cascade| {
+            self.pipeline.create_bind_groups(
+                &device,
+                &[((0, 0), shadow_map.get_cascade_buffer(cascade).as_entire_binding())]
+            )
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_pipelines::wgsl_struct_layout;
+
+    #[test]
+    fn shadows_snippet() {
+        let source = with_shadows("fn main() {}", 2);
+        assert!(!source.contains("SHADOW_GROUP"));
+        assert_eq!(source.matches("[[group(2), binding(").count(), 3);
+        assert!(source.ends_with("fn main() {}"));
+
+        let shadows = wgsl_struct_layout(&with_shadows("", 1), "Shadows").unwrap();
+        assert_eq!(shadows.size as usize, std::mem::size_of::<ShadowUniform>());
+    }
+
+    #[test]
+    fn cascade_splits() {
+        let splits = |lambda| (0..4).map(|cascade| cascade_split(1.0, 9.0, lambda, cascade, 4)).collect::<Vec<f32>>();
+        assert_eq!(splits(0.0), vec![3.0, 5.0, 7.0, 9.0]);
+
+        let log = splits(1.0);
+        for (split, expected) in log.iter().zip([3.0_f32.sqrt(), 3.0, 27.0_f32.sqrt(), 9.0].iter()) {
+            assert!((split - expected).abs() < 1e-4, "{:?}", log);
+        }
+
+        // The last cascade ends at far and the cascades don't overlap.
+        let blend = splits(0.5);
+        assert!((blend[3] - 9.0).abs() < 1e-4, "{:?}", blend);
+        assert!(blend.windows(2).all(|w| w[0] < w[1]) && blend[0] > 1.0, "{:?}", blend);
+    }
+
+    #[test]
+    fn texel_snapping() {
+        let size = 1024;
+        let half_size = 0.5 * size as f32;
+        let light_direction = Vector3::new(-1.0, -2.0, 0.5).normalize();
+        let radius = 8.0;
+
+        // The light projection of a cascade centered at center, as in ShadowMap::update.
+        let light_view_proj = |center: Vector3<f32>| {
+            let eye = center - light_direction * (radius + 100.0);
+            let view = Matrix4::look_at_rh(Point3::from_vec(eye), Point3::from_vec(center), Vector3::unit_y());
+            let proj = OPENGL_TO_WGPU_MATRIX * cgmath::ortho(-radius, radius, -radius, radius, 0.0, 2.0 * radius + 100.0);
+            let snapped = snap_to_texels(proj, &view, size);
+
+            // The projection moves less than half a texel.
+            assert!((snapped.w.x - proj.w.x).abs() <= 1.0 / size as f32, "{:?} {:?}", snapped.w, proj.w);
+            assert!((snapped.w.y - proj.w.y).abs() <= 1.0 / size as f32, "{:?} {:?}", snapped.w, proj.w);
+            snapped * view
+        };
+
+        // The world origin is at a texel corner and a world point moves in whole texels however the
+        // camera moves.
+        let texel = |m: Matrix4<f32>, p: Vector3<f32>| {
+            let q = m * p.extend(1.0);
+            [q.x * half_size, q.y * half_size]
+        };
+        let whole = |x: f32| (x - x.round()).abs() < 1e-2;
+        let a = light_view_proj(Vector3::new(0.1, 0.0, -5.0));
+        let b = light_view_proj(Vector3::new(0.47, 0.3, -5.9));
+        let origin = texel(a, Vector3::zero());
+        assert!(whole(origin[0]) && whole(origin[1]), "{:?}", origin);
+
+        let p = Vector3::new(1.3, 0.2, -2.7);
+        let (pa, pb) = (texel(a, p), texel(b, p));
+        assert!(whole(pa[0] - pb[0]) && whole(pa[1] - pb[1]), "{:?} {:?}", pa, pb);
+        assert!(pa != pb);
+    }
+}
@@ -1,31 +0,0 @@
-//struct DrawInfo {
-//    name: String,
-//    start_index: u32,
-//    end_index: u32,
-//    instances: u32,
-//    samples: u32,
-//    clear: bool,
-//}
-//
-//struct RPass {
-//    pipeline: wgpu::RenderPipeline,
-//    bind_groups: Vec<wgpu::BindGroup>,
-//}
-//
-//impl RPass {
-//    pub fn init() {}
-//    pub fn execute() {}
-//}
-//
-//struct CPass {
-//    pipeline: wgpu::ComputePipeline,
-//    bind_groups: Vec<wgpu::BindGroup>,
-//    dispatch_x: u32,
-//    dispatch_y: u32,
-//    dispatch_z: u32,
-//}
-//
-//impl CPass {
-//    pub fn init() {}
-//    pub fn execute() {}
-//}
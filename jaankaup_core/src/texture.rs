@@ -1,4 +1,4 @@
-use crate::misc::Convert2Vec;
+use crate::misc::{Convert2Vec, multisampled};
 use std::num::NonZeroU32;
 use bytemuck::Pod;
 //use std::mem;
@@ -18,13 +18,40 @@ pub struct Texture {
     width: u32,
     height: u32,
     depth: u32,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
 }
 
 impl Texture {
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
-    pub fn create_depth_texture(device: &wgpu::Device, sc_desc: &wgpu::SurfaceConfiguration, label: Option<&str>) -> Self {
+    /// The width, height and depth (or array layers) of the texture.
+    pub fn get_size(&self) -> [u32; 3] {
+        [self.width, self.height, self.depth]
+    }
+
+    pub fn get_format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    pub fn get_sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// The size of the texture in bytes (all textures have a single mip level).
+    pub fn get_byte_size(&self) -> u64 {
+        let info = self.format.describe();
+        let blocks_x = (self.width + info.block_dimensions.0 as u32 - 1) / info.block_dimensions.0 as u32;
+        let blocks_y = (self.height + info.block_dimensions.1 as u32 - 1) / info.block_dimensions.1 as u32;
+        blocks_x as u64 * blocks_y as u64 * self.depth as u64 * info.block_size as u64 * self.sample_count as u64
+    }
+
+    /// Create a depth texture with the size of the surface. Use the same sample count as the
+    /// color target and the pipeline (see RenderPipelineBuilder::sample_count).
+    pub fn create_depth_texture(device: &wgpu::Device, sc_desc: &wgpu::SurfaceConfiguration, sample_count: u32, label: Option<&str>) -> Self {
+
+        multisampled(sample_count);
 
         let width = sc_desc.width; 
         let height = sc_desc.height; 
@@ -40,7 +67,7 @@ impl Texture {
             size,
             // array_layer_count: 1,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count: sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT, // TODO: SAMPLED?
@@ -50,7 +77,65 @@ impl Texture {
         let texture = device.create_texture(&desc);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        let sampler = Self::create_comparison_sampler(device);
+
+        let texture_type = TextureType::Depth;
+
+        let format = Self::DEPTH_FORMAT;
+
+        Self { texture_type, texture, view, sampler, width, height, depth, format, sample_count }
+    }
+
+    /// Create a depth texture array for shadow maps, one layer per cascade. The view is a
+    /// D2Array view of all layers and the sampler is the comparison sampler of
+    /// create_depth_texture (the result is 1.0 where the reference depth is less than the stored
+    /// depth). Render to the layers with views of single layers.
+    pub fn create_shadow_map(device: &wgpu::Device, size: u32, layers: u32, label: Option<&str>) -> Self {
+
+        assert!(size > 0 && layers > 0, "{}", format!("Texture::create_shadow_map: the size {} and the layer count {} must be > 0.", size, layers));
+
+        let width = size;
+        let height = size;
+        let depth = layers;
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: label,
+            size: wgpu::Extent3d {
+                width: width,
+                height: height,
+                depth_or_array_layers: depth,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: label,
+            format: Some(Self::DEPTH_FORMAT),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            aspect: wgpu::TextureAspect::DepthOnly,
+            base_mip_level: 0,
+            mip_level_count: std::num::NonZeroU32::new(1),
+            base_array_layer: 0,
+            array_layer_count: std::num::NonZeroU32::new(layers),
+        });
+        let sampler = Self::create_comparison_sampler(device);
+
+        let texture_type = TextureType::Depth;
+
+        let format = Self::DEPTH_FORMAT;
+        let sample_count = 1;
+
+        Self { texture_type, texture, view, sampler, width, height, depth, format, sample_count }
+    }
+
+    /// The sampler of the depth textures. Linear filtering compares the four nearest texels, so
+    /// the result is already a 2x2 PCF.
+    fn create_comparison_sampler(device: &wgpu::Device) -> wgpu::Sampler {
+        device.create_sampler(&wgpu::SamplerDescriptor {
             label: None,
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
@@ -63,11 +148,7 @@ impl Texture {
             compare: Some(wgpu::CompareFunction::Less),
             //compare: Some(wgpu::CompareFunction::LessEqual),
             ..Default::default()
-        });
-
-        let texture_type = TextureType::Depth;
-
-        Self { texture_type, texture, view, sampler, width, height, depth }
+        })
     }
 
     /// Creates a texture from a sequency of bytes (expects bytes to be in png format in rgb). Now
@@ -196,6 +277,8 @@ impl Texture {
             width,
             height,
             depth,
+            format: sc_desc.format,
+            sample_count: sample_count,
         }
     }
 
@@ -267,7 +350,8 @@ impl Texture {
                             sc_desc: &wgpu::SurfaceConfiguration,
                             sample_count: u32,
                             width: u32,
-                            height: u32) -> Self {
+                            height: u32,
+                            label: Option<&str>) -> Self {
 
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::MirrorRepeat,
@@ -296,7 +380,7 @@ impl Texture {
             format: sc_desc.format, //wgpu::TextureFormat::Rgba8UnormSrgb,
             usage: wgpu::TextureUsages::TEXTURE_BINDING |
                    wgpu::TextureUsages::COPY_DST,
-            label: None,
+            label: label,
         });
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor {
@@ -323,7 +407,9 @@ impl Texture {
             sampler,
             width,
             height,
-            depth, 
+            depth,
+            format: sc_desc.format,
+            sample_count: sample_count,
         }
     }
 
@@ -381,6 +467,8 @@ impl Texture {
             width,
             height,
             depth,
+            format: *format,
+            sample_count: 1,
         }
     }
 
@@ -511,6 +599,8 @@ impl Texture {
             width,
             height,
             depth,
+            format: texture_format,
+            sample_count: 1,
         }
     }
 }
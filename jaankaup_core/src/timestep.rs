@@ -0,0 +1,219 @@
+/// The configuration of a fixed timestep.
+/// step: the simulation timestep in seconds.
+/// max_frame_time: the longest frame time (seconds) that is simulated. Longer frames (e.g. after
+/// a breakpoint or window dragging) are clamped so the simulation doesn't spiral.
+#[derive(Clone, Copy, Debug)]
+pub struct TimestepConfig {
+    pub step: f32,
+    pub max_frame_time: f32,
+}
+
+impl Default for TimestepConfig {
+    fn default() -> Self {
+        Self {
+            step: 1.0 / 60.0,
+            max_frame_time: 0.25,
+        }
+    }
+}
+
+/// A fixed timestep accumulator. The frame time is accumulated and consumed in fixed steps. The
+/// remainder is the interpolation alpha between the two latest simulation states. The time is
+/// given by the caller (nanoseconds, e.g. InputCache::get_time), so the accumulator can be driven
+/// by a manual clock in tests.
+#[derive(Clone, Debug)]
+pub struct FixedTimestep {
+    step: u128,
+    max_frame_time: u128,
+    accumulator: u128,
+    previous_time: Option<u128>,
+    paused: bool,
+    pending_single_steps: u32,
+    total_steps: u64,
+}
+
+impl FixedTimestep {
+
+    pub fn init(config: &TimestepConfig) -> Self {
+
+        assert!(config.step > 0.0, "{}", format!("step == {} > 0.0", config.step));
+        assert!(config.max_frame_time >= config.step, "{}", format!("max_frame_time == {} >= step == {}", config.max_frame_time, config.step));
+
+        Self {
+            step: (config.step as f64 * 1000000000.0).round() as u128,
+            max_frame_time: (config.max_frame_time as f64 * 1000000000.0).round() as u128,
+            accumulator: 0,
+            previous_time: None,
+            paused: false,
+            pending_single_steps: 0,
+            total_steps: 0,
+        }
+    }
+
+    /// Accumulate the time since the previous call and return the number of fixed steps to run
+    /// now. While paused only the single steps are returned.
+    pub fn advance(&mut self, time_now: u128) -> u32 {
+
+        let frame_time = match self.previous_time {
+            Some(previous) => time_now.saturating_sub(previous).min(self.max_frame_time),
+            None => 0,
+        };
+        self.previous_time = Some(time_now);
+
+        let steps = if self.paused {
+            std::mem::replace(&mut self.pending_single_steps, 0)
+        }
+        else {
+            self.accumulator += frame_time;
+            let steps = self.accumulator / self.step;
+            self.accumulator -= steps * self.step;
+            steps as u32
+        };
+
+        self.total_steps += steps as u64;
+        steps
+    }
+
+    /// The fraction of the next step that is already accumulated. Use this to interpolate between
+    /// the previous and the current simulation state when rendering.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator as f64 / self.step as f64) as f32
+    }
+
+    /// The timestep in seconds.
+    pub fn get_step(&self) -> f32 {
+        (self.step as f64 / 1000000000.0) as f32
+    }
+
+    /// The timestep in nanoseconds.
+    pub fn get_step_nanos(&self) -> u128 {
+        self.step
+    }
+
+    /// The number of steps since the start.
+    pub fn get_total_steps(&self) -> u64 {
+        self.total_steps
+    }
+
+    /// Stop the simulation. The accumulated time is kept.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.pending_single_steps = 0;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        if self.paused { self.resume(); } else { self.pause(); }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Run one step on the next advance while paused. Does nothing if the simulation is running.
+    pub fn single_step(&mut self) {
+        if self.paused { self.pending_single_steps += 1; }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u128 = 1000000;
+
+    /// A 10 ms step with a 50 ms max frame time.
+    fn timestep() -> FixedTimestep {
+        FixedTimestep::init(&TimestepConfig { step: 0.01, max_frame_time: 0.05 })
+    }
+
+    #[test]
+    fn accumulates_the_frame_time() {
+        let mut ts = timestep();
+        assert_eq!(ts.get_step_nanos(), 10 * MS);
+
+        // The first call only starts the clock.
+        assert_eq!(ts.advance(1000 * MS), 0);
+        assert_eq!(ts.advance(1004 * MS), 0);
+        assert_eq!(ts.advance(1012 * MS), 1);
+        assert_eq!(ts.advance(1035 * MS), 2);
+        assert_eq!(ts.advance(1040 * MS), 1);
+        assert_eq!(ts.get_total_steps(), 4);
+
+        // A manual clock advanced by one step per frame runs one step per frame.
+        for i in 1..100 {
+            assert_eq!(ts.advance(1040 * MS + i * ts.get_step_nanos()), 1);
+        }
+    }
+
+    #[test]
+    fn alpha_is_the_accumulated_fraction() {
+        let mut ts = timestep();
+        ts.advance(0);
+        assert_eq!(ts.alpha(), 0.0);
+        ts.advance(5 * MS);
+        assert!((ts.alpha() - 0.5).abs() < 1e-6);
+        assert_eq!(ts.advance(17 * MS), 1);
+        assert!((ts.alpha() - 0.7).abs() < 1e-6);
+        assert!(ts.alpha() < 1.0);
+    }
+
+    #[test]
+    fn long_frames_are_clamped() {
+        let mut ts = timestep();
+        ts.advance(0);
+        // A 2 s frame is simulated as 50 ms.
+        assert_eq!(ts.advance(2000 * MS), 5);
+        assert!(ts.alpha() < 1e-6);
+    }
+
+    #[test]
+    fn time_going_backwards_is_a_zero_frame() {
+        let mut ts = timestep();
+        ts.advance(100 * MS);
+        assert_eq!(ts.advance(50 * MS), 0);
+        assert_eq!(ts.alpha(), 0.0);
+        assert_eq!(ts.advance(60 * MS), 1);
+    }
+
+    #[test]
+    fn pause_and_single_step() {
+        let mut ts = timestep();
+        ts.advance(0);
+        assert_eq!(ts.advance(15 * MS), 1);
+        assert!((ts.alpha() - 0.5).abs() < 1e-6);
+
+        // Single steps are ignored while running.
+        ts.single_step();
+        assert_eq!(ts.advance(20 * MS), 1);
+
+        // The time doesn't accumulate while paused.
+        ts.toggle_pause();
+        assert!(ts.is_paused());
+        assert_eq!(ts.advance(100 * MS), 0);
+        assert_eq!(ts.alpha(), 0.0);
+
+        // Each single step runs exactly one step on the next advance.
+        ts.single_step();
+        ts.single_step();
+        assert_eq!(ts.advance(110 * MS), 2);
+        assert_eq!(ts.advance(120 * MS), 0);
+
+        // The pending single steps are dropped on resume and the paused time isn't simulated.
+        ts.single_step();
+        ts.toggle_pause();
+        assert!(!ts.is_paused());
+        assert_eq!(ts.advance(125 * MS), 0);
+        assert_eq!(ts.advance(130 * MS), 1);
+        assert_eq!(ts.get_total_steps(), 5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn max_frame_time_shorter_than_step() {
+        let _ = FixedTimestep::init(&TimestepConfig { step: 0.1, max_frame_time: 0.05 });
+    }
+}
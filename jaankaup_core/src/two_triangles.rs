@@ -2,97 +2,61 @@
 use std::borrow::Cow;
 use crate::buffer::*;
 use crate::texture as jaankaup;
-use crate::misc::create_vb_descriptor;
+use crate::render_pipelines::{RenderPipeline, RenderPipelineBuilder, ShaderBindings};
 
 /// Resources for rendering a single texture on the whole screen.
 pub struct TwoTriangles {
-    pipeline: wgpu::RenderPipeline,
+    pipeline: RenderPipeline,
     draw_buffer: wgpu::Buffer,
 }
 
 impl TwoTriangles {
 
-    /// Creates resources.
-    pub fn init(device: &wgpu::Device, sc_desc: &wgpu::SurfaceConfiguration) -> Self {
+    /// Creates resources. The sample_count must match the render targets given to draw.
+    pub fn init(device: &wgpu::Device, sc_desc: &wgpu::SurfaceConfiguration, sample_count: u32) -> Self {
         
         Self {
-            pipeline: TwoTriangles::create_pipeline(&device, &sc_desc),
+            pipeline: TwoTriangles::create_pipeline(&device, &sc_desc, sample_count),
             draw_buffer: TwoTriangles::create_screen_texture_buffer(&device),
         }
     }
 
-    /// Create a bind group for TwoTriangles using 2d texture.
-    pub fn create_bind_group(device: &wgpu::Device, texture: &jaankaup::Texture) -> wgpu::BindGroup {
+    /// Create a bind group for TwoTriangles using 2d texture. The bind group uses the layout of
+    /// the pipeline.
+    pub fn create_bind_group(&self, device: &wgpu::Device, texture: &jaankaup::Texture) -> Result<wgpu::BindGroup, String> {
 
-        let bind_group_layout = TwoTriangles::get_bind_group_layout(&device);
+        let mut bind_groups = self.pipeline.create_bind_groups(
+            &device,
+            &[((0, 0), wgpu::BindingResource::TextureView(&texture.view)),
+              ((0, 1), wgpu::BindingResource::Sampler(&texture.sampler))]
+        )?;
 
-        // Create bindings.
-        let bind_group_entry0 = wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&texture.view),
-        };
-
-        let bind_group_entry1 = wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::Sampler(&texture.sampler),
-        };
-
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
-            entries: &[bind_group_entry0, bind_group_entry1],
-            label: None,
-        });
-
-        bind_group
-    }
-
-    /// Creates the BindGroupLayout for TwoTriangles pipeline and bindgroup.
-    fn get_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
-
-        let bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler {
-                            filtering: true,
-                            comparison: false,
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("two_triangles_bind_group_layout"),
-        });
-        bind_group_layout
+        Ok(bind_groups.remove(0))
     }
 
+    /// Render the texture to view. If the pipeline is multisampled, view and depth_texture must
+    /// be multisampled and the result is resolved to resolve_target (e.g. the surface texture).
     pub fn draw(&self,
                 encoder: &mut wgpu::CommandEncoder,
                 //frame: &wgpu::SwapChainTexture,
-                frame: &wgpu::SurfaceTexture,
+                view: &wgpu::TextureView,
+                resolve_target: Option<&wgpu::TextureView>,
                 depth_texture: &jaankaup::Texture,
                 bind_group: &wgpu::BindGroup,
                 clear: bool) {
 
-        let view = frame.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        assert!(depth_texture.get_sample_count() == self.pipeline.get_sample_count(), "{}",
+                format!("TwoTriangles::draw: the depth texture has {} samples but the pipeline {}.", depth_texture.get_sample_count(), self.pipeline.get_sample_count()));
+        assert!(resolve_target.is_some() || self.pipeline.get_sample_count() == 1, "{}",
+                format!("TwoTriangles::draw: a multisampled ({}) pipeline needs a resolve target.", self.pipeline.get_sample_count()));
+
         let mut render_pass = encoder.begin_render_pass(
                 &wgpu::RenderPassDescriptor {
                     label: Some("two_triangles_rendes_pass_descriptor"),
                     color_attachments: &[
                         wgpu::RenderPassColorAttachment {
-                                view: &view,
-                                resolve_target: None,
+                                view: view,
+                                resolve_target: resolve_target,
                                 ops: wgpu::Operations {
                                     load: match clear {
                                         true => {
@@ -121,7 +85,7 @@ impl TwoTriangles {
                     }),
             });
 
-            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_pipeline(self.pipeline.get_pipeline());
             render_pass.set_bind_group(0, &bind_group, &[]);
 
             // Set vertex buffer.
@@ -145,89 +109,23 @@ impl TwoTriangles {
         (vert, frag)
     }
     
-    /// Create the pipeline for TwoTriangles.
-    fn create_pipeline(device: &wgpu::Device, sc_desc: &wgpu::SurfaceConfiguration) -> wgpu::RenderPipeline {
-    
-        // let (vs_module, fs_module) = TwoTriangles::load_shaders(&device);
-        
-        // Create bind group layout for pipeline.
-        let bind_group_layout = TwoTriangles::get_bind_group_layout(&device);
-        
-        // Create pipeline layout.
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("two_triangles_pipeline_layout"),
-            bind_group_layouts: &[&bind_group_layout],
-            push_constant_ranges: &[],
-        });
-        
-        // Create stride and vertex attribute descriptors.
-        let (stride, attributes) =  create_vb_descriptor(
-            &vec![wgpu::VertexFormat::Float32x4, wgpu::VertexFormat::Float32x4]
-        );
+    /// Create the pipeline for TwoTriangles. The layout is reflected from the shader.
+    fn create_pipeline(device: &wgpu::Device, sc_desc: &wgpu::SurfaceConfiguration, sample_count: u32) -> RenderPipeline {
 
+        let source = include_str!("../../shaders_wgsl/two_triangles.wgsl");
         let wgsl_module = &device.create_shader_module(&wgpu::ShaderModuleDescriptor {
             label: Some("two_triangles_wgsl_module"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../../shaders_wgsl/two_triangles.wgsl"))),
-        });
-        
-        // Create the pipeline.
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("two_triangles_pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &wgsl_module,
-                entry_point: "vs_main",
-                buffers: &[
-                    wgpu::VertexBufferLayout {
-                        array_stride: stride,
-                        step_mode: wgpu::VertexStepMode::Vertex, 
-                        attributes: &attributes,
-                    }],
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-                clamp_depth: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState {
-                    front: wgpu::StencilFaceState::IGNORE,
-                    back: wgpu::StencilFaceState::IGNORE,
-                    read_mask: 0,
-                    write_mask: 0,
-                },
-                bias: wgpu::DepthBiasState {
-                    constant: 0,
-                    slope_scale: 0.0,
-                    clamp: 0.0,
-                },
-                //clamp_depth: false,
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &wgsl_module,
-                entry_point: "fs_main",
-                targets: &[sc_desc.format.into()], // &[wgpu::ColorTargetState {
-                   //format: sc_desc.format,
-                   //alpha_blend: wgpu::BlendState::REPLACE,
-                   //color_blend: wgpu::BlendState::REPLACE, //REPLACE,
-                   //write_mask: wgpu::ColorWrite::COLOR,
-                },
-            ),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
         });
-
-        pipeline
+        let bindings = ShaderBindings::from_wgsl(source).unwrap();
+
+        RenderPipelineBuilder::init(&wgsl_module, sc_desc.format)
+            .label("two_triangles_pipeline")
+            .vertex_formats(&[wgpu::VertexFormat::Float32x4, wgpu::VertexFormat::Float32x4])
+            .cull_mode(Some(wgpu::Face::Back))
+            .bind_groups_from(&bindings)
+            .sample_count(sample_count)
+            .build(&device)
     }
     
     /// Creates a buffer for screen filling texture (two triangles).
@@ -0,0 +1,162 @@
+use std::borrow::Cow;
+use bytemuck::{Zeroable, Pod};
+use crate::buffer::buffer_from_data;
+use crate::compute::ComputeKernel;
+use crate::render_pipelines::ShaderBindings;
+
+/// Surface voxelization mode. A voxel is occupied if any of the triangles overlaps or touches it.
+pub const SURFACE: u32 = 0;
+
+/// Solid voxelization mode. A voxel is occupied if its center is inside the mesh.
+pub const SOLID: u32 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct VoxelUniform {
+    pub origin: cgmath::Vector4<f32>,
+    pub dimension: [u32; 4],
+    pub voxel_size: f32,
+    pub triangle_count: u32,
+    pub mode: u32,
+    pub future_usage: u32,
+}
+
+unsafe impl Pod for VoxelUniform {}
+unsafe impl Zeroable for VoxelUniform {}
+
+/// Uniform data for voxelization (set=0, binding=0) and the voxel buffer (set=0, binding=2).
+/// The voxel buffer has one u32 per voxel in x, y, z order (0 == empty, 1 == occupied).
+pub struct VoxelParams {
+    params: VoxelUniform,
+    buffer: wgpu::Buffer,
+    pub voxel_buffer: wgpu::Buffer,
+}
+
+impl VoxelParams {
+
+    /// Create an instance of VoxelParams. The voxel (x, y, z) covers the cube
+    /// [origin + voxel_size * (x, y, z), origin + voxel_size * (x + 1, y + 1, z + 1)].
+    pub fn init(device: &wgpu::Device,
+                origin: &cgmath::Vector3<f32>,
+                dimension: [u32; 3],
+                voxel_size: f32,
+                triangle_count: u32,
+                mode: u32) -> Self {
+
+        assert!(voxel_size > 0.0, "{}", format!("voxel_size == {} > 0.0", voxel_size));
+        assert!(mode == SURFACE || mode == SOLID, "{}", format!("mode == {} is not SURFACE or SOLID", mode));
+
+        let uniform = VoxelUniform {
+            origin: origin.extend(1.0),
+            dimension: [dimension[0], dimension[1], dimension[2], 0],
+            voxel_size: voxel_size,
+            triangle_count: triangle_count,
+            mode: mode,
+            future_usage: 0,
+        };
+
+        let voxel_count = (dimension[0] * dimension[1] * dimension[2]) as usize;
+
+        Self {
+            params: uniform,
+            buffer: buffer_from_data::<VoxelUniform>(
+                &device,
+                &[uniform],
+                wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+                None),
+            voxel_buffer: buffer_from_data::<u32>(
+                &device,
+                &vec![0 as u32 ; voxel_count],
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+                None),
+        }
+    }
+
+    pub fn get_uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn get_params(&self) -> &VoxelUniform {
+        &self.params
+    }
+
+    /// The number of voxels.
+    pub fn get_voxel_count(&self) -> u32 {
+        self.params.dimension[0] * self.params.dimension[1] * self.params.dimension[2]
+    }
+
+    /// Clear the voxel buffer. This must be done before solid voxelization.
+    pub fn reset_voxels(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.voxel_buffer,
+            0,
+            bytemuck::cast_slice(&vec![0 as u32 ; self.get_voxel_count() as usize])
+        );
+    }
+
+    /// Updates the triangle count and the voxelization mode and updates the buffer.
+    pub fn update_params(&mut self, queue: &wgpu::Queue, triangle_count: &Option<u32>, mode: &Option<u32>) {
+
+        if let Some(count) = *triangle_count {
+            self.params.triangle_count = count;
+        }
+        if let Some(m) = *mode {
+            assert!(m == SURFACE || m == SOLID, "{}", format!("mode == {} is not SURFACE or SOLID", m));
+            self.params.mode = m;
+        }
+
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[self.params])
+        );
+    }
+}
+
+/// Mesh voxelization on the gpu. The triangles are Triangle_vvvvnnnn. The same algorithms as in
+/// cpu_version::voxelize::VoxelGrid.
+pub struct Voxelizer {
+    kernel: ComputeKernel,
+    bindings: ShaderBindings,
+}
+
+impl Voxelizer {
+
+    pub fn init(device: &wgpu::Device) -> Self {
+
+        let source = include_str!("../../shaders_wgsl/voxelize.wgsl");
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("voxelize.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+        });
+        let bindings = ShaderBindings::from_wgsl(source).unwrap();
+
+        Self {
+            kernel: ComputeKernel::from_bindings(&device, &module, "main", &bindings, Some("voxelizer")).unwrap(),
+            bindings: bindings,
+        }
+    }
+
+    /// Creates the bind groups for voxelization.
+    pub fn create_bind_groups(&self,
+                              device: &wgpu::Device,
+                              params: &VoxelParams,
+                              triangle_buffer: &wgpu::Buffer) -> Result<Vec<wgpu::BindGroup>, String> {
+
+        let resources = self.bindings.resolve(
+            &[("params", params.get_uniform_buffer().as_entire_binding()),
+              ("triangles", triangle_buffer.as_entire_binding()),
+              ("voxels", params.voxel_buffer.as_entire_binding())]
+        )?;
+        self.kernel.create_bind_groups(&device, &resources)
+    }
+
+    /// Voxelize triangle_count triangles. One invocation per triangle.
+    pub fn dispatch(&self,
+                    bind_groups: &Vec<wgpu::BindGroup>,
+                    encoder: &mut wgpu::CommandEncoder,
+                    triangle_count: u32) {
+
+        self.kernel.dispatch_items(bind_groups, encoder, [triangle_count, 1, 1])
+    }
+}
@@ -17,7 +17,11 @@ use winit::{
 };
 pub use winit::event::VirtualKeyCode as Key;
 
-use crate::input::InputCache;
+use crate::input::{InputCache, Clock};
+use crate::timestep::{TimestepConfig, FixedTimestep};
+use crate::actions::ActionMap;
+use crate::gamepad::default_backend;
+use crate::input_record::{InputRecording, InputReplay};
 
 /// A trait for wgpu-rs based application.
 pub trait Application: Sized + 'static {
@@ -41,6 +45,34 @@ pub trait Application: Sized + 'static {
 
     /// A function for updating the state of the application.
     fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, input: &InputCache);
+
+    /// The actions of the application. The loop uses the "quit", "pause" and "single_step"
+    /// actions if the application defines them.
+    fn actions(&self) -> Option<&ActionMap> {
+        None
+    }
+
+    /// The fixed simulation timestep. If Some, the loop calls fixed_update with this timestep.
+    fn timestep(&self) -> Option<TimestepConfig> {
+        None
+    }
+
+    /// The time source of the loop. None uses the real time clock. A manual clock is advanced by
+    /// one timestep (or MANUAL_FRAME_TIME without a timestep) per frame, so the simulation doesn't
+    /// depend on the frame rate. Ignored during a replay.
+    fn clock(&self) -> Option<Clock> {
+        None
+    }
+
+    /// A function for updating the simulation with a fixed timestep (seconds). Called zero or
+    /// more times per frame before update.
+    fn fixed_update(&mut self, _device: &wgpu::Device, _queue: &wgpu::Queue, _input: &InputCache, _dt: f32) {
+    }
+
+    /// Called once per frame after the fixed updates. alpha in [0, 1[ is the accumulated fraction
+    /// of the next step, for interpolating between the two latest simulation states.
+    fn interpolate(&mut self, _alpha: f32) {
+    }
 }
 
 /// A trait for Loops.
@@ -85,13 +117,35 @@ pub trait WGPUFeatures: Sized + 'static {
     }
 }
 
-/// A basic loop.
-pub struct BasicLoop { }
+/// The default actions of the loop. pause and single_step control the fixed timestep.
+pub const DEFAULT_LOOP_ACTIONS: &str = "
+button quit = Q
+button pause = Pause
+button single_step = F10
+";
+
+/// The environment variable for the input recording file. If set, BasicLoop records the input
+/// events and saves them to the file when the loop ends.
+pub const RECORD_INPUT_VAR: &str = "JAANKAUP_RECORD_INPUT";
+
+/// The environment variable for the input replay file. If set, BasicLoop ignores the user input
+/// and replays the recorded input one tick per frame. The loop ends with the recording.
+pub const REPLAY_INPUT_VAR: &str = "JAANKAUP_REPLAY_INPUT";
+
+/// The frame time (nanoseconds) of a manual clock if the application has no timestep.
+pub const MANUAL_FRAME_TIME: u128 = 16666667;
+
+/// A basic loop. Calls input, the fixed updates, interpolate and update once per frame.
+pub struct BasicLoop {
+    actions: ActionMap,
+}
 
 impl Loop for BasicLoop {
 
     fn init() -> Self {
-        BasicLoop {}
+        BasicLoop {
+            actions: ActionMap::parse(DEFAULT_LOOP_ACTIONS).unwrap(),
+        }
     }
 
     fn run<A: Application>(&self, mut application: A, WGPUConfiguration {
@@ -109,7 +163,34 @@ impl Loop for BasicLoop {
 
     let spawner = Spawner::new();
 
-    let mut input = InputCache::init();
+    let default_actions = self.actions.clone();
+    let mut gamepad_backend = default_backend();
+
+    let record_file = std::env::var(RECORD_INPUT_VAR).ok();
+    let mut replay = std::env::var(REPLAY_INPUT_VAR).ok().and_then(|file| {
+        match InputRecording::load(&file) {
+            Ok(recording) => Some(InputReplay::init(recording)),
+            Err(e) => {
+                log::error!("Failed to load the input recording, using the live input: {}", e);
+                None
+            }
+        }
+    });
+
+    let mut input = match (&replay, application.clock()) {
+        (Some(r), _) => r.create_input_cache(),
+        (None, Some(clock)) => InputCache::init_with_clock(clock),
+        (None, None) => InputCache::init(),
+    };
+    if record_file.is_some() { input.start_recording(); }
+
+    let mut timestep = application.timestep().map(|config| FixedTimestep::init(&config));
+
+    // The time that a manual clock is advanced per frame.
+    let manual_frame_time = match (&replay, input.get_clock_mut()) {
+        (None, Clock::Manual(_)) => Some(timestep.as_ref().map_or(MANUAL_FRAME_TIME, |ts| ts.get_step_nanos())),
+        _ => None,
+    };
 
     // Launch the loop.
     event_loop.run(move |event, _, control_flow| {
@@ -126,6 +207,12 @@ impl Loop for BasicLoop {
                 &mut sc_desc,
                 &mut application,
                 &mut input,
+                &default_actions,
+                &mut gamepad_backend,
+                &record_file,
+                &mut replay,
+                &mut timestep,
+                &manual_frame_time,
                 &spawner);
 
         *control_flow = ControlFlow::Poll;
@@ -142,15 +229,39 @@ impl Loop for BasicLoop {
 
             Event::LoopDestroyed => {
                 // TODO: call clean up code. 
+                if let (Some(file), Some(recording)) = (&record_file, input.stop_recording()) {
+                    match recording.save(file) {
+                        Ok(_) => log::info!("Input recording saved to {}.", file),
+                        Err(e) => log::error!("Failed to save the input recording: {}", e),
+                    }
+                }
             }
 
             //Event::MainEventsCleared => {
             Event::MainEventsCleared => {
                 //log::info!("MainEventsCleared....");
+                match replay.as_mut() {
+                    Some(r) => {
+                        if !r.next_tick(&mut input) { *control_flow = ControlFlow::Exit; return; }
+                    }
+                    None => input.update_gamepads(gamepad_backend.as_mut()),
+                }
                 application.input(&queue, &input);
+                if let Some(ts) = timestep.as_mut() {
+                    let actions = loop_actions(&application, &default_actions, "pause");
+                    if actions.is_pressed(&input, "pause") { ts.toggle_pause(); }
+                    let actions = loop_actions(&application, &default_actions, "single_step");
+                    if actions.is_pressed(&input, "single_step") { ts.single_step(); }
+
+                    for _ in 0..ts.advance(input.get_time()) {
+                        application.fixed_update(&device, &queue, &input, ts.get_step());
+                    }
+                    application.interpolate(ts.alpha());
+                }
                 application.update(&device, &queue, &input);
                 window.request_redraw();
-                input.pre_update();
+                if let Some(frame_time) = manual_frame_time { input.get_clock_mut().advance(frame_time); }
+                if replay.is_none() { input.pre_update(); }
             }
             Event::RedrawEventsCleared => {
                 //log::info!("RedrawEventsCleared....");
@@ -164,14 +275,14 @@ impl Loop for BasicLoop {
 
                 //#[cfg(target_arch = "wasm32")]
                 //window.request_redraw();
-                let close_application = input.key_state(&Key::Q);
-                if !close_application.is_none() {
+                let actions = loop_actions(&application, &default_actions, "quit");
+                if actions.is_down(&input, "quit") {
                     *control_flow = ControlFlow::Exit;
                 }
             }
             Event::WindowEvent { event, ..} => {
-                // Update input cache.
-                input.update(&event);
+                // Update input cache. The user input is ignored during a replay.
+                if replay.is_none() { input.update(&event); }
 
                 match event {
                     WindowEvent::Resized(new_size) => {
@@ -201,6 +312,14 @@ impl Loop for BasicLoop {
 }
 
 
+/// The actions of the application if it defines the action name, otherwise the default actions.
+fn loop_actions<'a, A: Application>(application: &'a A, default_actions: &'a ActionMap, name: &str) -> &'a ActionMap {
+    match application.actions() {
+        Some(a) if a.get_action(name).is_some() => a,
+        _ => default_actions,
+    }
+}
+
 /// Initializes wgpu-rs system. TODO: finish the Result<...>.
 pub async fn setup<P: WGPUFeatures>(title: &str) -> Result<WGPUConfiguration, &'static str> {
 
@@ -213,6 +332,7 @@ pub async fn setup<P: WGPUFeatures>(title: &str) -> Result<WGPUConfiguration, &'
         .with_level(LevelFilter::Off)
         .with_module_level("jaankaup", LevelFilter::Info)
         .with_module_level("hello_project", LevelFilter::Info)
+        .with_module_level("fmm_project", LevelFilter::Info)
         //.with_module_level("wgpu", LevelFilter::Info)
         .init()
         .unwrap();
@@ -0,0 +1,34 @@
+use geometry::mesh::Mesh;
+use geometry::simplify::simplify;
+use model_loader::load_triangles_from_obj;
+use simple_logger::SimpleLogger;
+
+/// Simplify the bundled models to a quarter of the triangles.
+/// Run from the workspace root: cargo run -p model_loader --example simplify
+fn main() {
+
+    SimpleLogger::new().with_level(log::LevelFilter::Info).init().unwrap();
+
+    let models = ["assets/models/wood.obj",
+                  "assets/models/bunny.obj",
+                  "assets/models/rock1.obj",
+                  "assets/models/house.obj"];
+
+    for file_name in models.iter() {
+
+        let (triangles, triangles_vvvvnnnn, _) = load_triangles_from_obj(*file_name, 1.0, [0.0, 0.0, 0.0], None).unwrap();
+
+        // The loader returns the triangles with normals only if the model has normals.
+        let mesh = if triangles_vvvvnnnn.is_empty() { Mesh::from_triangles(&triangles) }
+                   else { Mesh::from_triangles_vvvvnnnn(&triangles_vvvvnnnn) };
+
+        let simplified = simplify(&mesh, mesh.triangle_count() / 4, std::f32::MAX);
+
+        log::info!("{}: {} triangles ({} vertices) -> {} triangles ({} vertices)",
+                 file_name,
+                 mesh.triangle_count(),
+                 mesh.positions.len(),
+                 simplified.triangle_count(),
+                 simplified.positions.len());
+    }
+}
@@ -3,7 +3,8 @@ use std::fs::File;
 use std::io::BufReader;
 use wavefront_obj::obj::*;
 use cgmath::{Vector3, Vector4};
-use geometry::aabb::{BBox, Triangle, Triangle_vvvvnnnn};
+use geometry::aabb::BBox;
+use geometry::triangle::{Triangle, Triangle_vvvvnnnn};
 
 pub fn load_triangles_from_obj(file_name: &'static str,
                                scale_factor: f32,
@@ -20,7 +21,7 @@ pub fn load_triangles_from_obj(file_name: &'static str,
     let obj_set = parse(file_content).map_err(|e| format!("cannot parse: {:?}", e)).unwrap();
     let objects = obj_set.objects;
 
-    let mut aabb = BBox { min: Vector3::<f32>::new(0.0, 0.0, 0.0), max: Vector3::<f32>::new(0.0, 0.0, 0.0), };
+    let mut aabb = BBox::empty();
     let mut result: Vec<Triangle> = Vec::new();
     let mut result_vvvvnnnn: Vec<Triangle_vvvvnnnn> = Vec::new();
 
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use cgmath::{prelude::*, Vector3};
+use geometry::aabb::BBox;
+use geometry::mesh::Mesh;
+use geometry::simplify::simplify;
+use model_loader::load_triangles_from_obj;
+
+// The tests are run in the model_loader directory.
+
+fn load(file_name: &'static str) -> Mesh {
+    let (triangles, triangles_vvvvnnnn, _) = load_triangles_from_obj(file_name, 1.0, [0.0, 0.0, 0.0], None).unwrap();
+    if triangles_vvvvnnnn.is_empty() { Mesh::from_triangles(&triangles) }
+    else { Mesh::from_triangles_vvvvnnnn(&triangles_vvvvnnnn) }
+}
+
+fn key(p: &Vector3<f32>) -> [u32; 3] {
+    [(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()]
+}
+
+/// The edges that have only one triangle. The vertices are compared by position, so the hard
+/// edges aren't boundaries.
+fn boundary_edges(mesh: &Mesh) -> Vec<([u32; 3], [u32; 3])> {
+    let mut edges: HashMap<([u32; 3], [u32; 3]), u32> = HashMap::new();
+    for t in mesh.indices.chunks(3) {
+        for i in 0..3 {
+            let a = key(&mesh.positions[t[i] as usize]);
+            let b = key(&mesh.positions[t[(i + 1) % 3] as usize]);
+            *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+    let mut result: Vec<([u32; 3], [u32; 3])> = edges.iter().filter(|(_, count)| **count == 1).map(|(e, _)| *e).collect();
+    result.sort();
+    result
+}
+
+#[test]
+fn simplify_to_target_triangle_count() {
+    let mesh = load("../assets/models/bunny.obj");
+    let target = mesh.triangle_count() / 4;
+    let simplified = simplify(&mesh, target, std::f32::MAX);
+
+    // A collapse removes one or two triangles.
+    let count = simplified.triangle_count();
+    assert!(count <= target && count + 1 >= target, "{}", format!("{} triangles, the target was {}.", count, target));
+    assert_eq!(simplified.positions.len(), simplified.normals.len());
+
+    // The shape stays in place.
+    let original = BBox::from_points(mesh.positions.iter().cloned());
+    let bbox = BBox::from_points(simplified.positions.iter().cloned());
+    let tolerance = 0.01 * original.extent().magnitude();
+    assert!((bbox.min - original.min).magnitude() < tolerance && (bbox.max - original.max).magnitude() < tolerance,
+            "{}", format!("The bbox {:?} differs from the original {:?}.", bbox, original));
+}
+
+#[test]
+fn simplify_keeps_boundary_edges() {
+    let mesh = load("../assets/models/bunny.obj");
+    let simplified = simplify(&mesh, mesh.triangle_count() / 4, std::f32::MAX);
+
+    let original_boundary = boundary_edges(&mesh);
+    assert!(!original_boundary.is_empty(), "The bunny should have holes.");
+    assert_eq!(boundary_edges(&simplified), original_boundary);
+}
+
+#[test]
+fn simplify_keeps_hard_edges() {
+    // The bunny has a few edges sharper than the crease angle of Mesh::from_triangles.
+    let mesh = load("../assets/models/bunny.obj");
+    let simplified = simplify(&mesh, mesh.triangle_count() / 4, std::f32::MAX);
+
+    // The vertices at the same position (hard edges) keep their positions and normals.
+    let mut counts: HashMap<[u32; 3], u32> = HashMap::new();
+    for p in mesh.positions.iter() { *counts.entry(key(p)).or_insert(0) += 1; }
+    assert!(counts.values().any(|c| *c > 1), "The bunny should have hard edges.");
+
+    let vertices: Vec<([u32; 3], Vector3<f32>)> = simplified.positions.iter().zip(simplified.normals.iter())
+        .map(|(p, n)| (key(p), *n))
+        .collect();
+
+    for (p, n) in mesh.positions.iter().zip(mesh.normals.iter()) {
+        if counts[&key(p)] < 2 { continue; }
+        assert!(vertices.iter().any(|(k, m)| *k == key(p) && (m - n).magnitude() < 1e-5),
+                "{}", format!("The hard edge vertex {:?} with normal {:?} has been removed.", p, n));
+    }
+}
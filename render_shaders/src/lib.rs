@@ -1,303 +1,201 @@
+pub mod pbr; 
+
+use std::borrow::Cow;
 use jaankaup_core::wgpu;
+use jaankaup_core::resources::ResourceRegistry;
+use jaankaup_core::shadow::{ShadowMap, shadow_bindings};
 use jaankaup_core::render_pipelines::{
-    draw,
-    create_bind_groups,
-    TestLayoutEntry,
-    check_correspondence,
+    RenderPipeline,
+    RenderPipelineBuilder,
+    ShaderBindings,
 };
-use jaankaup_core::misc::create_vb_descriptor;
 
-/// A pipeline/layout entries for render shaders that only consume vvvvnnnn data.
+/// A pipeline/layout entries for render shaders that only consume vvvvnnnn data. The layout is
+/// reflected from the wgsl source. With shadows the source must have the fs_main_shadow entry point
+/// and the shadow bindings in set 1, e.g. shadow::with_shadows(renderer_v4n4_plain.wgsl, 1).
 pub struct Render_vvvvnnnn {
-    layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>, 
-    bind_group_layouts: Vec<wgpu::BindGroupLayout>, 
-    pipeline: wgpu::RenderPipeline,
+    pipeline: RenderPipeline,
+    shadows: bool,
 }
 
 impl Render_vvvvnnnn {
 
     pub fn get_pipeline(&self) -> &wgpu::RenderPipeline {
-        &self.pipeline
+        self.pipeline.get_pipeline()
     }
 
     pub fn get_bind_group_layouts(&self) -> &Vec<wgpu::BindGroupLayout> {
-        &self.bind_group_layouts
+        self.pipeline.get_bind_group_layouts()
     }
 
     pub fn get_bind_group_layout_entries(&self) -> &Vec<Vec<wgpu::BindGroupLayoutEntry>> {
-        &self.layout_entries
+        self.pipeline.get_bind_group_layout_entries()
     }
 
-    pub fn create_bind_groups(&self, device: &wgpu::Device, camera_buffer: &wgpu::Buffer) -> Vec<wgpu::BindGroup> {
-        create_bind_groups(
-             &device, 
-             &self.layout_entries,
-             &vec![
-                 vec![
-                    &camera_buffer.as_entire_binding()
-                 ]
-             ]
-        )
+    pub fn get_sample_count(&self) -> u32 {
+        self.pipeline.get_sample_count()
+    }
+
+    pub fn has_shadows(&self) -> bool {
+        self.shadows
+    }
+
+    /// The shadow map and the registry of its texture must be given if and only if the pipeline was
+    /// created with shadows.
+    pub fn create_bind_groups(&self, device: &wgpu::Device, camera_buffer: &wgpu::Buffer, shadow_map: Option<(&ShadowMap, &ResourceRegistry)>) -> Result<Vec<wgpu::BindGroup>, String> {
+
+        if shadow_map.is_some() != self.shadows {
+            return Err(format!("Render_vvvvnnnn::create_bind_groups: shadows == {} but shadow_map.is_some() == {}.", self.shadows, shadow_map.is_some()));
+        }
+
+        let mut bindings = vec![((0, 0), camera_buffer.as_entire_binding())];
+        if let Some((shadow_map, resources)) = shadow_map {
+            bindings.extend(shadow_bindings(shadow_map, resources, 1));
+        }
+        self.pipeline.create_bind_groups(&device, &bindings)
     }
 
     pub fn init(device: &wgpu::Device,
                 sc_desc: &wgpu::SurfaceConfiguration,               
-                wgsl_module: &wgpu::ShaderModule,
-                //vs_module: &wgpu::ShaderModule,
-                //fs_module: &wgpu::ShaderModule,
+                sample_count: u32,
+                wgsl_source: &str,
+                shadows: bool,
                 ) -> Self {
 
-        // Camera uniform.
-        let layout_entries = vec![
-                // Set 0
-                vec![wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                            },
-                        count: None,
-                    },
-                ],
-        ];
-
-        let (stride, attributes) =  create_vb_descriptor(
-            &vec![wgpu::VertexFormat::Float32x4, wgpu::VertexFormat::Float32x4]
-        );
-
-        let mut bind_group_layouts: Vec<wgpu::BindGroupLayout> = Vec::new();
-
-        for ve in layout_entries.iter() {
-            bind_group_layouts.push(
-                device.create_bind_group_layout(
-                    &wgpu::BindGroupLayoutDescriptor {
-                        entries: &ve,
-                        label: None,
-                    }
-                )
-            );
-        }
-
-        // Create pipeline layout.
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
-            push_constant_ranges: &[],
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("vvvvnnnn_module"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(wgsl_source)),
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &wgsl_module,
-                entry_point: "vs_main",
-                buffers: &[
-                    wgpu::VertexBufferLayout {
-                        array_stride: stride,
-                        step_mode: wgpu::VertexStepMode::Vertex, 
-                        attributes: &attributes,
-                    }],
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None, //Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-                clamp_depth: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState {
-                    front: wgpu::StencilFaceState::IGNORE,
-                    back: wgpu::StencilFaceState::IGNORE,
-                    read_mask: 0,
-                    write_mask: 0,
-                },
-                bias: wgpu::DepthBiasState {
-                    constant: 0,
-                    slope_scale: 0.0,
-                    clamp: 0.0,
-                },
-                //clamp_depth: false,
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &wgsl_module,
-                entry_point: "fs_main",
-                targets: &[sc_desc.format.into()] // &[wgpu::ColorTargetState {
-                //   format: sc_desc.format,
-                //   alpha_blend: wgpu::BlendState::REPLACE,
-                //   color_blend: wgpu::BlendState::REPLACE,
-                //   write_mask: wgpu::ColorWrite::COLOR,
-                //}],
-                //targets: &[wgpu::ColorTargetState {
-                //   format: sc_desc.format,
-                //   alpha_blend: wgpu::BlendState::REPLACE,
-                //   color_blend: wgpu::BlendState::REPLACE,
-                //   write_mask: wgpu::ColorWrite::COLOR,
-                //}],
-            }),
-        });
+        // The shadow bindings are only used by fs_main_shadow.
+        let fs_entry_point = if shadows { "fs_main_shadow" } else { "fs_main" };
+        let bindings = ShaderBindings::from_wgsl_entry_points(wgsl_source, &["vs_main", fs_entry_point]).unwrap();
+
+        let pipeline = RenderPipelineBuilder::init(&module, sc_desc.format)
+            .entry_points("vs_main", Some(fs_entry_point))
+            .vertex_formats(&[wgpu::VertexFormat::Float32x4, wgpu::VertexFormat::Float32x4])
+            .bind_groups_from(&bindings)
+            .sample_count(sample_count)
+            .build(&device);
 
         Self {
-            layout_entries: layout_entries,
-            bind_group_layouts: bind_group_layouts,
             pipeline: pipeline,
+            shadows: shadows,
         }
     }
 }
 
-/// A pipeline/layout entries for rendering point data vvvc (c :: color).
+/// A pipeline/layout entries for rendering point data vvvc (c :: color). The layout is reflected
+/// from the wgsl source (see renderer_v3c1.wgsl).
 pub struct Render_vvvc {
-    layout_entries: Vec<Vec<wgpu::BindGroupLayoutEntry>>, 
-    bind_group_layouts: Vec<wgpu::BindGroupLayout>, 
-    pipeline: wgpu::RenderPipeline,
+    pipeline: RenderPipeline,
 }
 
 impl Render_vvvc {
 
     pub fn get_pipeline(&self) -> &wgpu::RenderPipeline {
-        &self.pipeline
+        self.pipeline.get_pipeline()
     }
 
     pub fn get_bind_group_layouts(&self) -> &Vec<wgpu::BindGroupLayout> {
-        &self.bind_group_layouts
+        self.pipeline.get_bind_group_layouts()
     }
 
     pub fn get_bind_group_layout_entries(&self) -> &Vec<Vec<wgpu::BindGroupLayoutEntry>> {
-        &self.layout_entries
+        self.pipeline.get_bind_group_layout_entries()
     }
 
-    pub fn create_bind_groups(&self, device: &wgpu::Device, camera_buffer: &wgpu::Buffer) -> Vec<wgpu::BindGroup> {
-        create_bind_groups(
-             &device, 
-             &self.layout_entries,
-             &vec![
-                 vec![
-                    &camera_buffer.as_entire_binding()
-                 ]
-             ]
+    pub fn get_sample_count(&self) -> u32 {
+        self.pipeline.get_sample_count()
+    }
+
+    pub fn create_bind_groups(&self, device: &wgpu::Device, camera_buffer: &wgpu::Buffer) -> Result<Vec<wgpu::BindGroup>, String> {
+        self.pipeline.create_bind_groups(
+             &device,
+             &[((0, 0), camera_buffer.as_entire_binding())]
         )
     }
 
     pub fn init(device: &wgpu::Device,
                 sc_desc: &wgpu::SurfaceConfiguration,               
-                wgsl_module: &wgpu::ShaderModule,
-                //vs_module: &wgpu::ShaderModule,
-                //fs_module: &wgpu::ShaderModule,
+                sample_count: u32,
+                wgsl_source: &str,
                 topology: wgpu::PrimitiveTopology,
                 ) -> Self {
 
-        // Camera uniform.
-        let layout_entries = vec![
-                // Set 0
-                vec![wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                            },
-                        count: None,
-                    },
-                ],
-        ];
-
-        let (stride, attributes) =  create_vb_descriptor(
-            &vec![wgpu::VertexFormat::Float32x3, wgpu::VertexFormat::Uint32]
-        );
-
-        let mut bind_group_layouts: Vec<wgpu::BindGroupLayout> = Vec::new();
-
-        for ve in layout_entries.iter() {
-            bind_group_layouts.push(
-                device.create_bind_group_layout(
-                    &wgpu::BindGroupLayoutDescriptor {
-                        entries: &ve,
-                        label: None,
-                    }
-                )
-            );
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("vvvc_module"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(wgsl_source)),
+        });
+        let bindings = ShaderBindings::from_wgsl(wgsl_source).unwrap();
+
+        let pipeline = RenderPipelineBuilder::init(&module, sc_desc.format)
+            .label("vvvc point")
+            .vertex_formats(&[wgpu::VertexFormat::Float32x3, wgpu::VertexFormat::Uint32])
+            .topology(topology)
+            .cull_mode(Some(wgpu::Face::Back))
+            .bind_groups_from(&bindings)
+            .sample_count(sample_count)
+            .build(&device);
+
+        Self {
+            pipeline: pipeline,
         }
+    }
+}
 
-        // Create pipeline layout.
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
-            push_constant_ranges: &[],
-        });
+/// A pipeline for drawing the edges of aabbs (BBox4) as instances (see aabb_lines.wgsl). The vertex
+/// buffer is the unit cube line list (BBox::to_lines) and the instance buffer has the aabb index of each instance,
+/// e.g. the visible buffer of the frustum culling. A highlight pipeline draws the edges with another color.
+pub struct Render_aabb_lines {
+    pipeline: RenderPipeline,
+    bindings: ShaderBindings,
+}
+
+impl Render_aabb_lines {
+
+    pub fn get_pipeline(&self) -> &wgpu::RenderPipeline {
+        self.pipeline.get_pipeline()
+    }
+
+    pub fn get_sample_count(&self) -> u32 {
+        self.pipeline.get_sample_count()
+    }
+
+    pub fn create_bind_groups(&self, device: &wgpu::Device, camera_buffer: &wgpu::Buffer, aabb_buffer: &wgpu::Buffer) -> Result<Vec<wgpu::BindGroup>, String> {
+        let resources = self.bindings.resolve(
+            &[("camerauniform", camera_buffer.as_entire_binding()),
+              ("aabbs", aabb_buffer.as_entire_binding())]
+        )?;
+        self.pipeline.create_bind_groups(&device, &resources)
+    }
+
+    pub fn init(device: &wgpu::Device,
+                sc_desc: &wgpu::SurfaceConfiguration,
+                sample_count: u32,
+                highlight: bool,
+                ) -> Self {
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("vvvc point"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &wgsl_module,
-                entry_point: "vs_main",
-                buffers: &[
-                    wgpu::VertexBufferLayout {
-                        array_stride: stride,
-                        step_mode: wgpu::VertexStepMode::Vertex, 
-                        attributes: &attributes,
-                    }],
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: topology, //wgpu::PrimitiveTopology::PointList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill, //Point,
-                conservative: false,
-                clamp_depth: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState {
-                    front: wgpu::StencilFaceState::IGNORE,
-                    back: wgpu::StencilFaceState::IGNORE,
-                    read_mask: 0,
-                    write_mask: 0,
-                },
-                bias: wgpu::DepthBiasState {
-                    constant: 0,
-                    slope_scale: 0.0,
-                    clamp: 0.0,
-                },
-                //clamp_depth: false,
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &wgsl_module,
-                entry_point: "fs_main",
-                targets: &[sc_desc.format.into()] // &[wgpu::ColorTargetState {
-                //   format: sc_desc.format,
-                //   alpha_blend: wgpu::BlendState::REPLACE,
-                //   color_blend: wgpu::BlendState::REPLACE,
-                //   write_mask: wgpu::ColorWrite::COLOR,
-                //}],
-            }),
+        let source = include_str!("../../shaders_wgsl/aabb_lines.wgsl");
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("aabb_lines.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
         });
+        let bindings = ShaderBindings::from_wgsl(source).unwrap();
+
+        let pipeline = RenderPipelineBuilder::init(&module, sc_desc.format)
+            .label("aabb lines")
+            .entry_points("vs_main", Some(if highlight { "fs_highlight" } else { "fs_main" }))
+            .vertex_formats(&[wgpu::VertexFormat::Float32x3])
+            .instance_formats(&[wgpu::VertexFormat::Uint32])
+            .topology(wgpu::PrimitiveTopology::LineList)
+            .bind_groups_from(&bindings)
+            .sample_count(sample_count)
+            .build(&device);
 
         Self {
-            layout_entries: layout_entries,
-            bind_group_layouts: bind_group_layouts,
             pipeline: pipeline,
+            bindings: bindings,
         }
     }
 }
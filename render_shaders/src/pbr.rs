@@ -0,0 +1,503 @@
+use std::borrow::Cow;
+use std::num::NonZeroU32;
+use bytemuck::{Zeroable, Pod};
+use jaankaup_core::wgpu;
+use jaankaup_core::buffer::buffer_from_data;
+use jaankaup_core::misc::Vertex_vvvvnnnn;
+use jaankaup_core::texture::Texture as JTexture;
+use jaankaup_core::resources::ResourceRegistry;
+use jaankaup_core::shadow::{ShadowMap, shadow_bindings, with_shadows};
+use jaankaup_core::render_pipelines::{
+    RenderPipeline,
+    RenderPipelineBuilder,
+    DrawCall,
+    ShaderBindings,
+};
+
+/// The maximum number of point lights in LightUniform.
+pub const MAX_POINT_LIGHTS: usize = 4;
+
+// The material flags. See pbr_v4n4.wgsl.
+const ALBEDO_TEXTURE: u32 = 1;
+const NORMAL_TEXTURE: u32 = 2;
+const ROUGHNESS_TEXTURE: u32 = 4;
+const ALBEDO_SRGB: u32 = 8;
+const NORMAL_SRGB: u32 = 16;
+const ROUGHNESS_SRGB: u32 = 32;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DirectionalLight {
+    /// The direction where the light travels (w is not used).
+    pub direction: [f32; 4],
+    /// rgb :: color, a :: intensity.
+    pub color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PointLight {
+    /// xyz :: position, w :: range. The light fades to zero at the range.
+    pub position: [f32; 4],
+    /// rgb :: color, a :: intensity.
+    pub color: [f32; 4],
+}
+
+unsafe impl Pod for DirectionalLight {}
+unsafe impl Zeroable for DirectionalLight {}
+unsafe impl Pod for PointLight {}
+unsafe impl Zeroable for PointLight {}
+
+/// The lights of the scene. The colors are linear and the result is multiplied by exposure before
+/// tonemapping.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct LightUniform {
+    pub directional: DirectionalLight,
+    pub point_lights: [PointLight; MAX_POINT_LIGHTS],
+    pub point_light_count: u32,
+    pub exposure: f32,
+    /// A constant ambient term multiplied by albedo.
+    pub ambient: f32,
+    pub future_usage1: f32,
+}
+
+unsafe impl Pod for LightUniform {}
+unsafe impl Zeroable for LightUniform {}
+
+impl LightUniform {
+
+    /// A white directional light from above and no point lights.
+    pub fn init() -> Self {
+        Self {
+            directional: DirectionalLight {
+                direction: [-0.3, -1.0, -0.4, 0.0],
+                color: [1.0, 0.98, 0.92, 3.0],
+            },
+            point_lights: [PointLight { position: [0.0, 0.0, 0.0, 1.0], color: [0.0, 0.0, 0.0, 0.0] }; MAX_POINT_LIGHTS],
+            point_light_count: 0,
+            exposure: 1.0,
+            ambient: 0.03,
+            future_usage1: 0.0,
+        }
+    }
+
+    /// Add a point light. Panics if there are already MAX_POINT_LIGHTS lights.
+    pub fn add_point_light(&mut self, light: PointLight) {
+        assert!((self.point_light_count as usize) < MAX_POINT_LIGHTS, "{}", format!("LightUniform::add_point_light: the maximum number of point lights is {}.", MAX_POINT_LIGHTS));
+        assert!(light.position[3] > 0.0, "LightUniform::add_point_light: the range must be > 0.");
+        self.point_lights[self.point_light_count as usize] = light;
+        self.point_light_count += 1;
+    }
+
+    pub fn clear_point_lights(&mut self) {
+        self.point_light_count = 0;
+    }
+}
+
+/// The light uniform and its buffer. Modify get_uniform_mut and call update to upload the changes.
+pub struct Lights {
+    uniform: LightUniform,
+    buffer: wgpu::Buffer,
+}
+
+impl Lights {
+
+    pub fn init(device: &wgpu::Device, uniform: LightUniform) -> Self {
+        Self {
+            uniform: uniform,
+            buffer: buffer_from_data::<LightUniform>(
+                device,
+                &[uniform],
+                wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                None),
+        }
+    }
+
+    pub fn get_uniform(&self) -> &LightUniform {
+        &self.uniform
+    }
+
+    pub fn get_uniform_mut(&mut self) -> &mut LightUniform {
+        &mut self.uniform
+    }
+
+    pub fn get_buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct MaterialUniform {
+    albedo: [f32; 4],
+    metallic: f32,
+    roughness: f32,
+    texture_scale: f32,
+    flags: u32,
+}
+
+unsafe impl Pod for MaterialUniform {}
+unsafe impl Zeroable for MaterialUniform {}
+
+/// A metallic-roughness material. The textures multiply the constant values and are mapped
+/// triplanarly (the vertices have no texture coordinates). The roughness texture follows glTF:
+/// roughness in green and metallic in blue. Textures with an sRGB format are handled in the
+/// shader, so both sRGB and linear textures can be used.
+pub struct PbrMaterial {
+    pub albedo: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    /// The number of texture repeats per world unit.
+    pub texture_scale: f32,
+    pub albedo_texture: Option<JTexture>,
+    pub normal_texture: Option<JTexture>,
+    pub roughness_texture: Option<JTexture>,
+    buffer: wgpu::Buffer,
+}
+
+impl PbrMaterial {
+
+    /// A material without textures. The values are uploaded by update.
+    pub fn init(device: &wgpu::Device, albedo: [f32; 4], metallic: f32, roughness: f32) -> Self {
+
+        assert!((0.0..=1.0).contains(&metallic), "{}", format!("PbrMaterial::init: metallic == {} isn't in range [0, 1].", metallic));
+        assert!((0.0..=1.0).contains(&roughness), "{}", format!("PbrMaterial::init: roughness == {} isn't in range [0, 1].", roughness));
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pbr material"),
+            size: std::mem::size_of::<MaterialUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            albedo: albedo,
+            metallic: metallic,
+            roughness: roughness,
+            texture_scale: 1.0,
+            albedo_texture: None,
+            normal_texture: None,
+            roughness_texture: None,
+            buffer: buffer,
+        }
+    }
+
+    pub fn with_albedo_texture(mut self, texture: JTexture) -> Self {
+        self.albedo_texture = Some(texture);
+        self
+    }
+
+    pub fn with_normal_texture(mut self, texture: JTexture) -> Self {
+        self.normal_texture = Some(texture);
+        self
+    }
+
+    pub fn with_roughness_texture(mut self, texture: JTexture) -> Self {
+        self.roughness_texture = Some(texture);
+        self
+    }
+
+    pub fn with_texture_scale(mut self, texture_scale: f32) -> Self {
+        self.texture_scale = texture_scale;
+        self
+    }
+
+    pub fn get_buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Upload the material values. Call after changing the fields. The bind groups must be
+    /// recreated if the textures change (see PbrPipeline::create_bind_groups).
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform()]));
+    }
+
+    fn uniform(&self) -> MaterialUniform {
+        let format = |texture: &Option<JTexture>| texture.as_ref().map(|t| t.get_format());
+        MaterialUniform {
+            albedo: self.albedo,
+            metallic: self.metallic,
+            roughness: self.roughness,
+            texture_scale: self.texture_scale,
+            flags: material_flags(format(&self.albedo_texture), format(&self.normal_texture), format(&self.roughness_texture)),
+        }
+    }
+}
+
+/// The material flags of the albedo, normal and roughness texture formats (None if there is no
+/// texture).
+fn material_flags(albedo: Option<wgpu::TextureFormat>,
+                  normal: Option<wgpu::TextureFormat>,
+                  roughness: Option<wgpu::TextureFormat>) -> u32 {
+    let flag = |format: Option<wgpu::TextureFormat>, present: u32, srgb: u32| -> u32 {
+        match format {
+            Some(f) if f.describe().srgb => present | srgb,
+            Some(_) => present,
+            None => 0,
+        }
+    };
+    flag(albedo, ALBEDO_TEXTURE, ALBEDO_SRGB) |
+    flag(normal, NORMAL_TEXTURE, NORMAL_SRGB) |
+    flag(roughness, ROUGHNESS_TEXTURE, ROUGHNESS_SRGB)
+}
+
+/// An indexed Vertex_vvvvnnnn mesh on the gpu.
+pub struct PbrMesh {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    index_count: u32,
+}
+
+impl PbrMesh {
+
+    pub fn init(device: &wgpu::Device, vertices: &[Vertex_vvvvnnnn], indices: &[u32]) -> Self {
+
+        assert!(indices.len() % 3 == 0, "{}", format!("PbrMesh::init: the index count {} isn't a multiple of 3.", indices.len()));
+
+        Self {
+            vertex_buffer: buffer_from_data::<Vertex_vvvvnnnn>(device, vertices, wgpu::BufferUsages::VERTEX, None),
+            index_buffer: buffer_from_data::<u32>(device, indices, wgpu::BufferUsages::INDEX, None),
+            index_count: indices.len() as u32,
+        }
+    }
+
+    pub fn get_vertex_buffer(&self) -> &wgpu::Buffer {
+        &self.vertex_buffer
+    }
+
+    pub fn get_index_buffer(&self) -> &wgpu::Buffer {
+        &self.index_buffer
+    }
+
+    pub fn get_index_count(&self) -> u32 {
+        self.index_count
+    }
+}
+
+/// A metallic-roughness render pipeline for Vertex_vvvvnnnn data (pbr_v4n4.wgsl). Group 0 has the
+/// camera and the lights, group 1 the material and group 2 the shadows if the pipeline samples a
+/// ShadowMap. The result is tonemapped with ACES and gamma corrected if the surface format isn't
+/// sRGB. Both sides of the triangles are rendered.
+///
+/// ```ignore
+/// let pbr = PbrPipeline::init(&device, &queue, &sc_desc, 1, false);
+/// let lights = Lights::init(&device, LightUniform::init());
+/// let material = PbrMaterial::init(&device, [0.8, 0.8, 0.8, 1.0], 0.0, 0.6)
+///     .with_albedo_texture(JTexture::create_from_bytes(&queue, &device, &sc_desc, 1, GRASS, None));
+/// material.update(&queue);
+/// let bind_groups = pbr.create_bind_groups(&device, camera.get_camera_uniform(&device), &lights, &material, None).unwrap();
+/// // In a render pass.
+/// pbr.draw_mesh(&mut pass, &bind_groups, &mesh);
+/// ```
+pub struct PbrPipeline {
+    pipeline: RenderPipeline,
+    default_texture: JTexture,
+    sampler: wgpu::Sampler,
+    shadows: bool,
+}
+
+impl PbrPipeline {
+
+    pub fn get_pipeline(&self) -> &wgpu::RenderPipeline {
+        self.pipeline.get_pipeline()
+    }
+
+    pub fn get_bind_group_layouts(&self) -> &Vec<wgpu::BindGroupLayout> {
+        self.pipeline.get_bind_group_layouts()
+    }
+
+    pub fn get_bind_group_layout_entries(&self) -> &Vec<Vec<wgpu::BindGroupLayoutEntry>> {
+        self.pipeline.get_bind_group_layout_entries()
+    }
+
+    pub fn get_sample_count(&self) -> u32 {
+        self.pipeline.get_sample_count()
+    }
+
+    pub fn has_shadows(&self) -> bool {
+        self.shadows
+    }
+
+    /// Create the bind groups. The missing textures of the material are replaced with a white
+    /// texture. The shadow map and the registry of its texture must be given if and only if the
+    /// pipeline was created with shadows.
+    pub fn create_bind_groups(&self,
+                              device: &wgpu::Device,
+                              camera_buffer: &wgpu::Buffer,
+                              lights: &Lights,
+                              material: &PbrMaterial,
+                              shadow_map: Option<(&ShadowMap, &ResourceRegistry)>) -> Result<Vec<wgpu::BindGroup>, String> {
+
+        if shadow_map.is_some() != self.shadows {
+            return Err(format!("PbrPipeline::create_bind_groups: shadows == {} but shadow_map.is_some() == {}.", self.shadows, shadow_map.is_some()));
+        }
+
+        let mut bindings = vec![
+            ((0, 0), camera_buffer.as_entire_binding()),
+            ((0, 1), lights.get_buffer().as_entire_binding()),
+            ((1, 0), material.get_buffer().as_entire_binding()),
+            ((1, 1), self.texture_view(&material.albedo_texture)),
+            ((1, 2), self.texture_view(&material.normal_texture)),
+            ((1, 3), self.texture_view(&material.roughness_texture)),
+            ((1, 4), wgpu::BindingResource::Sampler(&self.sampler)),
+        ];
+        if let Some((shadow_map, resources)) = shadow_map {
+            bindings.extend(shadow_bindings(shadow_map, resources, 2));
+        }
+        self.pipeline.create_bind_groups(&device, &bindings)
+    }
+
+    fn texture_view<'a>(&'a self, texture: &'a Option<JTexture>) -> wgpu::BindingResource<'a> {
+        wgpu::BindingResource::TextureView(&texture.as_ref().unwrap_or(&self.default_texture).view)
+    }
+
+    /// Draw an indexed mesh.
+    pub fn draw_mesh<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, bind_groups: &'a [wgpu::BindGroup], mesh: &'a PbrMesh) {
+        DrawCall::init(self.get_pipeline(), bind_groups)
+            .vertex_buffer(mesh.get_vertex_buffer())
+            .index_buffer(mesh.get_index_buffer(), wgpu::IndexFormat::Uint32)
+            .draw(render_pass, 0..mesh.get_index_count());
+    }
+
+    pub fn init(device: &wgpu::Device,
+                queue: &wgpu::Queue,
+                sc_desc: &wgpu::SurfaceConfiguration,
+                sample_count: u32,
+                shadows: bool,
+                ) -> Self {
+
+        let source = with_shadows(include_str!("../../shaders_wgsl/pbr_v4n4.wgsl"), 2);
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("pbr_v4n4.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(&source)),
+        });
+
+        // The shader output is linear. A linear surface needs the gamma correction.
+        let fs_entry_point = match (sc_desc.format.describe().srgb, shadows) {
+            (true, false) => "fs_main",
+            (false, false) => "fs_main_gamma",
+            (true, true) => "fs_main_shadow",
+            (false, true) => "fs_main_gamma_shadow",
+        };
+
+        // The shadow bindings (group 2) are only used by the shadow entry points.
+        let bindings = ShaderBindings::from_wgsl_entry_points(&source, &["vs_main", fs_entry_point]).unwrap();
+
+        let pipeline = RenderPipelineBuilder::init(&module, sc_desc.format)
+            .label("pbr v4n4")
+            .entry_points("vs_main", Some(fs_entry_point))
+            .vertex_formats(&[wgpu::VertexFormat::Float32x4, wgpu::VertexFormat::Float32x4])
+            .bind_groups_from(&bindings)
+            .sample_count(sample_count)
+            .build(&device);
+
+        // A 1x1 white texture for the missing material textures.
+        let default_texture = JTexture::create_texture2d(&device, &sc_desc, 1, 1, 1, Some("pbr default texture"));
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &default_texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &[255, 255, 255, 255],
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(NonZeroU32::new(4).unwrap()),
+                rows_per_image: Some(NonZeroU32::new(1).unwrap()),
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("pbr material sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            pipeline: pipeline,
+            default_texture: default_texture,
+            sampler: sampler,
+            shadows: shadows,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jaankaup_core::render_pipelines::wgsl_struct_layout;
+
+    fn pbr_v4n4() -> String {
+        with_shadows(include_str!("../../shaders_wgsl/pbr_v4n4.wgsl"), 2)
+    }
+
+    /// The byte offset of a field of value.
+    fn offset<T, F>(value: &T, field: &F) -> u32 {
+        (field as *const F as usize - value as *const T as usize) as u32
+    }
+
+    /// The struct must have the size and the member offsets of the wgsl struct. The size must be a
+    /// multiple of the wgsl alignment, so the arrays of the struct have no padding.
+    fn check_layout<T>(name: &str, offsets: &[u32]) {
+        let layout = wgsl_struct_layout(&pbr_v4n4(), name).unwrap();
+        assert_eq!(layout.size as usize, std::mem::size_of::<T>(), "the size of {}", name);
+        assert_eq!(layout.size % layout.alignment, 0, "the alignment of {}", name);
+        assert_eq!(layout.offsets.iter().map(|(_, o)| *o).collect::<Vec<u32>>(), offsets, "the offsets of {}", name);
+    }
+
+    #[test]
+    fn uniform_layouts() {
+        let m = MaterialUniform::zeroed();
+        check_layout::<MaterialUniform>("Material", &[offset(&m, &m.albedo), offset(&m, &m.metallic), offset(&m, &m.roughness),
+                                                      offset(&m, &m.texture_scale), offset(&m, &m.flags)]);
+
+        let l = LightUniform::zeroed();
+        check_layout::<LightUniform>("Lights", &[offset(&l, &l.directional), offset(&l, &l.point_lights), offset(&l, &l.point_light_count),
+                                                 offset(&l, &l.exposure), offset(&l, &l.ambient), offset(&l, &l.future_usage1)]);
+
+        let d = DirectionalLight::zeroed();
+        check_layout::<DirectionalLight>("DirectionalLight", &[offset(&d, &d.direction), offset(&d, &d.color)]);
+        let p = PointLight::zeroed();
+        check_layout::<PointLight>("PointLight", &[offset(&p, &p.position), offset(&p, &p.color)]);
+    }
+
+    #[test]
+    fn flag_bits() {
+        // The flags are distinct bits with the values of the shader constants.
+        let flags = [("ALBEDO_TEXTURE", ALBEDO_TEXTURE), ("NORMAL_TEXTURE", NORMAL_TEXTURE), ("ROUGHNESS_TEXTURE", ROUGHNESS_TEXTURE),
+                     ("ALBEDO_SRGB", ALBEDO_SRGB), ("NORMAL_SRGB", NORMAL_SRGB), ("ROUGHNESS_SRGB", ROUGHNESS_SRGB)];
+        assert_eq!(flags.iter().fold(0, |acc, (_, f)| acc | f).count_ones(), flags.len() as u32);
+        let source = pbr_v4n4();
+        for (name, value) in flags.iter() {
+            assert!(source.contains(&format!("let {}: u32 = {}u;", name, value)), "{} == {} isn't in pbr_v4n4.wgsl", name, value);
+        }
+    }
+
+    #[test]
+    fn material_flag_formats() {
+        let linear = Some(wgpu::TextureFormat::Rgba8Unorm);
+        let srgb = Some(wgpu::TextureFormat::Rgba8UnormSrgb);
+
+        assert_eq!(material_flags(None, None, None), 0);
+        assert_eq!(material_flags(linear, None, None), ALBEDO_TEXTURE);
+        assert_eq!(material_flags(srgb, None, None), ALBEDO_TEXTURE | ALBEDO_SRGB);
+        assert_eq!(material_flags(None, srgb, linear), NORMAL_TEXTURE | NORMAL_SRGB | ROUGHNESS_TEXTURE);
+        assert_eq!(material_flags(linear, linear, srgb), ALBEDO_TEXTURE | NORMAL_TEXTURE | ROUGHNESS_TEXTURE | ROUGHNESS_SRGB);
+    }
+}
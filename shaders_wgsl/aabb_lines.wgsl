@@ -0,0 +1,48 @@
+// Draws the edges of aabbs as a line list. The vertex buffer has the 24 line vertices of the
+// unit cube (BBox::to_lines) and the instance buffer the index of the aabb of each instance, e.g.
+// the visible buffer of frustum culling (frustum_cull.wgsl). fs_highlight draws the edges of the
+// selected aabbs.
+
+struct VertexOutput {
+    [[builtin(position)]] my_pos: vec4<f32>;
+};
+
+[[block]]
+struct Camera {
+    u_view_proj: mat4x4<f32>;
+    camera_pos: vec4<f32>;
+};
+
+struct AABB {
+    min: vec4<f32>;
+    max: vec4<f32>;
+};
+
+[[block]]
+struct AABBs {
+    data: [[stride(32)]] array<AABB>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> camerauniform: Camera;
+
+[[group(0), binding(1)]]
+var<storage, read> aabbs: AABBs;
+
+[[stage(vertex)]]
+fn vs_main([[location(0)]] corner: vec3<f32>, [[location(1)]] aabb_index: u32) -> VertexOutput {
+    let aabb = aabbs.data[aabb_index];
+    var out: VertexOutput;
+    out.my_pos = camerauniform.u_view_proj * vec4<f32>(mix(aabb.min.xyz, aabb.max.xyz, corner), 1.0);
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(0.2, 0.8, 0.2, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_highlight(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(1.0, 0.9, 0.1, 1.0);
+}
@@ -0,0 +1,112 @@
+// The active fmm blocks, i.e. the blocks with BAND nodes. find_active_blocks runs one invocation
+// per block, counts the BAND nodes of the block and appends the active blocks after the
+// block_count blocks of fmm_blocks. The number of the active blocks becomes the dispatch arguments
+// of band_points (IndirectArgsKernel::create_dispatch_args), which runs one workgroup per active
+// block and writes a debug point of each BAND node.
+
+let BAND: u32 = 1u;
+let MAX_WORKGROUPS_PER_DIMENSION: u32 = 65535u;
+
+[[block]]
+struct ActiveBlockParams {
+    global_dimensions: vec3<u32>;
+    block_count: u32;
+};
+
+struct FMM_Node {
+    value: f32;
+    tag: u32;
+};
+
+struct FMM_Block {
+    index: u32;
+    band_points_count: u32;
+};
+
+struct OutputVertex {
+    pos: vec3<f32>;
+    color_point_size: u32;
+};
+
+[[block]]
+struct FMM_Nodes {
+    data: [[stride(8)]] array<FMM_Node>;
+};
+
+[[block]]
+struct FMM_Blocks {
+    data: [[stride(8)]] array<FMM_Block>;
+};
+
+[[block]]
+struct Counters {
+    data: [[stride(4)]] array<atomic<u32>>;
+};
+
+[[block]]
+struct OutputVertices {
+    data: [[stride(16)]] array<OutputVertex>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: ActiveBlockParams;
+
+[[group(0), binding(1)]]
+var<storage, read> fmm_nodes: FMM_Nodes;
+
+[[group(0), binding(2)]]
+var<storage, read_write> fmm_blocks: FMM_Blocks;
+
+// 0 :: the number of the active blocks.
+[[group(0), binding(3)]]
+var<storage, read_write> active_block_counter: Counters;
+
+// 0 :: the number of the debug points.
+[[group(0), binding(4)]]
+var<storage, read_write> point_counter: Counters;
+
+[[group(0), binding(5)]]
+var<storage, read_write> output_points: OutputVertices;
+
+[[stage(compute), workgroup_size(64, 1, 1)]]
+fn find_active_blocks([[builtin(global_invocation_id)]] id: vec3<u32>) {
+
+    let block_index = id.x;
+    if (block_index >= params.block_count) { return; }
+
+    var band_points_count: u32 = 0u;
+    for (var i: u32 = 0u; i < 64u; i = i + 1u) {
+        if (fmm_nodes.data[block_index * 64u + i].tag == BAND) {
+            band_points_count = band_points_count + 1u;
+        }
+    }
+
+    fmm_blocks.data[block_index].band_points_count = band_points_count;
+
+    if (band_points_count > 0u) {
+        let active_index = atomicAdd(&active_block_counter.data[0], 1u);
+        fmm_blocks.data[params.block_count + active_index] = FMM_Block(block_index, band_points_count);
+    }
+}
+
+// Dispatched indirectly. The workgroups are split over y if there are more than 65535 active blocks.
+[[stage(compute), workgroup_size(64, 1, 1)]]
+fn band_points([[builtin(workgroup_id)]] workgroup_id: vec3<u32>,
+               [[builtin(local_invocation_index)]] local_index: u32) {
+
+    let active_index = workgroup_id.x + workgroup_id.y * MAX_WORKGROUPS_PER_DIMENSION;
+    if (active_index >= atomicLoad(&active_block_counter.data[0])) { return; }
+
+    let block_index = fmm_blocks.data[params.block_count + active_index].index;
+    let node = fmm_nodes.data[block_index * 64u + local_index];
+    if (node.tag != BAND) { return; }
+
+    // The nodes of a block are 4x4x4 and the blocks are in the x, y, z order.
+    let dim = params.global_dimensions;
+    let block = vec3<u32>(block_index % dim.x, (block_index / dim.x) % dim.y, block_index / (dim.x * dim.y));
+    let local = vec3<u32>(local_index % 4u, (local_index / 4u) % 4u, local_index / 16u);
+
+    let point_index = atomicAdd(&point_counter.data[0], 1u);
+    // rgba (255, 128, 0, 1).
+    output_points.data[point_index] = OutputVertex(vec3<f32>(block * 4u + local), 4286578689u);
+}
@@ -0,0 +1,69 @@
+// Frustum culling of aabbs. One invocation per aabb. The indices of the visible aabbs are written
+// to the visible buffer and the instance count of the draw indirect arguments is incremented. The
+// instance count must be reset to zero before dispatch.
+
+[[block]]
+struct CullParams {
+    // The frustum planes (n, d). Point p is inside the plane if dot(n, p) - d >= 0.
+    planes: array<vec4<f32>, 6>;
+    aabb_count: u32;
+    for_future_usage1: u32;
+    for_future_usage2: u32;
+    for_future_usage3: u32;
+};
+
+struct AABB {
+    min: vec4<f32>;
+    max: vec4<f32>;
+};
+
+[[block]]
+struct AABBs {
+    data: [[stride(32)]] array<AABB>;
+};
+
+[[block]]
+struct DrawIndirect {
+    vertex_count: u32;
+    instance_count: atomic<u32>;
+    base_vertex: u32;
+    base_instance: u32;
+};
+
+[[block]]
+struct Visible {
+    data: [[stride(4)]] array<u32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: CullParams;
+
+[[group(0), binding(1)]]
+var<storage, read> aabbs: AABBs;
+
+[[group(0), binding(2)]]
+var<storage, read_write> draw_indirect: DrawIndirect;
+
+[[group(0), binding(3)]]
+var<storage, read_write> visible: Visible;
+
+fn intersects_frustum(aabb: AABB) -> bool {
+    for (var i: u32 = 0u; i < 6u; i = i + 1u) {
+        let plane = params.planes[i];
+        // The corner that is farthest along the plane normal.
+        let p = select(aabb.min.xyz, aabb.max.xyz, plane.xyz >= vec3<f32>(0.0, 0.0, 0.0));
+        if (dot(plane.xyz, p) - plane.w < 0.0) { return false; }
+    }
+    return true;
+}
+
+[[stage(compute), workgroup_size(64,1,1)]]
+fn main([[builtin(global_invocation_id)]] global_id: vec3<u32>) {
+
+    if (global_id.x >= params.aabb_count) { return; }
+
+    if (intersects_frustum(aabbs.data[global_id.x])) {
+        let index = atomicAdd(&draw_indirect.instance_count, 1u);
+        visible.data[index] = global_id.x;
+    }
+}
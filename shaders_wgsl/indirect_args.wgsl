@@ -0,0 +1,63 @@
+// Turns a counter written by another kernel into draw indirect or dispatch indirect arguments so
+// the count doesn't have to be read back to the cpu. Dispatched with a single invocation.
+//
+// The dispatch arguments are split over y if the workgroups don't fit in x (max 65535), so the
+// kernel must compute the index as workgroup_id.x + workgroup_id.y * 65535 and skip the indices
+// past the count. The same math is in indirect::dispatch_args.
+
+let MAX_WORKGROUPS_PER_DIMENSION: u32 = 65535u;
+
+[[block]]
+struct IndirectParams {
+    // The index of the counter in the counter buffer.
+    counter_index: u32;
+    // 0 :: DrawIndirectArgs, 1 :: DispatchIndirectArgs.
+    kind: u32;
+    // Draw: the vertices first_vertex..counter are drawn.
+    first_vertex: u32;
+    // Draw: the number of instances.
+    instance_count: u32;
+    // Dispatch: the number of counted items per workgroup.
+    items_per_workgroup: u32;
+    for_future_usage1: u32;
+    for_future_usage2: u32;
+    for_future_usage3: u32;
+};
+
+[[block]]
+struct Counters {
+    data: [[stride(4)]] array<u32>;
+};
+
+[[block]]
+struct IndirectArgs {
+    data: [[stride(4)]] array<u32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: IndirectParams;
+
+[[group(0), binding(1)]]
+var<storage, read> counters: Counters;
+
+[[group(0), binding(2)]]
+var<storage, read_write> args: IndirectArgs;
+
+[[stage(compute), workgroup_size(1,1,1)]]
+fn main() {
+
+    let counter = counters.data[params.counter_index];
+
+    if (params.kind == 0u) {
+        args.data[0] = select(0u, counter - params.first_vertex, counter > params.first_vertex);
+        args.data[1] = params.instance_count;
+        args.data[2] = params.first_vertex;
+        args.data[3] = 0u;
+    }
+    else {
+        let workgroups = counter / params.items_per_workgroup + select(0u, 1u, counter % params.items_per_workgroup != 0u);
+        args.data[0] = min(workgroups, MAX_WORKGROUPS_PER_DIMENSION);
+        args.data[1] = workgroups / MAX_WORKGROUPS_PER_DIMENSION + select(0u, 1u, workgroups % MAX_WORKGROUPS_PER_DIMENSION != 0u);
+        args.data[2] = 1u;
+    }
+}
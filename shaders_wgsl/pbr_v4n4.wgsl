@@ -0,0 +1,260 @@
+// Metallic-roughness PBR for Vertex_vvvvnnnn data. The vertices have no texture coordinates, so
+// the textures are mapped triplanarly from the world space position. Cook-Torrance BRDF (GGX,
+// Smith, Schlick) with a directional light and point lights, ACES tonemapping. The _shadow entry
+// points shadow the directional light with cascaded shadow maps.
+
+[[block]]
+struct Camera {
+    u_view_proj: mat4x4<f32>;
+    camera_pos: vec4<f32>;
+};
+
+struct DirectionalLight {
+    // The direction where the light travels.
+    direction: vec4<f32>;
+    // rgb :: color, a :: intensity.
+    color: vec4<f32>;
+};
+
+struct PointLight {
+    // xyz :: position, w :: range.
+    position: vec4<f32>;
+    // rgb :: color, a :: intensity.
+    color: vec4<f32>;
+};
+
+[[block]]
+struct Lights {
+    directional: DirectionalLight;
+    point_lights: [[stride(32)]] array<PointLight, 4>;
+    point_light_count: u32;
+    exposure: f32;
+    ambient: f32;
+    for_future_usage1: f32;
+};
+
+[[block]]
+struct Material {
+    albedo: vec4<f32>;
+    metallic: f32;
+    roughness: f32;
+    // The world space size of a texture repeat is 1.0 / texture_scale.
+    texture_scale: f32;
+    // See the flag constants below.
+    flags: u32;
+};
+
+// Material flags.
+let ALBEDO_TEXTURE: u32 = 1u;
+let NORMAL_TEXTURE: u32 = 2u;
+let ROUGHNESS_TEXTURE: u32 = 4u;
+// The texture has an sRGB format, so the sampled values are already linear.
+let ALBEDO_SRGB: u32 = 8u;
+let NORMAL_SRGB: u32 = 16u;
+let ROUGHNESS_SRGB: u32 = 32u;
+
+let PI: f32 = 3.14159265359;
+
+[[group(0), binding(0)]]
+var<uniform> camerauniform: Camera;
+
+[[group(0), binding(1)]]
+var<uniform> lights: Lights;
+
+[[group(1), binding(0)]]
+var<uniform> material: Material;
+
+[[group(1), binding(1)]]
+var t_albedo: texture_2d<f32>;
+
+[[group(1), binding(2)]]
+var t_normal: texture_2d<f32>;
+
+// glTF convention: roughness in green and metallic in blue.
+[[group(1), binding(3)]]
+var t_roughness: texture_2d<f32>;
+
+[[group(1), binding(4)]]
+var s_material: sampler;
+
+// The cascaded shadow maps (Shadows, shadow_factor) of the _shadow entry points are in
+// shadows.wgsl in group 2 (see shadow::with_shadows).
+
+struct VertexOutput {
+    [[builtin(position)]] my_pos: vec4<f32>;
+    [[location(0)]] pos: vec4<f32>;
+    [[location(1)]] nor: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[location(0)]] pos: vec4<f32>, [[location(1)]] nor: vec4<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.my_pos = camerauniform.u_view_proj * vec4<f32>(pos.xyz, 1.0);
+    out.pos = vec4<f32>(pos.xyz, 1.0);
+    out.nor = vec4<f32>(nor.xyz, 0.0);
+    return out;
+}
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    return pow(c, vec3<f32>(2.2));
+}
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    return pow(c, vec3<f32>(1.0 / 2.2));
+}
+
+// The weights of the x, y and z projections.
+fn triplanar_weights(n: vec3<f32>) -> vec3<f32> {
+    var w: vec3<f32> = pow(abs(n), vec3<f32>(4.0));
+    return w / (w.x + w.y + w.z);
+}
+
+fn sample_albedo(p: vec3<f32>, w: vec3<f32>) -> vec4<f32> {
+    return textureSample(t_albedo, s_material, p.zy) * w.x +
+           textureSample(t_albedo, s_material, p.xz) * w.y +
+           textureSample(t_albedo, s_material, p.xy) * w.z;
+}
+
+fn sample_roughness(p: vec3<f32>, w: vec3<f32>) -> vec4<f32> {
+    return textureSample(t_roughness, s_material, p.zy) * w.x +
+           textureSample(t_roughness, s_material, p.xz) * w.y +
+           textureSample(t_roughness, s_material, p.xy) * w.z;
+}
+
+// Triplanar normal mapping with whiteout blending.
+fn triplanar_normal(p: vec3<f32>, n: vec3<f32>, w: vec3<f32>, srgb: bool) -> vec3<f32> {
+    var tx: vec3<f32> = textureSample(t_normal, s_material, p.zy).xyz;
+    var ty: vec3<f32> = textureSample(t_normal, s_material, p.xz).xyz;
+    var tz: vec3<f32> = textureSample(t_normal, s_material, p.xy).xyz;
+    if (srgb) {
+        tx = linear_to_srgb(tx);
+        ty = linear_to_srgb(ty);
+        tz = linear_to_srgb(tz);
+    }
+    tx = tx * 2.0 - vec3<f32>(1.0);
+    ty = ty * 2.0 - vec3<f32>(1.0);
+    tz = tz * 2.0 - vec3<f32>(1.0);
+    tx = vec3<f32>(tx.xy + n.zy, abs(tx.z) * n.x);
+    ty = vec3<f32>(ty.xy + n.xz, abs(ty.z) * n.y);
+    tz = vec3<f32>(tz.xy + n.xy, abs(tz.z) * n.z);
+    return normalize(tx.zyx * w.x + ty.xzy * w.y + tz.xyz * w.z);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let gv = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let gl = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return gv * gl;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (vec3<f32>(1.0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// The outgoing radiance from a light with direction l (towards the light) and radiance.
+fn brdf(n: vec3<f32>, v: vec3<f32>, l: vec3<f32>, radiance: vec3<f32>,
+        albedo: vec3<f32>, metallic: f32, roughness: f32) -> vec3<f32> {
+
+    let h = normalize(v + l);
+    let n_dot_l = max(dot(n, l), 0.0);
+    let n_dot_v = max(dot(n, v), 0.0001);
+    let n_dot_h = max(dot(n, h), 0.0);
+
+    let f0 = mix(vec3<f32>(0.04), albedo, vec3<f32>(metallic));
+    let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    let specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness) * f /
+                   (4.0 * n_dot_v * n_dot_l + 0.0001);
+    let kd = (vec3<f32>(1.0) - f) * (1.0 - metallic);
+
+    return (kd * albedo / PI + specular) * radiance * n_dot_l;
+}
+
+// Narkowicz's fit of the ACES filmic curve.
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return clamp((x * (2.51 * x + vec3<f32>(0.03))) / (x * (2.43 * x + vec3<f32>(0.59)) + vec3<f32>(0.14)), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// The tonemapped linear color. shadow scales the directional light.
+fn shade(in: VertexOutput, shadow: f32) -> vec3<f32> {
+
+    let p = in.pos.xyz * material.texture_scale;
+    var n: vec3<f32> = normalize(in.nor.xyz);
+    let w = triplanar_weights(n);
+
+    var albedo: vec3<f32> = material.albedo.rgb;
+    var metallic: f32 = material.metallic;
+    var roughness: f32 = material.roughness;
+
+    if ((material.flags & ALBEDO_TEXTURE) != 0u) {
+        var c: vec3<f32> = sample_albedo(p, w).rgb;
+        if ((material.flags & ALBEDO_SRGB) == 0u) {
+            c = srgb_to_linear(c);
+        }
+        albedo = albedo * c;
+    }
+    if ((material.flags & ROUGHNESS_TEXTURE) != 0u) {
+        var mr: vec3<f32> = sample_roughness(p, w).rgb;
+        if ((material.flags & ROUGHNESS_SRGB) != 0u) {
+            mr = linear_to_srgb(mr);
+        }
+        roughness = roughness * mr.g;
+        metallic = metallic * mr.b;
+    }
+    if ((material.flags & NORMAL_TEXTURE) != 0u) {
+        n = triplanar_normal(p, n, w, (material.flags & NORMAL_SRGB) != 0u);
+    }
+    roughness = clamp(roughness, 0.045, 1.0);
+
+    let v = normalize(camerauniform.camera_pos.xyz - in.pos.xyz);
+
+    var color: vec3<f32> = lights.ambient * albedo;
+
+    let dir_light = lights.directional;
+    color = color + brdf(n, v, normalize(-dir_light.direction.xyz), dir_light.color.rgb * dir_light.color.a * shadow,
+                         albedo, metallic, roughness);
+
+    for (var i: u32 = 0u; i < lights.point_light_count; i = i + 1u) {
+        let light = lights.point_lights[i];
+        let to_light = light.position.xyz - in.pos.xyz;
+        let d = length(to_light);
+        // Inverse square falloff windowed to zero at the range.
+        let window = clamp(1.0 - pow(d / light.position.w, 4.0), 0.0, 1.0);
+        let attenuation = window * window / (d * d + 1.0);
+        color = color + brdf(n, v, to_light / d, light.color.rgb * light.color.a * attenuation,
+                             albedo, metallic, roughness);
+    }
+
+    return aces(color * lights.exposure);
+}
+
+// For sRGB surfaces.
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(shade(in, 1.0), 1.0);
+}
+
+// For linear surfaces.
+[[stage(fragment)]]
+fn fs_main_gamma(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(linear_to_srgb(shade(in, 1.0)), 1.0);
+}
+
+// fs_main with shadows.
+[[stage(fragment)]]
+fn fs_main_shadow(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(shade(in, shadow_factor(in.pos.xyz, normalize(in.nor.xyz))), 1.0);
+}
+
+// fs_main_gamma with shadows.
+[[stage(fragment)]]
+fn fs_main_gamma_shadow(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(linear_to_srgb(shade(in, shadow_factor(in.pos.xyz, normalize(in.nor.xyz)))), 1.0);
+}
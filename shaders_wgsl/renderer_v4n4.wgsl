@@ -21,6 +21,9 @@ var t_diffuse2: texture_2d<f32>;
 [[group(1), binding(3)]]
 var s_diffuse2: sampler;
 
+// The cascaded shadow maps (Shadows, shadow_factor) of the _shadow entry points are in
+// shadows.wgsl in group 2 (see shadow::with_shadows).
+
 fn rgb2hsv(c: vec3<f32>) -> vec3<f32> {
     let K = vec4<f32>(0.0, -1.0 / 3.0, 2.0 / 3.0, -1.0);
     let p = mix(vec4<f32>(c.bg, K.wz), vec4<f32>(c.gb, K.xy), vec4<f32>(step(c.b, c.g)));
@@ -67,10 +70,8 @@ let material_shininess: f32 = 55.0;
 let ambient_coeffience: f32 = 0.15;
 let attentuation_factor: f32 = 0.013;
 
-[[stage(fragment)]]
-
 //fn fs_main(in: VertexOutput, [[builtin(position)]] frag_pos: vec4<f32>) -> [[location(0)]] vec4<f32> {
-fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+fn shade(in: VertexOutput, shadow: f32) -> vec4<f32> {
 
     var light_dir: vec3<f32> = normalize(light_pos - in.pos.xyz);
     var normal: vec3<f32> = normalize(in.nor).xyz; // is this necessery? 
@@ -104,7 +105,7 @@ fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
     var distance_to_light: f32 = distance(in.pos.xyz, light_pos); 
     var attentuation: f32 = 1.0 / (1.0 + attentuation_factor * pow(distance_to_light,2.0));
     
-    var final_color: vec4<f32> = vec4<f32>(ambient_component + attentuation * (diffuse_component + specular_component) , 1.0);
+    var final_color: vec4<f32> = vec4<f32>(ambient_component + attentuation * shadow * (diffuse_component + specular_component) , 1.0);
 
     var the_color: vec3<f32> = rgb2hsv(final_color.xyz);
 
@@ -116,3 +117,14 @@ fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
 
     return vec4<f32>(mix(vec3<f32>(0.5, 0.0, 0.0), the_color2, vec3<f32>(blah)), 1.0);
 }
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return shade(in, 1.0);
+}
+
+// The direct light is shadowed.
+[[stage(fragment)]]
+fn fs_main_shadow(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return shade(in, shadow_factor(in.pos.xyz, normalize(in.nor.xyz)));
+}
@@ -13,6 +13,9 @@ struct Camera {
 [[group(0), binding(0)]]
 var<uniform> camerauniform: Camera;
 
+// The cascaded shadow maps (Shadows, shadow_factor) of the _shadow entry points are in
+// shadows.wgsl in group 1 (see shadow::with_shadows).
+
 [[stage(vertex)]]
 fn vs_main([[location(0)]] pos: vec4<f32>, [[location(1)]] nor: vec4<f32>) -> VertexOutput {
     var out: VertexOutput;
@@ -30,9 +33,7 @@ let material_shininess: f32 = 75.0;
 let ambient_coeffience: f32 = 0.15;
 let attentuation_factor: f32 = 0.009;
 
-[[stage(fragment)]]
-
-fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+fn shade(in: VertexOutput, shadow: f32) -> vec4<f32> {
 
     var light_dir: vec3<f32> = normalize(light_pos - in.pos.xyz);
     var normal: vec3<f32> = normalize(in.nor).xyz; // is this necessery? 
@@ -56,7 +57,18 @@ fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
     var distance_to_light: f32 = distance(in.pos.xyz, light_pos); 
     var attentuation: f32 = 1.0 / (1.0 + attentuation_factor * pow(distance_to_light,2.0));
     
-    var final_color: vec4<f32> = vec4<f32>(ambient_component + attentuation * (diffuse_component + specular_component) , 1.0);
+    var final_color: vec4<f32> = vec4<f32>(ambient_component + attentuation * shadow * (diffuse_component + specular_component) , 1.0);
 
     return final_color;
 }
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return shade(in, 1.0);
+}
+
+// The direct light is shadowed. The light direction of the shadow map should match light_pos.
+[[stage(fragment)]]
+fn fs_main_shadow(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return shade(in, shadow_factor(in.pos.xyz, normalize(in.nor.xyz)));
+}
@@ -0,0 +1,14 @@
+// Renders the depth of a shadow cascade. The position must be the first vertex attribute.
+
+[[block]]
+struct Cascade {
+    light_view_proj: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> cascade: Cascade;
+
+[[stage(vertex)]]
+fn vs_main([[location(0)]] pos: vec4<f32>) -> [[builtin(position)]] vec4<f32> {
+    return cascade.light_view_proj * vec4<f32>(pos.xyz, 1.0);
+}
@@ -0,0 +1,60 @@
+// Cascaded shadow maps (see shadow::ShadowMap) for the _shadow entry points of renderer_v4n4.wgsl,
+// renderer_v4n4_plain.wgsl and pbr_v4n4.wgsl. Not a complete shader: shadow::with_shadows puts
+// this in front of the shader and replaces SHADOW_GROUP with the bind group of the shadows.
+
+[[block]]
+struct Shadows {
+    light_view_proj: [[stride(64)]] array<mat4x4<f32>, 4>;
+    // The far distance of each cascade along the view direction.
+    splits: vec4<f32>;
+    camera_pos: vec4<f32>;
+    view_dir: vec4<f32>;
+    // The world space size of a shadow map texel in each cascade.
+    texel_world_size: vec4<f32>;
+    cascade_count: u32;
+    texel_size: f32;
+    depth_bias: f32;
+    normal_bias: f32;
+};
+
+[[group(SHADOW_GROUP), binding(0)]]
+var<uniform> shadows: Shadows;
+
+[[group(SHADOW_GROUP), binding(1)]]
+var t_shadow: texture_depth_2d_array;
+
+[[group(SHADOW_GROUP), binding(2)]]
+var s_shadow: sampler_comparison;
+
+// The fraction of the directional light that reaches pos (1.0 :: lit). 3x3 PCF in the cascade
+// that contains pos. Beyond the last cascade everything is lit.
+fn shadow_factor(pos: vec3<f32>, normal: vec3<f32>) -> f32 {
+
+    let depth = dot(pos - shadows.camera_pos.xyz, shadows.view_dir.xyz);
+    if (depth > shadows.splits[shadows.cascade_count - 1u]) {
+        return 1.0;
+    }
+
+    var cascade: u32 = 0u;
+    for (var i: u32 = 0u; i < shadows.cascade_count - 1u; i = i + 1u) {
+        if (depth > shadows.splits[i]) {
+            cascade = i + 1u;
+        }
+    }
+
+    // The normal offset moves the receiver out of its own shadow.
+    let world = pos + normal * shadows.normal_bias * shadows.texel_world_size[cascade];
+    let clip = shadows.light_view_proj[cascade] * vec4<f32>(world, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+    let reference = ndc.z - shadows.depth_bias;
+
+    var lit: f32 = 0.0;
+    for (var y: i32 = -1; y <= 1; y = y + 1) {
+        for (var x: i32 = -1; x <= 1; x = x + 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadows.texel_size;
+            lit = lit + textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, i32(cascade), reference);
+        }
+    }
+    return lit / 9.0;
+}
@@ -0,0 +1,181 @@
+// Mesh voxelization. One invocation per triangle.
+// mode == 0u: surface voxelization (triangle-box overlap, separating axis theorem).
+// mode == 1u: solid voxelization (scanline parity along x-axis). The voxel buffer must be
+//             cleared before dispatch.
+// The same algorithms are implemented in cpu_version::voxelize.
+
+[[block]]
+struct VoxelParams {
+    origin: vec4<f32>;
+    dimension: vec4<u32>;
+    voxel_size: f32;
+    triangle_count: u32;
+    mode: u32;
+    for_future_usage: u32;
+};
+
+struct Triangle {
+    a: vec4<f32>;
+    na: vec4<f32>;
+    b: vec4<f32>;
+    nb: vec4<f32>;
+    c: vec4<f32>;
+    nc: vec4<f32>;
+};
+
+[[block]]
+struct Triangles {
+    data: [[stride(96)]] array<Triangle>;
+};
+
+[[block]]
+struct Voxels {
+    data: [[stride(4)]] array<atomic<u32>>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> params: VoxelParams;
+
+[[group(0), binding(1)]]
+var<storage, read> triangles: Triangles;
+
+[[group(0), binding(2)]]
+var<storage, read_write> voxels: Voxels;
+
+fn voxel_index(x: u32, y: u32, z: u32) -> u32 {
+    return x + y * params.dimension.x + z * params.dimension.x * params.dimension.y;
+}
+
+// Returns true if the axis separates the triangle (v0, v1, v2) and the box centered at origin.
+fn separates(axis: vec3<f32>, v0: vec3<f32>, v1: vec3<f32>, v2: vec3<f32>, half: vec3<f32>) -> bool {
+    let p0 = dot(axis, v0);
+    let p1 = dot(axis, v1);
+    let p2 = dot(axis, v2);
+    let r = dot(half, abs(axis));
+    return min(min(p0, p1), p2) > r || max(max(p0, p1), p2) < -r;
+}
+
+fn triangle_box_overlap(center: vec3<f32>, half: vec3<f32>, a: vec3<f32>, b: vec3<f32>, c: vec3<f32>) -> bool {
+
+    let v0 = a - center;
+    let v1 = b - center;
+    let v2 = c - center;
+
+    let e0 = v1 - v0;
+    let e1 = v2 - v1;
+    let e2 = v0 - v2;
+
+    let x = vec3<f32>(1.0, 0.0, 0.0);
+    let y = vec3<f32>(0.0, 1.0, 0.0);
+    let z = vec3<f32>(0.0, 0.0, 1.0);
+
+    if (separates(cross(x, e0), v0, v1, v2, half) ||
+        separates(cross(x, e1), v0, v1, v2, half) ||
+        separates(cross(x, e2), v0, v1, v2, half) ||
+        separates(cross(y, e0), v0, v1, v2, half) ||
+        separates(cross(y, e1), v0, v1, v2, half) ||
+        separates(cross(y, e2), v0, v1, v2, half) ||
+        separates(cross(z, e0), v0, v1, v2, half) ||
+        separates(cross(z, e1), v0, v1, v2, half) ||
+        separates(cross(z, e2), v0, v1, v2, half)) { return false; }
+
+    if (separates(x, v0, v1, v2, half) ||
+        separates(y, v0, v1, v2, half) ||
+        separates(z, v0, v1, v2, half)) { return false; }
+
+    return !separates(cross(e0, e1), v0, v1, v2, half);
+}
+
+// Twice the signed area of (a, b, p) on the yz-plane.
+fn edge_function(a: vec3<f32>, b: vec3<f32>, p: vec2<f32>) -> f32 {
+    return (a.y - p.x) * (b.z - p.y) - (a.z - p.y) * (b.y - p.x);
+}
+
+// The edge a->b owns the points on it if it is a top or left edge in ccw order.
+fn owns_edge(a: vec3<f32>, b: vec3<f32>, sign: f32) -> bool {
+    let du = sign * (b.y - a.y);
+    let dv = sign * (b.z - a.z);
+    return dv < 0.0 || (dv == 0.0 && du > 0.0);
+}
+
+fn inside_edge(w: f32, a: vec3<f32>, b: vec3<f32>, sign: f32) -> bool {
+    return w > 0.0 || (w == 0.0 && owns_edge(a, b, sign));
+}
+
+fn surface_voxelization(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>) {
+
+    let h = params.voxel_size;
+    let upper = vec3<f32>(params.dimension.xyz) - vec3<f32>(1.0, 1.0, 1.0);
+    let min_coord = max(ceil((min(min(a, b), c) - params.origin.xyz) / h) - vec3<f32>(1.0, 1.0, 1.0), vec3<f32>(0.0, 0.0, 0.0));
+    let max_coord = min(floor((max(max(a, b), c) - params.origin.xyz) / h), upper);
+
+    if (any(min_coord > max_coord)) { return; }
+
+    let half = vec3<f32>(0.5 * h, 0.5 * h, 0.5 * h);
+
+    for (var k: u32 = u32(min_coord.z); k <= u32(max_coord.z); k = k + 1u) {
+    for (var j: u32 = u32(min_coord.y); j <= u32(max_coord.y); j = j + 1u) {
+    for (var i: u32 = u32(min_coord.x); i <= u32(max_coord.x); i = i + 1u) {
+        let center = params.origin.xyz + h * vec3<f32>(f32(i) + 0.5, f32(j) + 0.5, f32(k) + 0.5);
+        if (triangle_box_overlap(center, half, a, b, c)) {
+            atomicStore(&voxels.data[voxel_index(i, j, k)], 1u);
+        }
+    }}}
+}
+
+fn solid_voxelization(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>) {
+
+    let area = (b.y - a.y) * (c.z - a.z) - (b.z - a.z) * (c.y - a.y);
+
+    // The triangle is parallel to the x-axis.
+    if (area == 0.0) { return; }
+
+    var sign: f32 = 1.0;
+    if (area < 0.0) { sign = -1.0; }
+
+    let h = params.voxel_size;
+    let upper = vec3<f32>(params.dimension.xyz) - vec3<f32>(1.0, 1.0, 1.0);
+    let min_coord = max(ceil((min(min(a, b), c) - params.origin.xyz) / h - vec3<f32>(0.5, 0.5, 0.5)), vec3<f32>(0.0, 0.0, 0.0));
+    let max_coord = min(floor((max(max(a, b), c) - params.origin.xyz) / h - vec3<f32>(0.5, 0.5, 0.5)), upper);
+
+    if (min_coord.y > max_coord.y || min_coord.z > max_coord.z) { return; }
+
+    for (var k: u32 = u32(min_coord.z); k <= u32(max_coord.z); k = k + 1u) {
+    for (var j: u32 = u32(min_coord.y); j <= u32(max_coord.y); j = j + 1u) {
+
+        let p = params.origin.yz + h * vec2<f32>(f32(j) + 0.5, f32(k) + 0.5);
+
+        let w0 = sign * edge_function(b, c, p);
+        let w1 = sign * edge_function(c, a, p);
+        let w2 = sign * edge_function(a, b, p);
+
+        if (!(inside_edge(w0, b, c, sign) && inside_edge(w1, c, a, sign) && inside_edge(w2, a, b, sign))) { continue; }
+
+        let sum = w0 + w1 + w2;
+        if (sum <= 0.0) { continue; }
+
+        let x = (w0 * a.x + w1 * b.x + w2 * c.x) / sum;
+
+        // Flip the voxels whose center is after the crossing.
+        let first = u32(clamp(floor((x - params.origin.x) / h - 0.5) + 1.0, 0.0, f32(params.dimension.x)));
+        for (var i: u32 = first; i < params.dimension.x; i = i + 1u) {
+            // The atomic functions are expressions, so the previous value is assigned.
+            let previous = atomicXor(&voxels.data[voxel_index(i, j, k)], 1u);
+        }
+    }}
+}
+
+[[stage(compute), workgroup_size(64,1,1)]]
+fn main([[builtin(global_invocation_id)]] global_id: vec3<u32>) {
+
+    if (global_id.x >= params.triangle_count) { return; }
+
+    let tr = triangles.data[global_id.x];
+
+    if (params.mode == 0u) {
+        surface_voxelization(tr.a.xyz, tr.b.xyz, tr.c.xyz);
+    }
+    else {
+        solid_voxelization(tr.a.xyz, tr.b.xyz, tr.c.xyz);
+    }
+}